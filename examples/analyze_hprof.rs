@@ -4,7 +4,6 @@ use chrono;
 use chrono::offset::TimeZone;
 use clap;
 use csv;
-use memmap;
 use num_cpus;
 use rayon;
 
@@ -13,24 +12,69 @@ use jvm_hprof::heap_dump::SubRecord;
 use jvm_hprof::{Hprof, RecordTag};
 use std::{collections, fs, io, path};
 
+#[path = "analyze_hprof/busy_monitors.rs"]
+mod busy_monitors;
 #[path = "analyze_hprof/class_hierarchy_dot.rs"]
 mod class_hierarchy_dot;
+#[path = "analyze_hprof/classes_without_instances.rs"]
+mod classes_without_instances;
+#[path = "analyze_hprof/classloaders.rs"]
+mod classloaders;
+#[path = "analyze_hprof/collection_sizes.rs"]
+mod collection_sizes;
 #[path = "analyze_hprof/counter.rs"]
 mod counter;
+#[path = "analyze_hprof/dominators.rs"]
+mod dominators;
 #[path = "analyze_hprof/dot.rs"]
 mod dot;
+#[path = "analyze_hprof/dump_enums.rs"]
+mod dump_enums;
+#[path = "analyze_hprof/dump_jsonl.rs"]
+mod dump_jsonl;
 #[path = "analyze_hprof/dump_objects.rs"]
 mod dump_objects;
+#[path = "analyze_hprof/duplicate_objects.rs"]
+mod duplicate_objects;
+#[path = "analyze_hprof/export_edges.rs"]
+mod export_edges;
+#[path = "analyze_hprof/gc_root_counts.rs"]
+mod gc_root_counts;
+#[path = "analyze_hprof/grep_utf8.rs"]
+mod grep_utf8;
+#[path = "analyze_hprof/hierarchy_stats.rs"]
+mod hierarchy_stats;
 #[path = "analyze_hprof/index/mod.rs"]
 mod index;
 #[path = "analyze_hprof/instance_counts.rs"]
 mod instance_counts;
+#[path = "analyze_hprof/java_string.rs"]
+mod java_string;
+#[path = "analyze_hprof/leak_suspects.rs"]
+mod leak_suspects;
+#[path = "analyze_hprof/object_graph.rs"]
+mod object_graph;
+#[path = "analyze_hprof/package_counts.rs"]
+mod package_counts;
+#[path = "analyze_hprof/prim_array_sizes.rs"]
+mod prim_array_sizes;
 #[path = "analyze_hprof/ref_count_graph.rs"]
 mod ref_count_graph;
+#[path = "analyze_hprof/retained_histogram.rs"]
+mod retained_histogram;
+#[path = "analyze_hprof/static_field_stats.rs"]
+mod static_field_stats;
+#[cfg(test)]
+#[path = "../src/test_util.rs"]
+mod test_util;
+#[path = "analyze_hprof/thread_dump.rs"]
+mod thread_dump;
+#[path = "analyze_hprof/unreachable_objects.rs"]
+mod unreachable_objects;
 #[path = "analyze_hprof/util.rs"]
 mod util;
 
-use crate::index::{lmdb::LmdbIndex, HprofFingerprint, Index};
+use crate::index::Index;
 use util::*;
 
 fn main() -> Result<(), anyhow::Error> {
@@ -51,20 +95,95 @@ fn main() -> Result<(), anyhow::Error> {
                 .takes_value(true)
                 .help("Number of threads to use, if subcommand is multithreaded. Defaults to 4 or the number of cores, whichever is smaller."),
         )
+        .arg(
+            clap::Arg::with_name("obj-id-range")
+                .long("obj-id-range")
+                .required(false)
+                .takes_value(true)
+                .value_name("START..END")
+                .help("Only process objects with ids in the half-open range START..END (either end may be omitted), with ids in decimal or 0x-prefixed hex. Applies to instance-counts, package-counts, prim-array-sizes, duplicate-objects, dump-objects, and export-edges. Object ids are addresses, not sequence numbers, so a range covers an unpredictable number of objects."),
+        )
+        .arg(
+            clap::Arg::with_name("id-format")
+                .long("id-format")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["dec", "hex"])
+                .default_value("dec")
+                .help("How to write ids: decimal, or 0x-prefixed hex zero-padded to the hprof's id size. Applies to dump-utf8, grep-utf8, dump-load-class, dump-stack-trace, dump-enums, dump-classes, and dump-objects."),
+        )
         .subcommand(clap::SubCommand::with_name("header")
             .about("Display metadata from the hprof header"))
         .subcommand(clap::SubCommand::with_name("record-counts")
             .about("Display the number of each of the top level hprof record types"))
         .subcommand(clap::SubCommand::with_name("dump-utf8")
             .about("Display Utf8 records as CSV"))
+        .subcommand(
+            clap::SubCommand::with_name("grep-utf8")
+                .about("Display Utf8 records whose text contains a substring as CSV")
+                .arg(
+                    clap::Arg::with_name("pattern")
+                        .short("p")
+                        .long("pattern")
+                        .help("Substring to search for")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("regex")
+                        .long("regex")
+                        .required(false)
+                        .takes_value(false)
+                        .help("Treat the pattern as a regular expression rather than a plain substring"),
+                ),
+        )
         .subcommand(clap::SubCommand::with_name("dump-load-class")
             .about("Display LoadClass records as CSV"))
         .subcommand(clap::SubCommand::with_name("dump-stack-trace")
             .about("Display StackTrace records"))
+        .subcommand(clap::SubCommand::with_name("dump-enums")
+            .about("Display the name and ordinal of each enum constant as CSV"))
+        .subcommand(clap::SubCommand::with_name("thread-dump")
+            .about("Display each thread's stack trace and the objects rooted by each frame"))
+        .subcommand(clap::SubCommand::with_name("busy-monitors")
+            .about("Display objects whose monitors are held, and the threads likely holding them, as CSV"))
         .subcommand(clap::SubCommand::with_name("dump-classes")
             .about("Display Class heap dump subrecords"))
-        .subcommand(clap::SubCommand::with_name("dump-objects")
-            .about("Display Object (and other associated) heap dump subrecords"))
+        .subcommand(
+            clap::SubCommand::with_name("dump-objects")
+                .about("Display Object (and other associated) heap dump subrecords")
+                .arg(
+                    clap::Arg::with_name("class-name")
+                        .long("class-name")
+                        .value_name("CLASS")
+                        .help("only show instances of this class (e.g. java.lang.String) and arrays of it")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("max-array-elements")
+                        .long("max-array-elements")
+                        .value_name("N")
+                        .help("show at most this many elements of each array")
+                        .required(false)
+                        .default_value("32")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("resolve-strings")
+                        .long("resolve-strings")
+                        .help("show the text of Strings that fields refer to")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    clap::Arg::with_name("with-alloc-stack")
+                        .long("with-alloc-stack")
+                        .help("show the stack trace each object was allocated at, if recorded")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
         .subcommand(
             clap::SubCommand::with_name("class-hierarchy")
                 .about("Generate a GraphViz dot file of class hierarchy")
@@ -78,16 +197,40 @@ fn main() -> Result<(), anyhow::Error> {
                 ),
         )
         .subcommand(
-            clap::SubCommand::with_name("ref-count-graph")
-                .about("Generate a GraphViz dot file of class fields to what types are pointed to by those fields")
+            clap::SubCommand::with_name("hierarchy-stats")
+                .about("Display the number of classes at each depth of the class hierarchy, and the deepest hierarchies")
+                .arg(
+                    clap::Arg::with_name("top")
+                        .long("top")
+                        .help("number of deepest hierarchies to show")
+                        .required(false)
+                        .default_value("10")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("export-edges")
+                .about("Write every object reference (from fields, arrays, static fields, and GC roots) as CSV")
                 .arg(
-                    clap::Arg::with_name("index")
-                        .short("i")
-                        .long("index")
-                        .help("path index for the hprof file (created with the build-index subcommand)")
+                    clap::Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("path to output CSV file")
                         .required(true)
                         .takes_value(true),
                 )
+                .arg(
+                    clap::Arg::with_name("label-weak")
+                        .long("label-weak")
+                        .help("label the referent of soft, weak, and phantom references as weak rather than field")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("ref-count-graph")
+                .about("Generate a GraphViz dot file of class fields to what types are pointed to by those fields")
+                .args(&index_args())
                 .arg(
                     clap::Arg::with_name("output")
                         .short("o")
@@ -103,10 +246,202 @@ fn main() -> Result<(), anyhow::Error> {
                         .required(false)
                         .default_value("1")
                         .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("edge-label-mode")
+                        .long("edge-label-mode")
+                        .help("label edges with the number of references, their percentage of all references from the same field or root type, or the base 10 log of the number of references")
+                        .required(false)
+                        .possible_values(&["absolute", "percent", "log"])
+                        .default_value("absolute")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("sample-edges")
+                        .long("sample-edges")
+                        .help("also write this many randomly sampled object-to-object references to --sample-output as CSV")
+                        .required(false)
+                        .requires("sample-output")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("sample-output")
+                        .long("sample-output")
+                        .help("path to output CSV of sampled references")
+                        .required(false)
+                        .requires("sample-edges")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("reference-count")
+                .about("Display the number of non-null references held by an object")
+                .args(&index_args())
+                .arg(
+                    clap::Arg::with_name("obj-id")
+                        .long("obj-id")
+                        .help("object id to look up, in decimal or 0x-prefixed hex")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("shallow-size")
+                .about("Display the shallow size in bytes of an object")
+                .args(&index_args())
+                .arg(
+                    clap::Arg::with_name("obj-id")
+                        .long("obj-id")
+                        .help("object id to look up, in decimal or 0x-prefixed hex")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("instance-count")
+                .about("Display the number of instances of a class")
+                .args(&index_args())
+                .arg(
+                    clap::Arg::with_name("class-id")
+                        .long("class-id")
+                        .help("class object id to look up, in decimal or 0x-prefixed hex")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("retained-size")
+                .about("Display the retained size in bytes of an object, as written to an LMDB index by build-retained")
+                .args(&index_args())
+                .arg(
+                    clap::Arg::with_name("obj-id")
+                        .long("obj-id")
+                        .help("object id to look up, in decimal or 0x-prefixed hex")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("inspect-object")
+                .about("Display a single object's sub record, located via the index rather than a full pass")
+                .args(&index_args())
+                .arg(
+                    clap::Arg::with_name("obj-id")
+                        .long("obj-id")
+                        .help("object id to look up, in decimal or 0x-prefixed hex")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("depth")
+                        .long("depth")
+                        .help("instead of the sub record, write a dot graph of the object and the objects it refers to, out to this many references away")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("referrers")
+                        .long("referrers")
+                        .requires("depth")
+                        .help("include the objects that refer directly to the object in the dot graph (requires a full pass over the hprof)"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("instance-counts")
+                .about("Display the instance count for each class as CSV")
+                .arg(
+                    clap::Arg::with_name("min-count")
+                        .long("min-count")
+                        .help("minimum instance count for a class to be included")
+                        .required(false)
+                        .default_value("1")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("top")
+                        .long("top")
+                        .help("only show this many classes, starting with the highest count")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("package-counts")
+                .about("Display instance counts and shallow sizes grouped by Java package as CSV")
+                .arg(
+                    clap::Arg::with_name("depth")
+                        .long("depth")
+                        .help("number of package name components to group by, e.g. 2 for com.example")
+                        .required(false)
+                        .default_value("2")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("classes-without-instances")
+            .about("Display classes that have no instances as CSV"))
+        .subcommand(clap::SubCommand::with_name("static-field-stats")
+            .about("Display the number of null and non-null object static fields of each class as CSV"))
+        .subcommand(clap::SubCommand::with_name("collection-sizes")
+            .about("Display the number of elements vs backing array capacity of ArrayList, HashMap, etc, by class as CSV"))
+        .subcommand(clap::SubCommand::with_name("classloaders")
+            .about("Display the number of classes loaded by each class loader, with a sample of their names, as CSV"))
+        .subcommand(clap::SubCommand::with_name("gc-root-counts")
+            .about("Display the number of GC roots of each kind as CSV"))
+        .subcommand(
+            clap::SubCommand::with_name("leak-suspects")
+                .about("Display the classes whose objects retain the most memory, where it accumulates, and the path to it from a GC root, as CSV")
+                .arg(
+                    clap::Arg::with_name("min-percent")
+                        .long("min-percent")
+                        .help("minimum percentage of reachable bytes a class's objects must retain to be a suspect")
+                        .required(false)
+                        .default_value("10")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("top")
+                        .long("top")
+                        .help("maximum number of suspects to show")
+                        .required(false)
+                        .default_value("10")
+                        .takes_value(true),
+                )
+                .args(&weak_ref_args()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("export-dominator-tree")
+                .about("Display each reachable object's immediate dominator and retained size as CSV")
+                .args(&weak_ref_args()),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("retained-histogram")
+                .about("Display the dominator tree as an indented tree of the biggest retained sizes")
+                .arg(
+                    clap::Arg::with_name("threshold")
+                        .long("threshold")
+                        .help("percentage of reachable bytes below which objects are collapsed into a summary line")
+                        .required(false)
+                        .default_value("1")
+                        .takes_value(true),
+                )
+                .args(&weak_ref_args()),
+        )
+        .subcommand(clap::SubCommand::with_name("dump-jsonl")
+            .about("Display each record, or each sub record of heap dump segments, as a line of JSON"))
+        .subcommand(clap::SubCommand::with_name("unreachable-objects")
+            .about("Display objects that are neither GC roots nor referenced by anything as CSV"))
+        .subcommand(clap::SubCommand::with_name("prim-array-sizes")
+            .about("Display the number of primitive arrays and total size of their contents for each element type as CSV"))
+        .subcommand(
+            clap::SubCommand::with_name("duplicate-objects")
+                .about("Display groups of instances with the same class and identical field values as CSV, ordered by wasted bytes")
+                .arg(
+                    clap::Arg::with_name("top")
+                        .long("top")
+                        .help("only show this many groups, starting with the most wasted bytes")
+                        .required(false)
+                        .takes_value(true),
                 ),
         )
-        .subcommand(clap::SubCommand::with_name("instance-counts")
-            .about("Display the instance count for each class as CSV"))
         .subcommand(clap::SubCommand::with_name("build-index")
             .about("Build an index on disk for subsequent use with other commands")
             .arg(clap::Arg::with_name("output")
@@ -115,16 +450,78 @@ fn main() -> Result<(), anyhow::Error> {
                 .help("path to output index at")
                 .required(true)
                 .takes_value(true))
+            .arg(clap::Arg::with_name("index-backend")
+                .long("index-backend")
+                .help("key-value store to build the index with")
+                .required(false)
+                .possible_values(&["lmdb", "sled"])
+                .default_value("lmdb")
+                .takes_value(true))
+            .arg(clap::Arg::with_name("skip-prim-arrays")
+                .long("skip-prim-arrays")
+                .help("don't index primitive array types, as used by ref-count-graph to recognize references to primitive arrays")
+                .required(false)
+                .takes_value(false))
+            .arg(clap::Arg::with_name("skip-ref-counts")
+                .long("skip-ref-counts")
+                .help("don't index reference counts, as used by reference-count")
+                .required(false)
+                .takes_value(false))
+            .arg(clap::Arg::with_name("skip-locations")
+                .long("skip-locations")
+                .help("don't index sub record locations, as used by inspect-object")
+                .required(false)
+                .takes_value(false))
+            .arg(clap::Arg::with_name("skip-shallow-sizes")
+                .long("skip-shallow-sizes")
+                .help("don't index shallow sizes, as used by shallow-size")
+                .required(false)
+                .takes_value(false))
+            .arg(clap::Arg::with_name("scratch-dir")
+                .long("scratch-dir")
+                .value_name("DIR")
+                .help("directory to write intermediate sorted chunks to, rather than the output path")
+                .required(false)
+                .takes_value(true))
+            .arg(clap::Arg::with_name("keep-chunks")
+                .long("keep-chunks")
+                .help("keep intermediate sorted chunks after building the index, for debugging")
+                .required(false)
+                .takes_value(false))
+        )
+        .subcommand(clap::SubCommand::with_name("build-retained")
+            .about("Compute the retained size of every reachable object and add them to an LMDB index")
+            .arg(clap::Arg::with_name("index")
+                .short("i")
+                .long("index")
+                .help("path index for the hprof file (created with the build-index subcommand)")
+                .required(true)
+                .takes_value(true))
+            .args(&weak_ref_args())
+        )
+        .subcommand(clap::SubCommand::with_name("export-obj-class-map")
+            .about("Write the class id of every object to a flat file of sorted binary (obj id, class id) pairs")
+            .arg(clap::Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("path to the directory to write the pairs and their manifest to")
+                .required(true)
+                .takes_value(true))
         );
     let matches = app.get_matches();
 
     let file_path = matches.value_of("file").expect("file must be specified");
 
-    let file = fs::File::open(file_path).unwrap();
+    let mapped = jvm_hprof::MappedHprof::open(file_path)?;
 
-    let memmap = unsafe { memmap::MmapOptions::new().map(&file) }.unwrap();
+    let hprof = mapped.hprof();
 
-    let hprof = jvm_hprof::parse_hprof(&memmap[..]).unwrap();
+    if hprof.header().profile_version().is_none() {
+        eprintln!(
+            "Warning: unrecognized hprof version in header label {:?}; some records may not be understood",
+            hprof.header().label().unwrap_or("(invalid UTF-8)")
+        );
+    }
 
     let threads = matches
         .value_of("threads")
@@ -138,6 +535,17 @@ fn main() -> Result<(), anyhow::Error> {
             std::cmp::min(cores, 4)
         });
 
+    let obj_id_range = matches
+        .value_of("obj-id-range")
+        .map(|s| s.parse::<ObjIdRange>())
+        .transpose()?
+        .unwrap_or_else(ObjIdRange::all);
+
+    let id_format = matches
+        .value_of("id-format")
+        .map(|s| s.parse::<IdFormat>())
+        .unwrap()?;
+
     rayon::ThreadPoolBuilder::new()
         .num_threads(threads)
         .build_global()?;
@@ -145,47 +553,289 @@ fn main() -> Result<(), anyhow::Error> {
     match matches.subcommand() {
         ("header", _) => header(&hprof),
         ("record-counts", _) => dump_record_counts(&hprof),
-        ("dump-utf8", _) => dump_utf8(&hprof)?,
-        ("dump-load-class", _) => dump_load_class(&hprof)?,
-        ("dump-stack-trace", _) => dump_stack_trace(&hprof),
-        ("dump-classes", _) => dump_classes(&hprof),
-        ("dump-objects", _) => dump_objects::dump_objects(&hprof),
+        ("dump-utf8", _) => dump_utf8(&hprof, id_format)?,
+        ("grep-utf8", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let pattern = grep_utf8::utf8_pattern(
+                matches
+                    .value_of("pattern")
+                    .expect("pattern must be specified"),
+                matches.is_present("regex"),
+            )?;
+            grep_utf8::grep_utf8(&hprof, &pattern, id_format, io::stdout())?
+        }
+        ("dump-load-class", _) => dump_load_class(&hprof, id_format)?,
+        ("dump-stack-trace", _) => dump_stack_trace(&hprof, id_format),
+        ("dump-enums", _) => dump_enums::dump_enums(&hprof, id_format, io::stdout())?,
+        ("thread-dump", _) => thread_dump::thread_dump(&hprof, io::stdout())?,
+        ("busy-monitors", _) => busy_monitors::busy_monitors(&hprof, io::stdout())?,
+        ("dump-classes", _) => dump_classes(&hprof, id_format),
+        ("dump-objects", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let max_array_elements = matches
+                .value_of("max-array-elements")
+                .map(|s| s.parse::<usize>())
+                .unwrap()?;
+            dump_objects::dump_objects(
+                &hprof,
+                &obj_id_range,
+                matches.value_of("class-name"),
+                max_array_elements,
+                matches.is_present("resolve-strings"),
+                matches.is_present("with-alloc-stack"),
+                id_format,
+                io::stdout(),
+            )?
+        }
         ("class-hierarchy", arg_matches) => class_hierarchy_dot::class_hierarchy_dot(
             &hprof,
             arg_matches
                 .expect("must provide args")
                 .value_of("output")
-                .map(|s| path::Path::new(s))
+                .map(path::Path::new)
                 .expect("must provide output path"),
         ),
+        ("hierarchy-stats", arg_matches) => {
+            let top = arg_matches
+                .expect("must provide args")
+                .value_of("top")
+                .map(|s| s.parse::<usize>())
+                .unwrap()?;
+            hierarchy_stats::hierarchy_stats(&hprof, top, io::stdout())?
+        }
+        ("export-edges", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            export_edges::export_edges(
+                &hprof,
+                &obj_id_range,
+                matches.is_present("label-weak"),
+                io::BufWriter::new(fs::File::create(
+                    matches
+                        .value_of("output")
+                        .expect("must provide output path"),
+                )?),
+            )?
+        }
         ("ref-count-graph", arg_matches) => {
             let matches = arg_matches.expect("must provide args");
-            let index = matches
-                .value_of("index")
-                .map(|s| {
-                    LmdbIndex::open_with_fingerprint(
-                        &HprofFingerprint::from_hprof(&hprof),
-                        path::Path::new(s),
-                    )
-                })
-                .unwrap()?;
+            let index = open_index(&hprof, matches)?;
             let min_edge_count = matches
                 .value_of("min-edge-count")
                 .map(|s| s.parse::<u64>().unwrap())
                 .unwrap();
-            let output = matches
-                .value_of("output")
-                .map(|s| path::Path::new(s))
-                .unwrap();
-            ref_count_graph::ref_count_graph(&hprof, &index, output, min_edge_count)
+            let output = matches.value_of("output").map(path::Path::new).unwrap();
+            let edge_label_mode = matches
+                .value_of("edge-label-mode")
+                .map(|s| s.parse::<ref_count_graph::EdgeLabelMode>())
+                .unwrap()?;
+            let sample_edges = matches
+                .value_of("sample-edges")
+                .map(|s| s.parse::<usize>())
+                .transpose()?;
+            let edge_sampling = sample_edges.map(|sample_size| ref_count_graph::EdgeSampling {
+                sample_size,
+                output: matches
+                    .value_of("sample-output")
+                    .map(path::Path::new)
+                    .expect("must provide sample output"),
+            });
+            ref_count_graph::ref_count_graph(
+                &hprof,
+                &index,
+                output,
+                min_edge_count,
+                edge_label_mode,
+                edge_sampling,
+            )
         }
-        ("instance-counts", _) => instance_counts::instance_counts(&hprof)?,
-        ("build-index", arg_matches) => index::build_index(
+        ("reference-count", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let index = open_index(&hprof, matches)?;
+            let obj_id = matches
+                .value_of("obj-id")
+                .expect("must provide obj id")
+                .parse::<jvm_hprof::Id>()?;
+            match index.reference_count(obj_id)? {
+                Some(count) => println!("{}", count),
+                None => println!("No reference count for obj id {}", obj_id),
+            }
+        }
+        ("shallow-size", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let index = open_index(&hprof, matches)?;
+            let obj_id = matches
+                .value_of("obj-id")
+                .expect("must provide obj id")
+                .parse::<jvm_hprof::Id>()?;
+            match index.get_shallow_size(obj_id)? {
+                Some(size) => println!("{}", size),
+                None => println!("No shallow size for obj id {}", obj_id),
+            }
+        }
+        ("instance-count", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let index = open_index(&hprof, matches)?;
+            let class_id = matches
+                .value_of("class-id")
+                .expect("must provide class id")
+                .parse::<jvm_hprof::Id>()?;
+            match index.instance_count(class_id)? {
+                Some(count) => println!("{}", count),
+                None => println!("No instances of class id {}", class_id),
+            }
+        }
+        ("retained-size", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let index = open_index(&hprof, matches)?;
+            let obj_id = matches
+                .value_of("obj-id")
+                .expect("must provide obj id")
+                .parse::<jvm_hprof::Id>()?;
+            match index.get_retained_size(obj_id)? {
+                Some(size) => println!("{}", size),
+                None => println!("No retained size for obj id {}", obj_id),
+            }
+        }
+        ("inspect-object", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let index = open_index(&hprof, matches)?;
+            let obj_id = matches
+                .value_of("obj-id")
+                .expect("must provide obj id")
+                .parse::<jvm_hprof::Id>()?;
+            let depth = matches
+                .value_of("depth")
+                .map(|s| s.parse::<usize>())
+                .transpose()?;
+            match depth {
+                Some(depth) => object_graph::object_graph(
+                    &hprof,
+                    &index,
+                    obj_id,
+                    depth,
+                    matches.is_present("referrers"),
+                    io::stdout(),
+                )?,
+                None => inspect_object(&hprof, &index, obj_id)?,
+            }
+        }
+        ("instance-counts", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let min_count = matches
+                .value_of("min-count")
+                .map(|s| s.parse::<u64>())
+                .unwrap()?;
+            let top = matches
+                .value_of("top")
+                .map(|s| s.parse::<usize>())
+                .transpose()?;
+            instance_counts::instance_counts(&hprof, &obj_id_range, min_count, top, io::stdout())?
+        }
+        ("package-counts", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let depth = matches
+                .value_of("depth")
+                .map(|s| s.parse::<usize>())
+                .unwrap()?;
+            package_counts::package_counts(&hprof, &obj_id_range, depth, io::stdout())?
+        }
+        ("classes-without-instances", _) => {
+            classes_without_instances::classes_without_instances(&hprof, io::stdout())?
+        }
+        ("static-field-stats", _) => static_field_stats::static_field_stats(&hprof, io::stdout())?,
+        ("collection-sizes", _) => collection_sizes::collection_sizes(&hprof, io::stdout())?,
+        ("classloaders", _) => classloaders::classloaders(&hprof, io::stdout())?,
+        ("gc-root-counts", _) => gc_root_counts::gc_root_counts(&hprof, io::stdout())?,
+        ("leak-suspects", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let min_percent = matches
+                .value_of("min-percent")
+                .map(|s| s.parse::<f64>())
+                .unwrap()?;
+            let top = matches
+                .value_of("top")
+                .map(|s| s.parse::<usize>())
+                .unwrap()?;
+            leak_suspects::leak_suspects(
+                &hprof,
+                min_percent,
+                top,
+                drop_weak_refs(matches),
+                io::stdout(),
+            )?
+        }
+        ("export-dominator-tree", arg_matches) => dominators::export_dominator_tree(
+            &hprof,
+            drop_weak_refs(arg_matches.expect("must provide args")),
+            io::stdout(),
+        )?,
+        ("retained-histogram", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let threshold = matches
+                .value_of("threshold")
+                .map(|s| s.parse::<f64>())
+                .unwrap()?;
+            retained_histogram::retained_histogram(
+                &hprof,
+                threshold,
+                drop_weak_refs(matches),
+                io::stdout(),
+            )?
+        }
+        ("dump-jsonl", _) => dump_jsonl::dump_jsonl(&hprof, io::stdout())?,
+        ("unreachable-objects", _) => {
+            unreachable_objects::unreachable_objects(&hprof, io::stdout())?
+        }
+        ("prim-array-sizes", _) => {
+            prim_array_sizes::prim_array_sizes(&hprof, &obj_id_range, io::stdout())?
+        }
+        ("duplicate-objects", arg_matches) => {
+            let top = arg_matches
+                .expect("must provide args")
+                .value_of("top")
+                .map(|s| s.parse::<usize>())
+                .transpose()?;
+            duplicate_objects::duplicate_objects(&hprof, &obj_id_range, top, io::stdout())?
+        }
+        ("build-index", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            let backend = matches
+                .value_of("index-backend")
+                .map(|s| s.parse::<index::IndexBackend>())
+                .unwrap()?;
+            index::build_index(
+                &hprof,
+                matches
+                    .value_of("output")
+                    .map(path::Path::new)
+                    .expect("must provide output path"),
+                backend,
+                index::IndexContents {
+                    prim_array_types: !matches.is_present("skip-prim-arrays"),
+                    reference_counts: !matches.is_present("skip-ref-counts"),
+                    locations: !matches.is_present("skip-locations"),
+                    shallow_sizes: !matches.is_present("skip-shallow-sizes"),
+                },
+                &index::ScratchSpace {
+                    dir: matches.value_of("scratch-dir").map(path::PathBuf::from),
+                    keep: matches.is_present("keep-chunks"),
+                },
+            )?
+        }
+        ("build-retained", arg_matches) => {
+            let matches = arg_matches.expect("must provide args");
+            dominators::build_retained(
+                &hprof,
+                path::Path::new(matches.value_of("index").expect("must provide index")),
+                drop_weak_refs(matches),
+            )?
+        }
+        ("export-obj-class-map", arg_matches) => index::export_obj_class_map(
             &hprof,
             arg_matches
                 .expect("must provide args")
                 .value_of("output")
-                .map(|s| path::Path::new(s))
+                .map(path::Path::new)
                 .expect("must provide output path"),
         )?,
         _ => panic!("Unknown subcommand"),
@@ -194,13 +844,133 @@ fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Flags for whether the `referent` of soft, weak, and phantom references is an edge in the
+/// dominator tree. Dropping them is the default, as in Eclipse MAT, since the GC can free objects
+/// that are only reachable that way.
+fn weak_ref_args<'a, 'b>() -> [clap::Arg<'a, 'b>; 2] {
+    [
+        clap::Arg::with_name("keep-weak")
+            .long("keep-weak")
+            .help("follow the referent of soft, weak, and phantom references, so that their referents count towards retained sizes")
+            .required(false)
+            .conflicts_with("drop-weak")
+            .takes_value(false),
+        clap::Arg::with_name("drop-weak")
+            .long("drop-weak")
+            // the flag's old name
+            .alias("exclude-weak-refs")
+            .help("don't follow the referent of soft, weak, and phantom references (the default)")
+            .required(false)
+            .takes_value(false),
+    ]
+}
+
+/// Whether the flags from [weak_ref_args] say to leave weak referents out of the dominator tree
+fn drop_weak_refs(matches: &clap::ArgMatches) -> bool {
+    !matches.is_present("keep-weak")
+}
+
+/// Args for subcommands that read an index: where it is, and which backend it was built with
+fn index_args<'a, 'b>() -> [clap::Arg<'a, 'b>; 2] {
+    [
+        clap::Arg::with_name("index")
+            .short("i")
+            .long("index")
+            .help("path index for the hprof file (created with the build-index subcommand), required unless using the memory index backend")
+            .required(false)
+            .takes_value(true),
+        clap::Arg::with_name("index-backend")
+            .long("index-backend")
+            .help("key-value store the index was built with, or memory to build the index in memory instead")
+            .required(false)
+            .possible_values(&["lmdb", "sled", "memory"])
+            .default_value("lmdb")
+            .takes_value(true),
+    ]
+}
+
+/// Open the index described by the flags from [index_args]
+fn open_index(hprof: &Hprof, matches: &clap::ArgMatches) -> Result<index::AnyIndex, anyhow::Error> {
+    let backend = matches
+        .value_of("index-backend")
+        .map(|s| s.parse::<index::IndexBackend>())
+        .unwrap()?;
+
+    index::open_index(
+        hprof,
+        backend,
+        matches.value_of("index").map(path::Path::new),
+    )
+}
+
 fn header(hprof: &Hprof) {
     println!("Label: {}", hprof.header().label().unwrap());
+    match hprof.header().profile_version() {
+        Some(version) => println!("Profile version: {}", version),
+        None => println!("Profile version: (unrecognized)"),
+    }
     println!("Id size: {:?}", hprof.header().id_size());
     let ts = chrono::Utc.timestamp_millis(hprof.header().timestamp_millis() as i64);
     println!("Timestamp: {}", ts);
 }
 
+fn inspect_object<I: Index>(
+    hprof: &Hprof,
+    index: &I,
+    obj_id: jvm_hprof::Id,
+) -> Result<(), anyhow::Error> {
+    let (record_index, offset) = match index.get_location(obj_id)? {
+        Some(location) => location,
+        None => {
+            println!("No location for obj id {}", obj_id);
+            return Ok(());
+        }
+    };
+
+    println!("Record index {}, offset {}", record_index, offset);
+
+    let id_size = hprof.header().id_size();
+
+    match hprof
+        .sub_record_at(record_index as usize, offset as usize)
+        .map_err(|e| anyhow::anyhow!("Could not parse sub record: {:?}", e))?
+    {
+        SubRecord::Class(c) => {
+            println!("Class obj id {}", c.obj_id());
+            println!(
+                "Super class obj id: {}",
+                c.super_class_obj_id()
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| String::from("(none)"))
+            );
+            println!("Instance size: {} bytes", c.instance_size_bytes());
+        }
+        SubRecord::Instance(instance) => {
+            println!("Instance obj id {}", instance.obj_id());
+            println!("Class obj id: {}", instance.class_obj_id());
+            println!("Field data: {} bytes", instance.fields().len());
+        }
+        SubRecord::ObjectArray(obj_array) => {
+            println!("Object array obj id {}", obj_array.obj_id());
+            println!("Array class obj id: {}", obj_array.array_class_obj_id());
+            println!("Elements:");
+            for e in obj_array.elements(id_size).map(|r| r.unwrap()) {
+                match e {
+                    Some(id) => println!("  {}", id),
+                    None => println!("  null"),
+                }
+            }
+        }
+        SubRecord::PrimitiveArray(pa) => {
+            println!("Primitive array obj id {}", pa.obj_id());
+            println!("Type: {}", pa.primitive_type().java_type_name());
+        }
+        other => println!("Unexpected sub record type: {:?}", other),
+    }
+
+    Ok(())
+}
+
 fn dump_record_counts(hprof: &Hprof) {
     let counts = record_counts(hprof);
 
@@ -217,7 +987,8 @@ fn dump_record_counts(hprof: &Hprof) {
     }
 }
 
-fn dump_utf8(hprof: &Hprof) -> Result<(), anyhow::Error> {
+fn dump_utf8(hprof: &Hprof, id_format: IdFormat) -> Result<(), anyhow::Error> {
+    let id_size = hprof.header().id_size();
     let mut wtr = csv::Writer::from_writer(io::stdout());
     wtr.write_record(&[
         "Name id",
@@ -234,13 +1005,13 @@ fn dump_utf8(hprof: &Hprof) -> Result<(), anyhow::Error> {
     {
         match u.text_as_str() {
             Ok(s) => wtr.write_record(&[
-                format!("{}", u.name_id()),
+                id_format.format(u.name_id(), id_size),
                 s.to_string(),
                 String::from(""),
                 String::from(""),
             ]),
             Err(e) => wtr.write_record(&[
-                format!("{}", u.name_id()),
+                id_format.format(u.name_id(), id_size),
                 String::from(""),
                 format!("{:?}", e),
                 base64::encode(u.text()),
@@ -251,8 +1022,9 @@ fn dump_utf8(hprof: &Hprof) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn dump_load_class(hprof: &Hprof) -> Result<(), anyhow::Error> {
+fn dump_load_class(hprof: &Hprof, id_format: IdFormat) -> Result<(), anyhow::Error> {
     let utf8 = utf8_by_id(hprof);
+    let id_size = hprof.header().id_size();
 
     let mut wtr = csv::Writer::from_writer(io::stdout());
     wtr.write_record(&[
@@ -271,9 +1043,11 @@ fn dump_load_class(hprof: &Hprof) -> Result<(), anyhow::Error> {
     {
         wtr.write_record(&[
             format!("{}", l.class_serial()),
-            format!("{}", l.class_obj_id()),
-            format!("{}", l.stack_trace_serial()),
-            format!("{}", l.class_name_id()),
+            id_format.format(l.class_obj_id(), id_size),
+            l.stack_trace_serial()
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            id_format.format(l.class_name_id(), id_size),
             get_utf8_if_available(&utf8, l.class_name_id()).to_string(),
         ])?;
     }
@@ -281,8 +1055,9 @@ fn dump_load_class(hprof: &Hprof) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn dump_stack_trace(hprof: &Hprof) {
+fn dump_stack_trace(hprof: &Hprof, id_format: IdFormat) {
     let utf8 = utf8_by_id(hprof);
+    let id_size = hprof.header().id_size();
 
     let frames = hprof
         .records_iter()
@@ -303,14 +1078,20 @@ fn dump_stack_trace(hprof: &Hprof) {
             println!("Trace serial: {}", t.stack_trace_serial());
             println!("Thread serial: {}", t.thread_serial());
 
-            for id in t.frame_ids().map(|r| r.unwrap()) {
-                print!("{}\t", id);
+            for (id, frame) in t
+                .frame_ids()
+                .map(|r| r.unwrap())
+                .zip(t.resolved_frames(&frames))
+            {
+                print!("{}\t", id_format.format(id, id_size));
 
-                match frames.get(&id) {
+                match frame {
                     None => println!("(no frame found)"),
                     Some(f) => println!(
                         "{}:{}\n\t↪ {}#{}({})",
-                        get_utf8_if_available(&utf8, f.source_file_name_id()),
+                        f.source_file_name_id()
+                            .map(|id| get_utf8_if_available(&utf8, id))
+                            .unwrap_or("(no source)"),
                         f.line_num(),
                         load_classes_by_serial
                             .get(&f.class_serial())
@@ -326,8 +1107,12 @@ fn dump_stack_trace(hprof: &Hprof) {
         })
 }
 
-fn dump_classes(hprof: &Hprof) {
+fn dump_classes(hprof: &Hprof, id_format: IdFormat) {
     let utf8 = utf8_by_id(hprof);
+    let id_size = hprof.header().id_size();
+    // absent ids are shown as 0
+    let format_opt_id =
+        |id: Option<jvm_hprof::Id>| id_format.format(id.unwrap_or_else(|| 0.into()), id_size);
     let load_classes_by_obj_id = classes_by_obj_id(hprof);
 
     hprof
@@ -341,7 +1126,7 @@ fn dump_classes(hprof: &Hprof) {
 
                 match s {
                     SubRecord::Class(class) => {
-                        println!("Obj id: {:#018X} = {}", class.obj_id(), class.obj_id());
+                        println!("Obj id: {}", id_format.format(class.obj_id(), id_size));
                         println!(
                             "Name (via LoadClass): {}",
                             load_classes_by_obj_id
@@ -349,37 +1134,40 @@ fn dump_classes(hprof: &Hprof) {
                                 .map(|lc| get_utf8_if_available(&utf8, lc.class_name_id()))
                                 .unwrap_or("(LoadClass not found)")
                         );
-                        println!("Stack trace serial: {:#010X}", class.stack_trace_serial());
                         println!(
-                            "Super class obj id: {:#018X}",
-                            class.super_class_obj_id().map(|i| i.id()).unwrap_or(0)
+                            "Stack trace serial: {}",
+                            class
+                                .stack_trace_serial()
+                                .map(|s| format!("{:#010X}", s))
+                                .unwrap_or_else(|| String::from("(none)"))
                         );
                         println!(
-                            "Class loader obj id: {:#018X}",
-                            class.class_loader_obj_id().map(|i| i.id()).unwrap_or(0)
+                            "Super class obj id: {}",
+                            format_opt_id(class.super_class_obj_id())
                         );
                         println!(
-                            "Signers obj id: {:#018X}",
-                            class.signers_obj_id().map(|i| i.id()).unwrap_or(0)
+                            "Class loader obj id: {}",
+                            format_opt_id(class.class_loader_obj_id())
                         );
+                        println!("Signers obj id: {}", format_opt_id(class.signers_obj_id()));
                         println!(
-                            "Protection domain obj id: {:#018X}",
-                            class
-                                .protection_domain_obj_id()
-                                .map(|i| i.id())
-                                .unwrap_or(0)
+                            "Protection domain obj id: {}",
+                            format_opt_id(class.protection_domain_obj_id())
                         );
                         println!("Instance size: {}", class.instance_size_bytes());
 
                         if class.static_fields().count() > 0 {
                             println!("Static fields:");
 
-                            for pr in class.static_fields() {
-                                let sf = pr.unwrap();
+                            for pr in class.resolved_static_fields(|id| {
+                                utf8.get(&id)
+                                    .map(|u| u.text_as_str().unwrap_or("(invalid utf8)"))
+                            }) {
+                                let (name, sf) = pr.unwrap();
                                 println!(
-                                    "\t{:#018X} ({}): {:?}",
-                                    sf.name_id(),
-                                    get_utf8_if_available(&utf8, sf.name_id()),
+                                    "\t{} ({}): {:?}",
+                                    id_format.format(sf.name_id(), id_size),
+                                    name.unwrap_or("(utf8 not found)"),
                                     sf.value()
                                 );
 
@@ -0,0 +1,169 @@
+use crate::thread_dump::{describe_frame, rooted_obj_types};
+use crate::util::*;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// Write a CSV of the objects whose monitors were held (`synchronized` on, or waited on) when the
+/// heap was dumped, along with the threads that likely hold them.
+///
+/// The hprof only records that a monitor is busy, not which thread holds it, so the candidates are
+/// the threads that have the object as a local or JNI local reference in one of their frames: a
+/// `synchronized` block keeps the object it locks in a local. A monitor with no such thread gets
+/// one row with the thread columns left empty.
+pub(crate) fn busy_monitors<W: io::Write>(hprof: &Hprof, out: W) -> Result<(), anyhow::Error> {
+    let utf8 = utf8_by_id(hprof);
+    let load_classes_by_serial = classes_by_serial(hprof);
+    let load_classes_by_obj_id = classes_by_obj_id(hprof);
+
+    let mut monitor_obj_ids = Vec::new();
+    // frame id -> StackFrame
+    let mut frames = collections::HashMap::new();
+    // stack trace serial -> StackTrace
+    let mut stack_traces = collections::HashMap::new();
+    // thread serial -> GcRootThreadObj
+    let mut threads = collections::HashMap::new();
+    // obj id -> (thread serial, frame index) of each stack reference to it
+    let mut stack_refs: collections::HashMap<Id, Vec<(Serial, Option<u32>)>> =
+        collections::HashMap::new();
+
+    for r in hprof.records_iter().map(|r| r.unwrap()) {
+        match r.tag() {
+            RecordTag::StackFrame => {
+                let f = r.as_stack_frame().unwrap().unwrap();
+                frames.insert(f.id(), f);
+            }
+            RecordTag::StackTrace => {
+                let t = r.as_stack_trace().unwrap().unwrap();
+                stack_traces.insert(t.stack_trace_serial(), t);
+            }
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                let segment = r.as_heap_dump_segment().unwrap().unwrap();
+                for p in segment.sub_records() {
+                    match p.unwrap() {
+                        SubRecord::GcRootBusyMonitor(m) => monitor_obj_ids.push(m.obj_id()),
+                        SubRecord::GcRootThreadObj(t) => {
+                            threads.insert(t.thread_serial(), t);
+                        }
+                        SubRecord::GcRootJavaStackFrame(sf) => stack_refs
+                            .entry(sf.obj_id())
+                            .or_default()
+                            .push((sf.thread_serial(), sf.frame_index())),
+                        SubRecord::GcRootJniLocalRef(jni) => stack_refs
+                            .entry(jni.obj_id())
+                            .or_default()
+                            .push((jni.thread_serial(), jni.frame_index())),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    monitor_obj_ids.sort();
+    monitor_obj_ids.dedup();
+
+    let monitor_types = rooted_obj_types(
+        hprof,
+        &monitor_obj_ids.iter().copied().collect(),
+        &load_classes_by_obj_id,
+        &utf8,
+    );
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Monitor obj id",
+        "Monitor class",
+        "Thread serial",
+        "Thread obj id",
+        "Frame",
+    ])?;
+
+    for obj_id in monitor_obj_ids {
+        let monitor_type = monitor_types
+            .get(&obj_id)
+            .map(|s| s.as_str())
+            .unwrap_or("type for obj id not found");
+
+        let refs = match stack_refs.get(&obj_id) {
+            Some(refs) => refs,
+            None => {
+                wtr.write_record([&obj_id.to_string(), monitor_type, "", "", ""])?;
+                continue;
+            }
+        };
+
+        for &(thread_serial, frame_index) in refs {
+            let thread = threads.get(&thread_serial);
+            let frame = thread
+                .and_then(|t| stack_traces.get(&t.stack_trace_serial()))
+                .zip(frame_index)
+                .and_then(|(trace, index)| trace.frame_ids().nth(index as usize))
+                .map(|r| r.unwrap())
+                .and_then(|frame_id| frames.get(&frame_id))
+                .map(|f| describe_frame(f, &load_classes_by_serial, &utf8))
+                .unwrap_or_else(|| "(frame not found)".to_owned());
+
+            wtr.write_record(&[
+                obj_id.to_string(),
+                monitor_type.to_owned(),
+                thread_serial.to_string(),
+                thread
+                    .and_then(|t| t.thread_obj_id())
+                    .map(|id| id.to_string())
+                    .unwrap_or_default(),
+                frame,
+            ])?;
+        }
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn monitor_held_by_thread_with_local() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "java/lang/Thread")
+            .utf8(2, "com/example/Lock")
+            .utf8(3, "work")
+            .utf8(4, "()V")
+            .utf8(5, "Worker.java")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .stack_frame(50, 3, 4, 5, 2, 42)
+            .stack_trace(9, 1, &[50]);
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_thread_obj(200, 1, 9)
+            .gc_root_java_stack_frame(300, 1, 0)
+            .gc_root_busy_monitor(300)
+            // nobody has this one on their stack
+            .gc_root_busy_monitor(301)
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .instance(200, 100, &[])
+            .instance(300, 101, &[])
+            .instance(301, 101, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        busy_monitors(&hprof, &mut out).unwrap();
+
+        assert_eq!(
+            "Monitor obj id,Monitor class,Thread serial,Thread obj id,Frame\n\
+             300,com/example/Lock,1,200,com/example/Lock#work()V (Worker.java:42)\n\
+             301,com/example/Lock,,,\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
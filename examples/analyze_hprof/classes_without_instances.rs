@@ -0,0 +1,86 @@
+use crate::counter::Counter;
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::io;
+
+/// Write a CSV of the classes that have no instances in the heap dump, ordered by class name.
+///
+/// Object arrays count as instances of their array class. Lots of these usually means lots of
+/// classes loaded for framework scaffolding, or class loaders that have loaded the same classes
+/// over and over.
+pub(crate) fn classes_without_instances<W: io::Write>(
+    hprof: &Hprof,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let classes = ez_classes_by_obj_id(hprof);
+
+    let mut instance_counts = Counter::<Id>::new();
+
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for p in segment.sub_records() {
+            match p.unwrap() {
+                SubRecord::Instance(instance) => instance_counts.increment(instance.class_obj_id()),
+                SubRecord::ObjectArray(obj_array) => {
+                    instance_counts.increment(obj_array.array_class_obj_id())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Class name", "Class obj id"])?;
+
+    for class in classes
+        .values()
+        .filter(|c| instance_counts.get(&c.obj_id).is_none())
+        .sorted_by_key(|c| (c.name, c.obj_id))
+    {
+        wtr.write_record(&[class.name.to_owned(), class.obj_id.to_string()])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn lists_only_classes_without_instances() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Used")
+            .utf8(2, "Unused")
+            .utf8(3, "[LUsed;")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .class(&TestClass::new(102, 0))
+            .instance(1000, 100, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        classes_without_instances(&hprof, &mut out).unwrap();
+
+        assert_eq!(
+            "Class name,Class obj id\nUnused,101\n[LUsed;,102\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
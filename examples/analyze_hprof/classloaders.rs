@@ -0,0 +1,128 @@
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// How many class names to show for each class loader
+const SAMPLE_SIZE: usize = 5;
+
+/// Write a CSV with a row for each class loader: how many classes it loaded, and a sample of their
+/// names. Classes loaded by the bootstrap loader, which has no object, are grouped in a row with
+/// an empty obj id.
+///
+/// Rows are ordered by number of classes, most first. A loader that has loaded an unexpectedly
+/// large number of classes, or many loaders of the same class each with a similar set of
+/// classes, are the usual signs of a class loader leak.
+pub(crate) fn classloaders<W: io::Write>(hprof: &Hprof, out: W) -> Result<(), anyhow::Error> {
+    let classes = ez_classes_by_obj_id(hprof);
+
+    let mut classes_by_loader: collections::HashMap<Option<Id>, Vec<Id>> =
+        collections::HashMap::new();
+
+    for_each_sub_record(hprof, |sub_record| {
+        if let SubRecord::Class(class) = sub_record {
+            classes_by_loader
+                .entry(class.class_loader_obj_id())
+                .or_default()
+                .push(class.obj_id());
+        }
+    });
+
+    // loaders are ordinary instances, which may come before or after the classes they loaded
+    let mut loader_class_ids = collections::HashMap::new();
+    for_each_sub_record(hprof, |sub_record| {
+        if let SubRecord::Instance(instance) = sub_record {
+            if classes_by_loader.contains_key(&Some(instance.obj_id())) {
+                loader_class_ids.insert(instance.obj_id(), instance.class_obj_id());
+            }
+        }
+    });
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Class loader obj id",
+        "Class loader class",
+        "Classes loaded",
+        "Sample class names",
+    ])?;
+
+    for (loader_obj_id, class_obj_ids) in
+        classes_by_loader
+            .iter()
+            .sorted_by_key(|(loader_obj_id, class_obj_ids)| {
+                (std::cmp::Reverse(class_obj_ids.len()), **loader_obj_id)
+            })
+    {
+        let loader_class = match loader_obj_id {
+            Some(id) => loader_class_ids
+                .get(id)
+                .map(|&class_obj_id| class_name_or_unknown(&classes, class_obj_id))
+                .unwrap_or_else(|| "(missing loader instance)".to_owned()),
+            None => "(bootstrap)".to_owned(),
+        };
+
+        let sample = class_obj_ids
+            .iter()
+            .map(|&class_obj_id| class_name_or_unknown(&classes, class_obj_id))
+            .sorted()
+            .take(SAMPLE_SIZE)
+            .join(" ");
+
+        wtr.write_record(&[
+            loader_obj_id.map(|id| id.to_string()).unwrap_or_default(),
+            loader_class,
+            class_obj_ids.len().to_string(),
+            sample,
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn groups_classes_by_loader() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Loader")
+            .utf8(2, "A")
+            .utf8(3, "B")
+            .utf8(4, "C")
+            .utf8(5, "java/lang/Object")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4)
+            .load_class(5, 104, 0, 5);
+        let loaded_by = |obj_id, loader_obj_id| TestClass {
+            class_loader_obj_id: loader_obj_id,
+            ..TestClass::new(obj_id, 0)
+        };
+        let mut segment = builder.segment();
+        segment
+            .class(&loaded_by(101, 1000))
+            .class(&loaded_by(102, 1000))
+            .class(&loaded_by(103, 1000))
+            .class(&loaded_by(100, 0))
+            .class(&loaded_by(104, 0))
+            .instance(1000, 100, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        classloaders(&hprof, &mut out).unwrap();
+
+        assert_eq!(
+            "Class loader obj id,Class loader class,Classes loaded,Sample class names\n\
+            1000,Loader,3,A B C\n\
+            ,(bootstrap),2,Loader java/lang/Object\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// A JDK collection that keeps its elements in an array that's allowed to be bigger than the
+/// number of elements.
+struct CollectionLayout {
+    class_name: &'static str,
+    /// int field with the number of elements
+    size_field: &'static str,
+    /// field with the backing array, which may be null before the first element is added
+    array_field: &'static str,
+}
+
+const COLLECTION_LAYOUTS: &[CollectionLayout] = &[
+    CollectionLayout {
+        class_name: "java/util/ArrayList",
+        size_field: "size",
+        array_field: "elementData",
+    },
+    CollectionLayout {
+        class_name: "java/util/Vector",
+        size_field: "elementCount",
+        array_field: "elementData",
+    },
+    CollectionLayout {
+        class_name: "java/util/PriorityQueue",
+        size_field: "size",
+        array_field: "queue",
+    },
+    CollectionLayout {
+        class_name: "java/util/HashMap",
+        size_field: "size",
+        array_field: "table",
+    },
+    CollectionLayout {
+        class_name: "java/util/Hashtable",
+        size_field: "count",
+        array_field: "table",
+    },
+];
+
+/// Write a CSV of the array-backed JDK collections (`ArrayList`, `HashMap`, etc, and their
+/// subclasses) of each class, comparing their total number of elements with the total length of
+/// their backing arrays. Classes with the most unused capacity are first.
+///
+/// Collections are sized for growth, and often never grow: a default `ArrayList` holding one
+/// element still has a 10 element array, so lots of small collections can waste a lot of heap.
+/// For hash tables, capacity is the number of buckets, so unused capacity is a lower bound on
+/// the number of empty buckets.
+///
+/// The backing arrays can be anywhere in the heap dump, so this takes two passes: one to read
+/// each collection's size and array id, and another to find the arrays' lengths.
+pub(crate) fn collection_sizes<W: io::Write>(hprof: &Hprof, out: W) -> Result<(), anyhow::Error> {
+    let id_size = hprof.header().id_size();
+    let utf8 = utf8_strings_by_id(hprof);
+    let classes = ez_classes_by_obj_id(hprof);
+    let field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+
+    // class obj id -> the layout of the nearest collection class it is or extends
+    let layouts: collections::HashMap<Id, &CollectionLayout> = classes
+        .values()
+        .filter_map(|c| {
+            std::iter::once(c)
+                .chain(super_classes(&classes, c.obj_id))
+                .find_map(|class| {
+                    COLLECTION_LAYOUTS
+                        .iter()
+                        .find(|l| l.class_name == class.name)
+                })
+                .map(|l| (c.obj_id, l))
+        })
+        .collect();
+
+    let mut stats: collections::HashMap<Id, CollectionStats> = collections::HashMap::new();
+    // backing array id -> the class of each collection using it. Empty collections may share
+    // one empty array.
+    let mut arrays: collections::HashMap<Id, Vec<Id>> = collections::HashMap::new();
+
+    for_each_sub_record(hprof, |s| {
+        if let SubRecord::Instance(instance) = s {
+            let layout = match layouts.get(&instance.class_obj_id()) {
+                None => return,
+                Some(l) => l,
+            };
+            let fds = instance_field_descriptors(&field_descriptors, instance.class_obj_id());
+
+            let size = match instance_field_value(&instance, fds, &utf8, layout.size_field, id_size)
+            {
+                Some(FieldValue::Int(i)) => i.max(0) as u64,
+                _ => 0,
+            };
+            let class_stats = stats.entry(instance.class_obj_id()).or_default();
+            class_stats.instances += 1;
+            class_stats.size += size;
+            if size == 0 {
+                class_stats.empty += 1;
+            }
+
+            if let Some(FieldValue::ObjectId(Some(array_id))) =
+                instance_field_value(&instance, fds, &utf8, layout.array_field, id_size)
+            {
+                arrays
+                    .entry(array_id)
+                    .or_default()
+                    .push(instance.class_obj_id());
+            }
+        }
+    });
+
+    for_each_sub_record(hprof, |s| {
+        if let SubRecord::ObjectArray(oa) = s {
+            for class_obj_id in arrays.get(&oa.obj_id()).into_iter().flatten() {
+                if let Some(class_stats) = stats.get_mut(class_obj_id) {
+                    class_stats.capacity += oa.num_elements() as u64;
+                }
+            }
+        }
+    });
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Class name",
+        "Instances",
+        "Empty",
+        "Total size",
+        "Total capacity",
+        "Unused capacity",
+    ])?;
+
+    for (class_name, class_stats) in stats
+        .iter()
+        .map(|(class_obj_id, s)| (class_name_or_unknown(&classes, *class_obj_id), s))
+        .sorted_by(|(left_name, left), (right_name, right)| {
+            // most unused first, then by name to be deterministic
+            Ord::cmp(&right.unused(), &left.unused()).then_with(|| left_name.cmp(right_name))
+        })
+    {
+        wtr.write_record(&[
+            class_name,
+            class_stats.instances.to_string(),
+            class_stats.empty.to_string(),
+            class_stats.size.to_string(),
+            class_stats.capacity.to_string(),
+            class_stats.unused().to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct CollectionStats {
+    instances: u64,
+    /// Instances with size 0
+    empty: u64,
+    /// Sum of the instances' sizes
+    size: u64,
+    /// Sum of the instances' backing array lengths
+    capacity: u64,
+}
+
+impl CollectionStats {
+    fn unused(&self) -> u64 {
+        self.capacity.saturating_sub(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn compares_size_with_backing_array_length() {
+        // class ArrayList { int size; Object[] elementData; }
+        let array_list_class = TestClass {
+            instance_fields: vec![(10, TYPE_INT), (11, TYPE_OBJ)],
+            ..TestClass::new(100, 0)
+        };
+        // class Holder { List list; }, which has nothing to do with collections
+        let holder_class = TestClass {
+            instance_fields: vec![(12, TYPE_OBJ)],
+            ..TestClass::new(103, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "java/util/ArrayList")
+            .utf8(2, "com/example/MyList")
+            .utf8(3, "[Ljava/lang/Object;")
+            .utf8(4, "com/example/Holder")
+            .utf8(10, "size")
+            .utf8(11, "elementData")
+            .utf8(12, "list")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&array_list_class)
+            // extends ArrayList without adding fields
+            .class(&TestClass::new(101, 100))
+            .class(&TestClass::new(102, 0))
+            .class(&holder_class)
+            // 1 of 10
+            .instance(1000, 100, &[Val::Int(1), Val::Obj(2000)])
+            // empty, sharing an empty array
+            .instance(1001, 100, &[Val::Int(0), Val::Obj(2001)])
+            .instance(1002, 100, &[Val::Int(0), Val::Obj(2001)])
+            // 3 of 4
+            .instance(1003, 101, &[Val::Int(3), Val::Obj(2002)])
+            .instance(1004, 103, &[Val::Obj(1000)])
+            .object_array(2000, 102, &[1004, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .object_array(2001, 102, &[])
+            .object_array(2002, 102, &[1004, 1004, 1004, 0]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        collection_sizes(&hprof, &mut out).unwrap();
+        assert_eq!(
+            "Class name,Instances,Empty,Total size,Total capacity,Unused capacity\n\
+             java/util/ArrayList,3,2,1,10,9\n\
+             com/example/MyList,1,0,3,4,1\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
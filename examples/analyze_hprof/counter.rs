@@ -14,13 +14,16 @@ impl<K: Eq + hash::Hash, V: CounterValue> Counter<K, V> {
     }
 
     pub(crate) fn increment(&mut self, key: K) {
+        self.add(key, V::one())
+    }
+
+    pub(crate) fn add(&mut self, key: K, amount: V) {
         self.counts
             .entry(key)
-            .and_modify(|count| *count += V::one())
-            .or_insert(V::one());
+            .and_modify(|count| *count += amount)
+            .or_insert(amount);
     }
 
-    #[allow(unused)] // used in tests, and it feels weird to only allow iteration?
     pub(crate) fn get<Q>(&mut self, key: &Q) -> Option<V>
     where
         K: borrow::Borrow<Q>,
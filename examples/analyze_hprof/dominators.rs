@@ -0,0 +1,560 @@
+use crate::index::lmdb::LmdbIndex;
+use crate::index::{HprofFingerprint, ReferenceTarget};
+use crate::util::*;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io, path};
+
+/// Write a CSV of the dominator tree of the whole object graph: each object reachable from a GC
+/// root, the object that immediately dominates it, and its shallow and retained sizes, ordered by
+/// obj id.
+///
+/// Objects that aren't dominated by any single other object (GC roots, or objects reachable via
+/// several independent paths) have an empty immediate dominator. Sizes are as in
+/// [crate::leak_suspects::leak_suspects], as is `exclude_weak_refs`.
+pub(crate) fn export_dominator_tree<W: io::Write>(
+    hprof: &Hprof,
+    exclude_weak_refs: bool,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let graph = ObjGraph::build(hprof, exclude_weak_refs);
+    let dominators = Dominators::compute(&graph);
+
+    let mut nodes = (0..graph.obj_ids.len())
+        .filter(|&n| n != SUPER_ROOT && dominators.idom[n] != usize::MAX)
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|&n| graph.obj_ids[n]);
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Obj id",
+        "Class",
+        "Immediate dominator obj id",
+        "Shallow bytes",
+        "Retained bytes",
+    ])?;
+
+    for n in nodes {
+        let idom = dominators.idom[n];
+
+        wtr.write_record(&[
+            graph.obj_ids[n].to_string(),
+            graph.type_names[n].clone(),
+            if idom == SUPER_ROOT {
+                String::new()
+            } else {
+                graph.obj_ids[idom].to_string()
+            },
+            graph.shallow_sizes[n].to_string(),
+            dominators.retained[n].to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Compute the retained size of every object reachable from a GC root and write them to the LMDB
+/// index at `index_path`, so that they can be looked up with [crate::index::Index::get_retained_size] without
+/// redoing the dominator analysis each time.
+///
+/// Sizes are the same as in [export_dominator_tree], as is `exclude_weak_refs`.
+pub(crate) fn build_retained(
+    hprof: &Hprof,
+    index_path: &path::Path,
+    exclude_weak_refs: bool,
+) -> Result<(), anyhow::Error> {
+    let graph = ObjGraph::build(hprof, exclude_weak_refs);
+    let dominators = Dominators::compute(&graph);
+
+    let mut retained_sizes = (0..graph.obj_ids.len())
+        .filter(|&n| n != SUPER_ROOT && dominators.idom[n] != usize::MAX)
+        .map(|n| (graph.obj_ids[n], dominators.retained[n]))
+        .collect::<Vec<_>>();
+    retained_sizes.sort_by_key(|&(obj_id, _)| obj_id);
+
+    LmdbIndex::write_retained_sizes(
+        &HprofFingerprint::from_hprof(hprof),
+        index_path,
+        retained_sizes,
+    )
+}
+
+/// Node index of the synthetic node that references all GC roots
+pub(crate) const SUPER_ROOT: usize = 0;
+
+/// Objects are followed down the dominator tree while a child retains at least this much of its
+/// parent's retained size.
+const ACCUMULATION_FRACTION: f64 = 0.8;
+
+/// The object graph, with objects identified by node index rather than obj id.
+pub(crate) struct ObjGraph {
+    /// node index -> obj id, with a meaningless id for [SUPER_ROOT]
+    pub(crate) obj_ids: Vec<Id>,
+    pub(crate) type_names: Vec<String>,
+    pub(crate) shallow_sizes: Vec<u64>,
+    /// node index -> node indices of referenced objects
+    pub(crate) edges: Vec<Vec<usize>>,
+}
+
+impl ObjGraph {
+    /// Every object in `hprof`, plus [SUPER_ROOT].
+    ///
+    /// If `exclude_weak_refs` is set, the `referent` of soft, weak, and phantom references (any
+    /// subclass of `java.lang.ref.Reference`) isn't treated as an edge.
+    pub(crate) fn build(hprof: &Hprof, exclude_weak_refs: bool) -> ObjGraph {
+        let classes = ez_classes_by_obj_id(hprof);
+        let class_instance_field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+        let referent_indices = if exclude_weak_refs {
+            referent_field_indices(&classes, &utf8_strings_by_id(hprof))
+        } else {
+            collections::HashMap::new()
+        };
+        let id_size = hprof.header().id_size();
+        let type_name = |target: ReferenceTarget| target.type_name(&classes);
+
+        let mut obj_ids = vec![Id::from(0)];
+        let mut type_names = vec![String::from("(GC roots)")];
+        let mut shallow_sizes = vec![0];
+        // edges by obj id, since the destinations may not have been seen yet
+        let mut id_edges: Vec<Vec<Id>> = vec![Vec::new()];
+
+        for r in hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+        {
+            let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+            for p in segment.sub_records() {
+                let sub_record = p.unwrap();
+                if sub_record.is_gc_root() {
+                    id_edges[SUPER_ROOT].extend(sub_record.as_gc_root_obj_id());
+                    continue;
+                }
+
+                let size = shallow_size(
+                    &sub_record,
+                    |id| classes.get(&id).map(|c| c.instance_size_bytes),
+                    id_size,
+                )
+                .unwrap_or(0);
+
+                let (obj_id, type_name, refs) = match sub_record {
+                    SubRecord::Class(c) => {
+                        let refs = c
+                            .static_fields()
+                            .map(|r| r.unwrap())
+                            .filter_map(|sf| match sf.value() {
+                                FieldValue::ObjectId(Some(id)) => Some(id),
+                                _ => None,
+                            })
+                            .collect();
+
+                        (
+                            c.obj_id(),
+                            type_name(ReferenceTarget::ClassObj {
+                                class_obj_id: c.obj_id(),
+                            }),
+                            refs,
+                        )
+                    }
+                    SubRecord::Instance(instance) => {
+                        let field_descriptors = instance_field_descriptors(
+                            &class_instance_field_descriptors,
+                            instance.class_obj_id(),
+                        );
+
+                        let referent_index = referent_indices.get(&instance.class_obj_id());
+
+                        let mut refs = Vec::new();
+                        for (index, (_fd, field_val)) in
+                            instance_field_values(&instance, field_descriptors, id_size).enumerate()
+                        {
+                            if referent_index == Some(&index) {
+                                continue;
+                            }
+
+                            if let FieldValue::ObjectId(Some(id)) = field_val {
+                                refs.push(id);
+                            }
+                        }
+
+                        (
+                            instance.obj_id(),
+                            type_name(ReferenceTarget::Instance {
+                                class_obj_id: instance.class_obj_id(),
+                            }),
+                            refs,
+                        )
+                    }
+                    SubRecord::ObjectArray(oa) => (
+                        oa.obj_id(),
+                        type_name(ReferenceTarget::Instance {
+                            class_obj_id: oa.array_class_obj_id(),
+                        }),
+                        oa.elements(id_size).filter_map(|r| r.unwrap()).collect(),
+                    ),
+                    SubRecord::PrimitiveArray(pa) => (
+                        pa.obj_id(),
+                        type_name(ReferenceTarget::PrimitiveArray {
+                            prim_type: pa.primitive_type(),
+                        }),
+                        Vec::new(),
+                    ),
+                    // handled above
+                    _ => continue,
+                };
+
+                obj_ids.push(obj_id);
+                type_names.push(type_name);
+                shallow_sizes.push(size);
+                id_edges.push(refs);
+            }
+        }
+
+        let node_indices = obj_ids
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(index, &id)| (id, index))
+            .collect::<collections::HashMap<_, _>>();
+
+        // references to objects that aren't in the dump are dropped
+        let edges = id_edges
+            .into_iter()
+            .map(|ids| {
+                ids.into_iter()
+                    .filter_map(|id| node_indices.get(&id).copied())
+                    .collect()
+            })
+            .collect();
+
+        ObjGraph {
+            obj_ids,
+            type_names,
+            shallow_sizes,
+            edges,
+        }
+    }
+
+    /// The shortest chain of references from a GC root to `node`, starting with the GC root and
+    /// ending with `node`, or `None` if `node` isn't reachable.
+    pub(crate) fn path_from_gc_root(&self, node: usize) -> Option<Vec<usize>> {
+        // node index -> the node it was first reached from
+        let mut parents = vec![usize::MAX; self.edges.len()];
+        parents[SUPER_ROOT] = SUPER_ROOT;
+        let mut queue = collections::VecDeque::new();
+        queue.push_back(SUPER_ROOT);
+
+        while let Some(current) = queue.pop_front() {
+            if current == node {
+                let mut path = vec![node];
+                let mut n = node;
+                while parents[n] != SUPER_ROOT {
+                    n = parents[n];
+                    path.push(n);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            for &dest in self.edges[current].iter() {
+                if parents[dest] == usize::MAX {
+                    parents[dest] = current;
+                    queue.push_back(dest);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// The dominator tree of an [ObjGraph], for the nodes reachable from [SUPER_ROOT].
+pub(crate) struct Dominators {
+    /// node index -> immediate dominator's node index, or `usize::MAX` if unreachable.
+    /// [SUPER_ROOT] is its own immediate dominator.
+    pub(crate) idom: Vec<usize>,
+    /// node index -> node indices immediately dominated by that node
+    pub(crate) children: Vec<Vec<usize>>,
+    /// node index -> bytes retained by that node, or 0 if unreachable
+    pub(crate) retained: Vec<u64>,
+}
+
+impl Dominators {
+    /// Computes immediate dominators with the iterative algorithm from "A Simple, Fast Dominance
+    /// Algorithm" (Cooper, Harvey, and Kennedy).
+    pub(crate) fn compute(graph: &ObjGraph) -> Dominators {
+        let num_nodes = graph.edges.len();
+        let rpo = reverse_postorder(&graph.edges);
+
+        let mut rpo_index = vec![usize::MAX; num_nodes];
+        for (index, &node) in rpo.iter().enumerate() {
+            rpo_index[node] = index;
+        }
+
+        let mut preds = vec![Vec::new(); num_nodes];
+        for &node in rpo.iter() {
+            for &dest in graph.edges[node].iter() {
+                preds[dest].push(node);
+            }
+        }
+
+        let mut idom = vec![usize::MAX; num_nodes];
+        idom[SUPER_ROOT] = SUPER_ROOT;
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &node in rpo.iter().skip(1) {
+                let mut new_idom = usize::MAX;
+                for &pred in preds[node].iter().filter(|&&p| idom[p] != usize::MAX) {
+                    new_idom = if new_idom == usize::MAX {
+                        pred
+                    } else {
+                        intersect(&idom, &rpo_index, pred, new_idom)
+                    };
+                }
+
+                if idom[node] != new_idom {
+                    idom[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); num_nodes];
+        let mut retained = vec![0; num_nodes];
+        for &node in rpo.iter() {
+            retained[node] = graph.shallow_sizes[node];
+        }
+        // dominators come before the nodes they dominate in reverse postorder, so going backwards
+        // finishes each node's total before it's added to its dominator
+        for &node in rpo.iter().skip(1).rev() {
+            retained[idom[node]] += retained[node];
+            children[idom[node]].push(node);
+        }
+
+        Dominators {
+            idom,
+            children,
+            retained,
+        }
+    }
+
+    /// The dominator tree path from the top of the tree (a node immediately dominated by
+    /// [SUPER_ROOT]) down to `node`, which must be reachable, found by walking up the chain of
+    /// immediate dominators.
+    pub(crate) fn path_from_top(&self, node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        let mut current = node;
+
+        while self.idom[current] != SUPER_ROOT {
+            current = self.idom[current];
+            path.push(current);
+        }
+
+        path.reverse();
+        path
+    }
+
+    /// The dominator tree path from `node` down to its accumulation point (inclusive).
+    pub(crate) fn accumulation_path(&self, node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        let mut current = node;
+
+        while let Some(&biggest) = self.children[current]
+            .iter()
+            .max_by_key(|&&child| self.retained[child])
+        {
+            if (self.retained[biggest] as f64)
+                < self.retained[current] as f64 * ACCUMULATION_FRACTION
+            {
+                break;
+            }
+
+            path.push(biggest);
+            current = biggest;
+        }
+
+        path
+    }
+}
+
+fn intersect(idom: &[usize], rpo_index: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a];
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b];
+        }
+    }
+
+    a
+}
+
+/// Nodes reachable from [SUPER_ROOT], in reverse postorder.
+fn reverse_postorder(edges: &[Vec<usize>]) -> Vec<usize> {
+    let mut visited = vec![false; edges.len()];
+    let mut postorder = Vec::new();
+    // node, index of the next edge to visit
+    let mut stack = vec![(SUPER_ROOT, 0)];
+    visited[SUPER_ROOT] = true;
+
+    while let Some((node, edge_index)) = stack.pop() {
+        match edges[node].get(edge_index) {
+            Some(&dest) => {
+                stack.push((node, edge_index + 1));
+                if !visited[dest] {
+                    visited[dest] = true;
+                    stack.push((dest, 0));
+                }
+            }
+            None => postorder.push(node),
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{Index, TempLmdbIndex};
+    use crate::test_util::*;
+
+    #[test]
+    fn exports_immediate_dominators() {
+        // root 1000 -> 1001 -> {1002, 1003}, both of which -> 1004
+        let node_class = TestClass {
+            instance_fields: vec![(10, TYPE_OBJ), (11, TYPE_OBJ)],
+            instance_size_bytes: 32,
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "Node").load_class(1, 100, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_unknown(1000)
+            .class(&node_class)
+            .instance(1000, 100, &[Val::Obj(1001), Val::Obj(0)])
+            .instance(1001, 100, &[Val::Obj(1002), Val::Obj(1003)])
+            .instance(1002, 100, &[Val::Obj(1004), Val::Obj(0)])
+            .instance(1003, 100, &[Val::Obj(1004), Val::Obj(0)])
+            .instance(1004, 100, &[Val::Obj(0), Val::Obj(0)])
+            // unreachable, so not in the tree
+            .instance(1005, 100, &[Val::Obj(1004), Val::Obj(0)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        export_dominator_tree(&hprof, false, &mut out).unwrap();
+
+        assert_eq!(
+            "Obj id,Class,Immediate dominator obj id,Shallow bytes,Retained bytes\n\
+             1000,Node,,32,160\n\
+             1001,Node,1000,32,128\n\
+             1002,Node,1001,32,32\n\
+             1003,Node,1001,32,32\n\
+             1004,Node,1001,32,32\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn stored_retained_sizes_match_recomputation() -> Result<(), anyhow::Error> {
+        // root 1000 -> {1001, 1002}, both of which -> 1003
+        let node_class = TestClass {
+            instance_fields: vec![(10, TYPE_OBJ), (11, TYPE_OBJ)],
+            instance_size_bytes: 32,
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "Node").load_class(1, 100, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_unknown(1000)
+            .class(&node_class)
+            .instance(1000, 100, &[Val::Obj(1001), Val::Obj(1002)])
+            .instance(1001, 100, &[Val::Obj(1003), Val::Obj(0)])
+            .instance(1002, 100, &[Val::Obj(1003), Val::Obj(0)])
+            .instance(1003, 100, &[Val::Obj(0), Val::Obj(0)])
+            // unreachable, so no retained size
+            .instance(1004, 100, &[Val::Obj(1003), Val::Obj(0)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let temp_index = TempLmdbIndex::build(&hprof)?;
+
+        // not written yet
+        let index = temp_index.open()?;
+        assert_eq!(None, index.get_retained_size(Id::from(1000))?);
+        drop(index);
+
+        build_retained(&hprof, temp_index.path(), false)?;
+
+        let index = temp_index.open()?;
+        let graph = ObjGraph::build(&hprof, false);
+        let dominators = Dominators::compute(&graph);
+        for n in 1..graph.obj_ids.len() {
+            let expected =
+                Some(dominators.retained[n]).filter(|_| dominators.idom[n] != usize::MAX);
+            assert_eq!(
+                expected,
+                index.get_retained_size(graph.obj_ids[n])?,
+                "obj id: {}",
+                graph.obj_ids[n]
+            );
+        }
+        assert_eq!(Some(128), index.get_retained_size(Id::from(1000))?);
+        assert_eq!(None, index.get_retained_size(Id::from(1004))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn retained_sizes_with_and_without_weak_referents() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        builder.reference_classes();
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_unknown(1000)
+            .reference_classes(32)
+            .instance(
+                1000,
+                WEAK_REFERENCE_CLASS_OBJ_ID,
+                &[Val::Obj(2000), Val::Obj(0)],
+            )
+            .byte_array(2000, &[0; 100]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let temp_index = TempLmdbIndex::build(&hprof)?;
+        let retained_sizes = || -> Result<_, anyhow::Error> {
+            let index = temp_index.open()?;
+            Ok((
+                index.get_retained_size(Id::from(1000))?,
+                index.get_retained_size(Id::from(2000))?,
+            ))
+        };
+
+        // the reference, plus the byte[] it keeps alive
+        build_retained(&hprof, temp_index.path(), false)?;
+        assert_eq!((Some(148), Some(116)), retained_sizes()?);
+
+        // the byte[] is only weakly reachable, so it's not in the tree at all
+        build_retained(&hprof, temp_index.path(), true)?;
+        assert_eq!((Some(32), None), retained_sizes()?);
+
+        Ok(())
+    }
+}
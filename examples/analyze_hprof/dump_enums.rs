@@ -0,0 +1,188 @@
+use crate::java_string::decode_strings;
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// Write a CSV of every enum constant (instances of subclasses of `java/lang/Enum`) with its
+/// name and ordinal.
+pub(crate) fn dump_enums<W: io::Write>(
+    hprof: &Hprof,
+    id_format: IdFormat,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let utf8 = utf8_strings_by_id(hprof);
+    let classes = ez_classes_by_obj_id(hprof);
+    let class_instance_field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+    let id_size = hprof.header().id_size();
+
+    let enum_class_ids = classes
+        .keys()
+        .filter(|&&id| is_subclass_of(&classes, id, "java/lang/Enum"))
+        .collect::<collections::HashSet<_>>();
+
+    let mut constants = Vec::new();
+
+    hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+        .for_each(|r| {
+            let segment = r.as_heap_dump_segment().unwrap().unwrap();
+            for p in segment.sub_records() {
+                if let SubRecord::Instance(instance) = p.unwrap() {
+                    if enum_class_ids.contains(&instance.class_obj_id()) {
+                        let field_descriptors = instance_field_descriptors(
+                            &class_instance_field_descriptors,
+                            instance.class_obj_id(),
+                        );
+
+                        constants.push(enum_constant(&instance, field_descriptors, &utf8, id_size));
+                    }
+                }
+            }
+        });
+
+    let names = decode_strings(
+        hprof,
+        &constants.iter().filter_map(|c| c.name_obj_id).collect(),
+        &class_instance_field_descriptors,
+        &utf8,
+    );
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Class name", "Ordinal", "Name", "Obj id"])?;
+
+    for c in constants.iter().sorted_by_key(|c| {
+        (
+            classes.get(&c.class_obj_id).map(|ec| ec.name),
+            c.ordinal,
+            c.obj_id.id(),
+        )
+    }) {
+        wtr.write_record(&[
+            classes
+                .get(&c.class_obj_id)
+                .map(|ec| ec.name)
+                .unwrap_or("(class not found)")
+                .to_owned(),
+            c.ordinal.map(|o| o.to_string()).unwrap_or_default(),
+            c.name_obj_id
+                .and_then(|id| names.get(&id))
+                .cloned()
+                .unwrap_or_else(|| String::from("(name not found)")),
+            id_format.format(c.obj_id, id_size),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// The fields an enum constant inherits from `java.lang.Enum`
+struct EnumConstant {
+    obj_id: Id,
+    class_obj_id: Id,
+    /// The `name` String
+    name_obj_id: Option<Id>,
+    ordinal: Option<i32>,
+}
+
+fn enum_constant(
+    instance: &Instance,
+    field_descriptors: &[FieldDescriptor],
+    utf8: &collections::HashMap<Id, &str>,
+    id_size: IdSize,
+) -> EnumConstant {
+    EnumConstant {
+        obj_id: instance.obj_id(),
+        class_obj_id: instance.class_obj_id(),
+        name_obj_id: match instance_field_value(instance, field_descriptors, utf8, "name", id_size)
+        {
+            Some(FieldValue::ObjectId(id)) => id,
+            _ => None,
+        },
+        ordinal: match instance_field_value(instance, field_descriptors, utf8, "ordinal", id_size) {
+            Some(FieldValue::Int(o)) => Some(o),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn dump_synthetic_enum() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "java/lang/Object")
+            .utf8(2, "java/lang/Enum")
+            .utf8(3, "com/example/Color")
+            .utf8(4, "java/lang/String")
+            .utf8(10, "name")
+            .utf8(11, "ordinal")
+            .utf8(12, "rgb")
+            .utf8(13, "value")
+            .utf8(14, "coder")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass {
+                instance_fields: vec![(10, TYPE_OBJ), (11, TYPE_INT)],
+                ..TestClass::new(101, 100)
+            })
+            .class(&TestClass {
+                instance_fields: vec![(12, TYPE_INT)],
+                ..TestClass::new(102, 101)
+            })
+            .class(&TestClass {
+                instance_fields: vec![(13, TYPE_OBJ), (14, TYPE_BYTE)],
+                ..TestClass::new(103, 100)
+            })
+            // Color fields, then Enum fields
+            .instance(
+                1001,
+                102,
+                &[Val::Int(0x00FF00), Val::Obj(2001), Val::Int(1)],
+            )
+            .instance(
+                1000,
+                102,
+                &[Val::Int(0xFF0000), Val::Obj(2000), Val::Int(0)],
+            )
+            .instance(
+                1002,
+                102,
+                &[Val::Int(0x0000FF), Val::Obj(2002), Val::Int(2)],
+            )
+            // Latin-1, pre-JDK 9 char[], and UTF-16
+            .instance(2000, 103, &[Val::Obj(3000), Val::Byte(0)])
+            .instance(2001, 103, &[Val::Obj(3001), Val::Byte(0)])
+            .instance(2002, 103, &[Val::Obj(3002), Val::Byte(1)])
+            .byte_array(3000, b"RED")
+            .char_array(3001, "GREEN")
+            .byte_array(3002, &[b'B', 0, b'L', 0, b'U', 0, b'E', 0]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_enums(&hprof, IdFormat::Dec, &mut out).unwrap();
+
+        assert_eq!(
+            "Class name,Ordinal,Name,Obj id\n\
+             com/example/Color,0,RED,1000\n\
+             com/example/Color,1,GREEN,1001\n\
+             com/example/Color,2,BLUE,1002\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
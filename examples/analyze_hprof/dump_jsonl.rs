@@ -0,0 +1,264 @@
+use jvm_hprof::{heap_dump::*, *};
+use serde_json::{json, Value};
+use std::io;
+
+/// Write each record as a line of JSON with its tag, timestamp, and body.
+///
+/// Heap dump segments are written as one line per sub record, under `sub_record` rather than
+/// `body`. Records whose contents aren't parsed (the ones that don't seem to ever be written) have
+/// a `null` body. Ids are written as numbers, so they won't survive a round trip through tools that
+/// use doubles for all JSON numbers if they exceed 2^53.
+pub(crate) fn dump_jsonl<W: io::Write>(hprof: &Hprof, mut out: W) -> Result<(), anyhow::Error> {
+    let header = hprof.header();
+    let id_size = header.id_size();
+
+    for record in hprof.records_iter().map(|r| r.unwrap()) {
+        let tag = format!("{:?}", record.tag());
+        let timestamp_micros = record.absolute_timestamp_micros(&header);
+
+        match record.tag() {
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                let segment = record.as_heap_dump_segment().unwrap().unwrap();
+                for s in segment.sub_records().map(|r| r.unwrap()) {
+                    let line = json!({
+                        "tag": tag,
+                        "timestamp_micros": timestamp_micros,
+                        "sub_record": sub_record_json(&s, id_size),
+                    });
+                    serde_json::to_writer(&mut out, &line)?;
+                    writeln!(out)?;
+                }
+            }
+            _ => {
+                let line = json!({
+                    "tag": tag,
+                    "timestamp_micros": timestamp_micros,
+                    "body": record_body_json(&record),
+                });
+                serde_json::to_writer(&mut out, &line)?;
+                writeln!(out)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn record_body_json(record: &Record) -> Value {
+    match record.tag() {
+        RecordTag::Utf8 => {
+            let utf8 = record.as_utf_8().unwrap().unwrap();
+            json!({
+                "name_id": utf8.name_id().id(),
+                "text": utf8.text_lossy(),
+            })
+        }
+        RecordTag::LoadClass => {
+            let lc = record.as_load_class().unwrap().unwrap();
+            json!({
+                "class_serial": lc.class_serial().num(),
+                "class_obj_id": lc.class_obj_id().id(),
+                "stack_trace_serial": lc.stack_trace_serial().map(|s| s.num()),
+                "class_name_id": lc.class_name_id().id(),
+            })
+        }
+        RecordTag::StackFrame => {
+            let sf = record.as_stack_frame().unwrap().unwrap();
+            json!({
+                "id": sf.id().id(),
+                "method_name_id": sf.method_name_id().id(),
+                "method_signature_id": sf.method_signature_id().id(),
+                "source_file_name_id": sf.source_file_name_id().map(|id| id.id()),
+                "class_serial": sf.class_serial().num(),
+                "line_num": match sf.line_num() {
+                    LineNum::Normal(n) => json!(n),
+                    LineNum::Unknown => json!("unknown"),
+                    LineNum::CompiledMethod => json!("compiled method"),
+                    LineNum::NativeMethod => json!("native method"),
+                },
+            })
+        }
+        RecordTag::StackTrace => {
+            let st = record.as_stack_trace().unwrap().unwrap();
+            json!({
+                "stack_trace_serial": st.stack_trace_serial().num(),
+                "thread_serial": st.thread_serial().num(),
+                "frame_ids": st
+                    .frame_ids()
+                    .map(|r| r.unwrap().id())
+                    .collect::<Vec<_>>(),
+            })
+        }
+        _ => Value::Null,
+    }
+}
+
+fn sub_record_json(s: &SubRecord, id_size: IdSize) -> Value {
+    let type_name = format!("{:?}", s);
+
+    match s {
+        SubRecord::GcRootUnknown(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+        }),
+        SubRecord::GcRootThreadObj(gc) => json!({
+            "type": type_name,
+            "thread_obj_id": gc.thread_obj_id().map(|id| id.id()),
+            "thread_serial": gc.thread_serial().num(),
+            "stack_trace_serial": gc.stack_trace_serial().num(),
+        }),
+        SubRecord::GcRootJniGlobal(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+            "jni_global_ref_id": gc.jni_global_ref_id().id(),
+        }),
+        SubRecord::GcRootJniLocalRef(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+            "thread_serial": gc.thread_serial().num(),
+            "frame_index": gc.frame_index(),
+        }),
+        SubRecord::GcRootJavaStackFrame(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+            "thread_serial": gc.thread_serial().num(),
+            "frame_index": gc.frame_index(),
+        }),
+        SubRecord::GcRootNativeStack(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+            "thread_serial": gc.thread_serial().num(),
+        }),
+        SubRecord::GcRootSystemClass(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+        }),
+        SubRecord::GcRootThreadBlock(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+            "thread_serial": gc.thread_serial().num(),
+        }),
+        SubRecord::GcRootBusyMonitor(gc) => json!({
+            "type": type_name,
+            "obj_id": gc.obj_id().id(),
+        }),
+        SubRecord::Class(c) => json!({
+            "type": type_name,
+            "obj_id": c.obj_id().id(),
+            "stack_trace_serial": c.stack_trace_serial().map(|s| s.num()),
+            "super_class_obj_id": c.super_class_obj_id().map(|id| id.id()),
+            "class_loader_obj_id": c.class_loader_obj_id().map(|id| id.id()),
+            "instance_size_bytes": c.instance_size_bytes(),
+            "num_static_fields": c.num_static_fields(),
+            "num_instance_fields": c.num_instance_fields(),
+        }),
+        SubRecord::Instance(instance) => json!({
+            "type": type_name,
+            "obj_id": instance.obj_id().id(),
+            "stack_trace_serial": instance.stack_trace_serial().map(|s| s.num()),
+            "class_obj_id": instance.class_obj_id().id(),
+        }),
+        SubRecord::ObjectArray(oa) => json!({
+            "type": type_name,
+            "obj_id": oa.obj_id().id(),
+            "stack_trace_serial": oa.stack_trace_serial().map(|s| s.num()),
+            "array_class_obj_id": oa.array_class_obj_id().id(),
+            "elements": oa
+                .elements(id_size)
+                .map(|r| r.unwrap().map(|id| id.id()))
+                .collect::<Vec<_>>(),
+        }),
+        SubRecord::PrimitiveArray(pa) => json!({
+            "type": type_name,
+            "obj_id": pa.obj_id().id(),
+            "stack_trace_serial": pa.stack_trace_serial().map(|s| s.num()),
+            "primitive_type": pa.primitive_type().java_type_name(),
+            "num_elements": pa.num_elements(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn one_valid_json_object_per_line() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .timestamp_millis(1000)
+            .utf8(1, "Thing")
+            .load_class(1, 100, 0, 1)
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .stack_trace(1, 100, &[10]);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 100, &[1000, 0])
+            .int_array(3000, &[1, 2, 3])
+            .gc_root_unknown(1000);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_jsonl(&hprof, &mut out).unwrap();
+
+        let lines = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "Utf8",
+                "LoadClass",
+                "StackFrame",
+                "StackTrace",
+                // one line for each of the 5 sub records
+                "HeapDumpSegment",
+                "HeapDumpSegment",
+                "HeapDumpSegment",
+                "HeapDumpSegment",
+                "HeapDumpSegment",
+                "HeapDumpEnd",
+            ],
+            lines
+                .iter()
+                .map(|l| l["tag"].as_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert!(lines
+            .iter()
+            .all(|l| l["timestamp_micros"] == json!(1_000_000)));
+
+        assert_eq!(json!({"name_id": 1, "text": "Thing"}), lines[0]["body"]);
+        assert_eq!(
+            json!({"stack_trace_serial": 1, "thread_serial": 100, "frame_ids": [10]}),
+            lines[3]["body"]
+        );
+        assert_eq!(
+            json!({
+                "type": "ObjectArray",
+                "obj_id": 2000,
+                "stack_trace_serial": null,
+                "array_class_obj_id": 100,
+                "elements": [1000, null],
+            }),
+            lines[6]["sub_record"]
+        );
+        assert_eq!(
+            json!({
+                "type": "PrimitiveArray",
+                "obj_id": 3000,
+                "stack_trace_serial": null,
+                "primitive_type": "int",
+                "num_elements": 3,
+            }),
+            lines[7]["sub_record"]
+        );
+        assert_eq!(Value::Null, lines[9]["body"]);
+    }
+}
@@ -1,347 +1,915 @@
+use crate::index::{resolve_reference_target, ObjTypeLookup};
+use crate::java_string::decode_strings;
+use crate::thread_dump::describe_frame;
 use crate::util::*;
 use jvm_hprof::{heap_dump::*, *};
-use std::collections;
+use std::{collections, io};
+
+/// Print every class, instance, and array, along with field values and array contents.
+///
+/// If `class_name` is provided, only instances of that class and arrays (of any dimension) of it
+/// are printed. Either JVM style (`java/lang/String`) or Java style (`java.lang.String`) names may
+/// be used.
+///
+/// At most `max_array_elements` elements of each array are printed, followed by how many more
+/// there are.
+///
+/// If `resolve_strings` is true, fields that refer to a `java/lang/String` also show the string's
+/// text (truncated if it's long), at the cost of an extra pass over the heap dump.
+///
+/// If `with_alloc_stack` is true, each object is followed by the stack trace it was allocated at.
+/// The JVM only records these when allocation tracking is enabled, so usually they're empty.
+///
+/// Ids are written in `id_format`.
+#[allow(clippy::too_many_arguments)]
+pub fn dump_objects<W: io::Write>(
+    hprof: &Hprof,
+    obj_id_range: &ObjIdRange,
+    class_name: Option<&str>,
+    max_array_elements: usize,
+    resolve_strings: bool,
+    with_alloc_stack: bool,
+    id_format: IdFormat,
+    mut out: W,
+) -> Result<(), anyhow::Error> {
+    let tables = HprofTables::build(hprof).unwrap();
+    let id_size = hprof.header().id_size();
+    let fmt_id = |id: Id| id_format.format(id, id_size);
 
-pub fn dump_objects(hprof: &Hprof) {
-    // class obj id -> LoadClass
-    let mut load_classes = collections::HashMap::new();
     // name id -> String
-    let mut utf8 = collections::HashMap::new();
+    let utf8: collections::HashMap<Id, &str> = tables
+        .utf8()
+        .iter()
+        .map(|(&id, u)| (id, u.text_as_str().unwrap_or("(invalid UTF-8)")))
+        .collect();
 
-    let mut classes: collections::HashMap<Id, EzClass> = collections::HashMap::new();
+    let classes: collections::HashMap<Id, EzClass> = tables
+        .classes()
+        .iter()
+        .map(|(&id, c)| {
+            (
+                id,
+                EzClass::from_class(c, tables.load_classes_by_obj_id(), &utf8),
+            )
+        })
+        .collect();
     // instance obj id to class obj id
     // TODO if this gets big, could use lmdb or similar to get it off-heap
-    let mut obj_id_to_class_obj_id: collections::HashMap<Id, Id> = collections::HashMap::new();
-    let mut prim_array_obj_id_to_type = collections::HashMap::new();
+    let obj_id_to_class_obj_id = tables.obj_class_ids();
+    let prim_array_obj_id_to_type = tables.prim_array_types();
 
     let missing_utf8 = "(missing utf8)";
 
-    // build obj -> class and class id -> class metadata maps
-    // TODO use index
-    hprof
-        .records_iter()
-        .map(|r| r.unwrap())
-        .for_each(|r| match r.tag() {
-            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
-                let segment = r.as_heap_dump_segment().unwrap().unwrap();
-                for p in segment.sub_records() {
-                    let s = p.unwrap();
-                    match s {
-                        SubRecord::Class(c) => {
-                            classes
-                                .insert(c.obj_id(), EzClass::from_class(&c, &load_classes, &utf8));
-                        }
-                        SubRecord::Instance(instance) => {
-                            obj_id_to_class_obj_id
-                                .insert(instance.obj_id(), instance.class_obj_id());
-                        }
-                        SubRecord::ObjectArray(obj_array) => {
-                            obj_id_to_class_obj_id
-                                .insert(obj_array.obj_id(), obj_array.array_class_obj_id());
-                        }
-                        SubRecord::PrimitiveArray(pa) => {
-                            prim_array_obj_id_to_type.insert(pa.obj_id(), pa.primitive_type());
-                        }
-                        _ => {}
-                    };
-                }
-            }
-            RecordTag::Utf8 => {
-                let u = r.as_utf_8().unwrap().unwrap();
-                utf8.insert(u.name_id(), u.text_as_str().unwrap_or("(invalid UTF-8)"));
-            }
-            RecordTag::LoadClass => {
-                let lc = r.as_load_class().unwrap().unwrap();
-                load_classes.insert(lc.class_obj_id(), lc);
-            }
-            _ => {}
-        });
-
     let class_instance_field_descriptors = build_type_hierarchy_field_descriptors(&classes);
 
-    hprof
-        .records_iter()
-        .map(|r| r.unwrap())
-        .for_each(|r| match r.tag() {
+    let strings = if resolve_strings {
+        let string_obj_ids = obj_id_to_class_obj_id
+            .iter()
+            .filter(|(_, class_obj_id)| {
+                classes.get(class_obj_id).map(|c| c.name) == Some("java/lang/String")
+            })
+            .map(|(&obj_id, _)| obj_id)
+            .collect();
+        decode_strings(
+            hprof,
+            &string_obj_ids,
+            &class_instance_field_descriptors,
+            &utf8,
+        )
+    } else {
+        collections::HashMap::new()
+    };
+
+    let alloc_stacks = if with_alloc_stack {
+        Some(AllocStacks {
+            stack_traces: hprof.stack_traces_by_serial().unwrap(),
+            frames: hprof
+                .records_iter_filtered(&[RecordTag::StackFrame])
+                .map(|r| r.unwrap().as_stack_frame().unwrap().unwrap())
+                .map(|f| (f.id(), f))
+                .collect(),
+            load_classes_by_serial: tables.load_classes_by_serial(),
+            utf8: tables.utf8(),
+        })
+    } else {
+        None
+    };
+
+    let ref_targets = RefTargets {
+        obj_id_to_class_obj_id,
+        classes: &classes,
+        prim_array_obj_id_to_type,
+        strings,
+    };
+
+    // the matching class and array class obj ids, if filtering by class
+    let class_filter: Option<collections::HashSet<Id>> = class_name.map(|name| {
+        let name = name.replace('.', "/");
+        classes
+            .values()
+            .filter(|c| c.name == name || is_array_of(c.name, &name))
+            .map(|c| c.obj_id)
+            .collect()
+    });
+    let class_matches = |class_obj_id: Id| match &class_filter {
+        Some(ids) => ids.contains(&class_obj_id),
+        None => true,
+    };
+
+    for r in hprof.records_iter().map(|r| r.unwrap()) {
+        match r.tag() {
             RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
                 let segment = r.as_heap_dump_segment().unwrap().unwrap();
                 for p in segment.sub_records() {
                     let s = p.unwrap();
 
                     match s {
-                        SubRecord::Class(class) => {
-                            let mc = match classes.get(&class.obj_id()) {
-                                None => panic!("Could not find class {}", class.obj_id()),
-                                Some(c) => c,
-                            };
-
-                            println!("\nid {}: class {}", class.obj_id(), mc.name);
-                            for sf in &mc.static_fields {
-                                let field_name =
-                                    utf8.get(&sf.name_id()).unwrap_or_else(|| &missing_utf8);
+                        SubRecord::Class(class)
+                            if class_filter.is_none() && obj_id_range.contains(class.obj_id()) =>
+                        {
+                            let mc = classes
+                                .get(&class.obj_id())
+                                .expect("Class sub records are all in the classes table");
+
+                            writeln!(out, "\nid {}: class {}", fmt_id(class.obj_id()), mc.name)?;
+                            for r in class.resolved_static_fields(|id| utf8.get(&id).copied()) {
+                                let (field_name, sf) = r.unwrap();
 
                                 print_field_val(
+                                    &mut out,
                                     &sf.value(),
-                                    field_name,
+                                    field_name.unwrap_or(missing_utf8),
                                     sf.field_type(),
-                                    &obj_id_to_class_obj_id,
-                                    &classes,
-                                    &prim_array_obj_id_to_type,
-                                );
+                                    &ref_targets,
+                                    &fmt_id,
+                                )?;
                             }
+
+                            write_alloc_stack(
+                                &mut out,
+                                class.stack_trace_serial(),
+                                alloc_stacks.as_ref(),
+                                &fmt_id,
+                            )?;
                         }
-                        SubRecord::Instance(instance) => {
-                            let mc = match classes.get(&instance.class_obj_id()) {
-                                None => panic!(
-                                    "Could not find class {} for instance {}",
-                                    instance.class_obj_id(),
-                                    instance.obj_id()
-                                ),
-                                Some(c) => c,
-                            };
-
-                            println!("\nid {}: {}", instance.obj_id(), mc.name);
-
-                            let field_descriptors = class_instance_field_descriptors
-                                .get(&instance.class_obj_id())
-                                .expect("Should have all classes available");
-
-                            let mut field_val_input: &[u8] = instance.fields();
-                            for fd in field_descriptors.iter() {
-                                let (input, field_val) = fd
-                                    .field_type()
-                                    .parse_value(field_val_input, hprof.header().id_size())
-                                    .unwrap();
-                                field_val_input = input;
+                        SubRecord::Instance(instance)
+                            if class_matches(instance.class_obj_id())
+                                && obj_id_range.contains(instance.obj_id()) =>
+                        {
+                            writeln!(
+                                out,
+                                "\nid {}: {}",
+                                fmt_id(instance.obj_id()),
+                                class_name_or_unknown(&classes, instance.class_obj_id())
+                            )?;
+
+                            let field_descriptors = instance_field_descriptors(
+                                &class_instance_field_descriptors,
+                                instance.class_obj_id(),
+                            );
 
+                            for (fd, field_val) in instance_field_values(
+                                &instance,
+                                field_descriptors,
+                                hprof.header().id_size(),
+                            ) {
                                 let field_name =
                                     utf8.get(&fd.name_id()).unwrap_or_else(|| &missing_utf8);
 
                                 print_field_val(
+                                    &mut out,
                                     &field_val,
                                     field_name,
                                     fd.field_type(),
-                                    &obj_id_to_class_obj_id,
-                                    &classes,
-                                    &prim_array_obj_id_to_type,
-                                );
+                                    &ref_targets,
+                                    &fmt_id,
+                                )?;
                             }
+
+                            write_alloc_stack(
+                                &mut out,
+                                instance.stack_trace_serial(),
+                                alloc_stacks.as_ref(),
+                                &fmt_id,
+                            )?;
                         }
-                        SubRecord::ObjectArray(oa) => {
-                            let mc = match classes.get(&oa.array_class_obj_id()) {
-                                None => panic!(
-                                    "Could not find class {} for instance {}",
-                                    oa.array_class_obj_id(),
-                                    oa.obj_id()
-                                ),
-                                Some(c) => c,
-                            };
-
-                            println!("\nid {}: {} = [", oa.obj_id(), mc.name);
-
-                            for pr in oa.elements(hprof.header().id_size()) {
+                        SubRecord::ObjectArray(oa)
+                            if class_matches(oa.array_class_obj_id())
+                                && obj_id_range.contains(oa.obj_id()) =>
+                        {
+                            writeln!(
+                                out,
+                                "\nid {}: {} = [",
+                                fmt_id(oa.obj_id()),
+                                class_name_or_unknown(&classes, oa.array_class_obj_id())
+                            )?;
+
+                            // elements of nested arrays like [[I are themselves arrays, so use
+                            // the declared element type when the element's class can't be found
+                            let element_type = oa
+                                .element_class_name(|id| classes.get(&id).map(|c| c.name))
+                                .unwrap_or("(unknown element type)");
+
+                            for pr in oa
+                                .elements(hprof.header().id_size())
+                                .take(max_array_elements)
+                            {
                                 match pr.unwrap() {
                                     Some(id) => {
                                         let element_class_name = obj_id_to_class_obj_id
                                             .get(&id)
                                             .and_then(|class_id| classes.get(class_id))
-                                            .map(|c| c.name)
-                                            .unwrap_or_else(|| "(could not resolve class)");
+                                            .map(|c| c.name.to_owned())
+                                            .or_else(|| {
+                                                prim_array_obj_id_to_type
+                                                    .get(&id)
+                                                    .map(|t| format!("{}[]", t.java_type_name()))
+                                            })
+                                            .unwrap_or_else(|| {
+                                                format!(
+                                                    "(could not resolve class, declared as {})",
+                                                    element_type
+                                                )
+                                            });
 
-                                        println!("  - id {}: {}", id, element_class_name);
+                                        writeln!(
+                                            out,
+                                            "  - id {}: {}",
+                                            fmt_id(id),
+                                            element_class_name
+                                        )?;
                                     }
                                     None => {
-                                        println!("  - null");
+                                        writeln!(out, "  - null")?;
                                     }
                                 }
                             }
 
-                            println!("]");
+                            if let Some(more) = more_elements(oa.num_elements(), max_array_elements)
+                            {
+                                writeln!(out, "  ... ({} more)", more)?;
+                            }
+
+                            writeln!(out, "]")?;
+
+                            write_alloc_stack(
+                                &mut out,
+                                oa.stack_trace_serial(),
+                                alloc_stacks.as_ref(),
+                                &fmt_id,
+                            )?;
                         }
-                        SubRecord::PrimitiveArray(pa) => {
-                            print!(
+                        SubRecord::PrimitiveArray(pa)
+                            if class_filter.is_none() && obj_id_range.contains(pa.obj_id()) =>
+                        {
+                            write!(
+                                out,
                                 "\n{}: {}[] = [",
-                                pa.obj_id(),
+                                fmt_id(pa.obj_id()),
                                 pa.primitive_type().java_type_name()
-                            );
+                            )?;
 
                             match pa.primitive_type() {
                                 PrimitiveArrayType::Boolean => {
-                                    pa.booleans()
+                                    for e in pa
+                                        .booleans()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Char => {
-                                    pa.chars()
+                                    for e in pa
+                                        .chars()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Float => {
-                                    pa.floats()
+                                    for e in pa
+                                        .floats()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Double => {
-                                    pa.doubles()
+                                    for e in pa
+                                        .doubles()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Byte => {
-                                    pa.bytes()
+                                    for e in pa
+                                        .bytes()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{:#X}, ", e));
+                                    {
+                                        write!(out, "{:#X}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Short => {
-                                    pa.shorts()
+                                    for e in pa
+                                        .shorts()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Int => {
-                                    pa.ints()
+                                    for e in pa
+                                        .ints()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                                 PrimitiveArrayType::Long => {
-                                    pa.longs()
+                                    for e in pa
+                                        .longs()
                                         .unwrap()
+                                        .take(max_array_elements)
                                         .map(|r| r.unwrap())
-                                        .for_each(|e| print!("{}, ", e));
+                                    {
+                                        write!(out, "{}, ", e)?;
+                                    }
                                 }
                             }
 
-                            println!("]");
+                            if let Some(more) = more_elements(pa.num_elements(), max_array_elements)
+                            {
+                                write!(out, "... ({} more)", more)?;
+                            }
+
+                            writeln!(out, "]")?;
+
+                            write_alloc_stack(
+                                &mut out,
+                                pa.stack_trace_serial(),
+                                alloc_stacks.as_ref(),
+                                &fmt_id,
+                            )?;
                         }
                         _ => {}
                     }
                 }
             }
             _ => {}
-        });
+        }
+    }
+
+    Ok(())
+}
+
+/// The number of elements not printed, if any.
+fn more_elements(num_elements: u32, max_array_elements: usize) -> Option<usize> {
+    (num_elements as usize)
+        .checked_sub(max_array_elements)
+        .filter(|&more| more > 0)
+}
+
+/// True if `array_class_name` is the JVM name of an array, of any dimension, of `class_name`.
+fn is_array_of(array_class_name: &str, class_name: &str) -> bool {
+    let mut element = array_class_name;
+    while let Some(e) = array_element_descriptor(element) {
+        element = e;
+    }
+
+    element.len() == class_name.len() + 2
+        && element.starts_with('L')
+        && element.ends_with(';')
+        && &element[1..element.len() - 1] == class_name
+}
+
+/// Strings longer than this many chars are truncated when shown inline
+const MAX_STRING_CHARS: usize = 64;
+
+/// Everything needed to describe the object a field refers to.
+struct RefTargets<'a> {
+    obj_id_to_class_obj_id: &'a collections::HashMap<Id, Id>,
+    classes: &'a collections::HashMap<Id, EzClass<'a>>,
+    prim_array_obj_id_to_type: &'a collections::HashMap<Id, PrimitiveArrayType>,
+    /// String obj id -> text, empty unless resolving strings
+    strings: collections::HashMap<Id, String>,
+}
+
+impl ObjTypeLookup for RefTargets<'_> {
+    fn lookup_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
+        Ok(self.obj_id_to_class_obj_id.get(&obj_id).copied())
+    }
+
+    fn lookup_prim_array_type(
+        &self,
+        obj_id: Id,
+    ) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
+        Ok(self.prim_array_obj_id_to_type.get(&obj_id).copied())
+    }
 }
 
-fn print_field_val(
+/// Everything needed to describe the stack trace an object was allocated at.
+struct AllocStacks<'a> {
+    stack_traces: collections::HashMap<Serial, StackTrace<'a>>,
+    /// frame id -> StackFrame
+    frames: collections::HashMap<Id, StackFrame>,
+    load_classes_by_serial: &'a collections::HashMap<Serial, LoadClass>,
+    utf8: &'a collections::HashMap<Id, Utf8<'a>>,
+}
+
+/// Write the frames of the allocation stack trace with serial `stack_trace_serial`, unless
+/// `alloc_stacks` is `None`.
+fn write_alloc_stack<W: io::Write>(
+    out: &mut W,
+    stack_trace_serial: Option<Serial>,
+    alloc_stacks: Option<&AllocStacks>,
+    fmt_id: &dyn Fn(Id) -> String,
+) -> Result<(), io::Error> {
+    let alloc_stacks = match alloc_stacks {
+        Some(a) => a,
+        None => return Ok(()),
+    };
+
+    let stack_trace_serial = match stack_trace_serial {
+        Some(s) => s,
+        None => return writeln!(out, "  allocated at: (no stack trace)"),
+    };
+
+    let trace = match alloc_stacks.stack_traces.get(&stack_trace_serial) {
+        Some(t) => t,
+        None => {
+            return writeln!(
+                out,
+                "  allocated at: (stack trace {} not found)",
+                stack_trace_serial
+            )
+        }
+    };
+
+    if trace.frame_ids().next().is_none() {
+        return writeln!(out, "  allocated at: (empty stack trace)");
+    }
+
+    writeln!(out, "  allocated at:")?;
+    for (frame_id, frame) in trace
+        .frame_ids()
+        .map(|r| r.unwrap())
+        .zip(trace.resolved_frames(&alloc_stacks.frames))
+    {
+        match frame {
+            Some(f) => writeln!(
+                out,
+                "    {}",
+                describe_frame(f, alloc_stacks.load_classes_by_serial, alloc_stacks.utf8)
+            )?,
+            None => writeln!(out, "    (no frame found for id {})", fmt_id(frame_id))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// A string as a quoted and escaped literal, truncated to [MAX_STRING_CHARS].
+fn string_preview(text: &str) -> String {
+    if text.chars().count() > MAX_STRING_CHARS {
+        format!(
+            "{:?}...",
+            text.chars().take(MAX_STRING_CHARS).collect::<String>()
+        )
+    } else {
+        format!("{:?}", text)
+    }
+}
+
+fn print_field_val<W: io::Write>(
+    out: &mut W,
     field_val: &FieldValue,
     field_name: &str,
     field_type: FieldType,
-    obj_id_to_class_obj_id: &collections::HashMap<Id, Id>,
-    classes: &collections::HashMap<Id, EzClass>,
-    prim_array_obj_id_to_type: &collections::HashMap<Id, PrimitiveArrayType>,
-) {
+    ref_targets: &RefTargets,
+    fmt_id: &dyn Fn(Id) -> String,
+) -> Result<(), io::Error> {
     match field_val {
         FieldValue::ObjectId(Some(field_ref_id)) => {
-            obj_id_to_class_obj_id
-                .get(&field_ref_id)
-                .map(|class_obj_id| {
-                    println!(
+            let id = fmt_id(*field_ref_id);
+            if let Some(text) = ref_targets.strings.get(field_ref_id) {
+                writeln!(
+                    out,
+                    "  - {} = id {} (java/lang/String) {}",
+                    field_name,
+                    id,
+                    string_preview(text),
+                )
+            } else {
+                match resolve_reference_target(*field_ref_id, ref_targets, ref_targets.classes)
+                    .expect("Looking up obj ids in RefTargets can't fail")
+                {
+                    Some(target) => writeln!(
+                        out,
                         "  - {} = id {} ({})",
                         field_name,
-                        field_ref_id,
-                        classes
-                            .get(class_obj_id)
-                            .map(|c| c.name)
-                            .unwrap_or("(class not found)"),
-                    );
-                })
-                .or_else(|| {
-                    prim_array_obj_id_to_type
-                        .get(&field_ref_id)
-                        .map(|prim_type| {
-                            println!(
-                                "  - {} = id {} ({}[])",
-                                field_name,
-                                field_ref_id,
-                                prim_type.java_type_name()
-                            );
-                        })
-                })
-                .or_else(|| {
-                    classes.get(&field_ref_id).map(|dest_class| {
-                        println!(
-                            "  - {} = id {} (class {})",
-                            field_name, field_ref_id, dest_class.name
-                        );
-                    })
-                })
-                .unwrap_or_else(|| {
-                    println!(
+                        id,
+                        target.type_name(ref_targets.classes)
+                    ),
+                    None => writeln!(
+                        out,
                         "  - {} = id {} (type for obj id not found)",
-                        field_name, field_ref_id
-                    );
-                });
-        }
-        FieldValue::ObjectId(None) => {
-            println!("  - {} = null", field_name,);
-        }
-        FieldValue::Boolean(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Char(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Float(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Double(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Byte(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Short(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Int(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
-        }
-        FieldValue::Long(v) => {
-            println!(
-                "  - {}: {} = {}",
-                field_name,
-                field_type.java_type_name(),
-                v
-            );
+                        field_name, id
+                    ),
+                }
+            }
         }
+        FieldValue::ObjectId(None) => writeln!(out, "  - {} = null", field_name),
+        FieldValue::Boolean(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Char(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Float(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Double(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Byte(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Short(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Int(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+        FieldValue::Long(v) => writeln!(
+            out,
+            "  - {}: {} = {}",
+            field_name,
+            field_type.java_type_name(),
+            v
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn filter_by_class_name() {
+        let point_class = TestClass {
+            instance_fields: vec![(10, TYPE_INT)],
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/Point")
+            .utf8(2, "com/example/Other")
+            .utf8(3, "[Lcom/example/Point;")
+            .utf8(4, "[[Lcom/example/Point;")
+            .utf8(10, "x")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&point_class)
+            .class(&TestClass::new(101, 0))
+            .class(&TestClass::new(102, 0))
+            .class(&TestClass::new(103, 0))
+            .instance(1000, 100, &[Val::Int(7)])
+            .instance(1001, 101, &[])
+            .object_array(2000, 102, &[1000])
+            .object_array(2001, 103, &[2000])
+            .int_array(3000, &[1]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            Some("com.example.Point"),
+            32,
+            false,
+            false,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "\nid 1000: com/example/Point\n\
+             \x20 - x: int = 7\n\
+             \nid 2000: [Lcom/example/Point; = [\n\
+             \x20 - id 1000: com/example/Point\n\
+             ]\n\
+             \nid 2001: [[Lcom/example/Point; = [\n\
+             \x20 - id 2000: [Lcom/example/Point;\n\
+             ]\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncates_long_arrays() {
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "[LThing;").load_class(1, 102, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(102, 0))
+            .object_array(2000, 102, &[0, 0, 0])
+            .object_array(2001, 102, &[0, 0])
+            .int_array(3000, &[1, 2, 3, 4, 5])
+            .int_array(3001, &[1, 2]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            None,
+            2,
+            false,
+            false,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "\nid 102: class [LThing;\n\
+             \nid 2000: [LThing; = [\n\
+             \x20 - null\n\
+             \x20 - null\n\
+             \x20 ... (1 more)\n\
+             ]\n\
+             \nid 2001: [LThing; = [\n\
+             \x20 - null\n\
+             \x20 - null\n\
+             ]\n\
+             \n3000: int[] = [1, 2, ... (3 more)]\n\
+             \n3001: int[] = [1, 2, ]\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_classes_dont_panic() {
+        // superclass 998 is missing
+        let point_class = TestClass {
+            instance_fields: vec![(10, TYPE_INT)],
+            ..TestClass::new(100, 998)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/Point")
+            .utf8(10, "x")
+            .load_class(1, 100, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&point_class)
+            .instance(1000, 100, &[Val::Int(7)])
+            // class 999 is missing entirely
+            .instance(1001, 999, &[Val::Int(8)])
+            .object_array(2000, 999, &[1001]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            None,
+            32,
+            false,
+            false,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "\nid 100: class com/example/Point\n\
+             \nid 1000: com/example/Point\n\
+             \x20 - x: int = 7\n\
+             \nid 1001: (unknown class 999)\n\
+             \nid 2000: (unknown class 999) = [\n\
+             \x20 - id 1001: (could not resolve class, declared as (unknown element type))\n\
+             ]\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn unresolved_elements_show_element_class_name() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "[Ljava/lang/String;")
+            .load_class(1, 102, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(102, 0))
+            // element 5000 isn't in the heap dump
+            .object_array(2000, 102, &[5000]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            None,
+            32,
+            false,
+            false,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "\nid 102: class [Ljava/lang/String;\n\
+             \nid 2000: [Ljava/lang/String; = [\n\
+             \x20 - id 5000: (could not resolve class, declared as java/lang/String)\n\
+             ]\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn with_alloc_stack_shows_frames() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/Thing")
+            .utf8(2, "com/example/Factory")
+            .utf8(3, "make")
+            .utf8(4, "()Lcom/example/Thing;")
+            .utf8(5, "Factory.java")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .stack_frame(50, 3, 4, 5, 2, 17)
+            .stack_trace(7, 1, &[50, 51]);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance_with_stack_trace(1000, 7, 100, &[])
+            .instance_with_stack_trace(1001, 0, 100, &[])
+            .instance_with_stack_trace(1002, 8, 100, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            Some("com.example.Thing"),
+            32,
+            false,
+            true,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "\nid 1000: com/example/Thing\n\
+             \x20 allocated at:\n\
+             \x20   com/example/Factory#make()Lcom/example/Thing; (Factory.java:17)\n\
+             \x20   (no frame found for id 51)\n\
+             \nid 1001: com/example/Thing\n\
+             \x20 allocated at: (no stack trace)\n\
+             \nid 1002: com/example/Thing\n\
+             \x20 allocated at: (stack trace 8 not found)\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn resolve_strings_shows_text() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/Person")
+            .utf8(2, "java/lang/String")
+            .utf8(10, "name")
+            .utf8(11, "bio")
+            .utf8(13, "value")
+            .utf8(14, "coder")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 103, 0, 2);
+
+        let long_text = "a".repeat(MAX_STRING_CHARS + 1);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass {
+                instance_fields: vec![(10, TYPE_OBJ), (11, TYPE_OBJ)],
+                ..TestClass::new(100, 0)
+            })
+            .class(&TestClass {
+                instance_fields: vec![(13, TYPE_OBJ), (14, TYPE_BYTE)],
+                ..TestClass::new(103, 0)
+            })
+            .instance(1000, 100, &[Val::Obj(2000), Val::Obj(2001)])
+            .instance(2000, 103, &[Val::Obj(3000), Val::Byte(0)])
+            .instance(2001, 103, &[Val::Obj(3001), Val::Byte(0)])
+            .byte_array(3000, b"Ada \"the\" Countess")
+            .byte_array(3001, long_text.as_bytes());
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            Some("com.example.Person"),
+            32,
+            true,
+            false,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            format!(
+                "\nid 1000: com/example/Person\n\
+                 \x20 - name = id 2000 (java/lang/String) \"Ada \\\"the\\\" Countess\"\n\
+                 \x20 - bio = id 2001 (java/lang/String) \"{}\"...\n",
+                "a".repeat(MAX_STRING_CHARS)
+            ),
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        dump_objects(
+            &hprof,
+            &ObjIdRange::all(),
+            Some("com.example.Person"),
+            32,
+            false,
+            false,
+            IdFormat::Dec,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "\nid 1000: com/example/Person\n\
+             \x20 - name = id 2000 (java/lang/String)\n\
+             \x20 - bio = id 2001 (java/lang/String)\n",
+            String::from_utf8(out).unwrap()
+        );
     }
 }
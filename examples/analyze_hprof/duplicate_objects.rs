@@ -0,0 +1,214 @@
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{borrow, collections, io};
+
+/// Write a CSV of groups of structurally identical instances (same class, byte-for-byte identical
+/// field values), most wasted bytes first.
+///
+/// Comparison is shallow: reference fields are equal only if they point to the same object.
+/// Float and double fields compare as with Java's `equals`, so NaNs with different bit patterns
+/// are the same, as per [FieldValue::to_hashable_bytes].
+/// A group's wasted bytes are the shallow sizes of all but one of its instances, since that's what
+/// sharing a single instance would save. Only groups of 2 or more instances are written, and if
+/// `top` is provided, at most that many groups.
+pub(crate) fn duplicate_objects<W: io::Write>(
+    hprof: &Hprof,
+    obj_id_range: &ObjIdRange,
+    top: Option<usize>,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let id_size = hprof.header().id_size();
+    let classes = ez_classes_by_obj_id(hprof);
+    let field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+
+    // (class obj id, field bytes) -> group
+    let mut groups: collections::HashMap<(Id, borrow::Cow<[u8]>), DuplicateGroup> =
+        collections::HashMap::new();
+
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for p in segment.sub_records() {
+            let s = p.unwrap();
+            match &s {
+                SubRecord::Instance(instance) if obj_id_range.contains(instance.obj_id()) => {
+                    let descriptors =
+                        instance_field_descriptors(&field_descriptors, instance.class_obj_id());
+                    groups
+                        .entry((
+                            instance.class_obj_id(),
+                            comparable_fields(instance, descriptors, id_size),
+                        ))
+                        .and_modify(|g| g.count += 1)
+                        .or_insert(DuplicateGroup {
+                            count: 1,
+                            instance_size: shallow_size(
+                                &s,
+                                |id| classes.get(&id).map(|c| c.instance_size_bytes),
+                                id_size,
+                            )
+                            .unwrap_or(0),
+                            example_obj_id: instance.obj_id(),
+                        });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Wasted bytes",
+        "Instance count",
+        "Instance size (bytes)",
+        "Class name",
+        "Class obj id",
+        "Example obj id",
+    ])?;
+
+    for ((class_obj_id, _fields), group) in groups
+        .iter()
+        .filter(|(_key, g)| g.count > 1)
+        .sorted_by(|(_, left), (_, right)| {
+            // reverse order to put the most waste on top
+            Ord::cmp(
+                &(right.wasted_bytes(), right.example_obj_id),
+                &(left.wasted_bytes(), left.example_obj_id),
+            )
+        })
+        .take(top.unwrap_or(usize::MAX))
+    {
+        wtr.write_record(&[
+            group.wasted_bytes().to_string(),
+            group.count.to_string(),
+            group.instance_size.to_string(),
+            classes
+                .get(class_obj_id)
+                .map(|c| c.name)
+                .unwrap_or("(unknown class)")
+                .to_owned(),
+            class_obj_id.to_string(),
+            group.example_obj_id.to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// The bytes to compare an instance's fields by: the field bytes as is, unless there are float or
+/// double fields, which need their NaNs canonicalized.
+///
+/// If the descriptors don't match the fields, the raw bytes are the best there is.
+fn comparable_fields<'a>(
+    instance: &Instance<'a>,
+    descriptors: &[FieldDescriptor],
+    id_size: IdSize,
+) -> borrow::Cow<'a, [u8]> {
+    let has_floats = descriptors
+        .iter()
+        .any(|fd| matches!(fd.field_type(), FieldType::Float | FieldType::Double));
+    if !has_floats {
+        return borrow::Cow::Borrowed(instance.fields());
+    }
+
+    let mut bytes = Vec::with_capacity(instance.fields().len());
+    for r in instance.field_values(descriptors, id_size) {
+        match r {
+            Ok((_fd, value)) => bytes.extend_from_slice(&value.to_hashable_bytes()),
+            Err(_) => return borrow::Cow::Borrowed(instance.fields()),
+        }
+    }
+
+    borrow::Cow::Owned(bytes)
+}
+
+struct DuplicateGroup {
+    count: u64,
+    instance_size: u64,
+    /// The first instance seen with this shape
+    example_obj_id: Id,
+}
+
+impl DuplicateGroup {
+    fn wasted_bytes(&self) -> u64 {
+        (self.count - 1) * self.instance_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn groups_identical_instances() {
+        let point_class = TestClass {
+            instance_fields: vec![(3, TYPE_INT), (4, TYPE_OBJ)],
+            instance_size_bytes: 24,
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "Point").load_class(1, 100, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&point_class)
+            .instance(1000, 100, &[Val::Int(1), Val::Obj(3000)])
+            .instance(1001, 100, &[Val::Int(1), Val::Obj(3000)])
+            // same int, different reference
+            .instance(1002, 100, &[Val::Int(1), Val::Obj(3001)])
+            .instance(1003, 100, &[Val::Int(1), Val::Obj(3000)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        duplicate_objects(&hprof, &ObjIdRange::all(), None, &mut out).unwrap();
+
+        assert_eq!(
+            "Wasted bytes,Instance count,Instance size (bytes),Class name,Class obj id,\
+             Example obj id\n\
+             48,3,24,Point,100,1000\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn nans_with_different_bits_are_duplicates() {
+        let reading_class = TestClass {
+            instance_fields: vec![(3, TYPE_FLOAT), (4, TYPE_INT)],
+            instance_size_bytes: 20,
+            ..TestClass::new(100, 0)
+        };
+        let other_nan = f32::from_bits(f32::NAN.to_bits() | 1);
+
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "Reading").load_class(1, 100, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&reading_class)
+            .instance(1000, 100, &[Val::Float(f32::NAN), Val::Int(1)])
+            .instance(1001, 100, &[Val::Float(other_nan), Val::Int(1)])
+            .instance(1002, 100, &[Val::Float(other_nan), Val::Int(2)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        duplicate_objects(&hprof, &ObjIdRange::all(), None, &mut out).unwrap();
+
+        assert_eq!(
+            "Wasted bytes,Instance count,Instance size (bytes),Class name,Class obj id,\
+             Example obj id\n\
+             20,2,20,Reading,100,1000\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
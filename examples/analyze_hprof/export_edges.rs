@@ -0,0 +1,245 @@
+use crate::util::*;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// Write every reference between objects as CSV, suitable for import into external graph tools.
+///
+/// GC roots have no source object, so their source id is left empty. A class's signers and
+/// protection domain get edge kinds of their own, since they're more about security than about
+/// what the class's code holds on to.
+///
+/// Only edges whose source object is in `obj_id_range` are written (or, for GC roots, whose
+/// destination is in the range).
+///
+/// If `label_weak` is set, the `referent` field of soft, weak, and phantom references is written
+/// as `weak` rather than `field`.
+pub(crate) fn export_edges<W: io::Write>(
+    hprof: &Hprof,
+    obj_id_range: &ObjIdRange,
+    label_weak: bool,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let classes = ez_classes_by_obj_id(hprof);
+    let class_instance_field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+    let referent_indices = if label_weak {
+        referent_field_indices(&classes, &utf8_strings_by_id(hprof))
+    } else {
+        collections::HashMap::new()
+    };
+    let id_size = hprof.header().id_size();
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["source_obj_id", "dest_obj_id", "edge_kind"])?;
+
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for source in segment.sub_records().reference_sources() {
+            match source.unwrap() {
+                ReferenceSource::GcRoot { obj_id, .. } if obj_id_range.contains(obj_id) => {
+                    write_root(&mut wtr, obj_id)?
+                }
+                ReferenceSource::Class(c) if obj_id_range.contains(c.obj_id()) => {
+                    if let Some(dest) = c.signers_obj_id() {
+                        write_edge(&mut wtr, c.obj_id(), dest, "signers")?;
+                    }
+                    if let Some(dest) = c.protection_domain_obj_id() {
+                        write_edge(&mut wtr, c.obj_id(), dest, "protection_domain")?;
+                    }
+                    for sf in c.static_fields().map(|r| r.unwrap()) {
+                        if let FieldValue::ObjectId(Some(dest)) = sf.value() {
+                            write_edge(&mut wtr, c.obj_id(), dest, "static")?;
+                        }
+                    }
+                }
+                ReferenceSource::Instance(instance) if obj_id_range.contains(instance.obj_id()) => {
+                    let field_descriptors = instance_field_descriptors(
+                        &class_instance_field_descriptors,
+                        instance.class_obj_id(),
+                    );
+
+                    let referent_index = referent_indices.get(&instance.class_obj_id());
+
+                    for (index, (_fd, field_val)) in
+                        instance_field_values(&instance, field_descriptors, id_size).enumerate()
+                    {
+                        if let FieldValue::ObjectId(Some(dest)) = field_val {
+                            let edge_kind = if referent_index == Some(&index) {
+                                "weak"
+                            } else {
+                                "field"
+                            };
+                            write_edge(&mut wtr, instance.obj_id(), dest, edge_kind)?;
+                        }
+                    }
+                }
+                ReferenceSource::ObjectArray(obj_array)
+                    if obj_id_range.contains(obj_array.obj_id()) =>
+                {
+                    for dest in obj_array.elements(id_size).filter_map(|r| r.unwrap()) {
+                        write_edge(&mut wtr, obj_array.obj_id(), dest, "array")?;
+                    }
+                }
+                // out of range
+                _ => {}
+            }
+        }
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+fn write_edge<W: io::Write>(
+    wtr: &mut csv::Writer<W>,
+    source: Id,
+    dest: Id,
+    edge_kind: &str,
+) -> Result<(), csv::Error> {
+    wtr.write_record(&[source.to_string(), dest.to_string(), edge_kind.to_string()])
+}
+
+fn write_root<W: io::Write>(wtr: &mut csv::Writer<W>, dest: Id) -> Result<(), csv::Error> {
+    wtr.write_record(&[String::new(), dest.to_string(), "root".to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn edges_for_tiny_heap() {
+        let hprof_bytes = tiny_heap();
+        let hprof = parse_hprof(&hprof_bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        export_edges(&hprof, &ObjIdRange::all(), false, &mut out).unwrap();
+
+        assert_eq!(
+            "source_obj_id,dest_obj_id,edge_kind\n\
+             ,100,root\n\
+             ,1000,root\n\
+             100,2000,static\n\
+             1000,2000,field\n\
+             2000,1000,array\n\
+             2000,3000,array\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn edges_for_obj_id_range() {
+        let hprof_bytes = tiny_heap();
+        let hprof = parse_hprof(&hprof_bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        export_edges(&hprof, &"1000..2000".parse().unwrap(), false, &mut out).unwrap();
+
+        // only the root of 1000 and the instance's field; the class and array are out of range
+        assert_eq!(
+            "source_obj_id,dest_obj_id,edge_kind\n\
+             ,1000,root\n\
+             1000,2000,field\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn edges_for_signers_and_protection_domain() {
+        let signed_class = TestClass {
+            signers_obj_id: 2000,
+            protection_domain_obj_id: 1000,
+            ..TestClass::new(100, 0)
+        };
+        // no statics, so only the protection domain edge
+        let unsigned_class = TestClass {
+            protection_domain_obj_id: 1000,
+            ..TestClass::new(101, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&signed_class)
+            .class(&unsigned_class)
+            .class(&TestClass::new(102, 0))
+            .instance(1000, 102, &[])
+            .object_array(2000, 102, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        export_edges(&hprof, &ObjIdRange::all(), false, &mut out).unwrap();
+
+        assert_eq!(
+            "source_obj_id,dest_obj_id,edge_kind\n\
+             100,2000,signers\n\
+             100,1000,protection_domain\n\
+             101,1000,protection_domain\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn edges_for_referent_are_weak_only_if_labelled() {
+        let mut builder = HprofBuilder::new();
+        builder.reference_classes();
+
+        let mut segment = builder.segment();
+        segment
+            .reference_classes(0)
+            .instance(
+                1000,
+                WEAK_REFERENCE_CLASS_OBJ_ID,
+                &[Val::Obj(2000), Val::Obj(3000)],
+            )
+            .byte_array(2000, &[0; 10])
+            .byte_array(3000, &[0; 10]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        export_edges(&hprof, &ObjIdRange::all(), false, &mut out).unwrap();
+        assert_eq!(
+            "source_obj_id,dest_obj_id,edge_kind\n\
+             1000,2000,field\n\
+             1000,3000,field\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        export_edges(&hprof, &ObjIdRange::all(), true, &mut out).unwrap();
+        assert_eq!(
+            "source_obj_id,dest_obj_id,edge_kind\n\
+             1000,2000,weak\n\
+             1000,3000,field\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    fn tiny_heap() -> Vec<u8> {
+        let node_class = TestClass {
+            static_fields: vec![(1, Val::Obj(2000)), (2, Val::Int(3))],
+            instance_fields: vec![(3, TYPE_OBJ), (4, TYPE_LONG), (5, TYPE_OBJ)],
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_system_class(100)
+            .gc_root_jni_global(1000, 1)
+            .class(&node_class)
+            .class(&TestClass::new(101, 0))
+            .instance(1000, 100, &[Val::Obj(2000), Val::Long(1), Val::Obj(0)])
+            .object_array(2000, 101, &[1000, 0, 3000])
+            .int_array(3000, &[1]);
+        builder.heap_dump_segment(&segment).heap_dump_end().build()
+    }
+}
@@ -0,0 +1,77 @@
+use crate::counter::Counter;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{cmp, io};
+
+/// Write a CSV of the number of GC root sub records of each kind, most common first.
+///
+/// Kinds that don't appear in the heap dump are left out. An object can be rooted more than once,
+/// e.g. by several stack frames, and each of those is counted.
+pub(crate) fn gc_root_counts<W: io::Write>(hprof: &Hprof, out: W) -> Result<(), anyhow::Error> {
+    let mut counts = Counter::<SubRecordTag>::new();
+
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for p in segment.sub_records() {
+            let sub_record = p.unwrap();
+            if sub_record.is_gc_root() {
+                counts.increment(sub_record.tag());
+            }
+        }
+    }
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["root_kind", "count"])?;
+
+    for (tag, count) in counts
+        .iter()
+        .map(|(tag, &count)| (format!("{:?}", tag), count))
+        .sorted_by(|(left_tag, left_count), (right_tag, right_count)| {
+            cmp::Ord::cmp(right_count, left_count).then_with(|| left_tag.cmp(right_tag))
+        })
+    {
+        wtr.write_record(&[tag, count.to_string()])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn counts_each_root_kind() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .gc_root_system_class(100)
+            .instance(1000, 100, &[])
+            .instance(1001, 100, &[])
+            .gc_root_unknown(1000)
+            .gc_root_unknown(1001)
+            // rooted twice
+            .gc_root_unknown(1001);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        gc_root_counts(&hprof, &mut out).unwrap();
+
+        assert_eq!(
+            "root_kind,count\n\
+            GcRootUnknown,3\n\
+            GcRootSystemClass,1\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
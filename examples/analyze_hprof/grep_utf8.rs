@@ -0,0 +1,86 @@
+use crate::util::*;
+use jvm_hprof::*;
+use std::io;
+
+/// Write a CSV of the `Utf8` records whose text matches `pattern`, in the order they appear.
+///
+/// Utf8 records hold class, field, and method names as well as other strings, so this is a quick
+/// way to find e.g. URLs or leaked secrets without walking the heap. Text that isn't valid UTF-8
+/// is matched with the invalid parts replaced by U+FFFD.
+pub(crate) fn grep_utf8<W: io::Write>(
+    hprof: &Hprof,
+    pattern: &regex::Regex,
+    id_format: IdFormat,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let id_size = hprof.header().id_size();
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Name id", "Contents"])?;
+
+    for u in hprof
+        .records_iter_filtered(&[RecordTag::Utf8])
+        .map(|r| r.unwrap().as_utf_8().unwrap().unwrap())
+    {
+        let text = String::from_utf8_lossy(u.text());
+        if pattern.is_match(&text) {
+            wtr.write_record(&[id_format.format(u.name_id(), id_size), text.into_owned()])?;
+        }
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// A regex matching `pattern` as a plain substring, or as a regex if `is_regex` is true.
+pub(crate) fn utf8_pattern(pattern: &str, is_regex: bool) -> Result<regex::Regex, regex::Error> {
+    if is_regex {
+        regex::Regex::new(pattern)
+    } else {
+        regex::Regex::new(&regex::escape(pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn prints_matching_records() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "java/lang/String")
+            .utf8(2, "https://example.com/api?token=abc")
+            .utf8(3, "count")
+            .utf8(4, "jdbc:postgresql://db.example.com/app");
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        let pattern = utf8_pattern("example.com", false).unwrap();
+        grep_utf8(&hprof, &pattern, IdFormat::Dec, &mut out).unwrap();
+        assert_eq!(
+            "Name id,Contents\n\
+             2,https://example.com/api?token=abc\n\
+             4,jdbc:postgresql://db.example.com/app\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // `.` is literal unless using a regex
+        let mut out = Vec::new();
+        let pattern = utf8_pattern("example.com/a.i", false).unwrap();
+        grep_utf8(&hprof, &pattern, IdFormat::Dec, &mut out).unwrap();
+        assert_eq!("Name id,Contents\n", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        let pattern = utf8_pattern("^https?://.*token=", true).unwrap();
+        grep_utf8(&hprof, &pattern, IdFormat::Dec, &mut out).unwrap();
+        assert_eq!(
+            "Name id,Contents\n\
+             2,https://example.com/api?token=abc\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
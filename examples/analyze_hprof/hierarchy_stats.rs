@@ -0,0 +1,101 @@
+use crate::counter::Counter;
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::*;
+use std::io;
+
+/// Print how many classes there are at each depth of the class hierarchy, then the `top` classes
+/// with the deepest hierarchies along with their superclasses.
+///
+/// A class's depth is its number of superclasses, so `java.lang.Object` has depth 0 and a class
+/// that directly extends it has depth 1.
+pub(crate) fn hierarchy_stats<W: io::Write>(
+    hprof: &Hprof,
+    top: usize,
+    mut out: W,
+) -> Result<(), anyhow::Error> {
+    let classes = ez_classes_by_obj_id(hprof);
+
+    let depths = classes
+        .values()
+        .map(|c| (c, super_classes(&classes, c.obj_id).count()))
+        .collect::<Vec<_>>();
+
+    let mut depth_counts: Counter<usize> = Counter::new();
+    for (_, depth) in depths.iter() {
+        depth_counts.increment(*depth);
+    }
+
+    writeln!(out, "Classes by hierarchy depth:")?;
+    for (depth, count) in depth_counts.iter().sorted() {
+        writeln!(out, "  {}: {}", depth, count)?;
+    }
+
+    writeln!(out, "\nDeepest hierarchies:")?;
+    for (class, depth) in depths
+        .iter()
+        // deepest first, then by name to be deterministic
+        .sorted_by_key(|(c, depth)| (usize::MAX - depth, c.name, c.obj_id))
+        .take(top)
+    {
+        writeln!(
+            out,
+            "  {}: {}",
+            depth,
+            std::iter::once(*class)
+                .chain(super_classes(&classes, class.obj_id))
+                .map(|c| c.name)
+                .join(" -> ")
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn three_level_hierarchy() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "java/lang/Object")
+            .utf8(2, "Animal")
+            .utf8(3, "Dog")
+            .utf8(4, "Cat")
+            .utf8(5, "Beagle")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4)
+            .load_class(5, 104, 0, 5);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 100))
+            .class(&TestClass::new(102, 101))
+            .class(&TestClass::new(103, 101))
+            .class(&TestClass::new(104, 102));
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        hierarchy_stats(&hprof, 2, &mut out).unwrap();
+
+        assert_eq!(
+            "Classes by hierarchy depth:\n\
+             \x20 0: 1\n\
+             \x20 1: 1\n\
+             \x20 2: 2\n\
+             \x20 3: 1\n\
+             \n\
+             Deepest hierarchies:\n\
+             \x20 3: Beagle -> Dog -> Animal -> java/lang/Object\n\
+             \x20 2: Cat -> Animal -> java/lang/Object\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -5,66 +5,230 @@ use std::{fs, io, marker, path};
 /// An [IndexSequenceBuilder] that delegates to [ChunkedRecordWriter] for the actual work
 pub(crate) struct ChunkedIndexSeqBuilder {
     dest: path::PathBuf,
+    contents: IndexContents,
+    keep_intermediates: bool,
+}
+
+impl ChunkedIndexSeqBuilder {
+    /// A writer factory for the chunks in `subdir`, or `None` if that chunk type isn't `enabled`
+    fn chunk_writer_factory(
+        &self,
+        subdir: &str,
+        enabled: bool,
+    ) -> Result<Option<DirWriterFactory>, io::Error> {
+        if !enabled {
+            return Ok(None);
+        }
+
+        let mut dest = self.dest.clone();
+        dest.push("chunks");
+        dest.push(subdir);
+        fs::create_dir_all(&dest)?;
+
+        Ok(Some(DirWriterFactory { dest }))
+    }
 }
 
 impl IndexSequenceBuilder for ChunkedIndexSeqBuilder {
-    type RecWriter = ChunkedRecordWriter<DirWriterFactory, U64PairData, U64U8Data>;
+    type RecWriter = ChunkedRecordWriter<
+        DirWriterFactory,
+        U64PairData,
+        U64U8Data,
+        U64U32Data,
+        U64U32PairData,
+        U64PairData,
+    >;
     type Seq = MergedFileIndexSequence;
 
-    fn new(dest: path::PathBuf) -> Result<Self, anyhow::Error> {
-        Ok(ChunkedIndexSeqBuilder { dest })
+    fn new(
+        dest: path::PathBuf,
+        contents: IndexContents,
+        keep_intermediates: bool,
+    ) -> Result<Self, anyhow::Error> {
+        // leftovers from an earlier build would otherwise be merged in with the new chunks
+        for subdir in ["chunks", "merge"].iter() {
+            let dir = dest.join(subdir);
+            if dir.is_dir() && fs::read_dir(&dir)?.next().is_some() {
+                return Err(anyhow::anyhow!(
+                    "{:?} isn't empty; remove it before building another index",
+                    dir
+                ));
+            }
+        }
+
+        Ok(ChunkedIndexSeqBuilder {
+            dest,
+            contents,
+            keep_intermediates,
+        })
     }
 
     fn record_writer(&self, record_index: usize) -> Result<Self::RecWriter, anyhow::Error> {
-        let mut class_dest = self.dest.clone();
-        class_dest.push("chunks");
-        class_dest.push(SUBDIR_OBJ_CLASS);
-        fs::create_dir_all(&class_dest)?;
-        let class_chunk_factory = DirWriterFactory { dest: class_dest };
-
-        let mut prim_type_dest = self.dest.clone();
-        prim_type_dest.push("chunks");
-        prim_type_dest.push(SUBDIR_OBJ_PRIM_ARRAY_TYPE);
-        fs::create_dir_all(&prim_type_dest)?;
-        let prim_array_chunk_factory = DirWriterFactory {
-            dest: prim_type_dest,
-        };
+        let prim_array_chunk_factory =
+            self.chunk_writer_factory(SUBDIR_OBJ_PRIM_ARRAY_TYPE, self.contents.prim_array_types)?;
+        let ref_count_chunk_factory =
+            self.chunk_writer_factory(SUBDIR_OBJ_REF_COUNT, self.contents.reference_counts)?;
+        let location_chunk_factory =
+            self.chunk_writer_factory(SUBDIR_OBJ_LOCATION, self.contents.locations)?;
+        let shallow_size_chunk_factory =
+            self.chunk_writer_factory(SUBDIR_OBJ_SHALLOW_SIZE, self.contents.shallow_sizes)?;
 
         Ok(ChunkedRecordWriter {
-            obj_class_chunk_writer: SortedChunkWriter::new(
-                record_index,
-                // 16M * 16 bytes per pair = 256MiB chunks
-                16 * 1024 * 1024,
-                class_chunk_factory,
-            ),
-            obj_prim_array_type_chunk_writer: SortedChunkWriter::new(
-                record_index,
-                // 28M * 9 bytes per pair = approx 256MiB chunks
-                28 * 1024 * 1024,
-                prim_array_chunk_factory,
-            ),
+            obj_class_chunk_writer: obj_class_chunk_writer(&self.dest, record_index)?,
+            obj_prim_array_type_chunk_writer: prim_array_chunk_factory.map(|f| {
+                SortedChunkWriter::new(
+                    record_index,
+                    // 28M * 9 bytes per pair = approx 256MiB chunks
+                    28 * 1024 * 1024,
+                    f,
+                )
+            }),
+            obj_ref_count_chunk_writer: ref_count_chunk_factory.map(|f| {
+                SortedChunkWriter::new(
+                    record_index,
+                    // 21M * 12 bytes per pair = approx 256MiB chunks
+                    21 * 1024 * 1024,
+                    f,
+                )
+            }),
+            obj_location_chunk_writer: location_chunk_factory.map(|f| {
+                SortedChunkWriter::new(
+                    record_index,
+                    // 16M * 16 bytes per entry = 256MiB chunks
+                    16 * 1024 * 1024,
+                    f,
+                )
+            }),
+            obj_shallow_size_chunk_writer: shallow_size_chunk_factory.map(|f| {
+                SortedChunkWriter::new(
+                    record_index,
+                    // 16M * 16 bytes per pair = 256MiB chunks
+                    16 * 1024 * 1024,
+                    f,
+                )
+            }),
         })
     }
 
     fn finalize(&self) -> Result<Self::Seq, anyhow::Error> {
-        println!("Merging obj id to class id files");
-        let merged_obj_class_file =
-            merge_chunk_type::<_, U64PairData>(&self.dest, SUBDIR_OBJ_CLASS)?;
-        println!("Merging obj id to primitive array type files");
-        let merged_obj_prim_type_file =
-            merge_chunk_type::<_, U64U8Data>(&self.dest, SUBDIR_OBJ_PRIM_ARRAY_TYPE)?;
+        eprintln!("Merging obj id to class id files");
+        let merged_obj_class_file = merge_chunk_type_or_empty::<_, U64PairData>(
+            &self.dest,
+            SUBDIR_OBJ_CLASS,
+            self.keep_intermediates,
+        )?;
+        eprintln!("Merging obj id to primitive array type files");
+        let merged_obj_prim_type_file = merge_chunk_type_or_empty::<_, U64U8Data>(
+            &self.dest,
+            SUBDIR_OBJ_PRIM_ARRAY_TYPE,
+            self.keep_intermediates,
+        )?;
+        eprintln!("Merging obj id to reference count files");
+        let merged_obj_ref_count_file = merge_chunk_type_or_empty::<_, U64U32Data>(
+            &self.dest,
+            SUBDIR_OBJ_REF_COUNT,
+            self.keep_intermediates,
+        )?;
+        eprintln!("Merging obj id to sub record location files");
+        let merged_obj_location_file = merge_chunk_type_or_empty::<_, U64U32PairData>(
+            &self.dest,
+            SUBDIR_OBJ_LOCATION,
+            self.keep_intermediates,
+        )?;
+        eprintln!("Merging obj id to shallow size files");
+        let merged_obj_shallow_size_file = merge_chunk_type_or_empty::<_, U64PairData>(
+            &self.dest,
+            SUBDIR_OBJ_SHALLOW_SIZE,
+            self.keep_intermediates,
+        )?;
 
         Ok(MergedFileIndexSequence {
             obj_id_class_id_file: merged_obj_class_file,
             obj_id_prim_array_type_file: merged_obj_prim_type_file,
+            obj_id_ref_count_file: merged_obj_ref_count_file,
+            obj_id_location_file: merged_obj_location_file,
+            obj_id_shallow_size_file: merged_obj_shallow_size_file,
+            contents: self.contents,
         })
     }
 }
 
+/// Like [merge_chunk_type], but if there are no chunks in `subdir` (either because that chunk type
+/// was skipped, or there was nothing of that type in the hprof), create an empty file instead.
+fn merge_chunk_type_or_empty<T, W: DatumDeserializer<T> + DatumSerializer<T> + Send + Sync>(
+    index_dir: &path::Path,
+    subdir: &str,
+    keep_inputs: bool,
+) -> Result<path::PathBuf, anyhow::Error> {
+    let mut chunks_dir = index_dir.to_path_buf();
+    chunks_dir.push("chunks");
+    chunks_dir.push(subdir);
+
+    if chunks_dir.is_dir() && fs::read_dir(&chunks_dir)?.next().is_some() {
+        return merge_chunk_type::<T, W>(index_dir, subdir, keep_inputs);
+    }
+
+    let mut empty_dir = index_dir.to_path_buf();
+    empty_dir.push("merge");
+    empty_dir.push(subdir);
+    fs::create_dir_all(&empty_dir)?;
+
+    let empty_file = empty_dir.join("empty");
+    fs::File::create(&empty_file)?;
+
+    Ok(empty_file)
+}
+
+fn obj_class_chunk_writer(
+    dest: &path::Path,
+    record_index: usize,
+) -> Result<SortedChunkWriter<DirWriterFactory, (u64, u64), U64PairData>, io::Error> {
+    let mut class_dest = dest.to_path_buf();
+    class_dest.push("chunks");
+    class_dest.push(SUBDIR_OBJ_CLASS);
+    fs::create_dir_all(&class_dest)?;
+
+    Ok(SortedChunkWriter::new(
+        record_index,
+        // 16M * 16 bytes per pair = 256MiB chunks
+        16 * 1024 * 1024,
+        DirWriterFactory { dest: class_dest },
+    ))
+}
+
+/// Writes only obj id to class id chunks, for when none of the rest of the index is needed.
+///
+/// The chunks are written to the same place as [ChunkedIndexSeqBuilder] would write them, so they
+/// can be merged the same way.
+pub(crate) struct ObjClassChunkWriter {
+    writer: SortedChunkWriter<DirWriterFactory, (u64, u64), U64PairData>,
+}
+
+impl ObjClassChunkWriter {
+    pub(crate) fn new(dest: &path::Path, record_index: usize) -> Result<Self, io::Error> {
+        Ok(ObjClassChunkWriter {
+            writer: obj_class_chunk_writer(dest, record_index)?,
+        })
+    }
+
+    pub(crate) fn write_class_id(&mut self, obj_id: Id, class_id: Id) -> Result<(), io::Error> {
+        self.writer.append((obj_id.id(), class_id.id()))
+    }
+
+    /// Write any remaining partial chunk
+    pub(crate) fn flush(mut self) -> Result<(), io::Error> {
+        self.writer.flush()
+    }
+}
+
 /// An [IndexSequence] that reads from the merge-sorted files generated by [ChunkedIndexSeqBuilder].
 pub(crate) struct MergedFileIndexSequence {
     obj_id_class_id_file: path::PathBuf,
     obj_id_prim_array_type_file: path::PathBuf,
+    obj_id_ref_count_file: path::PathBuf,
+    obj_id_location_file: path::PathBuf,
+    obj_id_shallow_size_file: path::PathBuf,
+    contents: IndexContents,
 }
 
 impl IndexSequence for MergedFileIndexSequence {
@@ -72,6 +236,12 @@ impl IndexSequence for MergedFileIndexSequence {
         ChunkDatumIterator<io::BufReader<fs::File>, (u64, u64), U64PairData>;
     type ObjIdPrimArrayTypeIterator =
         ChunkDatumIterator<io::BufReader<fs::File>, (u64, u8), U64U8Data>;
+    type ObjIdRefCountIterator =
+        ChunkDatumIterator<io::BufReader<fs::File>, (u64, u32), U64U32Data>;
+    type ObjIdLocationIterator =
+        ChunkDatumIterator<io::BufReader<fs::File>, (u64, (u32, u32)), U64U32PairData>;
+    type ObjIdShallowSizeIterator =
+        ChunkDatumIterator<io::BufReader<fs::File>, (u64, u64), U64PairData>;
 
     fn iter_obj_id_class_id(&self) -> Result<Self::ObjIdClassIdIterator, anyhow::Error> {
         Ok(ChunkDatumIterator::new(io::BufReader::new(fs::File::open(
@@ -87,30 +257,71 @@ impl IndexSequence for MergedFileIndexSequence {
         )?)))
     }
 
+    fn iter_obj_id_reference_count(&self) -> Result<Self::ObjIdRefCountIterator, anyhow::Error> {
+        Ok(ChunkDatumIterator::new(io::BufReader::new(fs::File::open(
+            &self.obj_id_ref_count_file,
+        )?)))
+    }
+
+    fn iter_obj_id_location(&self) -> Result<Self::ObjIdLocationIterator, anyhow::Error> {
+        Ok(ChunkDatumIterator::new(io::BufReader::new(fs::File::open(
+            &self.obj_id_location_file,
+        )?)))
+    }
+
+    fn iter_obj_id_shallow_size(&self) -> Result<Self::ObjIdShallowSizeIterator, anyhow::Error> {
+        Ok(ChunkDatumIterator::new(io::BufReader::new(fs::File::open(
+            &self.obj_id_shallow_size_file,
+        )?)))
+    }
+
+    fn contents(&self) -> IndexContents {
+        self.contents
+    }
+
     fn remove_tmp_files(self) -> Result<(), io::Error> {
         fs::remove_file(&self.obj_id_class_id_file)
             .and_then(|_| fs::remove_file(&self.obj_id_prim_array_type_file))
+            .and_then(|_| fs::remove_file(&self.obj_id_ref_count_file))
+            .and_then(|_| fs::remove_file(&self.obj_id_location_file))
+            .and_then(|_| fs::remove_file(&self.obj_id_shallow_size_file))
     }
 }
 
+/// A sub record's (record index, offset)
+type Location = (u32, u32);
+
 /// Write per-Record data into sorted chunks
-pub(crate) struct ChunkedRecordWriter<F, D1, D2>
+pub(crate) struct ChunkedRecordWriter<F, D1, D2, D3, D4, D5>
 where
     F: ChunkWriterFactory,
     // obj id -> class id
     D1: DatumSerializer<(u64, u64)>,
     // obj id -> prim type
     D2: DatumSerializer<(u64, u8)>,
+    // obj id -> ref count
+    D3: DatumSerializer<(u64, u32)>,
+    // obj id -> (record index, offset)
+    D4: DatumSerializer<(u64, (u32, u32))>,
+    // obj id -> shallow size
+    D5: DatumSerializer<(u64, u64)>,
 {
     obj_class_chunk_writer: SortedChunkWriter<F, (u64, u64), D1>,
-    obj_prim_array_type_chunk_writer: SortedChunkWriter<F, (u64, u8), D2>,
+    // the rest are None if their chunk type was skipped
+    obj_prim_array_type_chunk_writer: Option<SortedChunkWriter<F, (u64, u8), D2>>,
+    obj_ref_count_chunk_writer: Option<SortedChunkWriter<F, (u64, u32), D3>>,
+    obj_location_chunk_writer: Option<SortedChunkWriter<F, (u64, Location), D4>>,
+    obj_shallow_size_chunk_writer: Option<SortedChunkWriter<F, (u64, u64), D5>>,
 }
 
-impl<F, D1, D2> RecordWriter for ChunkedRecordWriter<F, D1, D2>
+impl<F, D1, D2, D3, D4, D5> RecordWriter for ChunkedRecordWriter<F, D1, D2, D3, D4, D5>
 where
     F: ChunkWriterFactory,
     D1: DatumSerializer<(u64, u64)>,
     D2: DatumSerializer<(u64, u8)>,
+    D3: DatumSerializer<(u64, u32)>,
+    D4: DatumSerializer<(u64, (u32, u32))>,
+    D5: DatumSerializer<(u64, u64)>,
 {
     fn write_class_id(&mut self, obj_id: Id, class_id: Id) -> Result<(), anyhow::Error> {
         self.obj_class_chunk_writer
@@ -124,13 +335,53 @@ where
         prim_array_type: PrimitiveArrayType,
     ) -> Result<(), anyhow::Error> {
         self.obj_prim_array_type_chunk_writer
-            .append((obj_id.id(), prim_array_type.type_code()))
-            .map_err(|e| anyhow::Error::from(e))
+            .as_mut()
+            .map_or(Ok(()), |w| {
+                w.append((obj_id.id(), prim_array_type.type_code()))
+            })
+            .map_err(anyhow::Error::from)
+    }
+
+    fn write_reference_count(&mut self, obj_id: Id, ref_count: u32) -> Result<(), anyhow::Error> {
+        self.obj_ref_count_chunk_writer
+            .as_mut()
+            .map_or(Ok(()), |w| w.append((obj_id.id(), ref_count)))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn write_location(
+        &mut self,
+        obj_id: Id,
+        record_index: u32,
+        offset: u32,
+    ) -> Result<(), anyhow::Error> {
+        self.obj_location_chunk_writer
+            .as_mut()
+            .map_or(Ok(()), |w| w.append((obj_id.id(), (record_index, offset))))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn write_shallow_size(&mut self, obj_id: Id, size: u64) -> Result<(), anyhow::Error> {
+        self.obj_shallow_size_chunk_writer
+            .as_mut()
+            .map_or(Ok(()), |w| w.append((obj_id.id(), size)))
+            .map_err(anyhow::Error::from)
     }
 
     fn flush(mut self) -> Result<(), anyhow::Error> {
         self.obj_class_chunk_writer.flush()?;
-        self.obj_prim_array_type_chunk_writer.flush()?;
+        if let Some(w) = self.obj_prim_array_type_chunk_writer.as_mut() {
+            w.flush()?;
+        }
+        if let Some(w) = self.obj_ref_count_chunk_writer.as_mut() {
+            w.flush()?;
+        }
+        if let Some(w) = self.obj_location_chunk_writer.as_mut() {
+            w.flush()?;
+        }
+        if let Some(w) = self.obj_shallow_size_chunk_writer.as_mut() {
+            w.flush()?;
+        }
 
         Ok(())
     }
@@ -296,6 +547,82 @@ impl DatumDeserializer<(u64, u8)> for U64U8Data {
     }
 }
 
+/// For (u64, u32) as used for reference counts
+pub(crate) struct U64U32Data;
+
+impl DatumSerializer<(u64, u32)> for U64U32Data {
+    type SortKey = u64;
+
+    fn extract_key(datum: &(u64, u32)) -> Self::SortKey {
+        datum.0
+    }
+
+    fn serialize<W: io::Write>(datum: &(u64, u32), writer: &mut W) -> Result<(), io::Error> {
+        writer
+            .write_all(&datum.0.to_le_bytes())
+            .and_then(|_| writer.write_all(&datum.1.to_le_bytes()))
+    }
+}
+
+impl DatumDeserializer<(u64, u32)> for U64U32Data {
+    fn deserialize<R: io::Read>(reader: &mut R) -> Option<Result<(u64, u32), io::Error>> {
+        let mut buf = [0_u8; 12];
+        match reader.read_exact(&mut buf[..]) {
+            Ok(_) => { /* no op */ }
+            Err(e) => {
+                return match e.kind() {
+                    // TODO error if there are leftover bytes
+                    io::ErrorKind::UnexpectedEof => None,
+                    _ => Some(Err(e)),
+                };
+            }
+        }
+        let key = u64::from_le_bytes(buf[0..8].try_into().unwrap());
+        let value = u32::from_le_bytes(buf[8..].try_into().unwrap());
+
+        Some(Ok((key, value)))
+    }
+}
+
+/// For (u64, (u32, u32)) as used for sub record locations
+pub(crate) struct U64U32PairData;
+
+impl DatumSerializer<(u64, (u32, u32))> for U64U32PairData {
+    type SortKey = u64;
+
+    fn extract_key(datum: &(u64, (u32, u32))) -> Self::SortKey {
+        datum.0
+    }
+
+    fn serialize<W: io::Write>(datum: &(u64, (u32, u32)), writer: &mut W) -> Result<(), io::Error> {
+        writer
+            .write_all(&datum.0.to_le_bytes())
+            .and_then(|_| writer.write_all(&(datum.1).0.to_le_bytes()))
+            .and_then(|_| writer.write_all(&(datum.1).1.to_le_bytes()))
+    }
+}
+
+impl DatumDeserializer<(u64, (u32, u32))> for U64U32PairData {
+    fn deserialize<R: io::Read>(reader: &mut R) -> Option<Result<(u64, (u32, u32)), io::Error>> {
+        let mut buf = [0_u8; 16];
+        match reader.read_exact(&mut buf[..]) {
+            Ok(_) => { /* no op */ }
+            Err(e) => {
+                return match e.kind() {
+                    // TODO error if there are leftover bytes
+                    io::ErrorKind::UnexpectedEof => None,
+                    _ => Some(Err(e)),
+                };
+            }
+        }
+        let key = u64::from_le_bytes(buf[0..8].try_into().unwrap());
+        let record_index = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        let offset = u32::from_le_bytes(buf[12..].try_into().unwrap());
+
+        Some(Ok((key, (record_index, offset))))
+    }
+}
+
 pub(crate) trait ChunkWriterFactory {
     type Writer: io::Write;
 
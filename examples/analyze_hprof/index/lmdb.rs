@@ -1,8 +1,9 @@
 use crate::index::{
-    build_if_fingerprint_match, HprofFingerprint, Index, IndexBuilder, IndexSequence,
+    build_if_fingerprint_match, require_mapping, HprofFingerprint, Index, IndexBuilder,
+    IndexContents, IndexSequence,
 };
 
-use std::{fs, io, path};
+use std::{collections, fs, io, path};
 
 use anyhow::Context;
 use itertools::Itertools;
@@ -16,19 +17,222 @@ use std::io::Write;
 // fingerprint keys
 const FP_TIMESTAMP: &str = "__hprof_header_fingerprint_timestamp";
 const FP_RECORD_COUNT: &str = "__hprof_header_fingerprint_record_count";
+// which optional mappings were written, as per IndexContents
+const INDEX_CONTENTS: &str = "__index_contents";
 
 // tree names
 const DB_METADATA: &str = "metadata";
 const DB_OBJ_ID_CLASS_ID: &str = "obj_id_class_id";
 const DB_OBJ_ID_PRIM_TYPE: &str = "obj_id_prim_type";
+const DB_OBJ_ID_REF_COUNT: &str = "obj_id_ref_count";
+const DB_OBJ_ID_LOCATION: &str = "obj_id_location";
+const DB_OBJ_ID_SHALLOW_SIZE: &str = "obj_id_shallow_size";
+// absent in indexes from older versions
+const DB_CLASS_ID_INSTANCE_COUNT: &str = "class_id_instance_count";
+// only present once retained sizes have been written
+const DB_OBJ_ID_RETAINED_SIZE: &str = "obj_id_retained_size";
+
+const MAX_DBS: u32 = 8;
 
 pub(crate) struct LmdbIndex {
     env: lmdb::Environment,
+    contents: IndexContents,
     obj_id_class_id_db: lmdb::Database,
     obj_id_prim_array_type_db: lmdb::Database,
+    obj_id_ref_count_db: lmdb::Database,
+    obj_id_location_db: lmdb::Database,
+    obj_id_shallow_size_db: lmdb::Database,
+    class_id_instance_count_db: Option<lmdb::Database>,
+    obj_id_retained_size_db: Option<lmdb::Database>,
 }
 
 impl Index for LmdbIndex {
+    fn get_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(self.obj_id_class_id_db, &obj_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| {
+                    Id::from(u64::from_be_bytes(
+                        bytes.try_into().expect("Invalid index value"),
+                    ))
+                })
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|id| txn.commit().map(|_| id))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn get_class_ids(&self, obj_ids: &[Id]) -> Result<Vec<Option<Id>>, anyhow::Error> {
+        let txn = self.env.begin_ro_txn()?;
+
+        // visiting keys in order keeps consecutive lookups on nearby pages
+        let mut lookup_order = (0..obj_ids.len()).collect_vec();
+        lookup_order.sort_unstable_by_key(|&i| obj_ids[i]);
+
+        let mut class_ids = vec![None; obj_ids.len()];
+        for i in lookup_order {
+            class_ids[i] = txn
+                .get_opt(self.obj_id_class_id_db, &obj_ids[i].id().to_be_bytes())?
+                .map(|bytes| {
+                    Id::from(u64::from_be_bytes(
+                        bytes.try_into().expect("Invalid index value"),
+                    ))
+                });
+        }
+
+        txn.commit()?;
+
+        Ok(class_ids)
+    }
+
+    fn get_prim_array_type(&self, obj_id: Id) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
+        require_mapping(self.contents.prim_array_types, "primitive array types")?;
+
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(self.obj_id_prim_array_type_db, &obj_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| {
+                    PrimitiveArrayType::from_type_code(bytes[0]).expect("Invalid index value")
+                })
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|id| txn.commit().map(|_| id))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn reference_count(&self, obj_id: Id) -> Result<Option<u32>, anyhow::Error> {
+        require_mapping(self.contents.reference_counts, "reference counts")?;
+
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(self.obj_id_ref_count_db, &obj_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| u32::from_be_bytes(bytes.try_into().expect("Invalid index value")))
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|count| txn.commit().map(|_| count))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn get_location(&self, obj_id: Id) -> Result<Option<(u32, u32)>, anyhow::Error> {
+        require_mapping(self.contents.locations, "sub record locations")?;
+
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(self.obj_id_location_db, &obj_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| {
+                    (
+                        u32::from_be_bytes(bytes[0..4].try_into().expect("Invalid index value")),
+                        u32::from_be_bytes(bytes[4..].try_into().expect("Invalid index value")),
+                    )
+                })
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|location| txn.commit().map(|_| location))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn get_shallow_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        require_mapping(self.contents.shallow_sizes, "shallow sizes")?;
+
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(self.obj_id_shallow_size_db, &obj_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| u64::from_be_bytes(bytes.try_into().expect("Invalid index value")))
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|size| txn.commit().map(|_| size))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn instance_count(&self, class_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        let db = self.class_id_instance_count_db.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Index has no instance counts (indexes from older versions must be rebuilt)"
+            )
+        })?;
+
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(db, &class_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| u64::from_be_bytes(bytes.try_into().expect("Invalid index value")))
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|count| txn.commit().map(|_| count))
+            .map_err(anyhow::Error::from)
+    }
+
+    fn get_retained_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        let db = match self.obj_id_retained_size_db {
+            Some(db) => db,
+            None => return Ok(None),
+        };
+
+        let txn = self.env.begin_ro_txn()?;
+
+        txn.get_opt(db, &obj_id.id().to_be_bytes())
+            .map(|opt| {
+                opt.map(|bytes| u64::from_be_bytes(bytes.try_into().expect("Invalid index value")))
+            })
+            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
+            .and_then(|size| txn.commit().map(|_| size))
+            .map_err(anyhow::Error::from)
+    }
+}
+
+impl LmdbIndex {
+    /// Write `(obj id, retained size)` pairs, in increasing obj id order, to the index at
+    /// `index_path`, replacing any retained sizes written before.
+    ///
+    /// Unlike the rest of the index, retained sizes need the whole object graph, so they're
+    /// written to an existing index rather than built from an [IndexSequence].
+    pub(crate) fn write_retained_sizes<I: IntoIterator<Item = (Id, u64)>>(
+        fingerprint: &HprofFingerprint,
+        index_path: &path::Path,
+        retained_sizes: I,
+    ) -> Result<(), anyhow::Error> {
+        // make sure the index is for the same hprof before changing anything
+        drop(LmdbIndex::open_with_fingerprint(fingerprint, index_path)?);
+
+        let mut lmdb_dir = index_path.to_path_buf();
+        lmdb_dir.push("lmdb");
+
+        let env = lmdb::Environment::new()
+            .set_map_size(1024 * 1024 * 1024 * 1024)
+            .set_max_dbs(MAX_DBS)
+            .open(&lmdb_dir)?;
+        let obj_id_retained_size_db = env.create_db(
+            Some(DB_OBJ_ID_RETAINED_SIZE),
+            lmdb::DatabaseFlags::default(),
+        )?;
+
+        let mut txn = env.begin_rw_txn()?;
+        txn.clear_db(obj_id_retained_size_db)?;
+
+        {
+            let mut cursor = txn.open_rw_cursor(obj_id_retained_size_db)?;
+
+            for (obj_id, size) in retained_sizes {
+                cursor.put(
+                    &obj_id.id().to_be_bytes(),
+                    &size.to_be_bytes(),
+                    lmdb::WriteFlags::APPEND,
+                )?;
+            }
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }
+}
+
+impl IndexBuilder for LmdbIndex {
     fn open_with_fingerprint(
         fingerprint: &HprofFingerprint,
         index_path: &path::Path,
@@ -38,7 +242,7 @@ impl Index for LmdbIndex {
 
         let env = lmdb::Environment::new()
             .set_flags(lmdb::EnvironmentFlags::READ_ONLY)
-            .set_max_dbs(3)
+            .set_max_dbs(MAX_DBS)
             .open(&lmdb_dir)?;
 
         let metadata_db = env
@@ -46,62 +250,58 @@ impl Index for LmdbIndex {
             .with_context(|| "Opening metadata DB")?;
         let obj_id_class_id_db = env.open_db(Some(DB_OBJ_ID_CLASS_ID))?;
         let obj_id_prim_array_type_db = env.open_db(Some(DB_OBJ_ID_PRIM_TYPE))?;
+        let obj_id_ref_count_db = env.open_db(Some(DB_OBJ_ID_REF_COUNT)).with_context(|| {
+            "Opening reference count DB (indexes from older versions must be rebuilt)"
+        })?;
+        let obj_id_location_db = env.open_db(Some(DB_OBJ_ID_LOCATION)).with_context(|| {
+            "Opening sub record location DB (indexes from older versions must be rebuilt)"
+        })?;
+        let obj_id_shallow_size_db =
+            env.open_db(Some(DB_OBJ_ID_SHALLOW_SIZE)).with_context(|| {
+                "Opening shallow size DB (indexes from older versions must be rebuilt)"
+            })?;
+        // only needed for instance counts, so older indexes without it can still be used for
+        // everything else
+        let class_id_instance_count_db = match env.open_db(Some(DB_CLASS_ID_INSTANCE_COUNT)) {
+            Ok(db) => Some(db),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let obj_id_retained_size_db = match env.open_db(Some(DB_OBJ_ID_RETAINED_SIZE)) {
+            Ok(db) => Some(db),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
 
         let txn = env.begin_ro_txn()?;
 
         let ts = txn
             .get_opt(metadata_db, &FP_TIMESTAMP)?
             // clone the data so we can commit the txn before moving env into the LmdbIndex
-            .map(|slice| slice.iter().map(|&b| b).collect_vec());
+            .map(|slice| slice.iter().copied().collect_vec());
         let record_count = txn
             .get_opt(metadata_db, &FP_RECORD_COUNT)?
-            .map(|slice| slice.iter().map(|&b| b).collect_vec());
+            .map(|slice| slice.iter().copied().collect_vec());
+        let contents =
+            IndexContents::from_stored_flags(txn.get_opt(metadata_db, &INDEX_CONTENTS)?)?;
 
         txn.commit()?;
 
-        let res = build_if_fingerprint_match(fingerprint, ts, record_count, || {
+        build_if_fingerprint_match(fingerprint, ts, record_count, || {
             Ok(LmdbIndex {
                 env,
+                contents,
                 obj_id_class_id_db,
                 obj_id_prim_array_type_db,
+                obj_id_ref_count_db,
+                obj_id_location_db,
+                obj_id_shallow_size_db,
+                class_id_instance_count_db,
+                obj_id_retained_size_db,
             })
-        });
-
-        res
-    }
-
-    fn get_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
-        let txn = self.env.begin_ro_txn()?;
-
-        txn.get_opt(self.obj_id_class_id_db, &obj_id.id().to_be_bytes())
-            .map(|opt| {
-                opt.map(|bytes| {
-                    Id::from(u64::from_be_bytes(
-                        bytes.try_into().expect("Invalid index value"),
-                    ))
-                })
-            })
-            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
-            .and_then(|id| txn.commit().map(|_| id))
-            .map_err(|e| anyhow::Error::from(e))
-    }
-
-    fn get_prim_array_type(&self, obj_id: Id) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
-        let txn = self.env.begin_ro_txn()?;
-
-        txn.get_opt(self.obj_id_prim_array_type_db, &obj_id.id().to_be_bytes())
-            .map(|opt| {
-                opt.map(|bytes| {
-                    PrimitiveArrayType::from_type_code(bytes[0]).expect("Invalid index value")
-                })
-            })
-            // txn will commit in its Drop impl but might as well be explicit if we haven't already errored out
-            .and_then(|id| txn.commit().map(|_| id))
-            .map_err(|e| anyhow::Error::from(e))
+        })
     }
-}
 
-impl IndexBuilder for LmdbIndex {
     fn build_index<S: IndexSequence>(
         seq: &S,
         fingerprint: &HprofFingerprint,
@@ -115,7 +315,7 @@ impl IndexBuilder for LmdbIndex {
         let env = lmdb::Environment::new()
             // a terabyte would be a very big index indeed
             .set_map_size(1024 * 1024 * 1024 * 1024)
-            .set_max_dbs(3)
+            .set_max_dbs(MAX_DBS)
             .open(&lmdb_dir)?;
 
         // TODO report bug: opening a db after opening a txn hangs
@@ -124,6 +324,16 @@ impl IndexBuilder for LmdbIndex {
             env.create_db(Some(DB_OBJ_ID_CLASS_ID), lmdb::DatabaseFlags::default())?;
         let obj_id_prim_type_db =
             env.create_db(Some(DB_OBJ_ID_PRIM_TYPE), lmdb::DatabaseFlags::default())?;
+        let obj_id_ref_count_db =
+            env.create_db(Some(DB_OBJ_ID_REF_COUNT), lmdb::DatabaseFlags::default())?;
+        let obj_id_location_db =
+            env.create_db(Some(DB_OBJ_ID_LOCATION), lmdb::DatabaseFlags::default())?;
+        let obj_id_shallow_size_db =
+            env.create_db(Some(DB_OBJ_ID_SHALLOW_SIZE), lmdb::DatabaseFlags::default())?;
+        let class_id_instance_count_db = env.create_db(
+            Some(DB_CLASS_ID_INSTANCE_COUNT),
+            lmdb::DatabaseFlags::default(),
+        )?;
 
         let mut txn = env.begin_rw_txn()?;
 
@@ -140,15 +350,26 @@ impl IndexBuilder for LmdbIndex {
             &fingerprint.record_count.to_be_bytes(),
             lmdb::WriteFlags::default(),
         )?;
+        txn.put(
+            metadata_db,
+            &INDEX_CONTENTS,
+            &[seq.contents().to_flags()],
+            lmdb::WriteFlags::default(),
+        )?;
 
         let mut count_since_last_print = 0_u64;
         let print_threshold = 1_000_000;
 
+        // class id -> instance count, accumulated while writing class ids. Sorted so that it can
+        // be appended like everything else.
+        let mut instance_counts = collections::BTreeMap::<u64, u64>::new();
+
         {
             let mut cursor = txn.open_rw_cursor(obj_id_class_id_db)?;
 
             for res in seq.iter_obj_id_class_id()? {
                 let (key, value): (u64, u64) = res?;
+                *instance_counts.entry(value).or_default() += 1;
                 cursor.put(
                     &key.to_be_bytes(),
                     &value.to_be_bytes(),
@@ -157,8 +378,8 @@ impl IndexBuilder for LmdbIndex {
                 count_since_last_print += 1;
 
                 if count_since_last_print == print_threshold {
-                    print!(".");
-                    io::stdout().flush()?;
+                    eprint!(".");
+                    io::stderr().flush()?;
                     count_since_last_print = 0;
                 }
             }
@@ -173,13 +394,84 @@ impl IndexBuilder for LmdbIndex {
                 count_since_last_print += 1;
 
                 if count_since_last_print == print_threshold {
-                    print!(".");
-                    io::stdout().flush()?;
+                    eprint!(".");
+                    io::stderr().flush()?;
+                    count_since_last_print = 0;
+                }
+            }
+        }
+
+        {
+            let mut cursor = txn.open_rw_cursor(obj_id_ref_count_db)?;
+
+            for res in seq.iter_obj_id_reference_count()? {
+                let (key, value): (u64, u32) = res?;
+                cursor.put(
+                    &key.to_be_bytes(),
+                    &value.to_be_bytes(),
+                    lmdb::WriteFlags::APPEND,
+                )?;
+                count_since_last_print += 1;
+
+                if count_since_last_print == print_threshold {
+                    eprint!(".");
+                    io::stderr().flush()?;
                     count_since_last_print = 0;
                 }
             }
         }
 
+        {
+            let mut cursor = txn.open_rw_cursor(obj_id_location_db)?;
+
+            for res in seq.iter_obj_id_location()? {
+                let (key, (record_index, offset)): (u64, (u32, u32)) = res?;
+                let mut value = [0_u8; 8];
+                value[0..4].copy_from_slice(&record_index.to_be_bytes());
+                value[4..].copy_from_slice(&offset.to_be_bytes());
+                cursor.put(&key.to_be_bytes(), &value, lmdb::WriteFlags::APPEND)?;
+                count_since_last_print += 1;
+
+                if count_since_last_print == print_threshold {
+                    eprint!(".");
+                    io::stderr().flush()?;
+                    count_since_last_print = 0;
+                }
+            }
+        }
+
+        {
+            let mut cursor = txn.open_rw_cursor(obj_id_shallow_size_db)?;
+
+            for res in seq.iter_obj_id_shallow_size()? {
+                let (key, value): (u64, u64) = res?;
+                cursor.put(
+                    &key.to_be_bytes(),
+                    &value.to_be_bytes(),
+                    lmdb::WriteFlags::APPEND,
+                )?;
+                count_since_last_print += 1;
+
+                if count_since_last_print == print_threshold {
+                    eprint!(".");
+                    io::stderr().flush()?;
+                    count_since_last_print = 0;
+                }
+            }
+        }
+
+        {
+            let mut cursor = txn.open_rw_cursor(class_id_instance_count_db)?;
+
+            for (class_id, count) in instance_counts {
+                cursor.put(
+                    &class_id.to_be_bytes(),
+                    &count.to_be_bytes(),
+                    lmdb::WriteFlags::APPEND,
+                )?;
+            }
+        }
+
         txn.commit()?;
 
         Ok(())
@@ -223,6 +515,9 @@ mod tests {
     fn build_index_from_seq() -> Result<(), anyhow::Error> {
         let mut obj_id_class_id = Vec::<(u64, u64)>::new();
         let mut obj_id_prim_array_type = Vec::<(u64, u8)>::new();
+        let mut obj_id_ref_count = Vec::<(u64, u32)>::new();
+        let mut obj_id_location = Vec::<(u64, (u32, u32))>::new();
+        let mut obj_id_shallow_size = Vec::<(u64, u64)>::new();
 
         let mut rng = rand::thread_rng();
         let array_types = PrimitiveArrayType::iter().collect_vec();
@@ -231,15 +526,24 @@ mod tests {
             obj_id_class_id.push((rng.gen(), rng.gen()));
             obj_id_prim_array_type
                 .push((rng.gen(), array_types.choose(&mut rng).unwrap().type_code()));
+            obj_id_ref_count.push((rng.gen(), rng.gen()));
+            obj_id_location.push((rng.gen(), (rng.gen(), rng.gen())));
+            obj_id_shallow_size.push((rng.gen(), rng.gen()));
         }
 
         // seq data must be sorted
         obj_id_class_id.sort_unstable_by_key(|&(obj_id, _)| obj_id);
         obj_id_prim_array_type.sort_unstable_by_key(|&(obj_id, _)| obj_id);
+        obj_id_ref_count.sort_unstable_by_key(|&(obj_id, _)| obj_id);
+        obj_id_location.sort_unstable_by_key(|&(obj_id, _)| obj_id);
+        obj_id_shallow_size.sort_unstable_by_key(|&(obj_id, _)| obj_id);
 
         let seq = VecIndexSeq {
             obj_id_class_id,
             obj_id_prim_array_type,
+            obj_id_ref_count,
+            obj_id_location,
+            obj_id_shallow_size,
         };
 
         let fingerprint = HprofFingerprint {
@@ -271,20 +575,102 @@ mod tests {
             );
         }
 
+        for &(obj_id, ref_count) in seq.obj_id_ref_count.iter() {
+            assert_eq!(
+                Some(ref_count),
+                index.reference_count(Id::from(obj_id))?,
+                "obj id: {}",
+                obj_id
+            );
+        }
+
+        for &(obj_id, location) in seq.obj_id_location.iter() {
+            assert_eq!(
+                Some(location),
+                index.get_location(Id::from(obj_id))?,
+                "obj id: {}",
+                obj_id
+            );
+        }
+
+        let mut instance_counts = collections::HashMap::<u64, u64>::new();
+        for &(_, class_id) in seq.obj_id_class_id.iter() {
+            *instance_counts.entry(class_id).or_default() += 1;
+        }
+        for (&class_id, &count) in instance_counts.iter() {
+            assert_eq!(
+                Some(count),
+                index.instance_count(Id::from(class_id))?,
+                "class id: {}",
+                class_id
+            );
+        }
+
+        for &(obj_id, size) in seq.obj_id_shallow_size.iter() {
+            assert_eq!(
+                Some(size),
+                index.get_shallow_size(Id::from(obj_id))?,
+                "obj id: {}",
+                obj_id
+            );
+        }
+
         // don't wipe the tmp dir until we're done reading from it
         drop(index_dir);
         Ok(())
     }
 
+    #[test]
+    fn opens_older_index_without_instance_counts() -> Result<(), anyhow::Error> {
+        let seq = VecIndexSeq {
+            obj_id_class_id: vec![(1000, 100)],
+            obj_id_prim_array_type: Vec::new(),
+            obj_id_ref_count: Vec::new(),
+            obj_id_location: Vec::new(),
+            obj_id_shallow_size: Vec::new(),
+        };
+        let fingerprint = HprofFingerprint {
+            timestamp: 1000,
+            record_count: 2000,
+        };
+
+        let index_dir = tempfile::tempdir()?;
+        LmdbIndex::build_index(&seq, &fingerprint, index_dir.path())?;
+
+        // remove the instance counts, as in indexes from before they were added
+        {
+            let env = lmdb::Environment::new()
+                .set_max_dbs(MAX_DBS)
+                .open(&index_dir.path().join("lmdb"))?;
+            let db = env.open_db(Some(DB_CLASS_ID_INSTANCE_COUNT))?;
+            let mut txn = env.begin_rw_txn()?;
+            unsafe { txn.drop_db(db)? };
+            txn.commit()?;
+        }
+
+        let index = LmdbIndex::open_with_fingerprint(&fingerprint, index_dir.path())?;
+        assert_eq!(Some(Id::from(100)), index.get_class_id(Id::from(1000))?);
+        assert!(index.instance_count(Id::from(100)).is_err());
+
+        drop(index_dir);
+        Ok(())
+    }
+
     struct VecIndexSeq {
         obj_id_class_id: Vec<(u64, u64)>,
         obj_id_prim_array_type: Vec<(u64, u8)>,
+        obj_id_ref_count: Vec<(u64, u32)>,
+        obj_id_location: Vec<(u64, (u32, u32))>,
+        obj_id_shallow_size: Vec<(u64, u64)>,
     }
 
     impl IndexSequence for VecIndexSeq {
         // accept the dynamic dispatch overhead so we don't have some gnarly type here
         type ObjIdClassIdIterator = Box<dyn Iterator<Item = Result<(u64, u64), io::Error>>>;
         type ObjIdPrimArrayTypeIterator = Box<dyn Iterator<Item = Result<(u64, u8), io::Error>>>;
+        type ObjIdRefCountIterator = Box<dyn Iterator<Item = Result<(u64, u32), io::Error>>>;
+        type ObjIdLocationIterator = Box<dyn Iterator<Item = Result<(u64, (u32, u32)), io::Error>>>;
+        type ObjIdShallowSizeIterator = Box<dyn Iterator<Item = Result<(u64, u64), io::Error>>>;
 
         fn iter_obj_id_class_id(&self) -> Result<Self::ObjIdClassIdIterator, anyhow::Error> {
             Ok(Box::new(
@@ -306,6 +692,41 @@ mod tests {
             ))
         }
 
+        fn iter_obj_id_reference_count(
+            &self,
+        ) -> Result<Self::ObjIdRefCountIterator, anyhow::Error> {
+            Ok(Box::new(
+                self.obj_id_ref_count
+                    .clone()
+                    .into_iter()
+                    .map(|elem| Ok(elem)),
+            ))
+        }
+
+        fn iter_obj_id_location(&self) -> Result<Self::ObjIdLocationIterator, anyhow::Error> {
+            Ok(Box::new(
+                self.obj_id_location
+                    .clone()
+                    .into_iter()
+                    .map(|elem| Ok(elem)),
+            ))
+        }
+
+        fn iter_obj_id_shallow_size(
+            &self,
+        ) -> Result<Self::ObjIdShallowSizeIterator, anyhow::Error> {
+            Ok(Box::new(
+                self.obj_id_shallow_size
+                    .clone()
+                    .into_iter()
+                    .map(|elem| Ok(elem)),
+            ))
+        }
+
+        fn contents(&self) -> IndexContents {
+            IndexContents::all()
+        }
+
         fn remove_tmp_files(self) -> Result<(), io::Error> {
             Ok(())
         }
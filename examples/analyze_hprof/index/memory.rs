@@ -0,0 +1,72 @@
+use crate::index::{Index, IndexSequence};
+
+use jvm_hprof::heap_dump::PrimitiveArrayType;
+use jvm_hprof::Id;
+use std::collections;
+
+/// An [Index] held entirely in memory, for heap dumps small enough that it's not worth building
+/// an index on disk first.
+pub(crate) struct MemoryIndex {
+    obj_id_class_id: collections::HashMap<u64, u64>,
+    obj_id_prim_array_type: collections::HashMap<u64, u8>,
+    obj_id_ref_count: collections::HashMap<u64, u32>,
+    obj_id_location: collections::HashMap<u64, (u32, u32)>,
+    obj_id_shallow_size: collections::HashMap<u64, u64>,
+    class_id_instance_count: collections::HashMap<u64, u64>,
+}
+
+impl MemoryIndex {
+    /// Load all of the data in `seq` into memory
+    pub(crate) fn from_seq<S: IndexSequence>(seq: &S) -> Result<MemoryIndex, anyhow::Error> {
+        let obj_id_class_id: collections::HashMap<u64, u64> =
+            seq.iter_obj_id_class_id()?.collect::<Result<_, _>>()?;
+        let mut class_id_instance_count = collections::HashMap::new();
+        for &class_id in obj_id_class_id.values() {
+            *class_id_instance_count.entry(class_id).or_default() += 1;
+        }
+
+        Ok(MemoryIndex {
+            obj_id_class_id,
+            obj_id_prim_array_type: seq
+                .iter_obj_id_prim_array_type()?
+                .collect::<Result<_, _>>()?,
+            obj_id_ref_count: seq
+                .iter_obj_id_reference_count()?
+                .collect::<Result<_, _>>()?,
+            obj_id_location: seq.iter_obj_id_location()?.collect::<Result<_, _>>()?,
+            obj_id_shallow_size: seq.iter_obj_id_shallow_size()?.collect::<Result<_, _>>()?,
+            class_id_instance_count,
+        })
+    }
+}
+
+impl Index for MemoryIndex {
+    fn get_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
+        Ok(self
+            .obj_id_class_id
+            .get(&obj_id.id())
+            .map(|&class_id| Id::from(class_id)))
+    }
+
+    fn get_prim_array_type(&self, obj_id: Id) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
+        Ok(self.obj_id_prim_array_type.get(&obj_id.id()).map(|&code| {
+            PrimitiveArrayType::from_type_code(code).expect("Invalid primitive array type code")
+        }))
+    }
+
+    fn reference_count(&self, obj_id: Id) -> Result<Option<u32>, anyhow::Error> {
+        Ok(self.obj_id_ref_count.get(&obj_id.id()).copied())
+    }
+
+    fn get_location(&self, obj_id: Id) -> Result<Option<(u32, u32)>, anyhow::Error> {
+        Ok(self.obj_id_location.get(&obj_id.id()).copied())
+    }
+
+    fn get_shallow_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        Ok(self.obj_id_shallow_size.get(&obj_id.id()).copied())
+    }
+
+    fn instance_count(&self, class_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        Ok(self.class_id_instance_count.get(&class_id.id()).copied())
+    }
+}
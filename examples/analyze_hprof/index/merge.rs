@@ -5,9 +5,12 @@ use std::iter;
 /// Total items/sec falls off precipitously when merging more than 8 sources:
 /// 4x -> 49m / sec, 8 -> 41m, 16 -> 29, 32 -> 20, 64 -> 12, 128 -> 6
 /// So, it's faster to do a few rounds of 8-way merge than one slower 128-way (or worse).
+///
+/// Each round's input files are removed once they've been merged, unless `keep_inputs` is true.
 pub(crate) fn merge_chunk_type<T, W: DatumDeserializer<T> + DatumSerializer<T> + Send + Sync>(
     index_dir: &path::Path,
     subdir: &str,
+    keep_inputs: bool,
 ) -> Result<path::PathBuf, anyhow::Error> {
     let mut chunks_dir = index_dir.to_path_buf();
     chunks_dir.push("chunks");
@@ -32,7 +35,7 @@ pub(crate) fn merge_chunk_type<T, W: DatumDeserializer<T> + DatumSerializer<T> +
         }
 
         let merge_factor = 8;
-        println!(
+        eprintln!(
             "Merge round {}, {} merged files to write",
             counter,
             files_to_merge.as_slice().chunks(merge_factor).count()
@@ -77,8 +80,8 @@ pub(crate) fn merge_chunk_type<T, W: DatumDeserializer<T> + DatumSerializer<T> +
 
                 writer.flush()?;
 
-                print!(".");
-                io::stdout().flush()?;
+                eprint!(".");
+                io::stderr().flush()?;
 
                 // ensure output is sorted
                 debug_assert!(
@@ -99,11 +102,13 @@ pub(crate) fn merge_chunk_type<T, W: DatumDeserializer<T> + DatumSerializer<T> +
             .for_each(|r: Result<(), anyhow::Error>| r.unwrap());
 
         // remove the files from the previous round that have now been merged
-        for de in files_to_merge.iter() {
-            fs::remove_file(de.path())?
+        if !keep_inputs {
+            for de in files_to_merge.iter() {
+                fs::remove_file(de.path())?
+            }
         }
 
-        println!();
+        eprintln!();
 
         // now, entries are what we just merged
         files_to_merge = fs::read_dir(&merge_dir)?.map(|r| r.unwrap()).collect();
@@ -2,64 +2,424 @@ use anyhow;
 use is_sorted;
 
 use crate::index::lmdb::LmdbIndex;
+use crate::index::memory::MemoryIndex;
+use crate::index::sled::SledIndex;
 use index_chunks::*;
 use is_sorted::IsSorted;
 use itertools::Itertools;
 use jvm_hprof::{heap_dump::*, *};
 use merge::*;
 use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::convert::TryInto;
 use std::io::Write;
-use std::{cmp, fmt, fs, io, path};
+use std::{cmp, collections, fmt, fs, io, path, str};
 
 mod index_chunks;
 pub mod lmdb;
+mod memory;
 mod merge;
+pub mod sled;
 
 // subdir where obj id to class id mappings are written
 const SUBDIR_OBJ_CLASS: &str = "obj-id-class-id";
 // same, but for obj id to primitive array type
 const SUBDIR_OBJ_PRIM_ARRAY_TYPE: &str = "obj-id-prim-array-type";
+// same, but for obj id to outbound reference count
+const SUBDIR_OBJ_REF_COUNT: &str = "obj-id-ref-count";
+// same, but for obj id to sub record location
+const SUBDIR_OBJ_LOCATION: &str = "obj-id-location";
+// same, but for obj id to shallow size
+const SUBDIR_OBJ_SHALLOW_SIZE: &str = "obj-id-shallow-size";
 
-pub(crate) fn build_index(hprof: &Hprof, output: &path::Path) -> Result<(), anyhow::Error> {
-    let fingerprint = HprofFingerprint::from_hprof(hprof);
+/// The available [Index] implementations.
+///
+/// Other key-value stores can be added by implementing [Index] to look up each of its mappings by
+/// obj id, and [IndexBuilder] to load them from the sorted [IndexSequence] into the store's own
+/// subdirectory of the index dir, along with the [HprofFingerprint] to check when the index is
+/// opened. Then, add a variant here and to [AnyIndex] and wire it up in [build_index] and
+/// [open_index].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum IndexBackend {
+    Lmdb,
+    Sled,
+    /// Built in memory every time, so it can't be used with [build_index]
+    Memory,
+}
+
+impl str::FromStr for IndexBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lmdb" => Ok(IndexBackend::Lmdb),
+            "sled" => Ok(IndexBackend::Sled),
+            "memory" => Ok(IndexBackend::Memory),
+            _ => Err(anyhow::anyhow!("Unknown index backend: {}", s)),
+        }
+    }
+}
+
+/// Which of the optional mappings to write when building an index.
+///
+/// The obj id to class id mapping is always written, since almost every use of an index needs it.
+/// Skipping the rest when they won't be needed makes the index faster to build and smaller. The
+/// contents are stored in the index alongside its fingerprint, and looking up an obj id in a
+/// skipped mapping is an error, rather than finding nothing as if the object weren't there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct IndexContents {
+    pub(crate) prim_array_types: bool,
+    pub(crate) reference_counts: bool,
+    pub(crate) locations: bool,
+    pub(crate) shallow_sizes: bool,
+}
+
+impl IndexContents {
+    /// Write every mapping
+    pub(crate) fn all() -> IndexContents {
+        IndexContents {
+            prim_array_types: true,
+            reference_counts: true,
+            locations: true,
+            shallow_sizes: true,
+        }
+    }
+
+    /// One bit per mapping, for storing in the index
+    fn to_flags(self) -> u8 {
+        (self.prim_array_types as u8)
+            | (self.reference_counts as u8) << 1
+            | (self.locations as u8) << 2
+            | (self.shallow_sizes as u8) << 3
+    }
+
+    /// The inverse of [IndexContents::to_flags]. Indexes from before mappings could be skipped
+    /// don't have any stored flags, and have every mapping.
+    fn from_stored_flags<R: AsRef<[u8]>>(
+        stored: Option<R>,
+    ) -> Result<IndexContents, anyhow::Error> {
+        let flags = match stored {
+            None => return Ok(IndexContents::all()),
+            Some(bytes) => match bytes.as_ref() {
+                &[flags] => flags,
+                other => {
+                    return Err(anyhow::anyhow!(
+                        "Invalid stored index contents: {:?}",
+                        other
+                    ))
+                }
+            },
+        };
+
+        Ok(IndexContents {
+            prim_array_types: flags & 1 != 0,
+            reference_counts: flags & 1 << 1 != 0,
+            locations: flags & 1 << 2 != 0,
+            shallow_sizes: flags & 1 << 3 != 0,
+        })
+    }
+}
+
+/// Error if `mapping` wasn't `included` when the index was built, so that lookups in it don't
+/// silently find nothing.
+fn require_mapping(included: bool, mapping: &str) -> Result<(), anyhow::Error> {
+    if included {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Index was built without {}; rebuild it without skipping them",
+            mapping
+        ))
+    }
+}
+
+/// Where [build_index] writes the sorted chunks it assembles the index from, and whether to keep
+/// them afterwards.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScratchSpace {
+    /// `None` to use the index's own dir. A separate dir on a faster disk can speed up building
+    /// an index on a slow volume, since every chunk is written and read back at least twice.
+    pub(crate) dir: Option<path::PathBuf>,
+    /// Keep the chunks, and the files from each round of merging them, rather than removing them
+    /// once the index is built, e.g. to debug the merge.
+    pub(crate) keep: bool,
+}
+
+/// Build an index in `output` with the `backend` store, containing the mappings in `contents`
+pub(crate) fn build_index(
+    hprof: &Hprof,
+    output: &path::Path,
+    backend: IndexBackend,
+    contents: IndexContents,
+    scratch: &ScratchSpace,
+) -> Result<(), anyhow::Error> {
+    let scratch_dir = scratch.dir.as_deref().unwrap_or(output);
+    let (index_seq, record_count) = build_index_seq(hprof, scratch_dir, contents, scratch.keep)?;
+    // counted while building, rather than by iterating over every record again
+    let fingerprint = HprofFingerprint::with_record_count(hprof, record_count);
+
+    eprintln!("\n[3/3] Assembling final index structure (. = 1,000,000 index entries inserted)");
+
+    match backend {
+        IndexBackend::Lmdb => LmdbIndex::build_index(&index_seq, &fingerprint, output)?,
+        IndexBackend::Sled => SledIndex::build_index(&index_seq, &fingerprint, output)?,
+        IndexBackend::Memory => {
+            return Err(anyhow::anyhow!(
+                "An in-memory index can't be written to disk"
+            ))
+        }
+    }
+
+    if !scratch.keep {
+        index_seq.remove_tmp_files()?;
+        remove_chunk_dirs(scratch_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the (by now empty, or no longer needed) dirs that chunks and merged chunks are written
+/// to under `dir`
+fn remove_chunk_dirs(dir: &path::Path) -> Result<(), io::Error> {
+    for subdir in [dir.join("chunks"), dir.join("merge")].iter() {
+        if subdir.is_dir() {
+            fs::remove_dir_all(subdir)?;
+        }
+    }
 
-    let builder = ChunkedIndexSeqBuilder::new(output.to_owned())?;
+    Ok(())
+}
+
+/// Build an index for `hprof` in memory, using a temporary dir for the intermediate sorted data
+pub(crate) fn build_memory_index(hprof: &Hprof) -> Result<MemoryIndex, anyhow::Error> {
+    let tmp_dir = tempfile::tempdir()?;
+
+    let (index_seq, _record_count) =
+        build_index_seq(hprof, tmp_dir.path(), IndexContents::all(), false)?;
+
+    eprintln!("\n[3/3] Loading index into memory");
+
+    let index = MemoryIndex::from_seq(&index_seq)?;
+
+    index_seq.remove_tmp_files()?;
+
+    Ok(index)
+}
 
-    println!("[1/3] Creating sorted chunks (. = 1,000,000 objects processed)");
+/// Open an index with the `backend` store.
+///
+/// `index_path` is required for every backend except [IndexBackend::Memory], which ignores it and
+/// builds a new index from `hprof` instead.
+pub(crate) fn open_index(
+    hprof: &Hprof,
+    backend: IndexBackend,
+    index_path: Option<&path::Path>,
+) -> Result<AnyIndex, anyhow::Error> {
+    let require_path =
+        || index_path.ok_or_else(|| anyhow::anyhow!("An index path is required for {:?}", backend));
+
+    // the in-memory index is built from this very hprof, so only stored indexes are fingerprinted
+    Ok(match backend {
+        IndexBackend::Lmdb => AnyIndex::Lmdb(LmdbIndex::open_with_fingerprint(
+            &HprofFingerprint::from_hprof(hprof),
+            require_path()?,
+        )?),
+        IndexBackend::Sled => AnyIndex::Sled(SledIndex::open_with_fingerprint(
+            &HprofFingerprint::from_hprof(hprof),
+            require_path()?,
+        )?),
+        IndexBackend::Memory => AnyIndex::Memory(Box::new(build_memory_index(hprof)?)),
+    })
+}
+
+/// Delegates to whichever [Index] was selected at runtime
+pub(crate) enum AnyIndex {
+    Lmdb(LmdbIndex),
+    Sled(SledIndex),
+    Memory(Box<MemoryIndex>),
+}
 
+impl Index for AnyIndex {
+    fn get_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.get_class_id(obj_id),
+            AnyIndex::Sled(i) => i.get_class_id(obj_id),
+            AnyIndex::Memory(i) => i.get_class_id(obj_id),
+        }
+    }
+
+    fn get_class_ids(&self, obj_ids: &[Id]) -> Result<Vec<Option<Id>>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.get_class_ids(obj_ids),
+            AnyIndex::Sled(i) => i.get_class_ids(obj_ids),
+            AnyIndex::Memory(i) => i.get_class_ids(obj_ids),
+        }
+    }
+
+    fn get_prim_array_type(&self, obj_id: Id) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.get_prim_array_type(obj_id),
+            AnyIndex::Sled(i) => i.get_prim_array_type(obj_id),
+            AnyIndex::Memory(i) => i.get_prim_array_type(obj_id),
+        }
+    }
+
+    fn reference_count(&self, obj_id: Id) -> Result<Option<u32>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.reference_count(obj_id),
+            AnyIndex::Sled(i) => i.reference_count(obj_id),
+            AnyIndex::Memory(i) => i.reference_count(obj_id),
+        }
+    }
+
+    fn get_location(&self, obj_id: Id) -> Result<Option<(u32, u32)>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.get_location(obj_id),
+            AnyIndex::Sled(i) => i.get_location(obj_id),
+            AnyIndex::Memory(i) => i.get_location(obj_id),
+        }
+    }
+
+    fn get_shallow_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.get_shallow_size(obj_id),
+            AnyIndex::Sled(i) => i.get_shallow_size(obj_id),
+            AnyIndex::Memory(i) => i.get_shallow_size(obj_id),
+        }
+    }
+
+    fn instance_count(&self, class_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.instance_count(class_id),
+            AnyIndex::Sled(i) => i.instance_count(class_id),
+            AnyIndex::Memory(i) => i.instance_count(class_id),
+        }
+    }
+
+    fn get_retained_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        match self {
+            AnyIndex::Lmdb(i) => i.get_retained_size(obj_id),
+            AnyIndex::Sled(i) => i.get_retained_size(obj_id),
+            AnyIndex::Memory(i) => i.get_retained_size(obj_id),
+        }
+    }
+}
+
+/// Write the index data in `contents` for `hprof` into sorted files in `output`, keeping the
+/// intermediate chunks if `keep_intermediates` is true.
+/// Returns the sequence and the number of records in `hprof`, which the fingerprint needs.
+fn build_index_seq(
+    hprof: &Hprof,
+    output: &path::Path,
+    contents: IndexContents,
+    keep_intermediates: bool,
+) -> Result<(MergedFileIndexSequence, u64), anyhow::Error> {
+    let builder = ChunkedIndexSeqBuilder::new(output.to_owned(), contents, keep_intermediates)?;
+
+    let classes = crate::util::ez_classes_by_obj_id(hprof);
+    // needed to find the object references in instance fields
+    let class_instance_field_descriptors =
+        crate::util::build_type_hierarchy_field_descriptors(&classes);
+    let class_instance_size =
+        |class_obj_id: Id| classes.get(&class_obj_id).map(|c| c.instance_size_bytes);
+    let id_size = hprof.header().id_size();
+
+    eprintln!("[1/3] Creating sorted chunks (. = 1,000,000 objects processed)");
+
+    let mut record_count = 0_u64;
     hprof
         .records_iter()
         .map(|r| r.unwrap())
+        .inspect(|_| record_count += 1)
         .enumerate()
         .par_bridge()
         .map(|(record_index, r)| match r.tag() {
             RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
                 let mut record_writer = builder.record_writer(record_index)?;
                 let segment = r.as_heap_dump_segment().unwrap().unwrap();
+                // records are length-prefixed with a u32, so offsets within them fit in a u32 too
+                let location_record_index: u32 = record_index.try_into()?;
 
                 let print_every = 1_000_000;
 
                 let mut count = 0_u64;
-                for p in segment.sub_records() {
-                    let s = p.unwrap();
+                for p in segment.sub_records_with_offsets() {
+                    let (offset, s) = p.unwrap();
 
                     count += 1;
                     if count == print_every {
                         count = 0;
-                        print!(".");
-                        io::stdout().flush()?;
+                        eprint!(".");
+                        io::stderr().flush()?;
+                    }
+
+                    let location_obj_id = match &s {
+                        SubRecord::Class(c) => Some(c.obj_id()),
+                        SubRecord::Instance(instance) => Some(instance.obj_id()),
+                        SubRecord::ObjectArray(obj_array) => Some(obj_array.obj_id()),
+                        SubRecord::PrimitiveArray(pa) => Some(pa.obj_id()),
+                        _ => None,
+                    };
+                    if let Some(obj_id) = location_obj_id {
+                        record_writer.write_location(
+                            obj_id,
+                            location_record_index,
+                            offset as u32,
+                        )?;
+                    }
+
+                    // array sizes depend on their lengths, so they can't be derived from the
+                    // class later on
+                    if let Some((obj_id, size)) =
+                        location_obj_id.zip(shallow_size(&s, class_instance_size, id_size))
+                    {
+                        record_writer.write_shallow_size(obj_id, size)?;
                     }
 
                     match s {
                         SubRecord::Instance(instance) => {
                             record_writer
                                 .write_class_id(instance.obj_id(), instance.class_obj_id())?;
+
+                            // parsing every field is the slowest part, so avoid it if possible
+                            if !contents.reference_counts {
+                                continue;
+                            }
+
+                            let field_descriptors = crate::util::instance_field_descriptors(
+                                &class_instance_field_descriptors,
+                                instance.class_obj_id(),
+                            );
+
+                            let ref_count = crate::util::instance_field_values(
+                                &instance,
+                                field_descriptors,
+                                id_size,
+                            )
+                            .filter(|(_fd, field_val)| {
+                                matches!(field_val, FieldValue::ObjectId(Some(_)))
+                            })
+                            .count() as u32;
+
+                            record_writer.write_reference_count(instance.obj_id(), ref_count)?;
                         }
                         SubRecord::ObjectArray(obj_array) => {
                             record_writer.write_class_id(
                                 obj_array.obj_id(),
                                 obj_array.array_class_obj_id(),
                             )?;
+
+                            if !contents.reference_counts {
+                                continue;
+                            }
+
+                            let ref_count = obj_array
+                                .elements(id_size)
+                                .map(|r| r.unwrap())
+                                .filter(|e| e.is_some())
+                                .count();
+
+                            record_writer
+                                .write_reference_count(obj_array.obj_id(), ref_count as u32)?;
                         }
                         SubRecord::PrimitiveArray(pa) => {
                             record_writer
@@ -80,40 +440,268 @@ pub(crate) fn build_index(hprof: &Hprof, output: &path::Path) -> Result<(), anyh
             res.unwrap();
         });
 
-    println!("\n[2/3] Merge-sorting index data (. = 1 merged file written)");
+    eprintln!("\n[2/3] Merge-sorting index data (. = 1 merged file written)");
 
-    let mut index_seq = builder.finalize()?;
+    Ok((builder.finalize()?, record_count))
+}
 
-    println!("\n[3/3] Assembling final index structure (. = 1,000,000 index entries inserted)");
+/// The file in an [export_obj_class_map] output dir holding the obj id to class id pairs
+pub(crate) const EXPORT_OBJ_CLASS_FILE: &str = "obj-id-class-id.bin";
+/// The file in an [export_obj_class_map] output dir describing the pairs file
+pub(crate) const EXPORT_MANIFEST_FILE: &str = "manifest.txt";
 
-    LmdbIndex::build_index(&mut index_seq, &fingerprint, output)?;
+/// Write the class id of each object (plain object or reference array) to a flat file of sorted
+/// `(obj id, class id)` pairs, each encoded as two little-endian u64s, with no header or other
+/// framing, so that anything that can mmap a file can binary search it.
+///
+/// A manifest alongside it records the fingerprint of the hprof the pairs came from.
+pub(crate) fn export_obj_class_map(
+    hprof: &Hprof,
+    output: &path::Path,
+) -> Result<(), anyhow::Error> {
+    eprintln!("[1/2] Creating sorted chunks");
 
-    index_seq.remove_tmp_files()?;
+    let mut record_count = 0_u64;
+    hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .inspect(|_| record_count += 1)
+        .enumerate()
+        .par_bridge()
+        .map(|(record_index, r)| match r.tag() {
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                let mut writer = ObjClassChunkWriter::new(output, record_index)?;
+                let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+                for p in segment.sub_records() {
+                    match p.unwrap() {
+                        SubRecord::Instance(instance) => {
+                            writer.write_class_id(instance.obj_id(), instance.class_obj_id())?
+                        }
+                        SubRecord::ObjectArray(obj_array) => writer
+                            .write_class_id(obj_array.obj_id(), obj_array.array_class_obj_id())?,
+                        _ => {}
+                    }
+                }
+
+                writer.flush()?;
+
+                Ok(())
+            }
+            _ => Ok(()),
+        })
+        .for_each(|res: Result<(), anyhow::Error>| {
+            res.unwrap();
+        });
+
+    let fingerprint = HprofFingerprint::with_record_count(hprof, record_count);
+
+    eprintln!("[2/2] Merge-sorting (. = 1 merged file written)");
+
+    let mut pairs_path = output.to_path_buf();
+    pairs_path.push(EXPORT_OBJ_CLASS_FILE);
+
+    let mut chunks_dir = output.to_path_buf();
+    chunks_dir.push("chunks");
+
+    let mut chunks_type_dir = chunks_dir.clone();
+    chunks_type_dir.push(SUBDIR_OBJ_CLASS);
+    if chunks_type_dir.is_dir() && fs::read_dir(&chunks_type_dir)?.next().is_some() {
+        let merged = merge_chunk_type::<_, U64PairData>(output, SUBDIR_OBJ_CLASS, false)?;
+        fs::rename(merged, &pairs_path)?;
+    } else {
+        // no objects at all, so nothing to merge
+        fs::File::create(&pairs_path)?;
+    }
+
+    remove_chunk_dirs(output)?;
+
+    let entry_count = fs::metadata(&pairs_path)?.len() / 16;
+
+    let mut manifest = io::BufWriter::new(fs::File::create(output.join(EXPORT_MANIFEST_FILE))?);
+    writeln!(manifest, "file={}", EXPORT_OBJ_CLASS_FILE)?;
+    writeln!(manifest, "format=u64 obj id, u64 class id, little endian")?;
+    writeln!(manifest, "entries={}", entry_count)?;
+    writeln!(manifest, "hprof_timestamp_millis={}", fingerprint.timestamp)?;
+    writeln!(manifest, "hprof_record_count={}", fingerprint.record_count)?;
+    manifest.flush()?;
 
     Ok(())
 }
 
-// Sized so Self can be used in return types
-pub trait Index: Sized + Sync {
-    /// Open the index at the provided path, and make sure that its stored fingerprint matches
-    /// `fingerprint`
-    fn open_with_fingerprint(
-        fingerprint: &HprofFingerprint,
-        index_path: &path::Path,
-    ) -> Result<Self, anyhow::Error>;
-
+/// Lookups of per-object data by obj id. See [IndexBackend] for the available implementations.
+pub trait Index: Sync {
     /// Get the class id for an object id, if available.
     ///
     /// The object id must be for a normal object or a reference array type, not a java.lang.Class
     /// (which are represented separately in an hprof) or a primitive array (ditto).
     fn get_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error>;
 
+    /// Get the class id for each of `obj_ids`, as per [Index::get_class_id], in the same order.
+    ///
+    /// Implementations can make this much faster than looking up each id separately, e.g. by
+    /// doing all the lookups in one transaction, so prefer it when there are many ids at once,
+    /// like the elements of a big object array.
+    fn get_class_ids(&self, obj_ids: &[Id]) -> Result<Vec<Option<Id>>, anyhow::Error> {
+        obj_ids.iter().map(|&id| self.get_class_id(id)).collect()
+    }
+
     /// Get the primitive array type for an object id, if available.
     fn get_prim_array_type(&self, obj_id: Id) -> Result<Option<PrimitiveArrayType>, anyhow::Error>;
+
+    /// Get the number of non-null object references held by an object id, if available.
+    ///
+    /// Counts are only available for normal objects (via their instance fields) and reference
+    /// arrays (via their elements). Duplicate references to the same object are each counted.
+    fn reference_count(&self, obj_id: Id) -> Result<Option<u32>, anyhow::Error>;
+
+    /// Get the location of an object id's sub record, if available, as the index of the heap dump
+    /// segment record and the offset within it, suitable for use with [Hprof::sub_record_at].
+    ///
+    /// Locations are available for classes, normal objects, and both types of array.
+    fn get_location(&self, obj_id: Id) -> Result<Option<(u32, u32)>, anyhow::Error>;
+
+    /// Get the shallow size in bytes of an object id, if available, as per
+    /// [jvm_hprof::heap_dump::shallow_size].
+    ///
+    /// Sizes are available for normal objects and both types of array, but not classes.
+    fn get_shallow_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error>;
+
+    /// Get the number of objects with class id `class_id`, if it has any.
+    ///
+    /// As with [Index::get_class_id], object arrays count as instances of their array class, and
+    /// primitive arrays aren't counted at all.
+    fn instance_count(&self, class_id: Id) -> Result<Option<u64>, anyhow::Error>;
+
+    /// Get the retained size in bytes of an object id, if available: its shallow size plus that of
+    /// every object only reachable through it.
+    ///
+    /// Retained sizes need a dominator analysis of the whole object graph, so they're only
+    /// available for reachable objects once the `build-retained` subcommand has written them to an
+    /// LMDB index. Other indexes don't have any.
+    fn get_retained_size(&self, _obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        Ok(None)
+    }
+}
+
+/// What an object id refers to, as determined by [resolve_reference_target].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ReferenceTarget {
+    /// A normal object or a reference array
+    Instance {
+        class_obj_id: Id,
+    },
+    PrimitiveArray {
+        prim_type: PrimitiveArrayType,
+    },
+    /// A `java.lang.Class`
+    ClassObj {
+        class_obj_id: Id,
+    },
+}
+
+impl ReferenceTarget {
+    /// The target's type as shown to users: the class name for instances (and reference
+    /// arrays), e.g. `int[]` for primitive arrays, and `class` followed by the class name for
+    /// classes.
+    pub(crate) fn type_name(
+        &self,
+        classes: &collections::HashMap<Id, crate::util::EzClass>,
+    ) -> String {
+        match self {
+            ReferenceTarget::Instance { class_obj_id } => {
+                crate::util::class_name_or_unknown(classes, *class_obj_id)
+            }
+            ReferenceTarget::PrimitiveArray { prim_type } => {
+                format!("{}[]", prim_type.java_type_name())
+            }
+            ReferenceTarget::ClassObj { class_obj_id } => format!(
+                "class {}",
+                crate::util::class_name_or_unknown(classes, *class_obj_id)
+            ),
+        }
+    }
+}
+
+/// The per-object lookups that [resolve_reference_target] needs. Every [Index] has them, but so
+/// can tables built in memory while reading the heap dump.
+pub(crate) trait ObjTypeLookup {
+    /// The class obj id of an instance or reference array
+    fn lookup_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error>;
+
+    fn lookup_prim_array_type(
+        &self,
+        obj_id: Id,
+    ) -> Result<Option<PrimitiveArrayType>, anyhow::Error>;
+}
+
+impl<I: Index> ObjTypeLookup for I {
+    fn lookup_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
+        self.get_class_id(obj_id)
+    }
+
+    fn lookup_prim_array_type(
+        &self,
+        obj_id: Id,
+    ) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
+        self.get_prim_array_type(obj_id)
+    }
 }
 
-/// Consumes an [IndexSequence] to produce the final [Index].
-pub trait IndexBuilder {
+/// Look in all the possible places an object id might be to determine what it refers to: the
+/// index's class ids, then its primitive array types, then the loaded classes.
+///
+/// Returns `None` if the id isn't found anywhere, which can happen for references to objects
+/// that weren't included in the heap dump.
+pub(crate) fn resolve_reference_target<L: ObjTypeLookup>(
+    obj_id: Id,
+    index: &L,
+    classes: &collections::HashMap<Id, crate::util::EzClass>,
+) -> Result<Option<ReferenceTarget>, anyhow::Error> {
+    if let Some(class_obj_id) = index.lookup_class_id(obj_id)? {
+        return Ok(Some(ReferenceTarget::Instance { class_obj_id }));
+    }
+
+    if let Some(prim_type) = index.lookup_prim_array_type(obj_id)? {
+        return Ok(Some(ReferenceTarget::PrimitiveArray { prim_type }));
+    }
+
+    Ok(classes
+        .get(&obj_id)
+        .map(|_class| ReferenceTarget::ClassObj {
+            class_obj_id: obj_id,
+        }))
+}
+
+/// Like [resolve_reference_target] for each of `obj_ids`, in the same order, but with the class
+/// ids looked up all at once with [Index::get_class_ids].
+pub(crate) fn resolve_reference_targets<I: Index>(
+    obj_ids: &[Id],
+    index: &I,
+    classes: &collections::HashMap<Id, crate::util::EzClass>,
+) -> Result<Vec<Option<ReferenceTarget>>, anyhow::Error> {
+    index
+        .get_class_ids(obj_ids)?
+        .into_iter()
+        .zip(obj_ids.iter())
+        .map(|(class_id, &obj_id)| match class_id {
+            Some(class_obj_id) => Ok(Some(ReferenceTarget::Instance { class_obj_id })),
+            // not a plain object or reference array, so it's somewhere else, if anywhere
+            None => resolve_reference_target(obj_id, index, classes),
+        })
+        .collect()
+}
+
+/// Consumes an [IndexSequence] to produce the final [Index] on disk, and opens it again later.
+// Sized so Self can be used in return types
+pub trait IndexBuilder: Index + Sized {
+    /// Open the index at the provided path, and make sure that its stored fingerprint matches
+    /// `fingerprint`
+    fn open_with_fingerprint(
+        fingerprint: &HprofFingerprint,
+        index_path: &path::Path,
+    ) -> Result<Self, anyhow::Error>;
+
     fn build_index<S: IndexSequence>(
         seq: &S,
         fingerprint: &HprofFingerprint,
@@ -133,7 +721,15 @@ pub trait IndexSequenceBuilder: Sized {
 
     /// Create a new index builder
     /// - `path` - Path to write the index data to
-    fn new(dest: path::PathBuf) -> Result<Self, anyhow::Error>;
+    /// - `contents` - Which mappings to write. [RecordWriter]s silently discard data for the rest,
+    ///   and their iterators in the resulting [IndexSequence] are empty.
+    /// - `keep_intermediates` - Don't remove any intermediate data written along the way, e.g.
+    ///   unmerged chunks, so it can be inspected afterwards.
+    fn new(
+        dest: path::PathBuf,
+        contents: IndexContents,
+        keep_intermediates: bool,
+    ) -> Result<Self, anyhow::Error>;
 
     /// Create a RecordWriter for a particular record index.
     ///
@@ -149,6 +745,9 @@ pub trait IndexSequenceBuilder: Sized {
 pub trait IndexSequence {
     type ObjIdClassIdIterator: Iterator<Item = Result<(u64, u64), io::Error>>;
     type ObjIdPrimArrayTypeIterator: Iterator<Item = Result<(u64, u8), io::Error>>;
+    type ObjIdRefCountIterator: Iterator<Item = Result<(u64, u32), io::Error>>;
+    type ObjIdLocationIterator: Iterator<Item = Result<(u64, (u32, u32)), io::Error>>;
+    type ObjIdShallowSizeIterator: Iterator<Item = Result<(u64, u64), io::Error>>;
 
     /// Produce an iterator over obj id to class id mappings, sorted by obj id
     fn iter_obj_id_class_id(&self) -> Result<Self::ObjIdClassIdIterator, anyhow::Error>;
@@ -158,6 +757,18 @@ pub trait IndexSequence {
         &self,
     ) -> Result<Self::ObjIdPrimArrayTypeIterator, anyhow::Error>;
 
+    /// Produce an iterator over obj id to outbound reference count mappings, sorted by obj id
+    fn iter_obj_id_reference_count(&self) -> Result<Self::ObjIdRefCountIterator, anyhow::Error>;
+
+    /// Produce an iterator over obj id to (record index, offset) mappings, sorted by obj id
+    fn iter_obj_id_location(&self) -> Result<Self::ObjIdLocationIterator, anyhow::Error>;
+
+    /// Produce an iterator over obj id to shallow size mappings, sorted by obj id
+    fn iter_obj_id_shallow_size(&self) -> Result<Self::ObjIdShallowSizeIterator, anyhow::Error>;
+
+    /// Which mappings were written. Iterators for the rest are empty.
+    fn contents(&self) -> IndexContents;
+
     fn remove_tmp_files(self) -> Result<(), io::Error>;
 }
 
@@ -173,6 +784,20 @@ pub trait RecordWriter {
         prim_array_type: PrimitiveArrayType,
     ) -> Result<(), anyhow::Error>;
 
+    /// Insert a mapping from an object id to the number of non-null references it holds
+    fn write_reference_count(&mut self, obj_id: Id, ref_count: u32) -> Result<(), anyhow::Error>;
+
+    /// Insert a mapping from an object id to the record index and offset of its sub record
+    fn write_location(
+        &mut self,
+        obj_id: Id,
+        record_index: u32,
+        offset: u32,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Insert a mapping from an object id to its shallow size in bytes
+    fn write_shallow_size(&mut self, obj_id: Id, size: u64) -> Result<(), anyhow::Error>;
+
     /// Flush any buffered data
     fn flush(self) -> Result<(), anyhow::Error>;
 }
@@ -188,14 +813,19 @@ pub struct HprofFingerprint {
 }
 
 impl HprofFingerprint {
+    /// Counts the records in `hprof` by scanning only their headers, so this still visits all of
+    /// them, and stops at a truncated record rather than failing. Use
+    /// [HprofFingerprint::with_record_count] if they're already being iterated over.
     pub(crate) fn from_hprof(hprof: &Hprof) -> HprofFingerprint {
-        let timestamp = hprof.header().timestamp_millis();
-        let record_count = crate::util::record_counts(hprof)
-            .iter()
-            .fold(0_u64, |acc, (_, count)| acc + count);
+        let record_count = hprof.scan_record_headers().count() as u64;
+
+        HprofFingerprint::with_record_count(hprof, record_count)
+    }
 
+    /// Use an already known count of the records in `hprof`.
+    pub(crate) fn with_record_count(hprof: &Hprof, record_count: u64) -> HprofFingerprint {
         HprofFingerprint {
-            timestamp,
+            timestamp: hprof.header().timestamp_millis(),
             record_count,
         }
     }
@@ -230,3 +860,667 @@ where
             )))
         })
 }
+
+/// A complete LMDB index of an hprof in a temporary directory, which is removed on drop, so tests
+/// don't each have to build and open one by hand.
+#[cfg(test)]
+pub(crate) struct TempLmdbIndex {
+    dir: tempfile::TempDir,
+    fingerprint: HprofFingerprint,
+}
+
+#[cfg(test)]
+impl TempLmdbIndex {
+    pub(crate) fn build(hprof: &Hprof) -> Result<TempLmdbIndex, anyhow::Error> {
+        TempLmdbIndex::build_with_contents(hprof, IndexContents::all())
+    }
+
+    pub(crate) fn build_with_contents(
+        hprof: &Hprof,
+        contents: IndexContents,
+    ) -> Result<TempLmdbIndex, anyhow::Error> {
+        let dir = tempfile::tempdir()?;
+        build_index(
+            hprof,
+            dir.path(),
+            IndexBackend::Lmdb,
+            contents,
+            &ScratchSpace::default(),
+        )?;
+
+        Ok(TempLmdbIndex {
+            dir,
+            fingerprint: HprofFingerprint::from_hprof(hprof),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &path::Path {
+        self.dir.path()
+    }
+
+    /// LMDB doesn't allow opening an index twice in the same process, so drop the result before
+    /// writing to the index (e.g. with `build-retained`) or opening it again.
+    pub(crate) fn open(&self) -> Result<LmdbIndex, anyhow::Error> {
+        LmdbIndex::open_with_fingerprint(&self.fingerprint, self.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use tempfile;
+
+    #[test]
+    fn build_index_counts_references() -> Result<(), anyhow::Error> {
+        let holder_class = TestClass {
+            instance_fields: vec![
+                (1, TYPE_OBJ),
+                (2, TYPE_INT),
+                (3, TYPE_OBJ),
+                (4, TYPE_OBJ),
+                (5, TYPE_OBJ),
+            ],
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&holder_class)
+            .class(&TestClass::new(101, 0))
+            .class(&TestClass::new(102, 0))
+            // three references and a null
+            .instance(
+                1000,
+                100,
+                &[
+                    Val::Obj(2000),
+                    Val::Int(7),
+                    Val::Obj(0),
+                    Val::Obj(2001),
+                    Val::Obj(3000),
+                ],
+            )
+            .instance(2000, 101, &[])
+            .instance(2001, 101, &[])
+            .object_array(3000, 102, &[2000, 0, 2001, 2000])
+            .int_array(4000, &[1, 2, 3]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let temp_index = TempLmdbIndex::build(&hprof)?;
+        let index = temp_index.open()?;
+
+        assert_eq!(Some(3), index.reference_count(Id::from(1000))?);
+        assert_eq!(Some(0), index.reference_count(Id::from(2000))?);
+        // duplicates are counted, nulls aren't
+        assert_eq!(Some(3), index.reference_count(Id::from(3000))?);
+        // no count for primitive arrays
+        assert_eq!(None, index.reference_count(Id::from(4000))?);
+
+        // locations lead back to the same objects
+        for &obj_id in [100, 1000, 3000, 4000].iter() {
+            let (record_index, offset) = index.get_location(Id::from(obj_id))?.unwrap();
+            let obj_id_at_location = match hprof
+                .sub_record_at(record_index as usize, offset as usize)
+                .unwrap()
+            {
+                SubRecord::Class(c) => c.obj_id(),
+                SubRecord::Instance(instance) => instance.obj_id(),
+                SubRecord::ObjectArray(obj_array) => obj_array.obj_id(),
+                SubRecord::PrimitiveArray(pa) => pa.obj_id(),
+                _ => panic!("Unexpected sub record type"),
+            };
+            assert_eq!(obj_id, obj_id_at_location.id());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_index_stores_shallow_sizes() -> Result<(), anyhow::Error> {
+        let class = TestClass {
+            instance_fields: vec![(1, TYPE_OBJ), (2, TYPE_INT)],
+            instance_size_bytes: 12,
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&class)
+            .class(&TestClass::new(101, 0))
+            .instance(1000, 100, &[Val::Obj(0), Val::Int(1)])
+            .object_array(2000, 101, &[1000, 0, 1000])
+            .int_array(3000, &[1, 2, 3, 4]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let temp_index = TempLmdbIndex::build(&hprof)?;
+        let index = temp_index.open()?;
+
+        // the class's instance size: an 8 byte id and a 4 byte int
+        assert_eq!(Some(12), index.get_shallow_size(Id::from(1000))?);
+        assert_eq!(
+            Some(ARRAY_HEADER_BYTES + 3 * 8),
+            index.get_shallow_size(Id::from(2000))?
+        );
+        assert_eq!(
+            Some(ARRAY_HEADER_BYTES + 4 * 4),
+            index.get_shallow_size(Id::from(3000))?
+        );
+        // classes aren't sized
+        assert_eq!(None, index.get_shallow_size(Id::from(100))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn instance_counts_match_full_heap_pass() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            // no instances
+            .class(&TestClass::new(102, 0))
+            .object_array(3000, 101, &[1000])
+            .int_array(4000, &[1]);
+        for obj_id in 1000..1005 {
+            segment.instance(obj_id, 100, &[]);
+        }
+        for obj_id in 2000..2002 {
+            segment.object_array(obj_id, 101, &[]);
+        }
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut expected = crate::counter::Counter::<Id>::new();
+        for r in hprof.records_iter().map(|r| r.unwrap()) {
+            if let Some(segment) = r.as_heap_dump_segment() {
+                for p in segment.unwrap().sub_records() {
+                    match p.unwrap() {
+                        SubRecord::Instance(i) => expected.increment(i.class_obj_id()),
+                        SubRecord::ObjectArray(oa) => expected.increment(oa.array_class_obj_id()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let lmdb_dir = tempfile::tempdir()?;
+        build_index(
+            &hprof,
+            lmdb_dir.path(),
+            IndexBackend::Lmdb,
+            IndexContents::all(),
+            &ScratchSpace::default(),
+        )?;
+        let sled_dir = tempfile::tempdir()?;
+        build_index(
+            &hprof,
+            sled_dir.path(),
+            IndexBackend::Sled,
+            IndexContents::all(),
+            &ScratchSpace::default(),
+        )?;
+
+        for index in [
+            open_index(&hprof, IndexBackend::Lmdb, Some(lmdb_dir.path()))?,
+            open_index(&hprof, IndexBackend::Sled, Some(sled_dir.path()))?,
+            open_index(&hprof, IndexBackend::Memory, None)?,
+        ]
+        .iter()
+        {
+            for class_id in [100, 101, 102].iter().map(|&id| Id::from(id)) {
+                assert_eq!(
+                    expected.get(&class_id),
+                    index.instance_count(class_id)?,
+                    "class id: {}",
+                    class_id
+                );
+            }
+        }
+        assert_eq!(Some(5), expected.get(&Id::from(100)));
+        assert_eq!(Some(3), expected.get(&Id::from(101)));
+
+        drop(lmdb_dir);
+        drop(sled_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn build_index_without_skipped_chunk_types() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[]);
+        for obj_id in 2000..2100 {
+            segment.int_array(obj_id, &[1, 2, 3]);
+        }
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let index_size = |dir: &path::Path| -> Result<u64, io::Error> {
+            fs::read_dir(dir.join("lmdb"))?
+                .map(|r| r.and_then(|de| de.metadata()).map(|m| m.len()))
+                .sum()
+        };
+
+        let full_index = TempLmdbIndex::build(&hprof)?;
+        let skipped_index = TempLmdbIndex::build_with_contents(
+            &hprof,
+            IndexContents {
+                prim_array_types: false,
+                ..IndexContents::all()
+            },
+        )?;
+
+        let full = full_index.open()?;
+        let skipped = skipped_index.open()?;
+
+        for index in [&full, &skipped].iter() {
+            assert_eq!(Some(Id::from(100)), index.get_class_id(Id::from(1000))?);
+            assert!(index.get_location(Id::from(2000))?.is_some());
+            assert_eq!(
+                Some(ARRAY_HEADER_BYTES + 3 * 4),
+                index.get_shallow_size(Id::from(2000))?
+            );
+        }
+        assert_eq!(
+            Some(PrimitiveArrayType::Int),
+            full.get_prim_array_type(Id::from(2000))?
+        );
+        assert_eq!(
+            "Index was built without primitive array types; rebuild it without skipping them",
+            skipped
+                .get_prim_array_type(Id::from(2000))
+                .unwrap_err()
+                .to_string()
+        );
+        assert!(index_size(skipped_index.path())? < index_size(full_index.path())?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_index_with_separate_scratch_dir() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            .int_array(2000, &[1]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let fingerprint = HprofFingerprint::from_hprof(&hprof);
+        let dir_names = |dir: &path::Path| -> Result<Vec<String>, io::Error> {
+            Ok(fs::read_dir(dir)?
+                .map(|r| r.map(|de| de.file_name().into_string().unwrap()))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .sorted()
+                .collect())
+        };
+
+        let index_dir = tempfile::tempdir()?;
+        let scratch_dir = tempfile::tempdir()?;
+        let scratch = ScratchSpace {
+            dir: Some(scratch_dir.path().to_path_buf()),
+            keep: false,
+        };
+        build_index(
+            &hprof,
+            index_dir.path(),
+            IndexBackend::Lmdb,
+            IndexContents::all(),
+            &scratch,
+        )?;
+
+        let index = LmdbIndex::open_with_fingerprint(&fingerprint, index_dir.path())?;
+        assert_eq!(Some(Id::from(100)), index.get_class_id(Id::from(1000))?);
+        assert_eq!(vec!["lmdb"], dir_names(index_dir.path())?);
+        assert!(dir_names(scratch_dir.path())?.is_empty());
+
+        // the same scratch dir can be used again, and this time the chunks are kept
+        let kept_index_dir = tempfile::tempdir()?;
+        let kept_scratch = ScratchSpace {
+            keep: true,
+            ..scratch.clone()
+        };
+        build_index(
+            &hprof,
+            kept_index_dir.path(),
+            IndexBackend::Lmdb,
+            IndexContents::all(),
+            &kept_scratch,
+        )?;
+
+        assert_eq!(vec!["lmdb"], dir_names(kept_index_dir.path())?);
+        // each type of data fits in one chunk, so there was nothing to merge
+        assert_eq!(vec!["chunks"], dir_names(scratch_dir.path())?);
+        assert_eq!(
+            vec!["record-0000000000-chunk-000"],
+            dir_names(&scratch_dir.path().join("chunks").join(SUBDIR_OBJ_CLASS))?
+        );
+
+        // but not while it still has chunks in it
+        assert!(build_index(
+            &hprof,
+            tempfile::tempdir()?.path(),
+            IndexBackend::Lmdb,
+            IndexContents::all(),
+            &kept_scratch,
+        )
+        .is_err());
+
+        drop(index_dir);
+        drop(kept_index_dir);
+        drop(scratch_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn build_index_seq_counts_records() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "Thing").load_class(1, 100, 0, 1);
+        let mut first_segment = builder.segment();
+        first_segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[]);
+        let mut second_segment = builder.segment();
+        second_segment.int_array(2000, &[1]);
+        let bytes = builder
+            .heap_dump_segment(&first_segment)
+            .heap_dump_segment(&second_segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let tmp_dir = tempfile::tempdir()?;
+        let (index_seq, record_count) =
+            build_index_seq(&hprof, tmp_dir.path(), IndexContents::all(), false)?;
+        index_seq.remove_tmp_files()?;
+
+        assert_eq!(hprof.records_iter().count() as u64, record_count);
+        assert_eq!(5, record_count);
+        assert_eq!(
+            HprofFingerprint::from_hprof(&hprof),
+            HprofFingerprint::with_record_count(&hprof, record_count)
+        );
+
+        drop(tmp_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn export_obj_class_map_reads_back_sorted() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        let mut first_segment = builder.segment();
+        first_segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .instance(3000, 100, &[])
+            .int_array(4000, &[1, 2]);
+        let mut second_segment = builder.segment();
+        second_segment
+            .object_array(2000, 101, &[3000])
+            .instance(1000, 100, &[]);
+        let bytes = builder
+            .heap_dump_segment(&first_segment)
+            .heap_dump_segment(&second_segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let output_dir = tempfile::tempdir()?;
+        export_obj_class_map(&hprof, output_dir.path())?;
+
+        let pairs = ChunkDatumIterator::<_, _, U64PairData>::new(io::BufReader::new(
+            fs::File::open(output_dir.path().join(EXPORT_OBJ_CLASS_FILE))?,
+        ))
+        .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(vec![(1000, 100), (2000, 101), (3000, 100)], pairs);
+
+        let fingerprint = HprofFingerprint::from_hprof(&hprof);
+        assert_eq!(
+            format!(
+                "file=obj-id-class-id.bin\n\
+                 format=u64 obj id, u64 class id, little endian\n\
+                 entries=3\n\
+                 hprof_timestamp_millis={}\n\
+                 hprof_record_count={}\n",
+                fingerprint.timestamp, fingerprint.record_count
+            ),
+            fs::read_to_string(output_dir.path().join(EXPORT_MANIFEST_FILE))?
+        );
+
+        // only the exported files are left behind
+        assert_eq!(
+            vec![EXPORT_MANIFEST_FILE, EXPORT_OBJ_CLASS_FILE],
+            fs::read_dir(output_dir.path())?
+                .map(|r| r.map(|de| de.file_name().into_string().unwrap()))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .sorted()
+                .collect::<Vec<_>>()
+        );
+
+        drop(output_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_all_reference_target_kinds() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Thing")
+            .utf8(2, "[LThing;")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 101, &[1000])
+            .int_array(3000, &[1, 2]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let temp_index = TempLmdbIndex::build(&hprof)?;
+        let index = temp_index.open()?;
+        let classes = crate::util::ez_classes_by_obj_id(&hprof);
+
+        assert_eq!(
+            Some(ReferenceTarget::Instance {
+                class_obj_id: Id::from(100)
+            }),
+            resolve_reference_target(Id::from(1000), &index, &classes)?
+        );
+        assert_eq!(
+            Some(ReferenceTarget::Instance {
+                class_obj_id: Id::from(101)
+            }),
+            resolve_reference_target(Id::from(2000), &index, &classes)?
+        );
+        assert_eq!(
+            Some(ReferenceTarget::PrimitiveArray {
+                prim_type: PrimitiveArrayType::Int
+            }),
+            resolve_reference_target(Id::from(3000), &index, &classes)?
+        );
+        assert_eq!(
+            Some(ReferenceTarget::ClassObj {
+                class_obj_id: Id::from(100)
+            }),
+            resolve_reference_target(Id::from(100), &index, &classes)?
+        );
+        assert_eq!(
+            None,
+            resolve_reference_target(Id::from(9999), &index, &classes)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch_class_id_lookups_match_single_lookups() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "Thing").load_class(1, 100, 0, 1);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            .instance(1001, 100, &[])
+            .object_array(2000, 100, &[1000, 1001])
+            .int_array(3000, &[1]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let classes = crate::util::ez_classes_by_obj_id(&hprof);
+
+        let temp_index = TempLmdbIndex::build(&hprof)?;
+
+        // unsorted, with duplicates, and things that aren't plain objects or aren't there at all
+        let obj_ids = [2000, 1001, 9999, 1000, 3000, 100, 1001]
+            .iter()
+            .map(|&id| Id::from(id))
+            .collect::<Vec<_>>();
+
+        for index in [
+            open_index(&hprof, IndexBackend::Lmdb, Some(temp_index.path()))?,
+            open_index(&hprof, IndexBackend::Memory, None)?,
+        ]
+        .iter()
+        {
+            let single = obj_ids
+                .iter()
+                .map(|&id| index.get_class_id(id))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(single, index.get_class_ids(&obj_ids)?);
+            assert_eq!(4, single.iter().filter(|c| c.is_some()).count());
+            assert!(index.get_class_ids(&[])?.is_empty());
+
+            let single_targets = obj_ids
+                .iter()
+                .map(|&id| resolve_reference_target(id, index, &classes))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(
+                single_targets,
+                resolve_reference_targets(&obj_ids, index, &classes)?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_index_with_each_backend() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 100, &[1000, 0])
+            .int_array(3000, &[1, 2]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let lmdb_dir = tempfile::tempdir()?;
+        build_index(
+            &hprof,
+            lmdb_dir.path(),
+            "lmdb".parse()?,
+            IndexContents::all(),
+            &ScratchSpace::default(),
+        )?;
+        let sled_dir = tempfile::tempdir()?;
+        build_index(
+            &hprof,
+            sled_dir.path(),
+            "sled".parse()?,
+            IndexContents::all(),
+            &ScratchSpace::default(),
+        )?;
+        assert!(build_index(
+            &hprof,
+            sled_dir.path(),
+            IndexBackend::Memory,
+            IndexContents::all(),
+            &ScratchSpace::default(),
+        )
+        .is_err());
+
+        let indexes = vec![
+            open_index(&hprof, "lmdb".parse()?, Some(lmdb_dir.path()))?,
+            open_index(&hprof, "sled".parse()?, Some(sled_dir.path()))?,
+            open_index(&hprof, "memory".parse()?, None)?,
+        ];
+        assert!(matches!(indexes[0], AnyIndex::Lmdb(_)));
+        assert!(matches!(indexes[1], AnyIndex::Sled(_)));
+        assert!(matches!(indexes[2], AnyIndex::Memory(_)));
+
+        for index in indexes.iter() {
+            assert_eq!(Some(Id::from(100)), index.get_class_id(Id::from(1000))?);
+            assert_eq!(Some(Id::from(100)), index.get_class_id(Id::from(2000))?);
+            assert_eq!(
+                Some(PrimitiveArrayType::Int),
+                index.get_prim_array_type(Id::from(3000))?
+            );
+            assert_eq!(Some(1), index.reference_count(Id::from(2000))?);
+            assert!(index.get_location(Id::from(3000))?.is_some());
+            assert_eq!(
+                Some(ARRAY_HEADER_BYTES + 2 * 8),
+                index.get_shallow_size(Id::from(2000))?
+            );
+            assert_eq!(None, index.get_class_id(Id::from(9999))?);
+        }
+
+        // only the in-memory index can do without a path
+        assert!(open_index(&hprof, IndexBackend::Sled, None).is_err());
+        assert!("rocksdb".parse::<IndexBackend>().is_err());
+
+        drop(lmdb_dir);
+        drop(sled_dir);
+        Ok(())
+    }
+
+    #[test]
+    fn open_missing_sled_index_fails_without_creating_it() -> Result<(), anyhow::Error> {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.class(&TestClass::new(100, 0));
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let index_dir = tempfile::tempdir()?;
+        let err = open_index(&hprof, IndexBackend::Sled, Some(index_dir.path()))
+            .err()
+            .expect("Opening a missing index should fail");
+        assert!(err.to_string().starts_with("No sled index at"), "{}", err);
+        assert!(!index_dir.path().join("sled").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_counts_records_of_truncated_hprof() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .int_array(3000, &[1, 2]);
+        let bytes = builder
+            .utf8(1, "hello")
+            .heap_dump_segment(&segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        assert_eq!(3, HprofFingerprint::from_hprof(&hprof).record_count);
+
+        // cut off the heap dump end record and part of the segment
+        let truncated = parse_hprof(&bytes[..bytes.len() - 12]).unwrap();
+        assert_eq!(1, HprofFingerprint::from_hprof(&truncated).record_count);
+    }
+}
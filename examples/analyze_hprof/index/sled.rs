@@ -0,0 +1,256 @@
+use crate::index::{
+    build_if_fingerprint_match, require_mapping, HprofFingerprint, Index, IndexBuilder,
+    IndexContents, IndexSequence,
+};
+
+use std::convert::TryInto;
+use std::io::Write;
+use std::{collections, fs, io, path};
+
+use anyhow::Context;
+use jvm_hprof::heap_dump::PrimitiveArrayType;
+use jvm_hprof::Id;
+use sled;
+
+// fingerprint keys
+const FP_TIMESTAMP: &str = "__hprof_header_fingerprint_timestamp";
+const FP_RECORD_COUNT: &str = "__hprof_header_fingerprint_record_count";
+// which optional mappings were written, as per IndexContents
+const INDEX_CONTENTS: &str = "__index_contents";
+
+// tree names
+const TREE_METADATA: &str = "metadata";
+const TREE_OBJ_ID_CLASS_ID: &str = "obj_id_class_id";
+const TREE_OBJ_ID_PRIM_TYPE: &str = "obj_id_prim_type";
+const TREE_OBJ_ID_REF_COUNT: &str = "obj_id_ref_count";
+const TREE_OBJ_ID_LOCATION: &str = "obj_id_location";
+const TREE_OBJ_ID_SHALLOW_SIZE: &str = "obj_id_shallow_size";
+// absent in indexes from older versions
+const TREE_CLASS_ID_INSTANCE_COUNT: &str = "class_id_instance_count";
+
+pub(crate) struct SledIndex {
+    contents: IndexContents,
+    obj_id_class_id_tree: sled::Tree,
+    obj_id_prim_array_type_tree: sled::Tree,
+    obj_id_ref_count_tree: sled::Tree,
+    obj_id_location_tree: sled::Tree,
+    obj_id_shallow_size_tree: sled::Tree,
+    class_id_instance_count_tree: Option<sled::Tree>,
+}
+
+impl Index for SledIndex {
+    fn get_class_id(&self, obj_id: Id) -> Result<Option<Id>, anyhow::Error> {
+        Ok(self
+            .obj_id_class_id_tree
+            .get(obj_id.id().to_be_bytes())?
+            .map(|bytes| {
+                Id::from(u64::from_be_bytes(
+                    bytes.as_ref().try_into().expect("Invalid index value"),
+                ))
+            }))
+    }
+
+    fn get_prim_array_type(&self, obj_id: Id) -> Result<Option<PrimitiveArrayType>, anyhow::Error> {
+        require_mapping(self.contents.prim_array_types, "primitive array types")?;
+
+        Ok(self
+            .obj_id_prim_array_type_tree
+            .get(obj_id.id().to_be_bytes())?
+            .map(|bytes| {
+                PrimitiveArrayType::from_type_code(bytes[0]).expect("Invalid index value")
+            }))
+    }
+
+    fn reference_count(&self, obj_id: Id) -> Result<Option<u32>, anyhow::Error> {
+        require_mapping(self.contents.reference_counts, "reference counts")?;
+
+        Ok(self
+            .obj_id_ref_count_tree
+            .get(obj_id.id().to_be_bytes())?
+            .map(|bytes| {
+                u32::from_be_bytes(bytes.as_ref().try_into().expect("Invalid index value"))
+            }))
+    }
+
+    fn get_location(&self, obj_id: Id) -> Result<Option<(u32, u32)>, anyhow::Error> {
+        require_mapping(self.contents.locations, "sub record locations")?;
+
+        Ok(self
+            .obj_id_location_tree
+            .get(obj_id.id().to_be_bytes())?
+            .map(|bytes| {
+                (
+                    u32::from_be_bytes(bytes[0..4].try_into().expect("Invalid index value")),
+                    u32::from_be_bytes(bytes[4..].try_into().expect("Invalid index value")),
+                )
+            }))
+    }
+
+    fn get_shallow_size(&self, obj_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        require_mapping(self.contents.shallow_sizes, "shallow sizes")?;
+
+        Ok(self
+            .obj_id_shallow_size_tree
+            .get(obj_id.id().to_be_bytes())?
+            .map(|bytes| {
+                u64::from_be_bytes(bytes.as_ref().try_into().expect("Invalid index value"))
+            }))
+    }
+
+    fn instance_count(&self, class_id: Id) -> Result<Option<u64>, anyhow::Error> {
+        let tree = self.class_id_instance_count_tree.as_ref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "Index has no instance counts (indexes from older versions must be rebuilt)"
+            )
+        })?;
+
+        Ok(tree.get(class_id.id().to_be_bytes())?.map(|bytes| {
+            u64::from_be_bytes(bytes.as_ref().try_into().expect("Invalid index value"))
+        }))
+    }
+}
+
+impl IndexBuilder for SledIndex {
+    fn open_with_fingerprint(
+        fingerprint: &HprofFingerprint,
+        index_path: &path::Path,
+    ) -> Result<Self, anyhow::Error> {
+        let sled_dir = sled_dir(index_path);
+        // sled::open would quietly create an empty db
+        if !sled_dir.exists() {
+            return Err(anyhow::anyhow!("No sled index at {}", index_path.display()));
+        }
+
+        let db = sled::open(&sled_dir).with_context(|| "Opening sled index")?;
+
+        let metadata_tree = db.open_tree(TREE_METADATA)?;
+        let ts = metadata_tree.get(FP_TIMESTAMP)?;
+        let record_count = metadata_tree.get(FP_RECORD_COUNT)?;
+        let contents = IndexContents::from_stored_flags(metadata_tree.get(INDEX_CONTENTS)?)?;
+
+        build_if_fingerprint_match(fingerprint, ts, record_count, || {
+            Ok(SledIndex {
+                contents,
+                obj_id_class_id_tree: db.open_tree(TREE_OBJ_ID_CLASS_ID)?,
+                obj_id_prim_array_type_tree: db.open_tree(TREE_OBJ_ID_PRIM_TYPE)?,
+                obj_id_ref_count_tree: db.open_tree(TREE_OBJ_ID_REF_COUNT)?,
+                obj_id_location_tree: db.open_tree(TREE_OBJ_ID_LOCATION)?,
+                obj_id_shallow_size_tree: db.open_tree(TREE_OBJ_ID_SHALLOW_SIZE)?,
+                // opening a tree creates it, so check that older indexes have it first
+                class_id_instance_count_tree: if db
+                    .tree_names()
+                    .iter()
+                    .any(|name| name == TREE_CLASS_ID_INSTANCE_COUNT.as_bytes())
+                {
+                    Some(db.open_tree(TREE_CLASS_ID_INSTANCE_COUNT)?)
+                } else {
+                    None
+                },
+            })
+        })
+    }
+
+    fn build_index<S: IndexSequence>(
+        seq: &S,
+        fingerprint: &HprofFingerprint,
+        index_path: &path::Path,
+    ) -> Result<(), anyhow::Error> {
+        let sled_dir = sled_dir(index_path);
+        // start from scratch so that keys from a previous build don't linger
+        if sled_dir.exists() {
+            fs::remove_dir_all(&sled_dir)?;
+        }
+
+        let db = sled::open(&sled_dir)?;
+
+        // using big-endian to stay consistent with the rest of the numbers
+        let metadata_tree = db.open_tree(TREE_METADATA)?;
+        metadata_tree.insert(FP_TIMESTAMP, &fingerprint.timestamp.to_be_bytes())?;
+        metadata_tree.insert(FP_RECORD_COUNT, &fingerprint.record_count.to_be_bytes())?;
+        metadata_tree.insert(INDEX_CONTENTS, &[seq.contents().to_flags()])?;
+
+        let mut progress = Progress::new();
+
+        // class id -> instance count, accumulated while writing class ids
+        let mut instance_counts = collections::HashMap::<u64, u64>::new();
+
+        let tree = db.open_tree(TREE_OBJ_ID_CLASS_ID)?;
+        for res in seq.iter_obj_id_class_id()? {
+            let (key, value) = res?;
+            *instance_counts.entry(value).or_default() += 1;
+            tree.insert(key.to_be_bytes(), &value.to_be_bytes())?;
+            progress.inserted()?;
+        }
+
+        let tree = db.open_tree(TREE_OBJ_ID_PRIM_TYPE)?;
+        for res in seq.iter_obj_id_prim_array_type()? {
+            let (key, value) = res?;
+            tree.insert(key.to_be_bytes(), &[value])?;
+            progress.inserted()?;
+        }
+
+        let tree = db.open_tree(TREE_OBJ_ID_REF_COUNT)?;
+        for res in seq.iter_obj_id_reference_count()? {
+            let (key, value) = res?;
+            tree.insert(key.to_be_bytes(), &value.to_be_bytes())?;
+            progress.inserted()?;
+        }
+
+        let tree = db.open_tree(TREE_OBJ_ID_LOCATION)?;
+        for res in seq.iter_obj_id_location()? {
+            let (key, (record_index, offset)) = res?;
+            let mut value = [0_u8; 8];
+            value[0..4].copy_from_slice(&record_index.to_be_bytes());
+            value[4..].copy_from_slice(&offset.to_be_bytes());
+            tree.insert(key.to_be_bytes(), &value)?;
+            progress.inserted()?;
+        }
+
+        let tree = db.open_tree(TREE_OBJ_ID_SHALLOW_SIZE)?;
+        for res in seq.iter_obj_id_shallow_size()? {
+            let (key, value) = res?;
+            tree.insert(key.to_be_bytes(), &value.to_be_bytes())?;
+            progress.inserted()?;
+        }
+
+        let tree = db.open_tree(TREE_CLASS_ID_INSTANCE_COUNT)?;
+        for (class_id, count) in instance_counts {
+            tree.insert(class_id.to_be_bytes(), &count.to_be_bytes())?;
+        }
+
+        db.flush()?;
+
+        Ok(())
+    }
+}
+
+fn sled_dir(index_path: &path::Path) -> path::PathBuf {
+    let mut sled_dir = index_path.to_path_buf();
+    sled_dir.push("sled");
+    sled_dir
+}
+
+/// Prints a `.` every million inserts
+struct Progress {
+    count_since_last_print: u64,
+}
+
+impl Progress {
+    fn new() -> Progress {
+        Progress {
+            count_since_last_print: 0,
+        }
+    }
+
+    fn inserted(&mut self) -> Result<(), io::Error> {
+        self.count_since_last_print += 1;
+
+        if self.count_since_last_print == 1_000_000 {
+            eprint!(".");
+            io::stderr().flush()?;
+            self.count_since_last_print = 0;
+        }
+
+        Ok(())
+    }
+}
@@ -3,12 +3,29 @@ use csv;
 use rayon;
 
 use crate::counter::Counter;
+use crate::util::ObjIdRange;
 use itertools::Itertools;
 use jvm_hprof::{heap_dump::*, *};
 use rayon::iter::{ParallelBridge, ParallelIterator};
-use std::{collections, io, ops};
+use std::{cmp, collections, io, ops};
+
+/// Write a CSV of the instance count for each class, highest count first.
+///
+/// Along with the total shallow size of each class's instances, the shallow size of its largest
+/// instance is included. That's just the instance size for ordinary classes, but for array classes
+/// it's the size of the longest array.
+///
+/// Classes with fewer than `min_count` instances are omitted, and if `top` is provided, at most
+/// that many classes are written.
+pub(crate) fn instance_counts<W: io::Write>(
+    hprof: &Hprof,
+    obj_id_range: &ObjIdRange,
+    min_count: u64,
+    top: Option<usize>,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let id_size = hprof.header().id_size();
 
-pub(crate) fn instance_counts(hprof: &Hprof) -> Result<(), anyhow::Error> {
     let accumulated_state: InstanceCountRecordState = hprof
         .records_iter()
         .map(|r| r.unwrap())
@@ -41,13 +58,23 @@ pub(crate) fn instance_counts(hprof: &Hprof) -> Result<(), anyhow::Error> {
                             SubRecord::Class(c) => {
                                 state.classes.insert(c.obj_id(), c);
                             }
-                            SubRecord::Instance(instance) => {
+                            SubRecord::Instance(instance)
+                                if obj_id_range.contains(instance.obj_id()) =>
+                            {
                                 state.instance_counts.increment(instance.class_obj_id())
                             }
-                            SubRecord::ObjectArray(obj_array) => state
-                                .instance_counts
-                                .increment(obj_array.array_class_obj_id()),
-                            SubRecord::PrimitiveArray(pa) => {
+                            SubRecord::ObjectArray(obj_array)
+                                if obj_id_range.contains(obj_array.obj_id()) =>
+                            {
+                                state
+                                    .instance_counts
+                                    .increment(obj_array.array_class_obj_id());
+                                state.update_max_array_len(
+                                    obj_array.array_class_obj_id(),
+                                    obj_array.num_elements(),
+                                );
+                            }
+                            SubRecord::PrimitiveArray(pa) if obj_id_range.contains(pa.obj_id()) => {
                                 state.prim_array_counts.increment(pa.primitive_type())
                             }
 
@@ -68,21 +95,26 @@ pub(crate) fn instance_counts(hprof: &Hprof) -> Result<(), anyhow::Error> {
             },
         );
 
-    let mut wtr = csv::Writer::from_writer(io::stdout());
+    let mut wtr = csv::Writer::from_writer(out);
     wtr.write_record(&[
         "Instance count",
         "Instance size (bytes)",
         "Total shallow instance size (bytes)",
+        "Max instance size (bytes)",
         "Class name",
         "Class obj id",
     ])?;
 
-    for (class_obj_id, count) in accumulated_state.instance_counts.iter().sorted_by(
-        |(_left_id, left_count), (_right_id, right_count)| {
+    for (class_obj_id, count) in accumulated_state
+        .instance_counts
+        .iter()
+        .filter(|(_id, &count)| count >= min_count)
+        .sorted_by(|(_left_id, left_count), (_right_id, right_count)| {
             // reverse order to put highest counts on top
             Ord::cmp(right_count, left_count)
-        },
-    ) {
+        })
+        .take(top.unwrap_or(usize::MAX))
+    {
         let class = accumulated_state.classes.get(class_obj_id);
         let load_class = accumulated_state.load_classes.get(class_obj_id);
 
@@ -90,6 +122,10 @@ pub(crate) fn instance_counts(hprof: &Hprof) -> Result<(), anyhow::Error> {
         let total_instance_size = instance_size
             .map(|s| (s as u64) * count)
             .map(|s| s.to_string());
+        let max_instance_size = match accumulated_state.max_array_lens.get(class_obj_id) {
+            Some(&len) => Some(ARRAY_HEADER_BYTES + len as u64 * id_size.size_in_bytes() as u64),
+            None => instance_size.map(u64::from),
+        };
 
         wtr.write_record(&[
             format!("{}", count),
@@ -97,6 +133,9 @@ pub(crate) fn instance_counts(hprof: &Hprof) -> Result<(), anyhow::Error> {
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| String::from("")),
             total_instance_size.unwrap_or_else(|| String::from("")),
+            max_instance_size
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| String::from("")),
             load_class
                 .map(|lc| lc.class_name_id())
                 .and_then(|id| accumulated_state.utf8.get(&id))
@@ -122,6 +161,15 @@ struct InstanceCountRecordState<'a> {
     // class id -> count
     instance_counts: Counter<Id>,
     prim_array_counts: Counter<PrimitiveArrayType>,
+    // array class id -> most elements in any one array
+    max_array_lens: collections::HashMap<Id, u32>,
+}
+
+impl<'a> InstanceCountRecordState<'a> {
+    fn update_max_array_len(&mut self, array_class_obj_id: Id, len: u32) {
+        let max = self.max_array_lens.entry(array_class_obj_id).or_insert(0);
+        *max = cmp::max(*max, len);
+    }
 }
 
 /// Merges the underlying data
@@ -135,5 +183,89 @@ impl<'a> ops::AddAssign for InstanceCountRecordState<'a> {
         // sum the counts for count maps
         self.instance_counts += rhs.instance_counts;
         self.prim_array_counts += rhs.prim_array_counts;
+        for (array_class_obj_id, len) in rhs.max_array_lens {
+            self.update_max_array_len(array_class_obj_id, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn filter_by_min_count_and_top() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Few")
+            .utf8(2, "Some")
+            .utf8(3, "Many")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .class(&TestClass::new(102, 0))
+            .instance(1000, 100, &[]);
+        for obj_id in 2000..2003 {
+            segment.instance(obj_id, 101, &[]);
+        }
+        for obj_id in 3000..3005 {
+            segment.instance(obj_id, 102, &[]);
+        }
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        instance_counts(&hprof, &ObjIdRange::all(), 2, None, &mut out).unwrap();
+        assert_eq!(
+            format!("{}5,0,0,0,Many,102\n3,0,0,0,Some,101\n", HEADER),
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        instance_counts(&hprof, &ObjIdRange::all(), 1, Some(1), &mut out).unwrap();
+        assert_eq!(
+            format!("{}5,0,0,0,Many,102\n", HEADER),
+            String::from_utf8(out).unwrap()
+        );
     }
+
+    #[test]
+    fn max_instance_size_is_longest_array() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Thing")
+            .utf8(2, "[LThing;")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass {
+                instance_size_bytes: 12,
+                ..TestClass::new(100, 0)
+            })
+            .class(&TestClass::new(101, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 101, &[1000, 1000, 1000])
+            .object_array(2001, 101, &[1000]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        instance_counts(&hprof, &ObjIdRange::all(), 1, None, &mut out).unwrap();
+        assert_eq!(
+            // longest array: 16 byte header + 3 8-byte ids
+            format!("{}2,0,0,40,[LThing;,101\n1,12,12,12,Thing,100\n", HEADER),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    const HEADER: &str =
+        "Instance count,Instance size (bytes),Total shallow instance size (bytes),\
+                          Max instance size (bytes),Class name,Class obj id\n";
 }
@@ -0,0 +1,95 @@
+use crate::util::*;
+use jvm_hprof::{heap_dump::*, *};
+use std::collections;
+
+// String.coder values as of JDK 9's compact strings
+const CODER_LATIN1: i8 = 0;
+const CODER_UTF16: i8 = 1;
+
+/// Decode the text of the `java/lang/String` instances with the provided ids.
+///
+/// The String instance and its backing array can be anywhere in the heap dump, so this takes two
+/// passes: one to find each String's `value` array, and another to decode the arrays.
+///
+/// Ids that aren't Strings, or whose arrays can't be found, are absent from the result.
+pub fn decode_strings(
+    hprof: &Hprof,
+    string_obj_ids: &collections::HashSet<Id>,
+    class_instance_field_descriptors: &collections::HashMap<Id, Vec<FieldDescriptor>>,
+    utf8: &collections::HashMap<Id, &str>,
+) -> collections::HashMap<Id, String> {
+    let id_size = hprof.header().id_size();
+
+    // backing array id -> (String obj id, coder)
+    let mut arrays: collections::HashMap<Id, (Id, Option<i8>)> = collections::HashMap::new();
+
+    for_each_sub_record(hprof, |s| {
+        if let SubRecord::Instance(instance) = s {
+            if !string_obj_ids.contains(&instance.obj_id()) {
+                return;
+            }
+
+            let field_descriptors =
+                match class_instance_field_descriptors.get(&instance.class_obj_id()) {
+                    None => return,
+                    Some(fds) => fds,
+                };
+
+            let value = instance_field_value(&instance, field_descriptors, utf8, "value", id_size);
+            // coder is absent before JDK 9
+            let coder = instance_field_value(&instance, field_descriptors, utf8, "coder", id_size)
+                .and_then(|v| match v {
+                    FieldValue::Byte(b) => Some(b),
+                    _ => None,
+                });
+
+            if let Some(FieldValue::ObjectId(Some(array_id))) = value {
+                arrays.insert(array_id, (instance.obj_id(), coder));
+            }
+        }
+    });
+
+    let mut strings = collections::HashMap::new();
+
+    for_each_sub_record(hprof, |s| {
+        if let SubRecord::PrimitiveArray(pa) = s {
+            if let Some((string_id, coder)) = arrays.get(&pa.obj_id()) {
+                if let Some(text) = decode_string_value(&pa, *coder) {
+                    strings.insert(*string_id, text);
+                }
+            }
+        }
+    });
+
+    strings
+}
+
+/// Decode the contents of a String's `value` array.
+///
+/// Before JDK 9, `value` is a `char[]`. After, it's a `byte[]` whose encoding is determined by
+/// `coder`: Latin-1, or UTF-16 in the platform's byte order (assumed to be little endian).
+pub fn decode_string_value(value: &PrimitiveArray, coder: Option<i8>) -> Option<String> {
+    match value.primitive_type() {
+        PrimitiveArrayType::Char => value.to_string_lossy(),
+        PrimitiveArrayType::Byte => {
+            let bytes = value
+                .to_vec_bytes()
+                .unwrap()
+                .into_iter()
+                .map(|b| b as u8)
+                .collect::<Vec<_>>();
+            match coder.unwrap_or(CODER_LATIN1) {
+                CODER_LATIN1 => Some(bytes.iter().map(|&b| b as char).collect()),
+                CODER_UTF16 => {
+                    let chars = bytes
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                        .collect::<Vec<_>>();
+                    Some(String::from_utf16_lossy(&chars))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,295 @@
+use crate::dominators::*;
+use jvm_hprof::*;
+use std::{collections, io};
+
+/// Write a CSV of the classes whose objects retain the most memory, in the spirit of Eclipse MAT's
+/// leak suspects report.
+///
+/// Builds the dominator tree of the whole object graph in memory, with every GC root reachable
+/// from a synthetic top-level node. Objects that aren't dominated by any other object (GC roots,
+/// or objects reachable via several independent paths) are grouped by class, and each class whose
+/// objects together retain at least `min_percent` of all reachable bytes is a suspect, biggest
+/// first, at most `top` of them. A single huge object and thousands of small ones of the same
+/// class are therefore both found.
+///
+/// For the biggest object of each suspect class, the dominator path is followed down to the
+/// "accumulation point": the deepest object that still retains most of what that object does,
+/// which is usually the collection that's growing without bound. The accumulation point's chain
+/// of immediate dominators is then walked back up, and prefixed with the shortest chain of
+/// references from a GC root if the top of the dominator tree isn't itself a GC root, so the
+/// path shown always starts at a GC root.
+///
+/// Sizes are shallow sizes as per [jvm_hprof::heap_dump::shallow_size]: each class's instance size
+/// for instances, and an estimated header plus elements for arrays.
+///
+/// If `exclude_weak_refs` is set, the `referent` of soft, weak, and phantom references (any
+/// subclass of `java.lang.ref.Reference`) isn't followed, so objects only reachable that way don't
+/// count towards anything's retained size.
+pub(crate) fn leak_suspects<W: io::Write>(
+    hprof: &Hprof,
+    min_percent: f64,
+    top: usize,
+    exclude_weak_refs: bool,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let graph = ObjGraph::build(hprof, exclude_weak_refs);
+    let dominators = Dominators::compute(&graph);
+    let total = dominators.retained[SUPER_ROOT];
+
+    // type name -> the top-level objects of that type
+    let mut by_class = collections::HashMap::<&str, ClassSuspect>::new();
+    for &node in dominators.children[SUPER_ROOT].iter() {
+        let suspect = by_class
+            .entry(graph.type_names[node].as_str())
+            .or_insert_with(|| ClassSuspect {
+                count: 0,
+                retained: 0,
+                biggest: node,
+            });
+        suspect.count += 1;
+        suspect.retained += dominators.retained[node];
+        if (u64::MAX - dominators.retained[node], graph.obj_ids[node])
+            < (
+                u64::MAX - dominators.retained[suspect.biggest],
+                graph.obj_ids[suspect.biggest],
+            )
+        {
+            suspect.biggest = node;
+        }
+    }
+
+    let mut suspects = by_class
+        .into_iter()
+        .filter(|(_, s)| s.retained as f64 >= total as f64 * min_percent / 100.0)
+        .collect::<Vec<_>>();
+    suspects.sort_by_key(|&(class, ref s)| (u64::MAX - s.retained, class));
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Class",
+        "Objects",
+        "Retained bytes",
+        "Retained %",
+        "Biggest obj id",
+        "Biggest retained bytes",
+        "Accumulation point retained bytes",
+        "Accumulation point class",
+        "Accumulation point obj id",
+        "GC root path",
+    ])?;
+
+    for (class, suspect) in suspects.iter().take(top) {
+        let biggest = suspect.biggest;
+        let accumulation_point = *dominators
+            .accumulation_path(biggest)
+            .last()
+            .expect("Path includes the suspect");
+
+        let dominator_path = dominators.path_from_top(accumulation_point);
+        let mut path = graph
+            .path_from_gc_root(dominator_path[0])
+            .expect("Suspects are reachable");
+        path.extend_from_slice(&dominator_path[1..]);
+
+        wtr.write_record(&[
+            class.to_string(),
+            suspect.count.to_string(),
+            suspect.retained.to_string(),
+            format!("{:.1}", suspect.retained as f64 * 100.0 / total as f64),
+            graph.obj_ids[biggest].to_string(),
+            dominators.retained[biggest].to_string(),
+            dominators.retained[accumulation_point].to_string(),
+            graph.type_names[accumulation_point].clone(),
+            graph.obj_ids[accumulation_point].to_string(),
+            path.iter()
+                .map(|&n| format!("{} ({})", graph.type_names[n], graph.obj_ids[n]))
+                .collect::<Vec<_>>()
+                .join(" -> "),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// The top-level objects of one class
+struct ClassSuspect {
+    count: u64,
+    /// total retained by all of them
+    retained: u64,
+    /// node index of the one that retains the most
+    biggest: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn finds_leaking_static_list() {
+        // class Leaky { static Node[] cache; }
+        let leaky_class = TestClass {
+            static_fields: vec![(10, Val::Obj(2000))],
+            ..TestClass::new(100, 0)
+        };
+        // class Node { byte[] payload; }
+        let node_class = TestClass {
+            instance_fields: vec![(11, TYPE_OBJ)],
+            instance_size_bytes: 16,
+            ..TestClass::new(101, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Leaky")
+            .utf8(2, "Node")
+            .utf8(3, "[LNode;")
+            .utf8(4, "Small")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4);
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_system_class(100)
+            .gc_root_system_class(103)
+            .class(&leaky_class)
+            .class(&node_class)
+            .class(&TestClass::new(102, 0))
+            .class(&TestClass::new(103, 0))
+            .object_array(2000, 102, &[3000, 3001, 3002, 3003]);
+        for i in 0..4 {
+            segment
+                .instance(3000 + i, 101, &[Val::Obj(4000 + i)])
+                .byte_array(4000 + i, &[0; 92]);
+        }
+        // a small object held by its own root, and shared between two roots
+        segment
+            .gc_root_jni_global(5000, 1)
+            .gc_root_jni_global(5000, 2)
+            .int_array(5000, &[1, 2]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        leak_suspects(&hprof, 10.0, 10, false, &mut out).unwrap();
+
+        // (16 + 4 * 8) byte array + 4 * (16 byte instance + (16 + 92) byte array) = 544, plus the
+        // (16 + 8) byte int[] = 568
+        assert_eq!(
+            "Class,Objects,Retained bytes,Retained %,Biggest obj id,Biggest retained bytes,\
+             Accumulation point retained bytes,Accumulation point class,\
+             Accumulation point obj id,GC root path\n\
+             class Leaky,1,544,95.8,100,544,544,[LNode;,2000,class Leaky (100) -> [LNode; (2000)\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn groups_suspects_by_class_with_gc_root_path() {
+        // class Holder { byte[] shared; }
+        let holder_class = TestClass {
+            instance_fields: vec![(10, TYPE_OBJ)],
+            instance_size_bytes: 16,
+            ..TestClass::new(101, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Session")
+            .utf8(2, "Holder")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass {
+                instance_size_bytes: 100,
+                ..TestClass::new(100, 0)
+            })
+            .class(&holder_class);
+        // two holders share one byte[], so neither dominates it
+        for i in 0..2 {
+            segment
+                .gc_root_jni_global(1000 + i, i)
+                .instance(1000 + i, 101, &[Val::Obj(4000)]);
+        }
+        segment.byte_array(4000, &[0; 300]);
+        // lots of sessions, each of which retains too little to be a suspect on its own
+        for i in 0..5 {
+            segment
+                .gc_root_jni_global(3000 + i, 10 + i)
+                .instance(3000 + i, 100, &[]);
+        }
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        leak_suspects(&hprof, 15.0, 10, false, &mut out).unwrap();
+
+        // 5 * 100 byte sessions, a (16 + 300) byte array, and 2 * 16 byte holders = 848
+        assert_eq!(
+            "Class,Objects,Retained bytes,Retained %,Biggest obj id,Biggest retained bytes,\
+             Accumulation point retained bytes,Accumulation point class,\
+             Accumulation point obj id,GC root path\n\
+             Session,5,500,59.0,3000,100,100,Session,3000,Session (3000)\n\
+             byte[],1,316,37.3,4000,316,316,byte[],4000,Holder (1000) -> byte[] (4000)\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn weak_referents_excluded_on_request() {
+        // class Cache { static WeakReference ref; }
+        let cache_class = TestClass {
+            static_fields: vec![(22, Val::Obj(1000))],
+            ..TestClass::new(102, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .reference_classes()
+            .utf8(3, "Cache")
+            .load_class(3, 102, 0, 3);
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_system_class(102)
+            .reference_classes(32)
+            .class(&cache_class)
+            .instance(
+                1000,
+                WEAK_REFERENCE_CLASS_OBJ_ID,
+                &[Val::Obj(2000), Val::Obj(0)],
+            )
+            .byte_array(2000, &[0; 200]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        leak_suspects(&hprof, 10.0, 10, false, &mut out).unwrap();
+        assert_eq!(
+            "Class,Objects,Retained bytes,Retained %,Biggest obj id,Biggest retained bytes,\
+             Accumulation point retained bytes,Accumulation point class,\
+             Accumulation point obj id,GC root path\n\
+             class Cache,1,248,100.0,102,248,216,byte[],2000,\
+             class Cache (102) -> java/lang/ref/WeakReference (1000) -> byte[] (2000)\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // the byte[] is only weakly reachable, so nothing retains it
+        let mut out = Vec::new();
+        leak_suspects(&hprof, 10.0, 10, true, &mut out).unwrap();
+        assert_eq!(
+            "Class,Objects,Retained bytes,Retained %,Biggest obj id,Biggest retained bytes,\
+             Accumulation point retained bytes,Accumulation point class,\
+             Accumulation point obj id,GC root path\n\
+             class Cache,1,32,100.0,102,32,32,java/lang/ref/WeakReference,1000,\
+             class Cache (102) -> java/lang/ref/WeakReference (1000)\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use crate::index::{resolve_reference_target, Index};
+use crate::util::*;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// Write a dot graph of the object with id `obj_id` and everything it refers to, out to `depth`
+/// references away, with each edge labelled with the field or array element it comes from.
+///
+/// Objects are found with `index`'s locations, so only the objects in the graph are parsed. If
+/// `include_referrers` is true, the objects that refer directly to `obj_id` are included too.
+/// There's no reverse index to find those with, so that takes a full pass over the heap dump.
+pub(crate) fn object_graph<I: Index, W: io::Write>(
+    hprof: &Hprof,
+    index: &I,
+    obj_id: Id,
+    depth: usize,
+    include_referrers: bool,
+    mut out: W,
+) -> Result<(), anyhow::Error> {
+    let id_size = hprof.header().id_size();
+    let utf8 = utf8_strings_by_id(hprof);
+    let classes = ez_classes_by_obj_id(hprof);
+    let field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+    let refs = LabelledRefs {
+        utf8: &utf8,
+        field_descriptors: &field_descriptors,
+        id_size,
+    };
+
+    // in the order they're found, so output is stable
+    let mut nodes = vec![obj_id];
+    let mut seen = collections::HashSet::new();
+    seen.insert(obj_id);
+    // source, label, dest
+    let mut edges = Vec::new();
+
+    let mut queue = collections::VecDeque::new();
+    queue.push_back((obj_id, 0));
+    while let Some((id, distance)) = queue.pop_front() {
+        if distance == depth {
+            continue;
+        }
+
+        let (record_index, offset) = match index.get_location(id)? {
+            Some(location) => location,
+            // not in the heap dump, so nothing to follow
+            None => continue,
+        };
+        let sub_record = hprof
+            .sub_record_at(record_index as usize, offset as usize)
+            .map_err(|e| anyhow::anyhow!("Could not parse sub record for {}: {:?}", id, e))?;
+
+        for (label, dest) in refs.of(&sub_record)? {
+            if seen.insert(dest) {
+                nodes.push(dest);
+                queue.push_back((dest, distance + 1));
+            }
+            edges.push((id, label, dest));
+        }
+    }
+
+    if include_referrers {
+        // objects that were followed above already have their edges to `obj_id`, but objects at
+        // `depth` weren't followed
+        let existing_edges = edges
+            .iter()
+            .filter(|(_, _, dest)| *dest == obj_id)
+            .map(|(source, label, _)| (*source, label.clone()))
+            .collect::<collections::HashSet<_>>();
+        let mut referrers = collections::HashSet::new();
+
+        for r in hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+        {
+            let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+            for p in segment.sub_records() {
+                let s = p.unwrap();
+                let source = match &s {
+                    SubRecord::Class(c) => c.obj_id(),
+                    SubRecord::Instance(i) => i.obj_id(),
+                    SubRecord::ObjectArray(oa) => oa.obj_id(),
+                    _ => continue,
+                };
+
+                for (label, dest) in refs.of(&s)? {
+                    if dest != obj_id || existing_edges.contains(&(source, label.clone())) {
+                        continue;
+                    }
+
+                    // a referrer may hold several references to `obj_id`, or already be in the
+                    // graph as something `obj_id` refers to
+                    if !seen.contains(&source) && referrers.insert(source) {
+                        nodes.push(source);
+                    }
+                    edges.push((source, label, dest));
+                }
+            }
+        }
+    }
+
+    writeln!(out, "digraph \"object {}\" {{", obj_id)?;
+    for &id in &nodes {
+        let type_name = match resolve_reference_target(id, index, &classes)? {
+            Some(target) => target.type_name(&classes),
+            None => String::from("(not in heap dump)"),
+        };
+        writeln!(
+            out,
+            "\t\"obj-{}\"[shape=box, label=\"{} ({})\"{}];",
+            id,
+            type_name,
+            id,
+            if id == obj_id { ", penwidth=2" } else { "" }
+        )?;
+    }
+    for (source, label, dest) in edges {
+        writeln!(
+            out,
+            "\t\"obj-{}\" -> \"obj-{}\"[label=\"{}\"];",
+            source, dest, label
+        )?;
+    }
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+/// Everything needed to name the references an object holds.
+struct LabelledRefs<'a> {
+    utf8: &'a collections::HashMap<Id, &'a str>,
+    /// class obj id -> all instance fields, as per [build_type_hierarchy_field_descriptors]
+    field_descriptors: &'a collections::HashMap<Id, Vec<FieldDescriptor>>,
+    id_size: IdSize,
+}
+
+impl<'a> LabelledRefs<'a> {
+    /// The non-null references held by a class, instance, or object array, each with the name of
+    /// the field it's in or the index of its array element.
+    fn of(&self, sub_record: &SubRecord) -> Result<Vec<(String, Id)>, anyhow::Error> {
+        let field_name = |name_id: Id| {
+            self.utf8
+                .get(&name_id)
+                .copied()
+                .unwrap_or("(missing utf8)")
+                .to_owned()
+        };
+
+        let mut refs = Vec::new();
+        match sub_record {
+            SubRecord::Class(c) => {
+                refs.extend(
+                    c.super_class_obj_id()
+                        .map(|id| (String::from("(superclass)"), id)),
+                );
+                refs.extend(
+                    c.class_loader_obj_id()
+                        .map(|id| (String::from("(class loader)"), id)),
+                );
+                refs.extend(c.signers_obj_id().map(|id| (String::from("(signers)"), id)));
+                refs.extend(
+                    c.protection_domain_obj_id()
+                        .map(|id| (String::from("(protection domain)"), id)),
+                );
+                for sf in c.static_fields() {
+                    let sf = sf.map_err(|e| anyhow::anyhow!("Invalid static field: {:?}", e))?;
+                    if let FieldValue::ObjectId(Some(id)) = sf.value() {
+                        refs.push((format!("static {}", field_name(sf.name_id())), id));
+                    }
+                }
+            }
+            SubRecord::Instance(instance) => {
+                let fds =
+                    instance_field_descriptors(self.field_descriptors, instance.class_obj_id());
+                for fv in instance.field_values(fds, self.id_size) {
+                    let (fd, value) = fv.map_err(|e| {
+                        anyhow::anyhow!("Invalid fields for {}: {:?}", instance.obj_id(), e)
+                    })?;
+                    if let FieldValue::ObjectId(Some(id)) = value {
+                        refs.push((field_name(fd.name_id()), id));
+                    }
+                }
+            }
+            SubRecord::ObjectArray(oa) => {
+                for (i, element) in oa.elements(self.id_size).enumerate() {
+                    let element =
+                        element.map_err(|e| anyhow::anyhow!("Invalid array element: {:?}", e))?;
+                    if let Some(id) = element {
+                        refs.push((format!("[{}]", i), id));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(refs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::build_memory_index;
+    use crate::test_util::*;
+
+    #[test]
+    fn graph_includes_neighbors_out_to_depth() -> Result<(), anyhow::Error> {
+        // class Node { Node next; Object[] items; }
+        let node_class = TestClass {
+            instance_fields: vec![(10, TYPE_OBJ), (11, TYPE_OBJ)],
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Node")
+            .utf8(2, "[Ljava/lang/Object;")
+            .utf8(10, "next")
+            .utf8(11, "items")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&node_class)
+            .class(&TestClass::new(101, 0))
+            // 999 -> 1000 -> 1001 -> 1002, and 1000 -> [3000, null] -> int[]
+            .instance(999, 100, &[Val::Obj(1000), Val::Obj(0)])
+            .instance(1000, 100, &[Val::Obj(1001), Val::Obj(2000)])
+            .instance(1001, 100, &[Val::Obj(1002), Val::Obj(0)])
+            .instance(1002, 100, &[Val::Obj(0), Val::Obj(0)])
+            .object_array(2000, 101, &[3000, 0])
+            .int_array(3000, &[1]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let index = build_memory_index(&hprof)?;
+
+        let mut out = Vec::new();
+        object_graph(&hprof, &index, Id::from(1000), 1, false, &mut out)?;
+        assert_eq!(
+            "digraph \"object 1000\" {\n\
+             \t\"obj-1000\"[shape=box, label=\"Node (1000)\", penwidth=2];\n\
+             \t\"obj-1001\"[shape=box, label=\"Node (1001)\"];\n\
+             \t\"obj-2000\"[shape=box, label=\"[Ljava/lang/Object; (2000)\"];\n\
+             \t\"obj-1000\" -> \"obj-1001\"[label=\"next\"];\n\
+             \t\"obj-1000\" -> \"obj-2000\"[label=\"items\"];\n\
+             }\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        object_graph(&hprof, &index, Id::from(1000), 2, true, &mut out)?;
+        assert_eq!(
+            "digraph \"object 1000\" {\n\
+             \t\"obj-1000\"[shape=box, label=\"Node (1000)\", penwidth=2];\n\
+             \t\"obj-1001\"[shape=box, label=\"Node (1001)\"];\n\
+             \t\"obj-2000\"[shape=box, label=\"[Ljava/lang/Object; (2000)\"];\n\
+             \t\"obj-1002\"[shape=box, label=\"Node (1002)\"];\n\
+             \t\"obj-3000\"[shape=box, label=\"int[] (3000)\"];\n\
+             \t\"obj-999\"[shape=box, label=\"Node (999)\"];\n\
+             \t\"obj-1000\" -> \"obj-1001\"[label=\"next\"];\n\
+             \t\"obj-1000\" -> \"obj-2000\"[label=\"items\"];\n\
+             \t\"obj-1001\" -> \"obj-1002\"[label=\"next\"];\n\
+             \t\"obj-2000\" -> \"obj-3000\"[label=\"[0]\"];\n\
+             \t\"obj-999\" -> \"obj-1000\"[label=\"next\"];\n\
+             }\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn referrers_include_multiple_and_back_edges() -> Result<(), anyhow::Error> {
+        // class Pair { Object left; Object right; }
+        let pair_class = TestClass {
+            instance_fields: vec![(10, TYPE_OBJ), (11, TYPE_OBJ)],
+            ..TestClass::new(100, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Pair")
+            .utf8(10, "left")
+            .utf8(11, "right")
+            .load_class(1, 100, 0, 1);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&pair_class)
+            // 999 => 1000 twice, and 1000 <-> 1001
+            .instance(999, 100, &[Val::Obj(1000), Val::Obj(1000)])
+            .instance(1000, 100, &[Val::Obj(1001), Val::Obj(0)])
+            .instance(1001, 100, &[Val::Obj(1000), Val::Obj(0)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let index = build_memory_index(&hprof)?;
+
+        // 1001 isn't followed at depth 1, so its edge back comes from the referrers
+        for depth in 1..=2 {
+            let mut out = Vec::new();
+            object_graph(&hprof, &index, Id::from(1000), depth, true, &mut out)?;
+            let out = String::from_utf8(out).unwrap();
+
+            let nodes = out.lines().filter(|l| l.contains("shape=box")).count();
+            assert_eq!(3, nodes, "depth {}: {}", depth, out);
+            let back_edges = out
+                .lines()
+                .filter(|l| l.contains("\"obj-1001\" -> \"obj-1000\""))
+                .count();
+            assert_eq!(1, back_edges, "depth {}: {}", depth, out);
+            assert!(out.contains("\t\"obj-999\" -> \"obj-1000\"[label=\"left\"];\n"));
+            assert!(out.contains("\t\"obj-999\" -> \"obj-1000\"[label=\"right\"];\n"));
+        }
+
+        Ok(())
+    }
+}
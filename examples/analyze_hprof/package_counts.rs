@@ -0,0 +1,186 @@
+use crate::counter::Counter;
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{cmp, io};
+
+const PRIMITIVE_ARRAYS: &str = "(primitive arrays)";
+const DEFAULT_PACKAGE: &str = "(default package)";
+const UNKNOWN_CLASS: &str = "(unknown class)";
+
+/// Write a CSV of instance counts and total shallow size grouped by Java package, most bytes first.
+///
+/// Packages are truncated to their first `depth` components, so with a depth of 2,
+/// `com.example.service.Foo` and `com.example.util.Bar` are both counted under `com.example`.
+/// Object arrays count towards the package of their base element class, and primitive arrays
+/// share a row of their own.
+pub(crate) fn package_counts<W: io::Write>(
+    hprof: &Hprof,
+    obj_id_range: &ObjIdRange,
+    depth: usize,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    if depth == 0 {
+        return Err(anyhow::anyhow!("Package depth must be at least 1"));
+    }
+
+    let id_size = hprof.header().id_size();
+    let classes = ez_classes_by_obj_id(hprof);
+
+    // class obj id -> count, bytes
+    let mut class_counts = Counter::<Id>::new();
+    let mut class_bytes = Counter::<Id>::new();
+    let mut prim_array_count = 0_u64;
+    let mut prim_array_bytes = 0_u64;
+
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for p in segment.sub_records() {
+            let s = p.unwrap();
+            let class_obj_id = match &s {
+                SubRecord::Instance(instance) if obj_id_range.contains(instance.obj_id()) => {
+                    instance.class_obj_id()
+                }
+                SubRecord::ObjectArray(oa) if obj_id_range.contains(oa.obj_id()) => {
+                    oa.array_class_obj_id()
+                }
+                SubRecord::PrimitiveArray(pa) if obj_id_range.contains(pa.obj_id()) => {
+                    prim_array_count += 1;
+                    prim_array_bytes += shallow_size(&s, |_| None, id_size).unwrap_or(0);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let size = shallow_size(
+                &s,
+                |id| classes.get(&id).map(|c| c.instance_size_bytes),
+                id_size,
+            );
+            class_counts.increment(class_obj_id);
+            class_bytes.add(class_obj_id, size.unwrap_or(0));
+        }
+    }
+
+    let mut package_counts = Counter::<String>::new();
+    let mut package_bytes = Counter::<String>::new();
+    for (class_obj_id, &count) in class_counts.iter() {
+        let package = classes
+            .get(class_obj_id)
+            .map(|c| package_name(c.name, depth))
+            .unwrap_or_else(|| UNKNOWN_CLASS.to_owned());
+        package_bytes.add(package.clone(), class_bytes.get(class_obj_id).unwrap_or(0));
+        package_counts.add(package, count);
+    }
+    if prim_array_count > 0 {
+        package_counts.add(PRIMITIVE_ARRAYS.to_owned(), prim_array_count);
+        package_bytes.add(PRIMITIVE_ARRAYS.to_owned(), prim_array_bytes);
+    }
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Package", "Instance count", "Total shallow size (bytes)"])?;
+
+    for (package, &count) in package_counts.iter().sorted_by_key(|(package, _count)| {
+        (
+            cmp::Reverse(package_bytes.get(package.as_str()).unwrap_or(0)),
+            package.as_str(),
+        )
+    }) {
+        wtr.write_record(&[
+            package.to_owned(),
+            count.to_string(),
+            package_bytes.get(package.as_str()).unwrap_or(0).to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// The dotted package of the JVM class name `class_name`, truncated to at most `depth`
+/// components. Arrays of objects use the package of their base element class.
+fn package_name(class_name: &str, depth: usize) -> String {
+    let class_name = match array_dimensions(class_name) {
+        Some((_, ArrayBaseType::Object(base))) => base,
+        Some((_, ArrayBaseType::Primitive(_))) => return PRIMITIVE_ARRAYS.to_owned(),
+        None => class_name,
+    };
+
+    match class_name.rsplit_once('/') {
+        Some((package, _simple_name)) => package.split('/').take(depth).join("."),
+        None => DEFAULT_PACKAGE.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn aggregates_at_prefix_depth() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/service/Handler")
+            .utf8(2, "com/example/util/Helper")
+            .utf8(3, "org/other/Thing")
+            .utf8(4, "[Lcom/example/service/Handler;")
+            .utf8(5, "Bare")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3)
+            .load_class(4, 103, 0, 4)
+            .load_class(5, 104, 0, 5);
+        let sized = |obj_id, size| TestClass {
+            instance_size_bytes: size,
+            ..TestClass::new(obj_id, 0)
+        };
+        let mut segment = builder.segment();
+        segment
+            .class(&sized(100, 24))
+            .class(&sized(101, 16))
+            .class(&sized(102, 8))
+            .class(&sized(103, 0))
+            .class(&sized(104, 4))
+            .instance(1000, 100, &[])
+            .instance(1001, 100, &[])
+            .instance(1002, 101, &[])
+            .instance(1003, 102, &[])
+            .instance(1004, 104, &[])
+            // 16 byte header + 2 8-byte ids
+            .object_array(2000, 103, &[1000, 1001])
+            // 16 byte header + 3 4-byte ints
+            .int_array(3000, &[1, 2, 3]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        package_counts(&hprof, &ObjIdRange::all(), 2, &mut out).unwrap();
+        assert_eq!(
+            "Package,Instance count,Total shallow size (bytes)\n\
+            com.example,4,96\n\
+            (primitive arrays),1,28\n\
+            org.other,1,8\n\
+            (default package),1,4\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        package_counts(&hprof, &ObjIdRange::all(), 3, &mut out).unwrap();
+        assert_eq!(
+            "Package,Instance count,Total shallow size (bytes)\n\
+            com.example.service,3,80\n\
+            (primitive arrays),1,28\n\
+            com.example.util,1,16\n\
+            org.other,1,8\n\
+            (default package),1,4\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
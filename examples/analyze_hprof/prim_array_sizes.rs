@@ -0,0 +1,112 @@
+use crate::counter::Counter;
+use crate::util::ObjIdRange;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::{io, ops};
+
+/// Write a CSV of the number of primitive arrays of each type and the total size of their
+/// contents, largest total first.
+///
+/// Sizes are of the array contents only, not the object header or length field.
+pub(crate) fn prim_array_sizes<W: io::Write>(
+    hprof: &Hprof,
+    obj_id_range: &ObjIdRange,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let mut totals = hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+        .par_bridge()
+        .map(|r| {
+            let mut totals = PrimArrayTotals::default();
+
+            let segment = r.as_heap_dump_segment().unwrap().unwrap();
+            for p in segment.sub_records() {
+                if let SubRecord::PrimitiveArray(pa) = p.unwrap() {
+                    if obj_id_range.contains(pa.obj_id()) {
+                        totals.counts.increment(pa.primitive_type());
+                        totals.bytes.add(
+                            pa.primitive_type(),
+                            pa.num_elements() as u64 * pa.primitive_type().element_size() as u64,
+                        );
+                    }
+                }
+            }
+
+            totals
+        })
+        .reduce(PrimArrayTotals::default, |mut acc, x| {
+            acc += x;
+            acc
+        });
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Array type", "Array count", "Total contents size (bytes)"])?;
+
+    for (prim_type, bytes) in
+        totals
+            .bytes
+            .iter()
+            .sorted_by(|(left_type, left_bytes), (right_type, right_bytes)| {
+                // reverse order to put the biggest on top, then by name to be deterministic
+                Ord::cmp(right_bytes, left_bytes)
+                    .then_with(|| left_type.java_type_name().cmp(right_type.java_type_name()))
+            })
+    {
+        wtr.write_record(&[
+            format!("{}[]", prim_type.java_type_name()),
+            totals.counts.get(prim_type).unwrap_or(0).to_string(),
+            bytes.to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct PrimArrayTotals {
+    counts: Counter<PrimitiveArrayType>,
+    bytes: Counter<PrimitiveArrayType>,
+}
+
+impl ops::AddAssign for PrimArrayTotals {
+    fn add_assign(&mut self, rhs: Self) {
+        self.counts += rhs.counts;
+        self.bytes += rhs.bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn totals_by_type() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .byte_array(1000, &[1, 2, 3])
+            .int_array(2000, &[1, 2])
+            .int_array(2001, &[3, 4, 5])
+            .byte_array(1001, &[4])
+            .char_array(3000, "ab");
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        prim_array_sizes(&hprof, &ObjIdRange::all(), &mut out).unwrap();
+
+        assert_eq!(
+            "Array type,Array count,Total contents size (bytes)\n\
+             int[],2,20\n\
+             byte[],2,4\n\
+             char[],1,4\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
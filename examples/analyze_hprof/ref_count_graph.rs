@@ -3,18 +3,23 @@ use crate::util::*;
 use jvm_hprof::{heap_dump::*, *};
 
 use crate::counter::Counter;
-use crate::index::Index;
+use crate::index::{resolve_reference_target, resolve_reference_targets, Index, ReferenceTarget};
 use rayon::iter::{ParallelBridge, ParallelIterator};
 use std::io::{self, Write};
-use std::{collections, fs, path};
+use std::{cmp, collections, fs, path, str};
 
 /// Assemble a graph of counts between _types_, not instances, as a way of compressing huge
 /// object tangles for easier visual analysis
+///
+/// If `edge_sampling` is provided, a random sample of the individual references between objects
+/// that the graph is built from is also written, for a look at the instances behind the types.
 pub fn ref_count_graph<I: Index>(
     hprof: &Hprof,
     index: &I,
     output: &path::Path,
     min_edge_count: u64,
+    edge_label_mode: EdgeLabelMode,
+    edge_sampling: Option<EdgeSampling>,
 ) {
     // class obj id -> LoadClass
     let mut load_classes = collections::HashMap::new();
@@ -64,37 +69,17 @@ pub fn ref_count_graph<I: Index>(
 
     let id_size = hprof.header().id_size();
 
-    // look in all the possible places an object id might be to build the right type of destination
     let edge_dest_for_obj_id = |obj_id: Id| {
-        index
-            .get_class_id(obj_id)
-            .map(|class_obj_id_opt| {
-                class_obj_id_opt.map(|class_obj_id| HeapGraphDest::InstanceOfClass { class_obj_id })
-            })
-            .and_then(|dest_opt| match dest_opt {
-                // didn't find it in the normal index lookup, so see if it's a primitive array
-                None => index.get_prim_array_type(obj_id).map(|prim_type_opt| {
-                    prim_type_opt.map(|prim_type| HeapGraphDest::PrimitiveArray { prim_type })
-                }),
-                Some(d) => Ok(Some(d)),
-            })
-            .map(|dest_opt|
-                // neither index lookup worked, so try classes
-                match dest_opt {
-                    None => classes
-                        .get(&obj_id)
-                        .map(|_dest_class| HeapGraphDest::ClassObj {
-                            class_obj_id: obj_id,
-                        }),
-                    Some(d) => Some(d)
-                })
+        resolve_reference_target(obj_id, index, &classes)
             // error is unrecoverable anyway, might as well just crash
             .expect("Error when reading index")
     };
 
+    let sample_size = edge_sampling.as_ref().map(|s| s.sample_size).unwrap_or(0);
+
     println!("Calculating reference counts");
     println!(". = 1,000,000 heap dump segment sub records");
-    let mut all_graph_edges = hprof
+    let (mut all_graph_edges, edge_sample) = hprof
         .records_iter()
         .par_bridge()
         .panic_fuse()
@@ -105,6 +90,16 @@ pub fn ref_count_graph<I: Index>(
                 let mut sub_records = 0_u64;
 
                 let mut graph_edges: Counter<GraphEdge> = Counter::new();
+                let mut edge_sample = EdgeSample::new(sample_size);
+                let mut rng = rand::thread_rng();
+
+                let mut add_edge = |source: HeapGraphSource, source_obj_id: Option<Id>, dest_obj_id: Id, dest: ReferenceTarget| {
+                    edge_sample.offer(
+                        SampledEdge { source: source.clone(), source_obj_id, dest_obj_id, dest },
+                        &mut rng,
+                    );
+                    graph_edges.increment(GraphEdge { source, dest: dest.into() });
+                };
 
                 for p in segment.sub_records() {
                     let s = p.unwrap();
@@ -125,7 +120,7 @@ pub fn ref_count_graph<I: Index>(
                                 "Could not find any match for obj {:?} in GcRootUnknown",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootUnknown, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootUnknown, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootThreadObj(gc_root) => gc_root.thread_obj_id().iter().for_each(|obj_id| {
                             match edge_dest_for_obj_id(*obj_id) {
@@ -133,7 +128,7 @@ pub fn ref_count_graph<I: Index>(
                                     "Could not find any match for obj {:?} in GcRootThreadObj",
                                     obj_id,
                                 ),
-                                Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootThreadObj, dest })
+                                Some(dest) => add_edge(HeapGraphSource::GcRootThreadObj, None, *obj_id, dest)
                             }
                         }),
                         SubRecord::GcRootJniGlobal(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
@@ -141,49 +136,49 @@ pub fn ref_count_graph<I: Index>(
                                 "Could not find any match for obj {:?} in GcRootJniGlobal",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootJniGlobal, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootJniGlobal, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootJniLocalRef(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
                             None => eprintln!(
                                 "Could not find any match for obj {:?} in GcRootJniLocalRef",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootJniLocalRef, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootJniLocalRef, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootJavaStackFrame(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
                             None => eprintln!(
                                 "Could not find any match for obj {:?} in GcRootJavaStackFrame",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootJavaStackFrame, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootJavaStackFrame, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootNativeStack(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
                             None => eprintln!(
                                 "Could not find any match for obj {:?} in GcRootNativeStack",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootNativeStack, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootNativeStack, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootSystemClass(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
                             None => eprintln!(
                                 "Could not find any match for obj {:?} in GcRootSystemClass",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootSystemClass, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootSystemClass, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootThreadBlock(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
                             None => eprintln!(
                                 "Could not find any match for obj {:?} in GcRootThreadBlock",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootThreadBlock, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootThreadBlock, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::GcRootBusyMonitor(gc_root) => match edge_dest_for_obj_id(gc_root.obj_id()) {
                             None => eprintln!(
                                 "Could not find any match for obj {:?} in GcRootBusyMonitor",
                                 gc_root.obj_id(),
                             ),
-                            Some(dest) => graph_edges.increment(GraphEdge { source: HeapGraphSource::GcRootBusyMonitor, dest })
+                            Some(dest) => add_edge(HeapGraphSource::GcRootBusyMonitor, None, gc_root.obj_id(), dest)
                         },
                         SubRecord::PrimitiveArray(_) => { /* primitive arrays have no refs */ }
                         SubRecord::Class(c) => {
@@ -191,6 +186,23 @@ pub fn ref_count_graph<I: Index>(
                                 // already know the class exists
                                 .unwrap();
 
+                            let security_refs = c.signers_obj_id()
+                                .map(|id| (id, HeapGraphSource::ClassSigners { class_obj_id: c.obj_id() }))
+                                .into_iter()
+                                .chain(c.protection_domain_obj_id()
+                                    .map(|id| (id, HeapGraphSource::ClassProtectionDomain { class_obj_id: c.obj_id() })));
+                            for (ref_id, source) in security_refs {
+                                match edge_dest_for_obj_id(ref_id) {
+                                    None => eprintln!(
+                                        "Could not find any match for obj {:?}: {} in {:?}",
+                                        ref_id,
+                                        mc.name,
+                                        source
+                                    ),
+                                    Some(dest) => add_edge(source, Some(c.obj_id()), ref_id, dest)
+                                }
+                            }
+
                             mc.static_fields.iter()
                                 .enumerate()
                                 .for_each(|(index, sf)| {
@@ -208,7 +220,7 @@ pub fn ref_count_graph<I: Index>(
                                                     mc.name,
                                                     utf8.get(&sf.name_id()).unwrap_or(&missing_utf8)
                                                 ),
-                                                Some(dest) => graph_edges.increment(GraphEdge { source, dest })
+                                                Some(dest) => add_edge(source, Some(c.obj_id()), field_ref_id, dest)
                                             }
                                         }
                                         _ => {}
@@ -217,22 +229,18 @@ pub fn ref_count_graph<I: Index>(
                         }
 
                         SubRecord::Instance(instance) => {
-                            let mc = classes.get(&instance.class_obj_id())
-                                // already know the class exists
-                                .unwrap();
-
-                            let field_descriptors = class_instance_field_descriptors
-                                .get(&instance.class_obj_id())
-                                .expect("Should have all classes available");
-
-                            let mut field_val_input: &[u8] = instance.fields();
-                            for (index, fd) in field_descriptors.iter().enumerate() {
-                                let (input, field_val) = fd
-                                    .field_type()
-                                    .parse_value(field_val_input, id_size)
-                                    .unwrap();
-                                field_val_input = input;
-
+                            // a missing class is counted under its obj id
+                            let class_name =
+                                class_name_or_unknown(&classes, instance.class_obj_id());
+
+                            let field_descriptors = instance_field_descriptors(
+                                &class_instance_field_descriptors,
+                                instance.class_obj_id(),
+                            );
+
+                            for (index, (fd, field_val)) in
+                                instance_field_values(&instance, field_descriptors, id_size).enumerate()
+                            {
                                 match field_val {
                                     FieldValue::ObjectId(Some(field_ref_id)) => {
                                         let source = HeapGraphSource::InstanceField {
@@ -244,10 +252,10 @@ pub fn ref_count_graph<I: Index>(
                                             None => eprintln!(
                                                 "Could not find any match for obj {:?}: {} in field {}",
                                                 field_ref_id,
-                                                mc.name,
+                                                class_name,
                                                 utf8.get(&fd.name_id()).unwrap_or(&missing_utf8)
                                             ),
-                                            Some(dest) => graph_edges.increment(GraphEdge { source, dest })
+                                            Some(dest) => add_edge(source, Some(instance.obj_id()), field_ref_id, dest)
                                         }
                                     }
                                     _ => {}
@@ -255,43 +263,65 @@ pub fn ref_count_graph<I: Index>(
                             }
                         }
                         SubRecord::ObjectArray(obj_array) => {
-                            let mc = classes.get(&obj_array.array_class_obj_id())
-                                // already know the class exists
-                                .unwrap();
-                            obj_array.elements(id_size)
+                            let class_name =
+                                class_name_or_unknown(&classes, obj_array.array_class_obj_id());
+                            let element_ids = obj_array.elements(id_size)
                                 .filter_map(|res| res.unwrap())
-                                .for_each(|id| {
+                                .collect::<Vec<_>>();
+                            // arrays can have millions of elements, so look them all up at once
+                            let dests = resolve_reference_targets(&element_ids, index, &classes)
+                                .expect("Error when reading index");
+                            element_ids.into_iter()
+                                .zip(dests)
+                                .for_each(|(id, dest)| {
                                     let source = HeapGraphSource::ObjectArray {
-                                        class_obj_id: mc.obj_id
+                                        class_obj_id: obj_array.array_class_obj_id()
                                     };
 
-                                    match edge_dest_for_obj_id(id) {
+                                    match dest {
                                         None => eprintln!(
                                             "Could not find any match for obj {:?} in array {:?} ({})",
                                             id,
                                             obj_array.array_class_obj_id(),
-                                            mc.name
+                                            class_name
                                         ),
-                                        Some(dest) => graph_edges.increment(GraphEdge { source, dest })
+                                        Some(dest) => add_edge(source, Some(obj_array.obj_id()), id, dest)
                                     }
                                 })
                         }
                     }
                 }
 
-                graph_edges
+                (graph_edges, edge_sample)
             }
             // empty counter for other cases
-            _ => Counter::new()
+            _ => (Counter::new(), EdgeSample::new(sample_size))
         })
-        .reduce(|| Counter::new(),
-                |mut acc, x| {
+        .reduce(|| (Counter::new(), EdgeSample::new(sample_size)),
+                |(mut acc, acc_sample), (x, x_sample)| {
                     acc += x;
-                    acc
+                    (acc, acc_sample.merge(x_sample, &mut rand::thread_rng()))
                 });
 
     println!();
 
+    if let Some(sampling) = edge_sampling {
+        write_edge_sample(
+            &edge_sample,
+            &classes,
+            &class_instance_field_descriptors,
+            &utf8,
+            fs::File::create(sampling.output).unwrap(),
+        )
+        .unwrap();
+    }
+
+    // totals for each source before filtering, so percentages are of all of a source's references
+    let mut source_totals: Counter<HeapGraphSource> = Counter::new();
+    for (edge, &count) in all_graph_edges.iter() {
+        source_totals.add(edge.source.clone(), count);
+    }
+
     all_graph_edges.retain(|_edge, count| *count >= min_edge_count);
 
     let mut output_file = fs::File::create(output).unwrap();
@@ -304,6 +334,8 @@ pub fn ref_count_graph<I: Index>(
         .map(|(k, _v)| k)
         .filter_map(|edge| match edge.source {
             HeapGraphSource::StaticField { class_obj_id, .. } => Some(class_obj_id),
+            HeapGraphSource::ClassSigners { class_obj_id } => Some(class_obj_id),
+            HeapGraphSource::ClassProtectionDomain { class_obj_id } => Some(class_obj_id),
             HeapGraphSource::InstanceField { class_obj_id, .. } => Some(class_obj_id),
             HeapGraphSource::ObjectArray { class_obj_id } => Some(class_obj_id),
             _ => None,
@@ -321,17 +353,22 @@ pub fn ref_count_graph<I: Index>(
         // uniqueify -- each id will only have one source mode
         .collect::<collections::HashSet<Id>>()
         .iter()
-        .for_each(|class_obj_id| {
-            let class = classes.get(class_obj_id).unwrap();
-            dot::write_class_node(
+        .for_each(|class_obj_id| match classes.get(class_obj_id) {
+            Some(class) => dot::write_class_node(
                 class,
-                class_instance_field_descriptors
-                    .get(class_obj_id)
-                    .expect("Should have fields for all classes"),
+                instance_field_descriptors(&class_instance_field_descriptors, *class_obj_id),
                 &utf8,
                 &mut output_file,
             )
-            .unwrap()
+            .unwrap(),
+            // no fields to show for a class that isn't in the heap dump
+            None => writeln!(
+                output_file,
+                "\t\"class-{}\"[shape=box, label=\"{}\"]",
+                class_obj_id,
+                unknown_class_name(*class_obj_id)
+            )
+            .unwrap(),
         });
 
     // gc roots
@@ -349,6 +386,8 @@ pub fn ref_count_graph<I: Index>(
             HeapGraphSource::GcRootThreadBlock => true,
             HeapGraphSource::GcRootBusyMonitor => true,
             HeapGraphSource::StaticField { .. } => false,
+            HeapGraphSource::ClassSigners { .. } => false,
+            HeapGraphSource::ClassProtectionDomain { .. } => false,
             HeapGraphSource::InstanceField { .. } => false,
             HeapGraphSource::ObjectArray { .. } => false,
         })
@@ -389,12 +428,187 @@ pub fn ref_count_graph<I: Index>(
     // now, write all the edges
 
     all_graph_edges.iter().for_each(|(edge, &count)| {
-        edge.write_dot_edge(count, &mut output_file).unwrap();
+        let source_total = source_totals
+            .get(&edge.source)
+            .expect("Should have totals for all sources");
+        edge.write_dot_edge(count, source_total, edge_label_mode, &mut output_file)
+            .unwrap();
     });
 
     writeln!(output_file, "}}").unwrap();
 }
 
+/// Where to write a sample of individual references, and how many to sample.
+///
+/// The sample is a fixed size, filled by reservoir sampling, rather than a rate: the total number
+/// of references isn't known until the pass is over, and a fixed size bounds both memory and
+/// output regardless of how big the heap dump is.
+pub(crate) struct EdgeSampling<'a> {
+    pub(crate) sample_size: usize,
+    pub(crate) output: &'a path::Path,
+}
+
+/// One reference from one object (or GC root) to another.
+struct SampledEdge {
+    source: HeapGraphSource,
+    /// `None` for GC roots
+    source_obj_id: Option<Id>,
+    dest_obj_id: Id,
+    dest: ReferenceTarget,
+}
+
+/// A uniformly random sample of up to `capacity` edges, maintained with reservoir sampling so
+/// that the total number of edges needn't be known up front.
+struct EdgeSample {
+    capacity: usize,
+    /// The number of edges offered so far, sampled or not
+    seen: u64,
+    edges: Vec<SampledEdge>,
+}
+
+impl EdgeSample {
+    fn new(capacity: usize) -> EdgeSample {
+        EdgeSample {
+            capacity,
+            seen: 0,
+            edges: Vec::new(),
+        }
+    }
+
+    fn offer<R: rand::Rng>(&mut self, edge: SampledEdge, rng: &mut R) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.seen += 1;
+        if self.edges.len() < self.capacity {
+            self.edges.push(edge);
+        } else {
+            let index = rng.gen_range(0..self.seen);
+            if index < self.capacity as u64 {
+                self.edges[index as usize] = edge;
+            }
+        }
+    }
+
+    /// Combine samples of two disjoint sets of edges into a sample of their union, as when
+    /// segments are sampled in parallel.
+    fn merge<R: rand::Rng>(self, other: EdgeSample, rng: &mut R) -> EdgeSample {
+        use rand::seq::SliceRandom;
+
+        let capacity = cmp::max(self.capacity, other.capacity);
+        let seen = self.seen + other.seen;
+        let mut left = self.edges;
+        let mut right = other.edges;
+        // the order of a reservoir isn't random, so shuffle before taking some of its edges
+        left.shuffle(rng);
+        right.shuffle(rng);
+
+        // Draw without replacement from all the edges either side saw, by picking each side in
+        // proportion to how many of its edges haven't been drawn yet. Each side's sample is a
+        // uniform sample of what it saw, so it can stand in for them.
+        let mut left_remaining = self.seen;
+        let mut right_remaining = other.seen;
+        let mut edges = Vec::new();
+        while edges.len() < capacity && left_remaining + right_remaining > 0 {
+            if rng.gen_range(0..left_remaining + right_remaining) < left_remaining {
+                edges.push(left.pop().expect("left sample exhausted"));
+                left_remaining -= 1;
+            } else {
+                edges.push(right.pop().expect("right sample exhausted"));
+                right_remaining -= 1;
+            }
+        }
+
+        EdgeSample {
+            capacity,
+            seen,
+            edges,
+        }
+    }
+}
+
+/// Write the edges in `sample` as CSV, describing each end by its class (and field, if any).
+fn write_edge_sample<W: Write>(
+    sample: &EdgeSample,
+    classes: &collections::HashMap<Id, EzClass>,
+    class_instance_field_descriptors: &collections::HashMap<Id, Vec<FieldDescriptor>>,
+    utf8: &collections::HashMap<Id, &str>,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let field_name = |name_id: Id| utf8.get(&name_id).copied().unwrap_or("(missing utf8)");
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Source obj id", "Source", "Dest obj id", "Dest class"])?;
+
+    for edge in sample.edges.iter() {
+        let source = match edge.source {
+            HeapGraphSource::StaticField {
+                class_obj_id,
+                field_offset,
+            } => format!(
+                "{}.{} (static)",
+                class_name_or_unknown(classes, class_obj_id),
+                classes
+                    .get(&class_obj_id)
+                    .and_then(|c| c.static_fields.get(field_offset))
+                    .map(|sf| field_name(sf.name_id()))
+                    .unwrap_or("(unknown field)")
+            ),
+            HeapGraphSource::InstanceField {
+                class_obj_id,
+                field_offset,
+            } => format!(
+                "{}.{}",
+                class_name_or_unknown(classes, class_obj_id),
+                instance_field_descriptors(class_instance_field_descriptors, class_obj_id)
+                    .get(field_offset)
+                    .map(|fd| field_name(fd.name_id()))
+                    .unwrap_or("(unknown field)")
+            ),
+            HeapGraphSource::ClassSigners { class_obj_id } => {
+                format!("{} (signers)", class_name_or_unknown(classes, class_obj_id))
+            }
+            HeapGraphSource::ClassProtectionDomain { class_obj_id } => format!(
+                "{} (protection domain)",
+                class_name_or_unknown(classes, class_obj_id)
+            ),
+            HeapGraphSource::ObjectArray { class_obj_id } => {
+                class_name_or_unknown(classes, class_obj_id)
+            }
+            // GC roots
+            _ => write_to_string(|s| edge.source.write_node_name(s))?
+                .trim_matches('"')
+                .to_owned(),
+        };
+
+        let dest_class = match edge.dest {
+            ReferenceTarget::Instance { class_obj_id } => {
+                class_name_or_unknown(classes, class_obj_id)
+            }
+            ReferenceTarget::ClassObj { class_obj_id } => {
+                format!("class {}", class_name_or_unknown(classes, class_obj_id))
+            }
+            ReferenceTarget::PrimitiveArray { prim_type } => {
+                format!("{}[]", prim_type.java_type_name())
+            }
+        };
+
+        wtr.write_record(&[
+            edge.source_obj_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            source,
+            edge.dest_obj_id.to_string(),
+            dest_class,
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
 #[derive(Hash, Eq, PartialEq)]
 struct GraphEdge {
     source: HeapGraphSource,
@@ -402,13 +616,24 @@ struct GraphEdge {
 }
 
 impl GraphEdge {
-    fn write_dot_edge<W: Write>(&self, count: u64, writer: &mut W) -> io::Result<()> {
+    /// `source_total` is the sum of the counts of all edges with the same source.
+    fn write_dot_edge<W: Write>(
+        &self,
+        count: u64,
+        source_total: u64,
+        label_mode: EdgeLabelMode,
+        writer: &mut W,
+    ) -> io::Result<()> {
         write!(writer, "\t")?;
         self.source.write_node_name(writer)?;
         write!(writer, " -> ")?;
         self.dest.write_node_name(writer)?;
         write!(writer, "[")?;
-        write!(writer, "label=\"x{}\"", count)?;
+        write!(
+            writer,
+            "label=\"{}\"",
+            label_mode.label(count, source_total)
+        )?;
         // arbitrary aesthetic scaling
         write!(
             writer,
@@ -423,7 +648,45 @@ impl GraphEdge {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+/// How to label edges in the graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EdgeLabelMode {
+    /// The number of references, e.g. `x1234`
+    Absolute,
+    /// The edge's share of all the references from the same source (e.g. the same field), e.g.
+    /// `12.5%`
+    PercentOfSource,
+    /// The base 10 logarithm of the number of references, e.g. `10^3.1`, to make it easier to
+    /// compare heaps of different sizes
+    Log,
+}
+
+impl EdgeLabelMode {
+    fn label(&self, count: u64, source_total: u64) -> String {
+        match self {
+            EdgeLabelMode::Absolute => format!("x{}", count),
+            EdgeLabelMode::PercentOfSource => {
+                format!("{:.1}%", count as f64 * 100.0 / source_total as f64)
+            }
+            EdgeLabelMode::Log => format!("10^{:.1}", (count as f64).log10()),
+        }
+    }
+}
+
+impl str::FromStr for EdgeLabelMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "absolute" => Ok(EdgeLabelMode::Absolute),
+            "percent" => Ok(EdgeLabelMode::PercentOfSource),
+            "log" => Ok(EdgeLabelMode::Log),
+            _ => Err(anyhow::anyhow!("Unknown edge label mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
 enum HeapGraphSource {
     GcRootUnknown,
     GcRootThreadObj,
@@ -438,6 +701,14 @@ enum HeapGraphSource {
         class_obj_id: Id,
         field_offset: usize,
     },
+    /// A class's signers object
+    ClassSigners {
+        class_obj_id: Id,
+    },
+    /// A class's protection domain object
+    ClassProtectionDomain {
+        class_obj_id: Id,
+    },
     InstanceField {
         class_obj_id: Id,
         field_offset: usize,
@@ -465,6 +736,12 @@ impl HeapGraphSource {
             HeapGraphSource::StaticField { class_obj_id, .. } => {
                 write!(writer, "class-{}", class_obj_id)
             }
+            HeapGraphSource::ClassSigners { class_obj_id } => {
+                write!(writer, "class-{}", class_obj_id)
+            }
+            HeapGraphSource::ClassProtectionDomain { class_obj_id } => {
+                write!(writer, "class-{}", class_obj_id)
+            }
             HeapGraphSource::InstanceField { class_obj_id, .. } => {
                 write!(writer, "class-{}", class_obj_id)
             }
@@ -498,6 +775,20 @@ enum HeapGraphDest {
     PrimitiveArray { prim_type: PrimitiveArrayType },
 }
 
+impl From<ReferenceTarget> for HeapGraphDest {
+    fn from(target: ReferenceTarget) -> Self {
+        match target {
+            ReferenceTarget::Instance { class_obj_id } => {
+                HeapGraphDest::InstanceOfClass { class_obj_id }
+            }
+            ReferenceTarget::ClassObj { class_obj_id } => HeapGraphDest::ClassObj { class_obj_id },
+            ReferenceTarget::PrimitiveArray { prim_type } => {
+                HeapGraphDest::PrimitiveArray { prim_type }
+            }
+        }
+    }
+}
+
 impl HeapGraphDest {
     fn write_node_name<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write!(writer, "\"")?;
@@ -525,3 +816,141 @@ fn write_to_string<F: FnOnce(&mut Vec<u8>) -> io::Result<()>>(writer: F) -> io::
         .map(|s| s.to_owned())
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn sample_of(num_edges: u64, capacity: usize, rng: &mut rand::rngs::StdRng) -> EdgeSample {
+        let mut sample = EdgeSample::new(capacity);
+        for obj_id in 0..num_edges {
+            sample.offer(
+                SampledEdge {
+                    source: HeapGraphSource::GcRootUnknown,
+                    source_obj_id: None,
+                    dest_obj_id: Id::from(obj_id),
+                    dest: ReferenceTarget::PrimitiveArray {
+                        prim_type: PrimitiveArrayType::Int,
+                    },
+                },
+                rng,
+            );
+        }
+        sample
+    }
+
+    #[test]
+    fn edge_sample_has_requested_size() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        let sample = sample_of(10_000, 100, &mut rng);
+        assert_eq!(10_000, sample.seen);
+        assert_eq!(100, sample.edges.len());
+        // spread across all the edges rather than bunched at the start
+        assert!(sample.edges.iter().any(|e| e.dest_obj_id.id() >= 5_000));
+
+        // fewer edges than the sample size: keep them all
+        assert_eq!(10, sample_of(10, 100, &mut rng).edges.len());
+
+        assert_eq!(0, sample_of(10_000, 0, &mut rng).edges.len());
+    }
+
+    #[test]
+    fn merged_edge_samples_are_proportional() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+
+        let small = sample_of(1_000, 1_000, &mut rng);
+        // ids offset so the two sides can be told apart
+        let mut big = EdgeSample::new(1_000);
+        for edge in sample_of(9_000, 1_000, &mut rng).edges {
+            big.edges.push(SampledEdge {
+                dest_obj_id: Id::from(edge.dest_obj_id.id() + 1_000_000),
+                ..edge
+            });
+        }
+        big.seen = 9_000;
+
+        let merged = small.merge(big, &mut rng);
+
+        assert_eq!(10_000, merged.seen);
+        assert_eq!(1_000, merged.edges.len());
+        // about 10% should come from the side that saw 10% of the edges
+        let from_small = merged
+            .edges
+            .iter()
+            .filter(|e| e.dest_obj_id.id() < 1_000_000)
+            .count();
+        assert!(
+            (50..150).contains(&from_small),
+            "{} edges from the smaller side",
+            from_small
+        );
+    }
+
+    #[test]
+    fn edge_labels_for_each_mode() {
+        let edge = GraphEdge {
+            source: HeapGraphSource::InstanceField {
+                class_obj_id: Id::from(100),
+                field_offset: 2,
+            },
+            dest: HeapGraphDest::PrimitiveArray {
+                prim_type: PrimitiveArrayType::Int,
+            },
+        };
+
+        let labels = ["absolute", "percent", "log"]
+            .iter()
+            .map(|mode| {
+                let mut out = Vec::new();
+                edge.write_dot_edge(1000, 8000, mode.parse().unwrap(), &mut out)
+                    .unwrap();
+                String::from_utf8(out).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "\t\"class-100\" -> \"prim-array-int\"[label=\"x1000\"penwidth=\"3\"\
+                 tailport=\"instance-field-val-2\"];\n",
+                "\t\"class-100\" -> \"prim-array-int\"[label=\"12.5%\"penwidth=\"3\"\
+                 tailport=\"instance-field-val-2\"];\n",
+                "\t\"class-100\" -> \"prim-array-int\"[label=\"10^3.0\"penwidth=\"3\"\
+                 tailport=\"instance-field-val-2\"];\n",
+            ],
+            labels
+        );
+        assert!("bogus".parse::<EdgeLabelMode>().is_err());
+    }
+
+    #[test]
+    fn objects_of_missing_classes_dont_panic() -> Result<(), anyhow::Error> {
+        use crate::test_util::*;
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_unknown(2000)
+            .class(&TestClass {
+                instance_fields: vec![(1, TYPE_OBJ)],
+                ..TestClass::new(100, 0)
+            })
+            .instance(2000, 100, &[Val::Obj(0)])
+            // classes 998 and 999 aren't in the heap dump
+            .instance(1000, 999, &[Val::Obj(2000)])
+            .object_array(3000, 998, &[2000, 0]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let index = crate::index::build_memory_index(&hprof)?;
+
+        let output_dir = tempfile::tempdir()?;
+        let output = output_dir.path().join("graph.dot");
+        ref_count_graph(&hprof, &index, &output, 1, EdgeLabelMode::Absolute, None);
+
+        let dot = fs::read_to_string(&output)?;
+        assert!(dot.contains("\t\"class-998\"[shape=box, label=\"(unknown class 998)\"]\n"));
+        assert!(dot.contains("\t\"class-998\" -> \"class-100\"[label=\"x1\""));
+        Ok(())
+    }
+}
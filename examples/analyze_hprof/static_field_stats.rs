@@ -0,0 +1,96 @@
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::io;
+
+/// Write a CSV of how many of each class's object static fields are null and how many aren't,
+/// ordered by class name.
+///
+/// Only classes with at least one object static field are included. Lazily initialized
+/// singletons and caches show up as null until first use, so this is a quick way to see which
+/// parts of a framework have actually been set up.
+pub(crate) fn static_field_stats<W: io::Write>(hprof: &Hprof, out: W) -> Result<(), anyhow::Error> {
+    let classes = ez_classes_by_obj_id(hprof);
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Class name", "Class obj id", "Null", "Non-null"])?;
+
+    for (class, null, non_null) in classes
+        .values()
+        .filter_map(|c| {
+            let (null, non_null) = c
+                .static_fields
+                .iter()
+                .filter_map(|sf| match sf.value() {
+                    FieldValue::ObjectId(id) => Some(id),
+                    _ => None,
+                })
+                .fold((0_u64, 0_u64), |(null, non_null), id| match id {
+                    Some(_) => (null, non_null + 1),
+                    None => (null + 1, non_null),
+                });
+
+            if null + non_null > 0 {
+                Some((c, null, non_null))
+            } else {
+                None
+            }
+        })
+        .sorted_by_key(|(c, _, _)| (c.name, c.obj_id))
+    {
+        wtr.write_record(&[
+            class.name.to_owned(),
+            class.obj_id.to_string(),
+            null.to_string(),
+            non_null.to_string(),
+        ])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn counts_null_and_non_null_object_statics() {
+        // class Registry { static Object instance = ...; static Object fallback = null; static int size; }
+        let registry_class = TestClass {
+            static_fields: vec![(10, Val::Obj(1000)), (11, Val::Obj(0)), (12, Val::Int(3))],
+            ..TestClass::new(100, 0)
+        };
+        // only primitive statics
+        let counter_class = TestClass {
+            static_fields: vec![(12, Val::Int(0))],
+            ..TestClass::new(101, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/Registry")
+            .utf8(2, "com/example/Counter")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&registry_class)
+            .class(&counter_class)
+            .class(&TestClass::new(102, 0))
+            .instance(1000, 102, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        static_field_stats(&hprof, &mut out).unwrap();
+        assert_eq!(
+            "Class name,Class obj id,Null,Non-null\n\
+             com/example/Registry,100,1,1\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// Print each thread's stack trace along with the objects rooted by each of its frames.
+pub(crate) fn thread_dump<W: io::Write>(hprof: &Hprof, mut out: W) -> Result<(), anyhow::Error> {
+    let utf8 = utf8_by_id(hprof);
+    let load_classes_by_serial = classes_by_serial(hprof);
+    let load_classes_by_obj_id = classes_by_obj_id(hprof);
+    let thread_names = hprof.thread_names().unwrap();
+
+    // frame id -> StackFrame
+    let mut frames = collections::HashMap::new();
+    // stack trace serial -> StackTrace
+    let mut stack_traces = collections::HashMap::new();
+    let mut thread_objs = Vec::new();
+    // thread serial -> roots from that thread's frames
+    let mut thread_roots: collections::HashMap<Serial, Vec<FrameRoot>> =
+        collections::HashMap::new();
+
+    for r in hprof.records_iter().map(|r| r.unwrap()) {
+        match r.tag() {
+            RecordTag::StackFrame => {
+                let f = r.as_stack_frame().unwrap().unwrap();
+                frames.insert(f.id(), f);
+            }
+            RecordTag::StackTrace => {
+                let t = r.as_stack_trace().unwrap().unwrap();
+                stack_traces.insert(t.stack_trace_serial(), t);
+            }
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                let segment = r.as_heap_dump_segment().unwrap().unwrap();
+                for p in segment.sub_records() {
+                    match p.unwrap() {
+                        SubRecord::GcRootThreadObj(t) => thread_objs.push(t),
+                        SubRecord::GcRootJavaStackFrame(sf) => thread_roots
+                            .entry(sf.thread_serial())
+                            .or_default()
+                            .push(FrameRoot {
+                                obj_id: sf.obj_id(),
+                                frame_index: sf.frame_index(),
+                                kind: "local",
+                            }),
+                        SubRecord::GcRootJniLocalRef(jni) => thread_roots
+                            .entry(jni.thread_serial())
+                            .or_default()
+                            .push(FrameRoot {
+                                obj_id: jni.obj_id(),
+                                frame_index: jni.frame_index(),
+                                kind: "JNI local",
+                            }),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Only the rooted objects need their types resolved, so a second pass avoids holding the
+    // type of every object in the heap.
+    let rooted_ids = thread_roots
+        .values()
+        .flat_map(|roots| roots.iter().map(|fr| fr.obj_id))
+        .collect::<collections::HashSet<_>>();
+    let rooted_types = rooted_obj_types(hprof, &rooted_ids, &load_classes_by_obj_id, &utf8);
+
+    thread_objs.sort_by_key(|t| t.thread_serial().num());
+
+    for t in thread_objs.iter() {
+        write!(out, "Thread serial {}", t.thread_serial())?;
+        if let Some(name) = thread_names.get(&t.thread_serial()) {
+            write!(out, " \"{}\"", name.text_lossy())?;
+        }
+        match t.thread_obj_id() {
+            Some(id) => write!(out, " (obj id {})", id)?,
+            None => write!(out, " (no thread obj)")?,
+        }
+        writeln!(out, ", stack trace serial {}", t.stack_trace_serial())?;
+
+        let roots = thread_roots.remove(&t.thread_serial()).unwrap_or_default();
+
+        let frame_ids = stack_traces
+            .get(&t.stack_trace_serial())
+            .map(|st| st.frame_ids().map(|r| r.unwrap()).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        if frame_ids.is_empty() {
+            writeln!(out, "  (no stack trace)")?;
+        }
+
+        for (index, frame_id) in frame_ids.iter().enumerate() {
+            match frames.get(frame_id) {
+                None => writeln!(out, "  #{} (no frame found for id {})", index, frame_id)?,
+                Some(f) => writeln!(
+                    out,
+                    "  #{} {}",
+                    index,
+                    describe_frame(f, &load_classes_by_serial, &utf8)
+                )?,
+            }
+
+            for fr in roots
+                .iter()
+                .filter(|fr| fr.frame_index == Some(index as u32))
+            {
+                write_frame_root(&mut out, fr, &rooted_types)?;
+            }
+        }
+
+        // roots that don't line up with a frame in the trace
+        let unmatched_roots = roots
+            .iter()
+            .filter(|fr| match fr.frame_index {
+                Some(index) => index as usize >= frame_ids.len(),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        if !unmatched_roots.is_empty() {
+            writeln!(out, "  (not associated with a frame)")?;
+            for fr in unmatched_roots {
+                write_frame_root(&mut out, fr, &rooted_types)?;
+            }
+        }
+
+        writeln!(out)?;
+    }
+
+    // roots for threads that didn't have a GcRootThreadObj
+    for (thread_serial, roots) in thread_roots
+        .iter()
+        .sorted_by_key(|(thread_serial, _)| thread_serial.num())
+    {
+        writeln!(out, "Thread serial {} (no thread obj root)", thread_serial)?;
+        for fr in roots {
+            write_frame_root(&mut out, fr, &rooted_types)?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// A frame as `class#method(signature) (file:line)`.
+pub(crate) fn describe_frame(
+    frame: &StackFrame,
+    load_classes_by_serial: &collections::HashMap<Serial, LoadClass>,
+    utf8: &collections::HashMap<Id, Utf8>,
+) -> String {
+    format!(
+        "{}#{}{} ({}:{})",
+        load_classes_by_serial
+            .get(&frame.class_serial())
+            .map(|lc| get_utf8_if_available(utf8, lc.class_name_id()))
+            .unwrap_or("(class not found)"),
+        get_utf8_if_available(utf8, frame.method_name_id()),
+        get_utf8_if_available(utf8, frame.method_signature_id()),
+        frame
+            .source_file_name_id()
+            .map(|id| get_utf8_if_available(utf8, id))
+            .unwrap_or("(no source)"),
+        frame.line_num()
+    )
+}
+
+struct FrameRoot {
+    obj_id: Id,
+    frame_index: Option<u32>,
+    kind: &'static str,
+}
+
+fn write_frame_root<W: io::Write>(
+    out: &mut W,
+    fr: &FrameRoot,
+    rooted_types: &collections::HashMap<Id, String>,
+) -> Result<(), io::Error> {
+    writeln!(
+        out,
+        "      {}: id {} ({})",
+        fr.kind,
+        fr.obj_id,
+        rooted_types
+            .get(&fr.obj_id)
+            .map(|s| s.as_str())
+            .unwrap_or("type for obj id not found")
+    )
+}
+
+/// The type of each object in `rooted_ids`, found with a pass over the heap dump.
+pub(crate) fn rooted_obj_types(
+    hprof: &Hprof,
+    rooted_ids: &collections::HashSet<Id>,
+    load_classes_by_obj_id: &collections::HashMap<Id, LoadClass>,
+    utf8: &collections::HashMap<Id, Utf8>,
+) -> collections::HashMap<Id, String> {
+    let class_name = |class_obj_id: Id| {
+        load_classes_by_obj_id
+            .get(&class_obj_id)
+            .map(|lc| get_utf8_if_available(utf8, lc.class_name_id()))
+            .unwrap_or("(class not found)")
+            .to_owned()
+    };
+
+    let mut types = collections::HashMap::new();
+
+    hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+        .for_each(|r| {
+            let segment = r.as_heap_dump_segment().unwrap().unwrap();
+            for p in segment.sub_records() {
+                let (obj_id, type_name) = match p.unwrap() {
+                    SubRecord::Class(c) => {
+                        (c.obj_id(), format!("class {}", class_name(c.obj_id())))
+                    }
+                    SubRecord::Instance(i) => (i.obj_id(), class_name(i.class_obj_id())),
+                    SubRecord::ObjectArray(oa) => {
+                        (oa.obj_id(), class_name(oa.array_class_obj_id()))
+                    }
+                    SubRecord::PrimitiveArray(pa) => (
+                        pa.obj_id(),
+                        format!("{}[]", pa.primitive_type().java_type_name()),
+                    ),
+                    _ => continue,
+                };
+
+                if rooted_ids.contains(&obj_id) {
+                    types.insert(obj_id, type_name);
+                }
+            }
+        });
+
+    types
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn one_thread_with_rooted_local() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "java/lang/Thread")
+            .utf8(2, "java/lang/String")
+            .utf8(3, "run")
+            .utf8(4, "()V")
+            .utf8(5, "Thread.java")
+            .utf8(6, "com/example/Worker")
+            .utf8(7, "work")
+            .utf8(8, "Worker.java")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 6)
+            // innermost frame first
+            .stack_frame(50, 7, 4, 8, 3, 42)
+            .stack_frame(51, 3, 4, 5, 1, 10)
+            .stack_trace(9, 1, &[50, 51]);
+
+        let mut segment = builder.segment();
+        segment
+            .gc_root_thread_obj(200, 1, 9)
+            .gc_root_java_stack_frame(300, 1, 0)
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .class(&TestClass::new(102, 0))
+            .instance(200, 100, &[])
+            .instance(300, 101, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let mut out = Vec::new();
+        thread_dump(&hprof, &mut out).unwrap();
+
+        assert_eq!(
+            "Thread serial 1 (obj id 200), stack trace serial 9\n\
+             \x20 #0 com/example/Worker#work()V (Worker.java:42)\n\
+             \x20     local: id 300 (java/lang/String)\n\
+             \x20 #1 java/lang/Thread#run()V (Thread.java:10)\n\
+             \n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::util::*;
+use itertools::Itertools;
+use jvm_hprof::{heap_dump::*, *};
+use std::{collections, io};
+
+/// Write a CSV of the objects (instances and arrays) that are neither GC roots nor referenced by
+/// any other object or class, ordered by obj id.
+///
+/// These are garbage that hadn't been collected yet when the heap dump was written. References an
+/// object holds to itself don't count. Garbage that's only referenced by other garbage (e.g. a
+/// discarded linked list, beyond its head) isn't listed, since finding that would require walking
+/// the whole object graph from the roots.
+pub(crate) fn unreachable_objects<W: io::Write>(
+    hprof: &Hprof,
+    out: W,
+) -> Result<(), anyhow::Error> {
+    let classes = ez_classes_by_obj_id(hprof);
+    let class_instance_field_descriptors = build_type_hierarchy_field_descriptors(&classes);
+    let id_size = hprof.header().id_size();
+
+    // obj id -> type name
+    let mut objects = collections::HashMap::new();
+    let mut referenced = collections::HashSet::new();
+
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for p in segment.sub_records() {
+            let s = p.unwrap();
+
+            let (source_obj_id, field_descriptors) = match &s {
+                SubRecord::Class(c) => {
+                    // class loaders may only be referenced by the classes they loaded, which
+                    // isn't a reference from the class as a source
+                    referenced.extend(c.class_loader_obj_id());
+                    (Some(c.obj_id()), None)
+                }
+                SubRecord::Instance(instance) => {
+                    objects.insert(
+                        instance.obj_id(),
+                        class_name_or_unknown(&classes, instance.class_obj_id()),
+                    );
+                    (
+                        Some(instance.obj_id()),
+                        Some(instance_field_descriptors(
+                            &class_instance_field_descriptors,
+                            instance.class_obj_id(),
+                        )),
+                    )
+                }
+                SubRecord::ObjectArray(obj_array) => {
+                    objects.insert(
+                        obj_array.obj_id(),
+                        class_name_or_unknown(&classes, obj_array.array_class_obj_id()),
+                    );
+                    (Some(obj_array.obj_id()), None)
+                }
+                SubRecord::PrimitiveArray(pa) => {
+                    objects.insert(
+                        pa.obj_id(),
+                        format!("{}[]", pa.primitive_type().java_type_name()),
+                    );
+                    (Some(pa.obj_id()), None)
+                }
+                // GC roots
+                _ => (None, None),
+            };
+
+            if let Some(source) = ReferenceSource::from_sub_record(s).unwrap() {
+                referenced.extend(
+                    source
+                        .referenced_ids(id_size, field_descriptors.unwrap_or(&[]))
+                        // instances whose fields don't match their class, e.g. because it's
+                        // missing, have no known references
+                        .map_while(|r| r.ok())
+                        .filter(|&id| Some(id) != source_obj_id),
+                );
+            }
+        }
+    }
+
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Obj id", "Type"])?;
+
+    for (obj_id, type_name) in objects
+        .iter()
+        .filter(|(obj_id, _)| !referenced.contains(obj_id))
+        .sorted_by_key(|(obj_id, _)| **obj_id)
+    {
+        wtr.write_record(&[obj_id.to_string(), type_name.clone()])?;
+    }
+
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn finds_orphaned_object() {
+        let node_class = TestClass {
+            instance_fields: vec![(10, TYPE_OBJ)],
+            ..TestClass::new(100, 0)
+        };
+        let holder_class = TestClass {
+            static_fields: vec![(11, Val::Obj(1000))],
+            ..TestClass::new(101, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Node")
+            .utf8(2, "Holder")
+            .utf8(3, "[LNode;")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .load_class(3, 102, 0, 3);
+
+        let mut segment = builder.segment();
+        segment
+            .class(&node_class)
+            .class(&holder_class)
+            .class(&TestClass::new(102, 0))
+            // referenced by a static field, and references the array
+            .instance(1000, 100, &[Val::Obj(2000)])
+            // a GC root, which references its own array
+            .gc_root_jni_global(1001, 1)
+            .instance(1001, 100, &[Val::Obj(3000)])
+            .int_array(3000, &[1])
+            .object_array(2000, 102, &[])
+            // orphaned: only refers to itself
+            .instance(1002, 100, &[Val::Obj(1002)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut out = Vec::new();
+        unreachable_objects(&hprof, &mut out).unwrap();
+
+        assert_eq!(
+            "Obj id,Type\n\
+             1002,Node\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
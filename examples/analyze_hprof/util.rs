@@ -1,5 +1,5 @@
 use jvm_hprof::{heap_dump::*, *};
-use std::collections;
+use std::{collections, str};
 
 /// A somewhat more convenient representation of a Class together with its name from the corresponding LoadClass
 pub struct EzClass<'a> {
@@ -40,11 +40,21 @@ impl<'a> EzClass<'a> {
     }
 }
 
+/// The name of a class, or [unknown_class_name] if it isn't in `classes`.
+pub fn class_name_or_unknown(
+    classes: &collections::HashMap<Id, EzClass>,
+    class_obj_id: Id,
+) -> String {
+    classes
+        .get(&class_obj_id)
+        .map(|c| c.name.to_owned())
+        .unwrap_or_else(|| unknown_class_name(class_obj_id))
+}
+
 pub fn utf8_by_id<'a>(hprof: &'a Hprof) -> collections::HashMap<Id, Utf8<'a>> {
     hprof
-        .records_iter()
+        .records_iter_filtered(&[jvm_hprof::RecordTag::Utf8])
         .map(|r| r.unwrap())
-        .filter(|r| r.tag() == jvm_hprof::RecordTag::Utf8)
         .map(|r| r.as_utf_8().unwrap().unwrap())
         .map(|u| (u.name_id(), u))
         .collect::<collections::HashMap<_, _>>()
@@ -52,9 +62,8 @@ pub fn utf8_by_id<'a>(hprof: &'a Hprof) -> collections::HashMap<Id, Utf8<'a>> {
 
 pub fn utf8_strings_by_id<'a>(hprof: &'a Hprof) -> collections::HashMap<Id, &'a str> {
     hprof
-        .records_iter()
+        .records_iter_filtered(&[jvm_hprof::RecordTag::Utf8])
         .map(|r| r.unwrap())
-        .filter(|r| r.tag() == jvm_hprof::RecordTag::Utf8)
         .map(|r| r.as_utf_8().unwrap().unwrap())
         .map(|u| {
             (
@@ -67,9 +76,8 @@ pub fn utf8_strings_by_id<'a>(hprof: &'a Hprof) -> collections::HashMap<Id, &'a
 
 pub fn classes_by_serial(hprof: &Hprof) -> collections::HashMap<Serial, LoadClass> {
     hprof
-        .records_iter()
+        .records_iter_filtered(&[jvm_hprof::RecordTag::LoadClass])
         .map(|r| r.unwrap())
-        .filter(|r| r.tag() == jvm_hprof::RecordTag::LoadClass)
         .map(|r| r.as_load_class().unwrap().unwrap())
         .map(|f| (f.class_serial(), f))
         .collect::<collections::HashMap<_, _>>()
@@ -77,14 +85,47 @@ pub fn classes_by_serial(hprof: &Hprof) -> collections::HashMap<Serial, LoadClas
 
 pub fn classes_by_obj_id(hprof: &Hprof) -> collections::HashMap<Id, LoadClass> {
     hprof
-        .records_iter()
+        .records_iter_filtered(&[jvm_hprof::RecordTag::LoadClass])
         .map(|r| r.unwrap())
-        .filter(|r| r.tag() == jvm_hprof::RecordTag::LoadClass)
         .map(|r| r.as_load_class().unwrap().unwrap())
         .map(|f| (f.class_obj_id(), f))
         .collect::<collections::HashMap<_, _>>()
 }
 
+/// All classes in the heap dump, with names resolved via their LoadClass records.
+pub fn ez_classes_by_obj_id<'a>(hprof: &Hprof<'a>) -> collections::HashMap<Id, EzClass<'a>> {
+    // class obj id -> LoadClass
+    let mut load_classes = collections::HashMap::new();
+    // name id -> String
+    let mut utf8 = collections::HashMap::new();
+    let mut classes = collections::HashMap::new();
+
+    hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .for_each(|r| match r.tag() {
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                let segment = r.as_heap_dump_segment().unwrap().unwrap();
+                for p in segment.sub_records() {
+                    if let SubRecord::Class(c) = p.unwrap() {
+                        classes.insert(c.obj_id(), EzClass::from_class(&c, &load_classes, &utf8));
+                    }
+                }
+            }
+            RecordTag::Utf8 => {
+                let u = r.as_utf_8().unwrap().unwrap();
+                utf8.insert(u.name_id(), u.text_as_str().unwrap_or("(invalid UTF-8)"));
+            }
+            RecordTag::LoadClass => {
+                let lc = r.as_load_class().unwrap().unwrap();
+                load_classes.insert(lc.class_obj_id(), lc);
+            }
+            _ => {}
+        });
+
+    classes
+}
+
 pub fn get_utf8_if_available<'a>(utf8: &'a collections::HashMap<Id, Utf8<'a>>, id: Id) -> &'a str {
     utf8.get(&id)
         .map(|u| u.text_as_str().unwrap_or("(invalid utf8)"))
@@ -100,15 +141,30 @@ pub fn record_counts(hprof: &Hprof) -> collections::HashMap<RecordTag, u64> {
 
     // overwrite zeros with real counts, if any
     hprof
-        .records_iter()
-        .map(|r| r.unwrap().tag())
-        .for_each(|tag| {
+        .scan_record_headers()
+        .for_each(|(tag, _micros, _len, _offset)| {
             counts.entry(tag).and_modify(|c| *c += 1).or_insert(1);
         });
 
     counts
 }
 
+/// Call `f` with each sub record of each heap dump or heap dump segment, in the order they're in
+/// the file.
+pub fn for_each_sub_record<'a, F: FnMut(SubRecord<'a>)>(hprof: &Hprof<'a>, mut f: F) {
+    for r in hprof
+        .records_iter()
+        .map(|r| r.unwrap())
+        .filter(|r| r.tag() == RecordTag::HeapDump || r.tag() == RecordTag::HeapDumpSegment)
+    {
+        let segment = r.as_heap_dump_segment().unwrap().unwrap();
+
+        for p in segment.sub_records() {
+            f(p.unwrap());
+        }
+    }
+}
+
 /// Walk the class hierarchy and build a per-class list of field descriptors, root type's fields last.
 ///
 /// Classes are not laid down super class first, so have to wait until the end to be able to
@@ -120,15 +176,12 @@ pub fn build_type_hierarchy_field_descriptors(
     let mut class_instance_field_descriptors = collections::HashMap::new();
 
     for (id, mc) in classes {
-        let mut opt_scid = mc.super_class_obj_id;
         let mut field_descriptors = Vec::<FieldDescriptor>::new();
         field_descriptors.extend(mc.instance_field_descriptors.iter());
-        while let Some(scid) = opt_scid {
-            let sc = classes
-                .get(&scid)
-                .expect("Corrupt heap dump? Could not find superclass");
+        // a missing superclass's fields can't be known, so stop there rather than give up
+        // entirely on a damaged heap dump, and likewise stop if the hierarchy has a cycle
+        for sc in super_classes(classes, *id) {
             field_descriptors.extend(sc.instance_field_descriptors.iter());
-            opt_scid = sc.super_class_obj_id;
         }
 
         class_instance_field_descriptors.insert(*id, field_descriptors);
@@ -136,3 +189,263 @@ pub fn build_type_hierarchy_field_descriptors(
 
     class_instance_field_descriptors
 }
+
+/// The field descriptors for instances of a class, as found in the output of
+/// [build_type_hierarchy_field_descriptors].
+///
+/// If the class isn't in the heap dump, there's no way to parse its instances' fields, so they're
+/// treated as having none.
+pub fn instance_field_descriptors(
+    class_instance_field_descriptors: &collections::HashMap<Id, Vec<FieldDescriptor>>,
+    class_obj_id: Id,
+) -> &[FieldDescriptor] {
+    class_instance_field_descriptors
+        .get(&class_obj_id)
+        .map(|fds| &fds[..])
+        .unwrap_or(&[])
+}
+
+/// An instance's field values, with the descriptor for each, as per [Instance::field_values].
+///
+/// If `field_descriptors` don't match the instance's fields, e.g. because its class or a
+/// superclass is missing from the heap dump, there are no values at all, since there's no telling
+/// which bytes belong to which field.
+pub fn instance_field_values<'a: 'd, 'd>(
+    instance: &Instance<'a>,
+    field_descriptors: &'d [FieldDescriptor],
+    id_size: IdSize,
+) -> impl Iterator<Item = (&'d FieldDescriptor, FieldValue)> + 'd {
+    instance
+        .field_values(field_descriptors, id_size)
+        .map_while(|r| r.ok())
+}
+
+/// Iterate over the superclasses of a class (not including the class itself), nearest first.
+///
+/// Iteration stops early if a superclass can't be found, and after as many superclasses as there
+/// are classes, in case of a cycle in a corrupt hprof.
+pub fn super_classes<'c, 'a>(
+    classes: &'c collections::HashMap<Id, EzClass<'a>>,
+    class_obj_id: Id,
+) -> SuperClasses<'c, 'a> {
+    SuperClasses {
+        classes,
+        next_obj_id: classes
+            .get(&class_obj_id)
+            .and_then(|c| c.super_class_obj_id),
+        remaining: classes.len(),
+    }
+}
+
+/// Iterator over a class's superclasses. See [super_classes].
+pub struct SuperClasses<'c, 'a> {
+    classes: &'c collections::HashMap<Id, EzClass<'a>>,
+    next_obj_id: Option<Id>,
+    /// No chain without a cycle can be longer than the number of classes
+    remaining: usize,
+}
+
+impl<'c, 'a> Iterator for SuperClasses<'c, 'a> {
+    type Item = &'c EzClass<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.remaining = self.remaining.checked_sub(1)?;
+        let sc = self.classes.get(&self.next_obj_id?)?;
+        self.next_obj_id = sc.super_class_obj_id;
+        Some(sc)
+    }
+}
+
+/// Returns true if any superclass of the class (not including the class itself) has the given name.
+///
+/// Names are as they appear in LoadClass records, e.g. `java/lang/Enum`.
+pub fn is_subclass_of(
+    classes: &collections::HashMap<Id, EzClass>,
+    class_obj_id: Id,
+    super_class_name: &str,
+) -> bool {
+    super_classes(classes, class_obj_id).any(|sc| sc.name == super_class_name)
+}
+
+/// Name of the superclass of soft, weak, phantom, and finalizer references
+pub const REFERENCE_CLASS_NAME: &str = "java/lang/ref/Reference";
+
+/// For each class that is or extends `java/lang/ref/Reference`, the index of the `referent` field
+/// in that class's field descriptors as per [build_type_hierarchy_field_descriptors].
+///
+/// The referent of a reference object doesn't keep it alive the way a normal field does, so those
+/// edges are "weak" for the purposes of reachability. Classes outside the `Reference` hierarchy
+/// aren't included.
+pub fn referent_field_indices(
+    classes: &collections::HashMap<Id, EzClass>,
+    utf8: &collections::HashMap<Id, &str>,
+) -> collections::HashMap<Id, usize> {
+    let reference_class = match classes.values().find(|c| c.name == REFERENCE_CLASS_NAME) {
+        Some(c) => c,
+        None => return collections::HashMap::new(),
+    };
+    let referent_index = match reference_class
+        .instance_field_descriptors
+        .iter()
+        .position(|fd| utf8.get(&fd.name_id()) == Some(&"referent"))
+    {
+        Some(i) => i,
+        None => return collections::HashMap::new(),
+    };
+
+    classes
+        .values()
+        .filter_map(|c| {
+            // subclass fields come first, so count them until Reference is reached
+            let mut offset = 0;
+            for class in std::iter::once(c).chain(super_classes(classes, c.obj_id)) {
+                if class.obj_id == reference_class.obj_id {
+                    return Some((c.obj_id, offset + referent_index));
+                }
+                offset += class.instance_field_descriptors.len();
+            }
+            None
+        })
+        .collect()
+}
+
+/// Parse an instance's fields and return the value of the first one with the provided name.
+///
+/// `field_descriptors` are for the whole type hierarchy, as per
+/// [build_type_hierarchy_field_descriptors], so a field in a subclass will shadow a field with
+/// the same name in a superclass.
+pub fn instance_field_value(
+    instance: &Instance,
+    field_descriptors: &[FieldDescriptor],
+    utf8: &collections::HashMap<Id, &str>,
+    field_name: &str,
+    id_size: IdSize,
+) -> Option<FieldValue> {
+    instance_field_values(instance, field_descriptors, id_size)
+        .find(|(fd, _)| utf8.get(&fd.name_id()) == Some(&field_name))
+        .map(|(_, field_val)| field_val)
+}
+
+/// A half-open range of object ids (`START..END`), used to split heap-walking work into shards
+/// that can be processed separately, e.g. on different machines.
+///
+/// Object ids are derived from addresses, so they aren't contiguous: ranges of the same size
+/// won't necessarily hold similar numbers of objects, and shards are approximate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjIdRange {
+    start: u64,
+    /// `None` for no upper bound
+    end: Option<u64>,
+}
+
+impl ObjIdRange {
+    /// A range that contains every id.
+    pub fn all() -> ObjIdRange {
+        ObjIdRange {
+            start: 0,
+            end: None,
+        }
+    }
+
+    pub fn contains(&self, obj_id: Id) -> bool {
+        obj_id.id() >= self.start && self.end.map(|end| obj_id.id() < end).unwrap_or(true)
+    }
+}
+
+/// Parses `START..END`, where either end may be omitted.
+impl str::FromStr for ObjIdRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| anyhow::anyhow!("Obj id range must be START..END: {}", s))?;
+
+        Ok(ObjIdRange {
+            start: match start {
+                "" => 0,
+                _ => start.parse::<Id>()?.id(),
+            },
+            end: match end {
+                "" => None,
+                _ => Some(end.parse::<Id>()?.id()),
+            },
+        })
+    }
+}
+
+/// How ids are written in human-readable output, so that every subcommand agrees.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdFormat {
+    Dec,
+    /// `0x`-prefixed and zero-padded to the width of the hprof's ids
+    Hex,
+}
+
+impl IdFormat {
+    pub fn format(&self, id: Id, id_size: IdSize) -> String {
+        match self {
+            IdFormat::Dec => id.to_string(),
+            IdFormat::Hex => format!(
+                "{:#0width$X}",
+                id,
+                // 2 hex digits per byte, plus the 0x
+                width = 2 + 2 * id_size.size_in_bytes()
+            ),
+        }
+    }
+}
+
+/// Parses `dec` or `hex`.
+impl str::FromStr for IdFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dec" => Ok(IdFormat::Dec),
+            "hex" => Ok(IdFormat::Hex),
+            _ => Err(anyhow::anyhow!("Id format must be dec or hex: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_format_dec_and_hex() {
+        let id = Id::from(0x7f3a_5c8d);
+
+        assert_eq!("2134531213", IdFormat::Dec.format(id, IdSize::U64));
+        assert_eq!("0x000000007F3A5C8D", IdFormat::Hex.format(id, IdSize::U64));
+        assert_eq!("0x7F3A5C8D", IdFormat::Hex.format(id, IdSize::U32));
+
+        assert_eq!(IdFormat::Hex, "hex".parse::<IdFormat>().unwrap());
+        assert_eq!(IdFormat::Dec, "dec".parse::<IdFormat>().unwrap());
+        assert!("oct".parse::<IdFormat>().is_err());
+    }
+
+    #[test]
+    fn super_classes_stops_at_a_cycle() {
+        let class = |obj_id: u64, super_class_obj_id: u64| EzClass {
+            obj_id: Id::from(obj_id),
+            super_class_obj_id: Some(Id::from(super_class_obj_id)),
+            static_fields: Vec::new(),
+            instance_field_descriptors: Vec::new(),
+            name: "Cyclic",
+            instance_size_bytes: 0,
+        };
+        let classes = vec![class(100, 101), class(101, 100)]
+            .into_iter()
+            .map(|c| (c.obj_id, c))
+            .collect::<collections::HashMap<_, _>>();
+
+        let supers = super_classes(&classes, Id::from(100))
+            .map(|c| c.obj_id.id())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![101, 100], supers);
+        assert!(!is_subclass_of(&classes, Id::from(100), "java/lang/Object"));
+        assert_eq!(2, build_type_hierarchy_field_descriptors(&classes).len());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::heap_dump::SubRecordTag;
+use crate::*;
+use std::collections;
+
+type RecordHandler<'h> = dyn Fn(&[u8], IdSize) + Send + Sync + 'h;
+type SubRecordHandler<'h> = dyn Fn(&[u8], IdSize) -> nom::IResult<&[u8], ()> + Send + Sync + 'h;
+
+/// Handlers for record and sub record tags that this library doesn't know about, as written by
+/// modified or experimental JVMs. Use with [parse_hprof_with_handlers].
+///
+/// Normally, iterating over a record or sub record with an unknown tag panics. With a handler
+/// registered for that tag, the handler is called instead and iteration carries on with the
+/// following record or sub record, so handlers typically accumulate whatever they find into
+/// state they share with the caller (e.g. via a `Mutex`). Unknown tags without a handler still
+/// panic.
+///
+/// Handlers are called from whichever thread is iterating, so they must be `Send` and `Sync`.
+#[derive(Default)]
+pub struct Handlers<'h> {
+    records: collections::HashMap<u8, Box<RecordHandler<'h>>>,
+    sub_records: collections::HashMap<u8, Box<SubRecordHandler<'h>>>,
+}
+
+impl<'h> Handlers<'h> {
+    pub fn new() -> Handlers<'h> {
+        Handlers::default()
+    }
+
+    /// Call `handler` with the body of each record with tag `tag_byte`.
+    ///
+    /// Records are length-prefixed, so the body is exactly the record's contents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tag_byte` is a known [RecordTag], as those are always parsed normally.
+    pub fn record<F>(&mut self, tag_byte: u8, handler: F) -> &mut Self
+    where
+        F: Fn(&[u8], IdSize) + Send + Sync + 'h,
+    {
+        assert!(
+            RecordTag::from_tag_byte(tag_byte).is_none(),
+            "Tag {:#X} is already a known record tag",
+            tag_byte
+        );
+        self.records.insert(tag_byte, Box::new(handler));
+        self
+    }
+
+    /// Call `handler` with the input following the tag byte of each sub record with tag
+    /// `tag_byte`.
+    ///
+    /// Sub records aren't length-prefixed, so the input extends to the end of the heap dump
+    /// segment, and the handler must return whatever input follows its sub record so that
+    /// iteration can continue from there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tag_byte` is a known [SubRecordTag], as those are always parsed normally.
+    pub fn sub_record<F>(&mut self, tag_byte: u8, handler: F) -> &mut Self
+    where
+        F: Fn(&[u8], IdSize) -> nom::IResult<&[u8], ()> + Send + Sync + 'h,
+    {
+        assert!(
+            SubRecordTag::from_tag_byte(tag_byte).is_none(),
+            "Tag {:#X} is already a known sub record tag",
+            tag_byte
+        );
+        self.sub_records.insert(tag_byte, Box::new(handler));
+        self
+    }
+
+    pub(crate) fn record_handler(&self, tag_byte: u8) -> Option<&RecordHandler<'h>> {
+        self.records.get(&tag_byte).map(|h| h.as_ref())
+    }
+
+    pub(crate) fn sub_record_handler(&self, tag_byte: u8) -> Option<&SubRecordHandler<'h>> {
+        self.sub_records.get(&tag_byte).map(|h| h.as_ref())
+    }
+}
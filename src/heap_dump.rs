@@ -7,6 +7,8 @@
 //! That said, it is still reasonably fast at approximately 1GiB/s per core, and there is room to
 //! make parsing lazier should the need arise.
 use getset::{CopyGetters, Getters};
+use std::marker;
+use strum_macros::EnumIter;
 
 use crate::*;
 
@@ -59,47 +61,335 @@ impl<'a> fmt::Debug for SubRecord<'a> {
 }
 
 impl<'a> SubRecord<'a> {
+    /// The tag for this sub record's variant.
+    pub fn tag(&self) -> SubRecordTag {
+        match self {
+            SubRecord::GcRootUnknown(_) => SubRecordTag::GcRootUnknown,
+            SubRecord::GcRootThreadObj(_) => SubRecordTag::GcRootThreadObj,
+            SubRecord::GcRootJniGlobal(_) => SubRecordTag::GcRootJniGlobal,
+            SubRecord::GcRootJniLocalRef(_) => SubRecordTag::GcRootJniLocalRef,
+            SubRecord::GcRootJavaStackFrame(_) => SubRecordTag::GcRootJavaStackFrame,
+            SubRecord::GcRootNativeStack(_) => SubRecordTag::GcRootNativeStack,
+            SubRecord::GcRootSystemClass(_) => SubRecordTag::GcRootSystemClass,
+            SubRecord::GcRootThreadBlock(_) => SubRecordTag::GcRootThreadBlock,
+            SubRecord::GcRootBusyMonitor(_) => SubRecordTag::GcRootBusyMonitor,
+            SubRecord::Class(_) => SubRecordTag::Class,
+            SubRecord::Instance(_) => SubRecordTag::Instance,
+            SubRecord::ObjectArray(_) => SubRecordTag::ObjectArray,
+            SubRecord::PrimitiveArray(_) => SubRecordTag::PrimitiveArray,
+        }
+    }
+
+    /// Returns `true` for the `GcRoot*` variants, and `false` for the objects (classes, instances,
+    /// and arrays).
+    pub fn is_gc_root(&self) -> bool {
+        match self {
+            SubRecord::GcRootUnknown(_)
+            | SubRecord::GcRootThreadObj(_)
+            | SubRecord::GcRootJniGlobal(_)
+            | SubRecord::GcRootJniLocalRef(_)
+            | SubRecord::GcRootJavaStackFrame(_)
+            | SubRecord::GcRootNativeStack(_)
+            | SubRecord::GcRootSystemClass(_)
+            | SubRecord::GcRootThreadBlock(_)
+            | SubRecord::GcRootBusyMonitor(_) => true,
+            SubRecord::Class(_)
+            | SubRecord::Instance(_)
+            | SubRecord::ObjectArray(_)
+            | SubRecord::PrimitiveArray(_) => false,
+        }
+    }
+
+    /// The obj id of the object rooted by a GC root, for when the kind of root doesn't matter.
+    ///
+    /// Returns `None` if this isn't a GC root (see [SubRecord::is_gc_root]), or if it's a
+    /// [GcRootThreadObj] without a thread object.
+    pub fn as_gc_root_obj_id(&self) -> Option<Id> {
+        match self {
+            SubRecord::GcRootUnknown(r) => Some(r.obj_id()),
+            SubRecord::GcRootThreadObj(r) => r.thread_obj_id(),
+            SubRecord::GcRootJniGlobal(r) => Some(r.obj_id()),
+            SubRecord::GcRootJniLocalRef(r) => Some(r.obj_id()),
+            SubRecord::GcRootJavaStackFrame(r) => Some(r.obj_id()),
+            SubRecord::GcRootNativeStack(r) => Some(r.obj_id()),
+            SubRecord::GcRootSystemClass(r) => Some(r.obj_id()),
+            SubRecord::GcRootThreadBlock(r) => Some(r.obj_id()),
+            SubRecord::GcRootBusyMonitor(r) => Some(r.obj_id()),
+            SubRecord::Class(_)
+            | SubRecord::Instance(_)
+            | SubRecord::ObjectArray(_)
+            | SubRecord::PrimitiveArray(_) => None,
+        }
+    }
+
+    /// Parse the single sub record at the start of `input`, returning how many bytes it took up
+    /// along with the sub record, so that the next one (if any) starts at that many bytes in.
+    ///
+    /// This is for tools that keep track of where sub records are and later want to look at one
+    /// without iterating over the rest of the segment (see also [crate::Hprof::sub_record_at]).
+    /// `input` must start at a sub record boundary, i.e. with a sub record's tag byte. A slice
+    /// that starts anywhere else will most likely produce a parse error, but could also produce
+    /// garbage. `id_size` should be from the header of the hprof the sub record is in.
+    ///
+    /// Only the standard sub record types are supported: a tag that isn't a known
+    /// [SubRecordTag], including those of sub records that would be handled by [crate::Handlers],
+    /// produces an error rather than a panic.
+    pub fn parse_one(input: &'a [u8], id_size: IdSize) -> ParseResult<'a, (usize, SubRecord<'a>)> {
+        match input.first().copied().map(SubRecordTag::from_tag_byte) {
+            Some(Some(_)) => SubRecord::parse(input, id_size)
+                .map(|(rest, sub_record)| (input.len() - rest.len(), sub_record)),
+            _ => Err(nom::Err::Error((input, nom::error::ErrorKind::Tag))),
+        }
+    }
+
     pub(crate) fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], SubRecord> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L178
         let (input, tag_byte) = number::be_u8(input)?;
 
+        let tag = match SubRecordTag::from_tag_byte(tag_byte) {
+            Some(t) => t,
+            None => panic!("Unexpected sub-record type {:#X}", tag_byte),
+        };
+
         // have to parse now since ClassObject, etc, have variable size
 
-        let (input, variant) = match tag_byte {
-            0xFF => GcRootUnknown::parse(input, id_size)
+        let (input, variant) = match tag {
+            SubRecordTag::GcRootUnknown => GcRootUnknown::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootUnknown(r))),
-            0x08 => GcRootThreadObj::parse(input, id_size)
+            SubRecordTag::GcRootThreadObj => GcRootThreadObj::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootThreadObj(r))),
-            0x01 => GcRootJniGlobal::parse(input, id_size)
+            SubRecordTag::GcRootJniGlobal => GcRootJniGlobal::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootJniGlobal(r))),
-            0x02 => GcRootJniLocalRef::parse(input, id_size)
+            SubRecordTag::GcRootJniLocalRef => GcRootJniLocalRef::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootJniLocalRef(r))),
-            0x03 => GcRootJavaStackFrame::parse(input, id_size)
+            SubRecordTag::GcRootJavaStackFrame => GcRootJavaStackFrame::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootJavaStackFrame(r))),
-            0x04 => GcRootNativeStack::parse(input, id_size)
+            SubRecordTag::GcRootNativeStack => GcRootNativeStack::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootNativeStack(r))),
-            0x05 => GcRootSystemClass::parse(input, id_size)
+            SubRecordTag::GcRootSystemClass => GcRootSystemClass::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootSystemClass(r))),
-            0x06 => GcRootThreadBlock::parse(input, id_size)
+            SubRecordTag::GcRootThreadBlock => GcRootThreadBlock::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootThreadBlock(r))),
-            0x07 => GcRootBusyMonitor::parse(input, id_size)
+            SubRecordTag::GcRootBusyMonitor => GcRootBusyMonitor::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::GcRootBusyMonitor(r))),
-            0x20 => Class::parse(input, id_size).map(|(input, r)| (input, SubRecord::Class(r))),
-            0x21 => {
+            SubRecordTag::Class => {
+                Class::parse(input, id_size).map(|(input, r)| (input, SubRecord::Class(r)))
+            }
+            SubRecordTag::Instance => {
                 Instance::parse(input, id_size).map(|(input, r)| (input, SubRecord::Instance(r)))
             }
-            0x22 => ObjectArray::parse(input, id_size)
+            SubRecordTag::ObjectArray => ObjectArray::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::ObjectArray(r))),
-            0x23 => PrimitiveArray::parse(input, id_size)
+            SubRecordTag::PrimitiveArray => PrimitiveArray::parse(input, id_size)
                 .map(|(input, r)| (input, SubRecord::PrimitiveArray(r))),
-            _ => panic!("Unexpected sub-record type {:#X}", tag_byte),
         }?;
 
         Ok((input, variant))
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+/// A sub record that can refer to other objects, as yielded by
+/// [crate::SubRecords::reference_sources].
+pub enum ReferenceSource<'a> {
+    /// Any of the GC root sub records. `tag` is the kind of root, and `obj_id` is the rooted
+    /// object.
+    GcRoot {
+        tag: SubRecordTag,
+        obj_id: Id,
+    },
+    /// A class with a signers or protection domain object, or at least one static field of
+    /// object type (which may still be null).
+    Class(Class<'a>),
+    Instance(Instance<'a>),
+    ObjectArray(ObjectArray<'a>),
+}
+
+impl<'a> ReferenceSource<'a> {
+    /// Returns `None` for sub records that can't refer to other objects, as per
+    /// [crate::SubRecords::reference_sources].
+    pub fn from_sub_record(sub_record: SubRecord<'a>) -> ParseResult<'a, Option<Self>> {
+        let tag = sub_record.tag();
+        let gc_root = |obj_id| Ok(Some(ReferenceSource::GcRoot { tag, obj_id }));
+
+        match sub_record {
+            SubRecord::GcRootUnknown(r) => gc_root(r.obj_id()),
+            // no reference for a thread newly attached through JNI
+            SubRecord::GcRootThreadObj(r) => match r.thread_obj_id() {
+                Some(obj_id) => gc_root(obj_id),
+                None => Ok(None),
+            },
+            SubRecord::GcRootJniGlobal(r) => gc_root(r.obj_id()),
+            SubRecord::GcRootJniLocalRef(r) => gc_root(r.obj_id()),
+            SubRecord::GcRootJavaStackFrame(r) => gc_root(r.obj_id()),
+            SubRecord::GcRootNativeStack(r) => gc_root(r.obj_id()),
+            SubRecord::GcRootSystemClass(r) => gc_root(r.obj_id()),
+            SubRecord::GcRootThreadBlock(r) => gc_root(r.obj_id()),
+            SubRecord::GcRootBusyMonitor(r) => gc_root(r.obj_id()),
+            SubRecord::Class(c) => {
+                if c.signers_obj_id().is_some() || c.protection_domain_obj_id().is_some() {
+                    return Ok(Some(ReferenceSource::Class(c)));
+                }
+                for sf in c.static_fields() {
+                    if sf?.field_type() == FieldType::ObjectId {
+                        return Ok(Some(ReferenceSource::Class(c)));
+                    }
+                }
+                Ok(None)
+            }
+            SubRecord::Instance(i) => Ok(Some(ReferenceSource::Instance(i))),
+            SubRecord::ObjectArray(oa) => Ok(Some(ReferenceSource::ObjectArray(oa))),
+            SubRecord::PrimitiveArray(_) => Ok(None),
+        }
+    }
+
+    /// The obj ids of the non-null references held by this source: the rooted object for GC
+    /// roots, the signers, protection domain, and static object fields of classes, the object
+    /// fields of instances, and the elements of object arrays.
+    ///
+    /// `field_descriptors` is only used for instances, and should be all of the instance's fields
+    /// in the order they're stored, as with [Instance::field_values]. Pass `&[]` for other
+    /// sources.
+    ///
+    /// Comparing referenced ids with the obj ids of the objects in the heap dump is one way to
+    /// find dangling references.
+    pub fn referenced_ids<'d>(
+        &self,
+        id_size: IdSize,
+        field_descriptors: &'d [FieldDescriptor],
+    ) -> ReferencedIds<'a, 'd> {
+        let state = match self {
+            ReferenceSource::GcRoot { obj_id, .. } => ReferencedIdsState::GcRoot(Some(*obj_id)),
+            ReferenceSource::Class(c) => ReferencedIdsState::Class {
+                signers: c.signers_obj_id(),
+                protection_domain: c.protection_domain_obj_id(),
+                static_fields: c.static_fields(),
+            },
+            ReferenceSource::Instance(i) => {
+                ReferencedIdsState::Instance(i.field_values(field_descriptors, id_size))
+            }
+            ReferenceSource::ObjectArray(oa) => {
+                ReferencedIdsState::ObjectArray(oa.elements(id_size))
+            }
+        };
+
+        ReferencedIds { state }
+    }
+}
+
+/// Iterator over the obj ids referred to by a [ReferenceSource], as per
+/// [ReferenceSource::referenced_ids].
+pub struct ReferencedIds<'a, 'd> {
+    state: ReferencedIdsState<'a, 'd>,
+}
+
+enum ReferencedIdsState<'a, 'd> {
+    GcRoot(Option<Id>),
+    Class {
+        signers: Option<Id>,
+        protection_domain: Option<Id>,
+        static_fields: StaticFieldEntries<'a>,
+    },
+    Instance(FieldValues<'a, 'd>),
+    ObjectArray(NullableIds<'a>),
+}
+
+impl<'a, 'd> Iterator for ReferencedIds<'a, 'd> {
+    type Item = ParseResult<'a, Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.state {
+            ReferencedIdsState::GcRoot(obj_id) => obj_id.take().map(Ok),
+            ReferencedIdsState::Class {
+                signers,
+                protection_domain,
+                static_fields,
+            } => {
+                if let Some(id) = signers.take().or_else(|| protection_domain.take()) {
+                    return Some(Ok(id));
+                }
+
+                static_fields.find_map(|r| match r {
+                    Ok(sf) => match sf.value() {
+                        FieldValue::ObjectId(Some(id)) => Some(Ok(id)),
+                        _ => None,
+                    },
+                    Err(e) => Some(Err(e)),
+                })
+            }
+            ReferencedIdsState::Instance(field_values) => field_values.find_map(|r| match r {
+                Ok((_fd, FieldValue::ObjectId(Some(id)))) => Some(Ok(id)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            }),
+            ReferencedIdsState::ObjectArray(elements) => elements.find_map(|r| match r {
+                Ok(element) => element.map(Ok),
+                Err(e) => Some(Err(e)),
+            }),
+        }
+    }
+}
+
+/// Indicates which variant of [SubRecord] a sub record is.
+// Since this enum has no data, add EnumIter to allow enumerating across the variants
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumIter)]
+pub enum SubRecordTag {
+    GcRootUnknown,
+    GcRootThreadObj,
+    GcRootJniGlobal,
+    GcRootJniLocalRef,
+    GcRootJavaStackFrame,
+    GcRootNativeStack,
+    GcRootSystemClass,
+    GcRootThreadBlock,
+    GcRootBusyMonitor,
+    Class,
+    Instance,
+    ObjectArray,
+    PrimitiveArray,
+}
+
+impl SubRecordTag {
+    /// The byte that precedes a sub record of this type.
+    pub fn tag_byte(&self) -> u8 {
+        match self {
+            SubRecordTag::GcRootUnknown => 0xFF,
+            SubRecordTag::GcRootThreadObj => 0x08,
+            SubRecordTag::GcRootJniGlobal => 0x01,
+            SubRecordTag::GcRootJniLocalRef => 0x02,
+            SubRecordTag::GcRootJavaStackFrame => 0x03,
+            SubRecordTag::GcRootNativeStack => 0x04,
+            SubRecordTag::GcRootSystemClass => 0x05,
+            SubRecordTag::GcRootThreadBlock => 0x06,
+            SubRecordTag::GcRootBusyMonitor => 0x07,
+            SubRecordTag::Class => 0x20,
+            SubRecordTag::Instance => 0x21,
+            SubRecordTag::ObjectArray => 0x22,
+            SubRecordTag::PrimitiveArray => 0x23,
+        }
+    }
+
+    /// Returns `None` if the byte isn't a known sub record tag.
+    pub fn from_tag_byte(tag_byte: u8) -> Option<SubRecordTag> {
+        match tag_byte {
+            0xFF => Some(SubRecordTag::GcRootUnknown),
+            0x08 => Some(SubRecordTag::GcRootThreadObj),
+            0x01 => Some(SubRecordTag::GcRootJniGlobal),
+            0x02 => Some(SubRecordTag::GcRootJniLocalRef),
+            0x03 => Some(SubRecordTag::GcRootJavaStackFrame),
+            0x04 => Some(SubRecordTag::GcRootNativeStack),
+            0x05 => Some(SubRecordTag::GcRootSystemClass),
+            0x06 => Some(SubRecordTag::GcRootThreadBlock),
+            0x07 => Some(SubRecordTag::GcRootBusyMonitor),
+            0x20 => Some(SubRecordTag::Class),
+            0x21 => Some(SubRecordTag::Instance),
+            0x22 => Some(SubRecordTag::ObjectArray),
+            0x23 => Some(SubRecordTag::PrimitiveArray),
+            _ => None,
+        }
+    }
+}
+
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootUnknown {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -114,7 +404,7 @@ impl GcRootUnknown {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootThreadObj {
     /// May be missing for a thread newly attached through JNI
     #[get_copy = "pub"]
@@ -143,7 +433,7 @@ impl GcRootThreadObj {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootJniGlobal {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -167,7 +457,7 @@ impl GcRootJniGlobal {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootJniLocalRef {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -178,6 +468,11 @@ pub struct GcRootJniLocalRef {
 }
 
 impl GcRootJniLocalRef {
+    /// The frame that holds this root, if it can be found.
+    pub fn stack_frame<'f>(&self, thread_frames: &'f ThreadFrames) -> Option<&'f StackFrame> {
+        thread_frames.frame(self.thread_serial, self.frame_index)
+    }
+
     fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Self> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L196
         let (input, obj_id) = Id::parse(input, id_size)?;
@@ -195,7 +490,7 @@ impl GcRootJniLocalRef {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootJavaStackFrame {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -206,6 +501,11 @@ pub struct GcRootJavaStackFrame {
 }
 
 impl GcRootJavaStackFrame {
+    /// The frame that holds this root, if it can be found.
+    pub fn stack_frame<'f>(&self, thread_frames: &'f ThreadFrames) -> Option<&'f StackFrame> {
+        thread_frames.frame(self.thread_serial, self.frame_index)
+    }
+
     fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Self> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L202
         let (input, obj_id) = Id::parse(input, id_size)?;
@@ -223,7 +523,7 @@ impl GcRootJavaStackFrame {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootNativeStack {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -247,7 +547,7 @@ impl GcRootNativeStack {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootSystemClass {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -262,7 +562,7 @@ impl GcRootSystemClass {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootThreadBlock {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -286,7 +586,7 @@ impl GcRootThreadBlock {
     }
 }
 
-#[derive(CopyGetters, Copy, Clone, Debug)]
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GcRootBusyMonitor {
     #[get_copy = "pub"]
     obj_id: Id,
@@ -310,8 +610,9 @@ pub struct Class<'a> {
     id_size: IdSize,
     #[get_copy = "pub"]
     obj_id: Id,
+    /// `None` if the JVM didn't record where this was allocated, which it writes as serial 0
     #[get_copy = "pub"]
-    stack_trace_serial: Serial,
+    stack_trace_serial: Option<Serial>,
     /// `None` when there is no superclass, e.g. for `java.lang.Object`.
     #[get_copy = "pub"]
     super_class_obj_id: Option<Id>,
@@ -324,15 +625,36 @@ pub struct Class<'a> {
     protection_domain_obj_id: Option<Id>,
     #[get_copy = "pub"]
     instance_size_bytes: u32,
+    /// The number of entries [Class::constant_pool] will produce.
+    #[get_copy = "pub"]
+    num_constant_pool_entries: u16,
+    constant_pool: &'a [u8],
+    /// The number of entries [Class::static_fields] will produce.
+    #[get_copy = "pub"]
     num_static_fields: u16,
     static_fields: &'a [u8],
+    /// The number of entries [Class::instance_field_descriptors] will produce.
+    #[get_copy = "pub"]
     num_instance_fields: u16,
     instance_fields: &'a [u8],
 }
 
 impl<'a> Class<'a> {
+    /// Iterate over [ConstantPoolEntry] data.
+    ///
+    /// HotSpot always writes an empty constant pool, but other JVMs may not.
+    pub fn constant_pool(&self) -> ConstantPoolEntries<'a> {
+        ConstantPoolEntries {
+            iter: ParsingIterator::new_stateless_id_size(
+                self.id_size,
+                self.constant_pool,
+                self.num_constant_pool_entries as u32,
+            ),
+        }
+    }
+
     /// Iterate over [StaticFieldEntry] data.
-    pub fn static_fields(&self) -> StaticFieldEntries {
+    pub fn static_fields(&self) -> StaticFieldEntries<'a> {
         StaticFieldEntries {
             iter: ParsingIterator::new_stateless_id_size(
                 self.id_size,
@@ -342,13 +664,37 @@ impl<'a> Class<'a> {
         }
     }
 
+    /// Iterate over [StaticFieldEntry] data along with each field's name.
+    ///
+    /// `field_name` looks up the text of the [crate::Utf8] record for a name id, e.g. from a map
+    /// built while iterating over records. The name is `None` if it can't be found.
+    ///
+    /// This is deliberately looser than a plain `(&str, FieldValue)` iterator over a utf8 table:
+    /// there's no single utf8 table type (callers variously hold [crate::Utf8] records, as in
+    /// [crate::HprofTables::utf8], or already-decoded `&str`s), so a lookup function works with
+    /// any of them. Names are missing often enough in damaged heap dumps that the caller
+    /// should choose the fallback rather than have the field dropped or a placeholder imposed.
+    /// The whole [StaticFieldEntry] is kept since printing a field usually needs its type and
+    /// name id as well as its value. Parse errors are passed through as with
+    /// [Class::static_fields].
+    pub fn resolved_static_fields<'n, F>(&self, field_name: F) -> ResolvedStaticFields<'a, 'n, F>
+    where
+        F: Fn(Id) -> Option<&'n str>,
+    {
+        ResolvedStaticFields {
+            entries: self.static_fields(),
+            field_name,
+            phantom: marker::PhantomData,
+        }
+    }
+
     /// Iterate over [FieldDescriptor] data.
     ///
     /// Contains only the instance fields defined in this class, not in superclasses.
     ///
     /// An object's fields are serialized with the concrete type's fields first, then that class's
     /// superclass, and so forth, up to the root of the hierarchy.
-    pub fn instance_field_descriptors(&self) -> FieldDescriptors {
+    pub fn instance_field_descriptors(&self) -> FieldDescriptors<'a> {
         FieldDescriptors {
             iter: ParsingIterator::new_stateless_id_size(
                 self.id_size,
@@ -362,7 +708,7 @@ impl<'a> Class<'a> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L226
         // dump_class_and_array_classes https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L995
         let (input, obj_id) = Id::parse(input, id_size)?;
-        let (input, stack_trace_serial) = number::be_u32(input)?;
+        let (input, stack_trace_serial) = parse_optional_serial(input)?;
         let (input, super_class_obj_id) = parse_optional_id(input, id_size)?;
         let (input, class_loader_obj_id) = parse_optional_id(input, id_size)?;
         let (input, signers_obj_id) = parse_optional_id(input, id_size)?;
@@ -371,20 +717,30 @@ impl<'a> Class<'a> {
         let (input, _) = Id::parse(input, id_size)?;
         let (input, _) = Id::parse(input, id_size)?;
         let (input, instance_size_bytes) = number::be_u32(input)?;
-        let (input, constant_pool_len) = number::be_u16(input)?;
-        // constant pool len always 0 as per
+        // constant pool len always 0 in HotSpot as per
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L1031
-        // TODO parse failure
-        assert_eq!(0, constant_pool_len);
-
-        let (input, num_static_fields) = number::be_u16(input)?;
+        let (input, num_constant_pool_entries) = number::be_u16(input)?;
 
-        // since we get a _number of fields_ not a length in bytes, we have to parse now :(
+        // since we get a _number of entries_ not a length in bytes, we have to parse now :(
         // Fortunately, the number of classes << number of objects, so we only will have to do this
         // tens of thousands of times, not billions.
         // To save memory, we parse now to get the length, then just keep a slice and parse on
         // demand later.
 
+        let input_before_constant_pool = input;
+        let mut input_after_constant_pool = input;
+        for _ in 0..num_constant_pool_entries {
+            let (input, _) = ConstantPoolEntry::parse(input_after_constant_pool, id_size)?;
+            input_after_constant_pool = input;
+        }
+
+        let constant_pool_byte_len =
+            input_before_constant_pool.len() - input_after_constant_pool.len();
+        let (input, constant_pool) = bytes::take(constant_pool_byte_len)(input)?;
+
+        let (input, num_static_fields) = number::be_u16(input)?;
+
+        // same for static fields
         let input_before_static_fields = input;
         // need to keep track of input outside the loop scope
         let mut input_after_static_fields = input;
@@ -410,12 +766,14 @@ impl<'a> Class<'a> {
             Class {
                 id_size,
                 obj_id,
-                stack_trace_serial: stack_trace_serial.into(),
+                stack_trace_serial,
                 super_class_obj_id,
                 class_loader_obj_id,
                 signers_obj_id,
                 protection_domain_obj_id,
                 instance_size_bytes,
+                num_constant_pool_entries,
+                constant_pool,
                 num_static_fields,
                 static_fields,
                 num_instance_fields,
@@ -425,6 +783,19 @@ impl<'a> Class<'a> {
     }
 }
 
+/// Iterator over [ConstantPoolEntry] for a [Class].
+pub struct ConstantPoolEntries<'a> {
+    iter: ParsingIterator<'a, ConstantPoolEntry, IdSizeParserWrapper<ConstantPoolEntry>>,
+}
+
+impl<'a> Iterator for ConstantPoolEntries<'a> {
+    type Item = ParseResult<'a, ConstantPoolEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
 /// Iterator over [StaticFieldEntry] for a [Class].
 pub struct StaticFieldEntries<'a> {
     iter: ParsingIterator<'a, StaticFieldEntry, IdSizeParserWrapper<StaticFieldEntry>>,
@@ -438,6 +809,28 @@ impl<'a> Iterator for StaticFieldEntries<'a> {
     }
 }
 
+/// Iterator over [StaticFieldEntry]s and their names.
+pub struct ResolvedStaticFields<'a, 'n, F> {
+    entries: StaticFieldEntries<'a>,
+    field_name: F,
+    phantom: marker::PhantomData<&'n str>,
+}
+
+impl<'a, 'n, F> Iterator for ResolvedStaticFields<'a, 'n, F>
+where
+    F: Fn(Id) -> Option<&'n str>,
+{
+    type Item = ParseResult<'a, (Option<&'n str>, StaticFieldEntry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let field_name = &self.field_name;
+
+        self.entries
+            .next()
+            .map(|res| res.map(|entry| (field_name(entry.name_id()), entry)))
+    }
+}
+
 /// Iterator over [FieldDescriptor]s.
 pub struct FieldDescriptors<'a> {
     iter: ParsingIterator<'a, FieldDescriptor, IdSizeParserWrapper<FieldDescriptor>>,
@@ -456,12 +849,16 @@ impl<'a> Iterator for FieldDescriptors<'a> {
 pub struct Instance<'a> {
     #[get_copy = "pub"]
     obj_id: Id,
+    /// `None` if the JVM didn't record where this was allocated, which it writes as serial 0
     #[get_copy = "pub"]
-    stack_trace_serial: Serial,
+    stack_trace_serial: Option<Serial>,
     #[get_copy = "pub"]
     class_obj_id: Id,
     /// Instance field values, root class's fields last.
     ///
+    /// See [Instance::field_values] for a way to parse them that checks that the field
+    /// descriptors actually match the field data.
+    ///
     /// # Example
     ///
     /// Once you have assembled the class hierarchy and have accumulated all the field descriptors,
@@ -502,10 +899,35 @@ pub struct Instance<'a> {
 }
 
 impl<'a> Instance<'a> {
+    /// Iterate over the instance's field values, along with the descriptor for each one.
+    ///
+    /// `field_descriptors` should be all the instance fields of the instance's class in the order
+    /// they're stored, as with [field_offsets]. If their sizes don't add up to exactly the length
+    /// of [Instance::fields], the descriptors don't describe this instance (e.g. a superclass was
+    /// missed, or they're for some other class), and rather than produce wrong values, the
+    /// iterator produces a single [nom::error::ErrorKind::LengthValue] error.
+    pub fn field_values<'d>(
+        &self,
+        field_descriptors: &'d [FieldDescriptor],
+        id_size: IdSize,
+    ) -> FieldValues<'a, 'd> {
+        let descriptors_len: usize = field_descriptors
+            .iter()
+            .map(|fd| fd.field_type().size_in_bytes(id_size))
+            .sum();
+
+        FieldValues {
+            id_size,
+            remaining: self.fields,
+            descriptors: field_descriptors.iter(),
+            layout_mismatch: descriptors_len != self.fields.len(),
+        }
+    }
+
     fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Instance> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L262
         let (input, obj_id) = Id::parse(input, id_size)?;
-        let (input, stack_trace_serial) = number::be_u32(input)?;
+        let (input, stack_trace_serial) = parse_optional_serial(input)?;
         let (input, class_obj_id) = Id::parse(input, id_size)?;
         let (input, fields_byte_len) = number::be_u32(input)?;
         let (input, fields) = bytes::take(fields_byte_len)(input)?;
@@ -514,7 +936,7 @@ impl<'a> Instance<'a> {
             input,
             Instance {
                 obj_id,
-                stack_trace_serial: stack_trace_serial.into(),
+                stack_trace_serial,
                 class_obj_id,
                 fields,
             },
@@ -522,16 +944,55 @@ impl<'a> Instance<'a> {
     }
 }
 
+/// Iterator over an [Instance]'s field values, as per [Instance::field_values].
+pub struct FieldValues<'a, 'd> {
+    id_size: IdSize,
+    remaining: &'a [u8],
+    descriptors: std::slice::Iter<'d, FieldDescriptor>,
+    layout_mismatch: bool,
+}
+
+impl<'a, 'd> Iterator for FieldValues<'a, 'd> {
+    type Item = ParseResult<'a, (&'d FieldDescriptor, FieldValue)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.layout_mismatch {
+            // report it once, then stop
+            self.layout_mismatch = false;
+            self.descriptors = [].iter();
+            return Some(Err(nom::Err::Error((
+                self.remaining,
+                nom::error::ErrorKind::LengthValue,
+            ))));
+        }
+
+        let fd = self.descriptors.next()?;
+        match fd.field_type().parse_value(self.remaining, self.id_size) {
+            Ok((input, value)) => {
+                self.remaining = input;
+                Some(Ok((fd, value)))
+            }
+            Err(e) => {
+                self.descriptors = [].iter();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 /// An array of anything other than a primitive type.
 #[derive(CopyGetters)]
 pub struct ObjectArray<'a> {
     #[get_copy = "pub"]
     obj_id: Id,
+    /// `None` if the JVM didn't record where this was allocated, which it writes as serial 0
     #[get_copy = "pub"]
-    stack_trace_serial: Serial,
+    stack_trace_serial: Option<Serial>,
     /// The obj id of the class that this is an array of
     #[get_copy = "pub"]
     array_class_obj_id: Id,
+    /// The length of the array.
+    #[get_copy = "pub"]
     num_elements: u32,
     contents: &'a [u8],
 }
@@ -540,7 +1001,7 @@ impl<'a> ObjectArray<'a> {
     fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], ObjectArray> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L271
         let (input, obj_id) = Id::parse(input, id_size)?;
-        let (input, stack_trace_serial) = number::be_u32(input)?;
+        let (input, stack_trace_serial) = parse_optional_serial(input)?;
         let (input, num_elements) = number::be_u32(input)?;
         let (input, array_class_id) = Id::parse(input, id_size)?;
 
@@ -552,7 +1013,7 @@ impl<'a> ObjectArray<'a> {
             input,
             ObjectArray {
                 obj_id,
-                stack_trace_serial: stack_trace_serial.into(),
+                stack_trace_serial,
                 array_class_obj_id: array_class_id,
                 num_elements,
                 contents,
@@ -561,16 +1022,233 @@ impl<'a> ObjectArray<'a> {
     }
 
     /// The obj ids of the objects in the array
-    pub fn elements(&self, id_size: IdSize) -> NullableIds {
+    pub fn elements(&self, id_size: IdSize) -> NullableIds<'a> {
         NullableIds {
             iter: ParsingIterator::new_stateless_id_size(id_size, self.contents, self.num_elements),
         }
     }
+
+    /// The JVM type descriptor of the array's elements, e.g. `Ljava/lang/String;` for a
+    /// `String[]`, or `[Ljava/lang/String;` for a `String[][]`.
+    ///
+    /// `class_name` looks up the JVM class name (as found via [crate::LoadClass]) for a class obj
+    /// id. Returns `None` if the array class's name can't be found or isn't an array class name.
+    pub fn element_type_descriptor<'n, F>(&self, class_name: F) -> Option<&'n str>
+    where
+        F: Fn(Id) -> Option<&'n str>,
+    {
+        class_name(self.array_class_obj_id).and_then(array_element_descriptor)
+    }
+
+    /// The JVM class name of the array's elements, e.g. `java/lang/String` for a `String[]`, or
+    /// `[Ljava/lang/String;` for a `String[][]`. See [array_element_class_name].
+    ///
+    /// `class_name` is as per [ObjectArray::element_type_descriptor].
+    pub fn element_class_name<'n, F>(&self, class_name: F) -> Option<&'n str>
+    where
+        F: Fn(Id) -> Option<&'n str>,
+    {
+        class_name(self.array_class_obj_id).and_then(array_element_class_name)
+    }
+}
+
+/// Strip one dimension from a JVM array class name like `[[Ljava/lang/String;`, producing the
+/// descriptor of the array's elements (`[Ljava/lang/String;`).
+///
+/// Returns `None` if `array_class_name` isn't the name of an array class.
+pub fn array_element_descriptor(array_class_name: &str) -> Option<&str> {
+    match array_class_name.strip_prefix('[') {
+        Some(element) if !element.is_empty() => Some(element),
+        _ => None,
+    }
+}
+
+/// Strip one dimension from a JVM array class name, producing the JVM class name of the array's
+/// elements: `[Ljava/lang/String;` becomes `java/lang/String`, and `[[Ljava/lang/String;` becomes
+/// `[Ljava/lang/String;`.
+///
+/// Returns `None` if `array_class_name` isn't the name of an array class, or its elements are
+/// primitives (e.g. `[I`), which don't have a class.
+pub fn array_element_class_name(array_class_name: &str) -> Option<&str> {
+    let element = array_element_descriptor(array_class_name)?;
+    if element.starts_with('[') {
+        return Some(element);
+    }
+
+    element
+        .strip_prefix('L')
+        .and_then(|d| d.strip_suffix(';'))
+        .filter(|class_name| !class_name.is_empty())
+}
+
+/// The type at the bottom of a (possibly multi-dimensional) array, as found by [array_dimensions].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArrayBaseType<'n> {
+    Primitive(PrimitiveArrayType),
+    /// The JVM name of the class, e.g. `java/lang/String`
+    Object(&'n str),
+}
+
+/// Split a JVM array class name like `[[I` or `[Ljava/lang/String;` into its number of
+/// dimensions and the type at the bottom of all those dimensions.
+///
+/// Returns `None` if `array_class_name` isn't the name of an array class or its base type isn't a
+/// valid descriptor.
+pub fn array_dimensions(array_class_name: &str) -> Option<(usize, ArrayBaseType<'_>)> {
+    let base_descriptor = array_class_name.trim_start_matches('[');
+    let dimensions = array_class_name.len() - base_descriptor.len();
+    if dimensions == 0 {
+        return None;
+    }
+
+    let base_type = match base_descriptor {
+        "Z" => ArrayBaseType::Primitive(PrimitiveArrayType::Boolean),
+        "C" => ArrayBaseType::Primitive(PrimitiveArrayType::Char),
+        "F" => ArrayBaseType::Primitive(PrimitiveArrayType::Float),
+        "D" => ArrayBaseType::Primitive(PrimitiveArrayType::Double),
+        "B" => ArrayBaseType::Primitive(PrimitiveArrayType::Byte),
+        "S" => ArrayBaseType::Primitive(PrimitiveArrayType::Short),
+        "I" => ArrayBaseType::Primitive(PrimitiveArrayType::Int),
+        "J" => ArrayBaseType::Primitive(PrimitiveArrayType::Long),
+        _ => match base_descriptor
+            .strip_prefix('L')
+            .and_then(|d| d.strip_suffix(';'))
+        {
+            Some(class_name) if !class_name.is_empty() => ArrayBaseType::Object(class_name),
+            _ => return None,
+        },
+    };
+
+    Some((dimensions, base_type))
+}
+
+/// Convert a JVM class name, as found via [crate::LoadClass], to how it would be written in Java
+/// source: `java/lang/String` becomes `java.lang.String`, `[[I` becomes `int[][]`, and
+/// `[Ljava/lang/String;` becomes `java.lang.String[]`.
+///
+/// Nested classes keep their `$`, e.g. `java.util.HashMap$Node`, since the name alone doesn't say
+/// whether a `$` separates a nested class or is just part of a name.
+pub fn jvm_name_to_java(name: &str) -> String {
+    match array_dimensions(name) {
+        Some((dimensions, base_type)) => {
+            let base_name = match base_type {
+                ArrayBaseType::Primitive(t) => t.java_type_name().to_owned(),
+                ArrayBaseType::Object(class_name) => class_name.replace('/', "."),
+            };
+            format!("{}{}", base_name, "[]".repeat(dimensions))
+        }
+        None => name.replace('/', "."),
+    }
 }
 
-// TODO referenced in heapDumper.cpp, but not actually written?
-#[allow(unused)]
-enum ConstantPoolEntry {}
+/// Where `instance` was allocated, as the class and method of the innermost frame of its
+/// allocation stack trace, e.g. `com.example.Cache.put`. Handy as a key when counting objects by
+/// allocation site.
+///
+/// Returns `None` if the instance has no stack trace (as is usual, since the JVM only records
+/// them when allocation tracking is enabled), or if the trace, its innermost frame, or the names
+/// for that frame aren't in `tables`.
+pub fn allocation_site(instance: &Instance, tables: &HprofTables) -> Option<String> {
+    let trace = tables.stack_traces().get(&instance.stack_trace_serial()?)?;
+    let frame_id = trace.frame_ids().next()?.ok()?;
+    let frame = tables.stack_frames().get(&frame_id)?;
+
+    let class_name = tables
+        .load_classes_by_serial()
+        .get(&frame.class_serial())
+        .and_then(|lc| tables.class_name(lc.class_obj_id()))?;
+    let method_name = tables
+        .utf8()
+        .get(&frame.method_name_id())
+        .and_then(|u| u.text_as_str().ok())?;
+
+    Some(format!("{}.{}", jvm_name_to_java(class_name), method_name))
+}
+
+/// The size of an array's header (mark word, compressed class pointer, and length) in a 64-bit
+/// HotSpot JVM with compressed class pointers, which is the default. Heap dumps don't record
+/// header sizes, so this is an approximation for other JVM configurations.
+pub const ARRAY_HEADER_BYTES: u64 = 16;
+
+/// The shallow size in bytes of an object: its own fields or elements, not anything it refers to.
+///
+/// For instances, this is the class's [Class::instance_size_bytes], which `class_instance_size`
+/// looks up by class obj id. Arrays are [ARRAY_HEADER_BYTES] plus their elements.
+///
+/// Returns `None` for sub records that aren't objects (classes and GC roots), or for instances
+/// whose class can't be found.
+pub fn shallow_size<F>(
+    sub_record: &SubRecord,
+    class_instance_size: F,
+    id_size: IdSize,
+) -> Option<u64>
+where
+    F: Fn(Id) -> Option<u32>,
+{
+    match sub_record {
+        SubRecord::Instance(instance) => {
+            class_instance_size(instance.class_obj_id()).map(u64::from)
+        }
+        SubRecord::ObjectArray(oa) => {
+            Some(ARRAY_HEADER_BYTES + oa.num_elements() as u64 * id_size.size_in_bytes() as u64)
+        }
+        SubRecord::PrimitiveArray(pa) => Some(
+            ARRAY_HEADER_BYTES
+                + pa.num_elements() as u64 * pa.primitive_type().element_size() as u64,
+        ),
+        _ => None,
+    }
+}
+
+/// The index, field type, and value for an entry in a [Class]'s constant pool.
+#[derive(CopyGetters, Clone, Copy, Debug)]
+pub struct ConstantPoolEntry {
+    #[get_copy = "pub"]
+    constant_pool_index: u16,
+    #[get_copy = "pub"]
+    field_type: FieldType,
+    #[get_copy = "pub"]
+    value: FieldValue,
+}
+
+impl StatelessParserWithId for ConstantPoolEntry {
+    fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Self> {
+        let (input, constant_pool_index) = number::be_u16(input)?;
+
+        let (input, field_type) = FieldType::parse(input)?;
+        let (input, value) = field_type.parse_value(input, id_size)?;
+
+        Ok((
+            input,
+            ConstantPoolEntry {
+                constant_pool_index,
+                field_type,
+                value,
+            },
+        ))
+    }
+}
+
+/// The name id, byte offset within [Instance::fields], and type of each field in
+/// `field_descriptors`.
+///
+/// `field_descriptors` should be all the instance fields for a class in the order they're stored
+/// in instances: the class's own fields, then its superclass's, and so on. With the offsets
+/// computed once per class, a particular field can be read from many instances with
+/// [FieldType::parse_value] without parsing all the fields that precede it.
+pub fn field_offsets(
+    field_descriptors: &[FieldDescriptor],
+    id_size: IdSize,
+) -> Vec<(Id, usize, FieldType)> {
+    field_descriptors
+        .iter()
+        .scan(0, |offset, fd| {
+            let field_offset = *offset;
+            *offset += fd.field_type().size_in_bytes(id_size);
+            Some((fd.name_id(), field_offset, fd.field_type()))
+        })
+        .collect()
+}
 
 /// The field type and value for a static field in a [Class].
 #[derive(CopyGetters, Clone, Copy, Debug)]
@@ -614,8 +1292,42 @@ pub enum FieldValue {
     Long(i64),
 }
 
+impl FieldValue {
+    /// The value as big-endian bytes, suitable for hashing or comparing field values, which can't
+    /// be done directly since `f32` and `f64` are neither `Eq` nor `Hash`.
+    ///
+    /// Floats and doubles are compared the way Java's `Float.equals` and `Double.equals` do: every
+    /// NaN is canonicalized to the same bit pattern so that NaN equals NaN, while `0.0` and `-0.0`
+    /// keep their distinct bit patterns and so are not equal. Null references are the same as an
+    /// id of 0.
+    ///
+    /// The bytes don't say what type the value is, so only compare values of the same
+    /// [FieldType], e.g. the same field in two instances of a class.
+    pub fn to_hashable_bytes(&self) -> smallvec::SmallVec<[u8; 8]> {
+        match *self {
+            FieldValue::ObjectId(id) => {
+                smallvec::SmallVec::from_slice(&id.map(|id| id.id()).unwrap_or(0).to_be_bytes())
+            }
+            FieldValue::Boolean(b) => smallvec::smallvec![b as u8],
+            FieldValue::Char(c) => smallvec::SmallVec::from_slice(&c.to_be_bytes()),
+            FieldValue::Float(f) => {
+                let f = if f.is_nan() { f32::NAN } else { f };
+                smallvec::SmallVec::from_slice(&f.to_bits().to_be_bytes())
+            }
+            FieldValue::Double(d) => {
+                let d = if d.is_nan() { f64::NAN } else { d };
+                smallvec::SmallVec::from_slice(&d.to_bits().to_be_bytes())
+            }
+            FieldValue::Byte(b) => smallvec::SmallVec::from_slice(&b.to_be_bytes()),
+            FieldValue::Short(s) => smallvec::SmallVec::from_slice(&s.to_be_bytes()),
+            FieldValue::Int(i) => smallvec::SmallVec::from_slice(&i.to_be_bytes()),
+            FieldValue::Long(l) => smallvec::SmallVec::from_slice(&l.to_be_bytes()),
+        }
+    }
+}
+
 /// The name and type of an instance field.
-#[derive(CopyGetters, Clone, Copy, Debug)]
+#[derive(CopyGetters, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct FieldDescriptor {
     #[get_copy = "pub"]
     name_id: Id,
@@ -639,7 +1351,7 @@ impl StatelessParserWithId for FieldDescriptor {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum FieldType {
     ObjectId,
     Boolean,
@@ -697,6 +1409,21 @@ impl FieldType {
         }
     }
 
+    /// The number of bytes a value of this type takes up in an instance's field data.
+    pub fn size_in_bytes(&self, id_size: IdSize) -> usize {
+        match self {
+            FieldType::ObjectId => id_size.size_in_bytes(),
+            FieldType::Boolean => 1,
+            FieldType::Char => 2,
+            FieldType::Float => 4,
+            FieldType::Double => 8,
+            FieldType::Byte => 1,
+            FieldType::Short => 2,
+            FieldType::Int => 4,
+            FieldType::Long => 8,
+        }
+    }
+
     pub fn java_type_name(&self) -> &'static str {
         match self {
             FieldType::ObjectId => "Object",
@@ -712,7 +1439,19 @@ impl FieldType {
     }
 }
 
-fn parse_optional_id(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Option<Id>> {
+/// Parse a stack trace serial where 0 means there is no stack trace.
+pub(crate) fn parse_optional_serial(input: &[u8]) -> nom::IResult<&[u8], Option<Serial>> {
+    number::be_u32(input).map(|(input, num)| {
+        if num == 0 {
+            (input, None)
+        } else {
+            (input, Some(Serial::from(num)))
+        }
+    })
+}
+
+/// Parse an id where 0 means absent, as is the case for null references.
+pub(crate) fn parse_optional_id(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Option<Id>> {
     Id::parse(input, id_size).map(|(input, id)| {
         if id.id == 0 {
             (input, None)
@@ -750,3 +1489,587 @@ impl StatelessParserWithId for Option<Id> {
         parse_optional_id(input, id_size)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::collections;
+    use std::hash::{Hash, Hasher};
+
+    #[test]
+    fn gc_root_obj_ids_for_each_variant() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_unknown(1)
+            .gc_root_thread_obj(2, 1, 0)
+            // no thread obj
+            .gc_root_thread_obj(0, 2, 0)
+            .gc_root_jni_global(3, 10)
+            .gc_root_jni_local_ref(4, 1, 0)
+            .gc_root_java_stack_frame(5, 1, 0)
+            .gc_root_native_stack(6, 1)
+            .gc_root_system_class(7)
+            .gc_root_thread_block(8, 1)
+            .gc_root_busy_monitor(9)
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 100, &[1000])
+            .int_array(3000, &[1]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let record = hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .find(|r| r.tag() == RecordTag::HeapDumpSegment)
+            .unwrap();
+        let sub_records = record
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap()
+            .sub_records()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        let expected = [
+            (SubRecordTag::GcRootUnknown, true, Some(1)),
+            (SubRecordTag::GcRootThreadObj, true, Some(2)),
+            (SubRecordTag::GcRootThreadObj, true, None),
+            (SubRecordTag::GcRootJniGlobal, true, Some(3)),
+            (SubRecordTag::GcRootJniLocalRef, true, Some(4)),
+            (SubRecordTag::GcRootJavaStackFrame, true, Some(5)),
+            (SubRecordTag::GcRootNativeStack, true, Some(6)),
+            (SubRecordTag::GcRootSystemClass, true, Some(7)),
+            (SubRecordTag::GcRootThreadBlock, true, Some(8)),
+            (SubRecordTag::GcRootBusyMonitor, true, Some(9)),
+            (SubRecordTag::Class, false, None),
+            (SubRecordTag::Instance, false, None),
+            (SubRecordTag::ObjectArray, false, None),
+            (SubRecordTag::PrimitiveArray, false, None),
+        ];
+        assert_eq!(expected.len(), sub_records.len());
+        for (s, &(tag, is_gc_root, obj_id)) in sub_records.iter().zip(expected.iter()) {
+            assert_eq!(tag, s.tag());
+            assert_eq!(is_gc_root, s.is_gc_root(), "{:?}", s);
+            assert_eq!(obj_id.map(Id::from), s.as_gc_root_obj_id(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_one_reports_bytes_consumed() {
+        let mut segment = HprofBuilder::new().segment();
+        segment
+            .gc_root_system_class(100)
+            .instance(1000, 100, &[Val::Int(7)]);
+        let body = segment.body();
+
+        let (consumed, first) = SubRecord::parse_one(body, IdSize::U64).unwrap();
+        // tag + id
+        assert_eq!(9, consumed);
+        assert_eq!(Some(Id::from(100)), first.as_gc_root_obj_id());
+
+        let (consumed, second) = SubRecord::parse_one(&body[9..], IdSize::U64).unwrap();
+        assert_eq!(body.len() - 9, consumed);
+        match second {
+            SubRecord::Instance(instance) => assert_eq!(Id::from(1000), instance.obj_id()),
+            _ => panic!("Expected instance, got {:?}", second),
+        }
+
+        // not at a sub record boundary
+        assert!(SubRecord::parse_one(&body[1..], IdSize::U64).is_err());
+        assert!(SubRecord::parse_one(&[], IdSize::U64).is_err());
+        // a known tag, but cut short
+        assert!(SubRecord::parse_one(&body[..5], IdSize::U64).is_err());
+    }
+
+    #[test]
+    fn class_field_counts() {
+        let class = TestClass {
+            static_fields: vec![(1, Val::Int(1)), (2, Val::Obj(0)), (3, Val::Long(2))],
+            instance_fields: vec![(4, TYPE_INT), (5, TYPE_OBJ)],
+            ..TestClass::new(100, 0)
+        };
+        let mut segment = HprofBuilder::new().segment();
+        segment.class(&class);
+
+        let c = match SubRecord::parse(segment.body(), IdSize::U64).unwrap().1 {
+            SubRecord::Class(c) => c,
+            _ => panic!("Expected class"),
+        };
+
+        assert_eq!(3, c.num_static_fields());
+        assert_eq!(3, c.static_fields().count());
+        assert_eq!(2, c.num_instance_fields());
+        assert_eq!(2, c.instance_field_descriptors().count());
+    }
+
+    #[test]
+    fn class_constant_pool() {
+        let class = TestClass {
+            constant_pool: vec![(3, Val::Int(7)), (5, Val::Obj(1000))],
+            static_fields: vec![(1, Val::Long(2))],
+            instance_fields: vec![(2, TYPE_INT)],
+            ..TestClass::new(100, 0)
+        };
+        let mut segment = HprofBuilder::new().segment();
+        segment.class(&class);
+
+        let c = match SubRecord::parse(segment.body(), IdSize::U64).unwrap().1 {
+            SubRecord::Class(c) => c,
+            _ => panic!("Expected class"),
+        };
+
+        assert_eq!(2, c.num_constant_pool_entries());
+        let entries = c.constant_pool().map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(2, entries.len());
+        assert_eq!(3, entries[0].constant_pool_index());
+        assert_eq!(FieldType::Int, entries[0].field_type());
+        assert!(matches!(entries[0].value(), FieldValue::Int(7)));
+        assert_eq!(5, entries[1].constant_pool_index());
+        assert_eq!(FieldType::ObjectId, entries[1].field_type());
+        assert!(matches!(
+            entries[1].value(),
+            FieldValue::ObjectId(Some(id)) if id == Id::from(1000)
+        ));
+
+        // the rest of the class is still found after the constant pool
+        assert!(matches!(
+            c.static_fields().next().unwrap().unwrap().value(),
+            FieldValue::Long(2)
+        ));
+        assert_eq!(
+            Id::from(2),
+            c.instance_field_descriptors()
+                .next()
+                .unwrap()
+                .unwrap()
+                .name_id()
+        );
+    }
+
+    #[test]
+    fn field_offsets_for_mixed_fields() {
+        let field_descriptors = [
+            (1, FieldType::Byte),
+            (2, FieldType::ObjectId),
+            (3, FieldType::Long),
+            (4, FieldType::Char),
+            (5, FieldType::Boolean),
+            (6, FieldType::Int),
+        ]
+        .iter()
+        .map(|&(name_id, field_type)| FieldDescriptor {
+            name_id: Id::from(name_id),
+            field_type,
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (Id::from(1), 0, FieldType::Byte),
+                (Id::from(2), 1, FieldType::ObjectId),
+                (Id::from(3), 5, FieldType::Long),
+                (Id::from(4), 13, FieldType::Char),
+                (Id::from(5), 15, FieldType::Boolean),
+                (Id::from(6), 16, FieldType::Int),
+            ],
+            field_offsets(&field_descriptors, IdSize::U32)
+        );
+
+        let offsets = field_offsets(&field_descriptors, IdSize::U64);
+        assert_eq!(9, offsets[2].1);
+        assert_eq!(20, offsets[5].1);
+    }
+
+    #[test]
+    fn array_element_descriptor_one_dimension() {
+        assert_eq!(
+            Some("Ljava/lang/String;"),
+            array_element_descriptor("[Ljava/lang/String;")
+        );
+    }
+
+    #[test]
+    fn array_element_descriptor_two_dimensions() {
+        assert_eq!(
+            Some("[Ljava/lang/String;"),
+            array_element_descriptor("[[Ljava/lang/String;")
+        );
+    }
+
+    #[test]
+    fn array_element_descriptor_not_array() {
+        assert_eq!(None, array_element_descriptor("java/lang/String"));
+        assert_eq!(None, array_element_descriptor("["));
+    }
+
+    #[test]
+    fn array_element_class_name_strips_one_dimension() {
+        assert_eq!(
+            Some("java/lang/String"),
+            array_element_class_name("[Ljava/lang/String;")
+        );
+        assert_eq!(
+            Some("[Ljava/lang/String;"),
+            array_element_class_name("[[Ljava/lang/String;")
+        );
+        assert_eq!(Some("[I"), array_element_class_name("[[I"));
+        assert_eq!(None, array_element_class_name("[I"));
+        assert_eq!(None, array_element_class_name("[L;"));
+        assert_eq!(None, array_element_class_name("java/lang/String"));
+    }
+
+    #[test]
+    fn array_dimensions_of_primitive_arrays() {
+        assert_eq!(
+            Some((1, ArrayBaseType::Primitive(PrimitiveArrayType::Int))),
+            array_dimensions("[I")
+        );
+        assert_eq!(
+            Some((2, ArrayBaseType::Primitive(PrimitiveArrayType::Int))),
+            array_dimensions("[[I")
+        );
+    }
+
+    #[test]
+    fn array_dimensions_of_object_arrays() {
+        assert_eq!(
+            Some((1, ArrayBaseType::Object("java/lang/String"))),
+            array_dimensions("[Ljava/lang/String;")
+        );
+        assert_eq!(
+            Some((3, ArrayBaseType::Object("java/lang/Object"))),
+            array_dimensions("[[[Ljava/lang/Object;")
+        );
+    }
+
+    #[test]
+    fn array_dimensions_not_array() {
+        assert_eq!(None, array_dimensions("java/lang/String"));
+        assert_eq!(None, array_dimensions("["));
+        assert_eq!(None, array_dimensions("[Q"));
+        assert_eq!(None, array_dimensions("[II"));
+        assert_eq!(None, array_dimensions("[Ljava/lang/String"));
+        assert_eq!(None, array_dimensions("[L;"));
+    }
+
+    #[test]
+    fn jvm_name_to_java_classes() {
+        assert_eq!("java.lang.String", jvm_name_to_java("java/lang/String"));
+        assert_eq!(
+            "java.util.HashMap$Node",
+            jvm_name_to_java("java/util/HashMap$Node")
+        );
+        assert_eq!("NoPackage", jvm_name_to_java("NoPackage"));
+    }
+
+    #[test]
+    fn jvm_name_to_java_arrays() {
+        assert_eq!("int[]", jvm_name_to_java("[I"));
+        assert_eq!("boolean[][]", jvm_name_to_java("[[Z"));
+        assert_eq!(
+            "java.lang.String[]",
+            jvm_name_to_java("[Ljava/lang/String;")
+        );
+        assert_eq!(
+            "java.lang.Object[][][]",
+            jvm_name_to_java("[[[Ljava/lang/Object;")
+        );
+        // not a valid array descriptor, so left alone apart from separators
+        assert_eq!("[Q", jvm_name_to_java("[Q"));
+    }
+
+    #[test]
+    fn allocation_site_uses_innermost_frame() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "com/example/Cache")
+            .utf8(2, "com/example/Main")
+            .utf8(3, "put")
+            .utf8(4, "main")
+            .utf8(5, "()V")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .stack_frame(10, 3, 5, 0, 1, 20)
+            .stack_frame(11, 4, 5, 0, 2, 10)
+            // innermost frame first
+            .stack_trace(7, 1, &[10, 11])
+            // frame record is missing
+            .stack_trace(8, 1, &[12])
+            .stack_trace(9, 1, &[]);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance_with_stack_trace(1000, 7, 100, &[])
+            .instance(1001, 100, &[])
+            .instance_with_stack_trace(1002, 8, 100, &[])
+            .instance_with_stack_trace(1003, 9, 100, &[])
+            // no such trace
+            .instance_with_stack_trace(1004, 99, 100, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let tables = HprofTables::build(&hprof).unwrap();
+
+        let sites = hprof
+            .instances_with_fields()
+            .map(|r| allocation_site(&r.unwrap().0, &tables))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Some("com.example.Cache.put".to_owned()),
+                None,
+                None,
+                None,
+                None
+            ],
+            sites
+        );
+    }
+
+    fn descriptors(field_types: &[FieldType]) -> Vec<FieldDescriptor> {
+        field_types
+            .iter()
+            .enumerate()
+            .map(|(index, &field_type)| FieldDescriptor {
+                name_id: Id::from(index as u64),
+                field_type,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn field_values_match_layout() {
+        let mut segment = HprofBuilder::new().segment();
+        segment.instance(1000, 100, &[Val::Int(1), Val::Long(2)]);
+        let instance = match SubRecord::parse(segment.body(), IdSize::U64).unwrap().1 {
+            SubRecord::Instance(i) => i,
+            _ => panic!("not an instance"),
+        };
+
+        let field_descriptors = descriptors(&[FieldType::Int, FieldType::Long]);
+        let values = instance
+            .field_values(&field_descriptors, IdSize::U64)
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(2, values.len());
+        assert_eq!(Id::from(0), values[0].0.name_id());
+        assert!(matches!(values[0].1, FieldValue::Int(1)));
+        assert_eq!(Id::from(1), values[1].0.name_id());
+        assert!(matches!(values[1].1, FieldValue::Long(2)));
+    }
+
+    #[test]
+    fn field_values_reject_mismatched_layout() {
+        let mut segment = HprofBuilder::new().segment();
+        segment.instance(1000, 100, &[Val::Int(1), Val::Long(2)]);
+        let instance = match SubRecord::parse(segment.body(), IdSize::U64).unwrap().1 {
+            SubRecord::Instance(i) => i,
+            _ => panic!("not an instance"),
+        };
+
+        // as if the superclass's long field were missed, and as if there were an extra field
+        for field_types in &[
+            &[FieldType::Int][..],
+            &[FieldType::Int, FieldType::Long, FieldType::Int][..],
+            &[FieldType::Long, FieldType::ObjectId][..],
+        ] {
+            let field_descriptors = descriptors(field_types);
+            let mut values = instance.field_values(&field_descriptors, IdSize::U64);
+            match values.next() {
+                Some(Err(nom::Err::Error((_, nom::error::ErrorKind::LengthValue)))) => {}
+                _ => panic!("no length error for {:?}", field_types),
+            }
+            assert!(values.next().is_none());
+        }
+    }
+
+    #[test]
+    fn hashable_bytes_of_float_fields() {
+        // a NaN with a different payload than f64::NAN
+        let other_nan = f64::from_bits(f64::NAN.to_bits() | 1);
+        assert!(other_nan.is_nan());
+
+        let field_descriptors = descriptors(&[FieldType::Float, FieldType::Double]);
+        let hash_fields = |fields: &[Val]| {
+            let mut segment = HprofBuilder::new().segment();
+            segment.instance(1000, 100, fields);
+            let instance = match SubRecord::parse(segment.body(), IdSize::U64).unwrap().1 {
+                SubRecord::Instance(i) => i,
+                _ => panic!("not an instance"),
+            };
+
+            let mut hasher = collections::hash_map::DefaultHasher::new();
+            for v in instance.field_values(&field_descriptors, IdSize::U64) {
+                v.unwrap().1.to_hashable_bytes().hash(&mut hasher);
+            }
+            hasher.finish()
+        };
+
+        let hashes = [
+            hash_fields(&[Val::Float(1.5), Val::Double(f64::NAN)]),
+            hash_fields(&[Val::Float(1.5), Val::Double(other_nan)]),
+            hash_fields(&[Val::Float(-1.5), Val::Double(f64::NAN)]),
+            hash_fields(&[Val::Float(0.0), Val::Double(0.0)]),
+            hash_fields(&[Val::Float(-0.0), Val::Double(-0.0)]),
+        ];
+
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+        // as in Java's Double.equals, zeroes of different signs aren't equal
+        assert_ne!(hashes[3], hashes[4]);
+
+        assert_eq!(
+            FieldValue::Float(f32::NAN).to_hashable_bytes(),
+            FieldValue::Float(-f32::NAN).to_hashable_bytes()
+        );
+        assert_eq!(
+            &[0, 0, 0, 0, 0, 0, 0x03, 0xE8][..],
+            &FieldValue::ObjectId(Some(Id::from(1000))).to_hashable_bytes()[..]
+        );
+        assert_eq!(
+            FieldValue::Long(0).to_hashable_bytes(),
+            FieldValue::ObjectId(None).to_hashable_bytes()
+        );
+    }
+
+    #[test]
+    fn shallow_size_of_instance_uses_class_size() {
+        let mut segment = HprofBuilder::new().segment();
+        segment.instance(1000, 100, &[Val::Int(1), Val::Long(2)]);
+        let instance = SubRecord::parse(segment.body(), IdSize::U64).unwrap().1;
+
+        let class_size = |id: Id| if id == Id::from(100) { Some(12) } else { None };
+        assert_eq!(Some(12), shallow_size(&instance, class_size, IdSize::U64));
+        assert_eq!(None, shallow_size(&instance, |_| None, IdSize::U64));
+    }
+
+    #[test]
+    fn shallow_size_of_object_array() {
+        let mut segment = HprofBuilder::new().segment();
+        segment.object_array(2000, 101, &[1000, 0, 1001]);
+        let array = SubRecord::parse(segment.body(), IdSize::U64).unwrap().1;
+
+        assert_eq!(
+            Some(16 + 3 * 8),
+            shallow_size(&array, |_| None, IdSize::U64)
+        );
+    }
+
+    #[test]
+    fn shallow_size_of_primitive_arrays() {
+        let mut segment = HprofBuilder::new().segment();
+        segment.int_array(3000, &[1, 2, 3, 4, 5]);
+        let ints = SubRecord::parse(segment.body(), IdSize::U64).unwrap().1;
+        let mut segment = HprofBuilder::new().segment();
+        segment.byte_array(3001, &[1, 2, 3]);
+        let bytes = SubRecord::parse(segment.body(), IdSize::U64).unwrap().1;
+
+        assert_eq!(Some(16 + 5 * 4), shallow_size(&ints, |_| None, IdSize::U64));
+        assert_eq!(Some(16 + 3), shallow_size(&bytes, |_| None, IdSize::U64));
+    }
+
+    #[test]
+    fn shallow_size_of_non_objects() {
+        let mut segment = HprofBuilder::new().segment();
+        segment.class(&TestClass::new(100, 0));
+        let class = SubRecord::parse(segment.body(), IdSize::U64).unwrap().1;
+        let mut segment = HprofBuilder::new().segment();
+        segment.gc_root_unknown(1000);
+        let root = SubRecord::parse(segment.body(), IdSize::U64).unwrap().1;
+
+        assert_eq!(None, shallow_size(&class, |_| Some(8), IdSize::U64));
+        assert_eq!(None, shallow_size(&root, |_| Some(8), IdSize::U64));
+    }
+
+    #[test]
+    fn object_array_element_type_descriptor_uses_array_class_name() {
+        let mut names = collections::HashMap::new();
+        names.insert(Id::from(100), "[Ljava/lang/String;");
+        names.insert(Id::from(200), "[[Ljava/lang/String;");
+
+        let lookup = |id: Id| names.get(&id).copied();
+
+        assert_eq!(
+            Some("Ljava/lang/String;"),
+            empty_array(100).element_type_descriptor(lookup)
+        );
+        assert_eq!(
+            Some("[Ljava/lang/String;"),
+            empty_array(200).element_type_descriptor(lookup)
+        );
+        assert_eq!(None, empty_array(300).element_type_descriptor(lookup));
+
+        assert_eq!(
+            Some("java/lang/String"),
+            empty_array(100).element_class_name(lookup)
+        );
+        assert_eq!(
+            Some("[Ljava/lang/String;"),
+            empty_array(200).element_class_name(lookup)
+        );
+    }
+
+    #[test]
+    fn class_resolved_static_fields_have_names() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.class(&TestClass {
+            static_fields: vec![
+                (1, Val::Obj(2000)),
+                (2, Val::Int(42)),
+                // no utf8 for this one
+                (3, Val::Int(7)),
+            ],
+            ..TestClass::new(100, 0)
+        });
+        let bytes = builder.heap_dump_segment(&segment).build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let record = hprof.records_iter().next().unwrap().unwrap();
+        let segment = record.as_heap_dump_segment().unwrap().unwrap();
+        let class = match segment.sub_records().next().unwrap().unwrap() {
+            SubRecord::Class(c) => c,
+            _ => panic!("Should be a class"),
+        };
+
+        let mut names = collections::HashMap::new();
+        names.insert(Id::from(1), "INSTANCE");
+        names.insert(Id::from(2), "COUNT");
+
+        let fields = class
+            .resolved_static_fields(|id| names.get(&id).copied())
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(3, fields.len());
+
+        assert_eq!(Some("INSTANCE"), fields[0].0);
+        match fields[0].1.value() {
+            FieldValue::ObjectId(Some(id)) => assert_eq!(Id::from(2000), id),
+            _ => panic!("Should be an obj id"),
+        }
+
+        assert_eq!(Some("COUNT"), fields[1].0);
+        match fields[1].1.value() {
+            FieldValue::Int(i) => assert_eq!(42, i),
+            _ => panic!("Should be an int"),
+        }
+
+        assert_eq!(None, fields[2].0);
+        assert_eq!(Id::from(3), fields[2].1.name_id());
+    }
+
+    fn empty_array(array_class_obj_id: u64) -> ObjectArray<'static> {
+        ObjectArray {
+            obj_id: Id::from(1),
+            stack_trace_serial: None,
+            array_class_obj_id: Id::from(array_class_obj_id),
+            num_elements: 0,
+            contents: &[],
+        }
+    }
+}
@@ -10,8 +10,9 @@ use strum_macros::EnumIter;
 pub struct PrimitiveArray<'a> {
     #[get_copy = "pub"]
     obj_id: Id,
+    /// `None` if the JVM didn't record where this was allocated, which it writes as serial 0
     #[get_copy = "pub"]
-    stack_trace_serial: Serial,
+    stack_trace_serial: Option<Serial>,
     /// The type of primitive in the array.
     ///
     /// Methods for accessing the contents of the array (`ints()`, etc)  return `Some` for method
@@ -19,6 +20,8 @@ pub struct PrimitiveArray<'a> {
     /// [PrimitiveArray::floats()] will return `Some` and all other accessors will return `None`.
     #[get_copy = "pub"]
     primitive_type: PrimitiveArrayType,
+    /// The length of the array.
+    #[get_copy = "pub"]
     num_elements: u32,
     contents: &'a [u8],
 }
@@ -37,11 +40,27 @@ macro_rules! iterator_method {
     };
 }
 
+macro_rules! vec_method {
+    ($method_name:tt, $iter_method:tt, $item_type:ty) => {
+        /// Collects the contents into a `Vec`.
+        ///
+        /// Returns `Some` if `primitive_type()` returns the matching variant and `None` otherwise.
+        pub fn $method_name(&self) -> Option<Vec<$item_type>> {
+            self.$iter_method().map(|iter| {
+                iter
+                    // the contents slice was sized to fit num_elements when parsing the array
+                    .map(|r| r.expect("Array contents should hold all elements"))
+                    .collect()
+            })
+        }
+    };
+}
+
 impl<'a> PrimitiveArray<'a> {
     pub(crate) fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], PrimitiveArray> {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L279
         let (input, obj_id) = Id::parse(input, id_size)?;
-        let (input, stack_trace_serial) = number::be_u32(input)?;
+        let (input, stack_trace_serial) = heap_dump::parse_optional_serial(input)?;
         let (input, num_elements) = number::be_u32(input)?;
         let (input, type_byte) = number::be_u8(input)?;
 
@@ -50,24 +69,14 @@ impl<'a> PrimitiveArray<'a> {
             None => panic!("Unexpected primitive array type {:#X}", type_byte),
         };
 
-        let size: u32 = match array_type {
-            PrimitiveArrayType::Boolean => 1,
-            PrimitiveArrayType::Char => 2,
-            PrimitiveArrayType::Float => 4,
-            PrimitiveArrayType::Double => 8,
-            PrimitiveArrayType::Byte => 1,
-            PrimitiveArrayType::Short => 2,
-            PrimitiveArrayType::Int => 4,
-            PrimitiveArrayType::Long => 8,
-        };
-
-        let (input, contents) = bytes::take(num_elements * size)(input)?;
+        let (input, contents) =
+            bytes::take(num_elements as usize * array_type.element_size())(input)?;
 
         Ok((
             input,
             PrimitiveArray {
                 obj_id,
-                stack_trace_serial: stack_trace_serial.into(),
+                stack_trace_serial,
                 primitive_type: array_type,
                 num_elements,
                 contents,
@@ -83,6 +92,24 @@ impl<'a> PrimitiveArray<'a> {
     iterator_method!(shorts, Short, Shorts);
     iterator_method!(ints, Int, Ints);
     iterator_method!(longs, Long, Longs);
+
+    vec_method!(to_vec_booleans, booleans, bool);
+    vec_method!(to_vec_chars, chars, u16);
+    vec_method!(to_vec_floats, floats, f32);
+    vec_method!(to_vec_doubles, doubles, f64);
+    vec_method!(to_vec_bytes, bytes, i8);
+    vec_method!(to_vec_shorts, shorts, i16);
+    vec_method!(to_vec_ints, ints, i32);
+    vec_method!(to_vec_longs, longs, i64);
+
+    /// Decodes a `char[]`'s UTF-16 contents, replacing invalid data (e.g. unpaired surrogates)
+    /// with U+FFFD.
+    ///
+    /// Returns `None` if this isn't a [PrimitiveArrayType::Char] array.
+    pub fn to_string_lossy(&self) -> Option<String> {
+        self.to_vec_chars()
+            .map(|chars| String::from_utf16_lossy(&chars))
+    }
 }
 
 impl StatelessParser for bool {
@@ -160,6 +187,38 @@ impl PrimitiveArrayType {
         }
     }
 
+    /// The Rust type that elements are parsed as, e.g. `i8` for `byte` or `u16` for `char`.
+    ///
+    /// This is the item type of the corresponding iterator on [PrimitiveArray], e.g.
+    /// [PrimitiveArray::bytes].
+    pub fn rust_type_name(&self) -> &'static str {
+        match self {
+            PrimitiveArrayType::Boolean => "bool",
+            PrimitiveArrayType::Char => "u16",
+            PrimitiveArrayType::Float => "f32",
+            PrimitiveArrayType::Double => "f64",
+            PrimitiveArrayType::Byte => "i8",
+            PrimitiveArrayType::Short => "i16",
+            PrimitiveArrayType::Int => "i32",
+            PrimitiveArrayType::Long => "i64",
+        }
+    }
+
+    /// The size in bytes of each element in the hprof, which is also its size in the JVM
+    /// heap (`boolean`s take a whole byte in arrays).
+    pub fn element_size(&self) -> usize {
+        match self {
+            PrimitiveArrayType::Boolean => 1,
+            PrimitiveArrayType::Char => 2,
+            PrimitiveArrayType::Float => 4,
+            PrimitiveArrayType::Double => 8,
+            PrimitiveArrayType::Byte => 1,
+            PrimitiveArrayType::Short => 2,
+            PrimitiveArrayType::Int => 4,
+            PrimitiveArrayType::Long => 8,
+        }
+    }
+
     /// Returns the hprof type code for the array type
     ///
     /// See https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L279
@@ -193,3 +252,111 @@ impl PrimitiveArrayType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_dump::SubRecord;
+    use crate::test_util::*;
+
+    #[test]
+    fn char_array_to_string() {
+        with_arrays(
+            |segment| {
+                segment.char_array(1000, "héllo, wörld ☃");
+            },
+            |arrays| {
+                assert_eq!(
+                    Some(String::from("héllo, wörld ☃")),
+                    arrays[0].to_string_lossy()
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn unpaired_surrogate_to_string() {
+        with_arrays(
+            |segment| {
+                // 'a', a lone high surrogate, 'b'
+                segment.primitive_array(
+                    1000,
+                    PrimitiveArrayType::Char.type_code(),
+                    3,
+                    &[0x00, 0x61, 0xD8, 0x00, 0x00, 0x62],
+                );
+            },
+            |arrays| {
+                assert_eq!(
+                    Some(String::from("a\u{FFFD}b")),
+                    arrays[0].to_string_lossy()
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn to_vec_matches_type() {
+        with_arrays(
+            |segment| {
+                segment
+                    .int_array(1000, &[1, -2, 3])
+                    .byte_array(2000, &[0xFF, 7]);
+            },
+            |arrays| {
+                assert_eq!(Some(vec![1, -2, 3]), arrays[0].to_vec_ints());
+                assert_eq!(None, arrays[0].to_vec_longs());
+                assert_eq!(None, arrays[0].to_string_lossy());
+
+                assert_eq!(Some(vec![-1, 7]), arrays[1].to_vec_bytes());
+                assert_eq!(None, arrays[1].to_vec_ints());
+            },
+        );
+    }
+
+    #[test]
+    fn type_metadata_for_all_types() {
+        let expected = [
+            (PrimitiveArrayType::Boolean, "boolean", "bool", 1),
+            (PrimitiveArrayType::Char, "char", "u16", 2),
+            (PrimitiveArrayType::Float, "float", "f32", 4),
+            (PrimitiveArrayType::Double, "double", "f64", 8),
+            (PrimitiveArrayType::Byte, "byte", "i8", 1),
+            (PrimitiveArrayType::Short, "short", "i16", 2),
+            (PrimitiveArrayType::Int, "int", "i32", 4),
+            (PrimitiveArrayType::Long, "long", "i64", 8),
+        ];
+        assert_eq!(PrimitiveArrayType::iter().count(), expected.len());
+
+        for &(t, java_name, rust_name, size) in expected.iter() {
+            assert_eq!(java_name, t.java_type_name());
+            assert_eq!(rust_name, t.rust_type_name());
+            assert_eq!(size, t.element_size());
+            assert_eq!(Some(t), PrimitiveArrayType::from_type_code(t.type_code()));
+        }
+    }
+
+    fn with_arrays<B, F>(build: B, check: F)
+    where
+        B: FnOnce(&mut SegmentBuilder),
+        F: FnOnce(&[PrimitiveArray]),
+    {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        build(&mut segment);
+        let bytes = builder.heap_dump_segment(&segment).build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let record = hprof.records_iter().next().unwrap().unwrap();
+        let segment = record.as_heap_dump_segment().unwrap().unwrap();
+        let arrays = segment
+            .sub_records()
+            .map(|r| match r.unwrap() {
+                SubRecord::PrimitiveArray(pa) => pa,
+                _ => panic!("Should be a primitive array"),
+            })
+            .collect::<Vec<_>>();
+
+        check(&arrays);
+    }
+}
@@ -1,6 +1,8 @@
 //! A library for parsing hprof files.
 //!
-//! See [parse_hprof] to get started, or see the examples in the repo.
+//! See [parse_hprof] to get started (or [MappedHprof] to parse a file via a memory map, or
+//! [HprofReader] to read one record at a time when the file can't be mapped), or see the examples
+//! in the repo.
 //!
 //! # Examples
 //!
@@ -8,13 +10,12 @@
 //! the `analyze_hprof` example's `record-counts` subcommand):
 //!
 //! ```
-//! use std::{fs, collections};
-//! use jvm_hprof::{Hprof, parse_hprof, RecordTag, EnumIterable};
+//! use std::collections;
+//! use jvm_hprof::{parse_hprof, Hprof, RecordTag, EnumIterable};
 //!
-//! fn count_records(file: fs::File) {
-//!     let memmap = unsafe { memmap::MmapOptions::new().map(&file) }.unwrap();
-//!
-//!     let hprof: Hprof = parse_hprof(&memmap[..]).unwrap();
+//! // e.g. from `std::fs::read`, or `MappedHprof` for files too big to read into memory
+//! fn count_records(bytes: &[u8]) {
+//!     let hprof: Hprof = parse_hprof(bytes).unwrap();
 //!
 //!     // start with zero counts for all types
 //!     let mut counts = RecordTag::iter()
@@ -47,31 +48,80 @@ use nom::bytes::complete as bytes;
 use nom::number::complete as number;
 use std::cmp::Ordering;
 use std::fmt::{Error, Formatter};
-use std::{cmp, fmt};
+use std::{borrow, cmp, collections, fmt, hash, num, str};
 use strum_macros;
 use strum_macros::EnumIter;
 
+mod handlers;
 pub mod heap_dump;
+#[cfg(feature = "mmap")]
+mod mapped;
+mod owned;
+mod parse_report;
 mod parsing_iterator;
-
+mod record_reader;
+#[cfg(feature = "channel")]
+mod record_stream;
+mod tables;
+#[cfg(test)]
+mod test_util;
+mod visitor;
+
+pub use handlers::Handlers;
+#[cfg(feature = "mmap")]
+pub use mapped::MappedHprof;
+pub use owned::{
+    OwnedClass, OwnedHprof, OwnedInstance, OwnedObjectArray, OwnedPrimitiveArray,
+    PrimitiveArrayValues,
+};
+pub use parse_report::{ParseCounts, ParseReport};
 use parsing_iterator::*;
+pub use record_reader::{HprofReader, OwnedRecord};
+#[cfg(feature = "channel")]
+pub use record_stream::spawn_record_stream;
+pub use tables::{unknown_class_name, HprofTables};
+pub use visitor::HprofVisitor;
 
 /// Ids are used to identify many things in an hprof file: objects, classes, utf8 blobs, etc.
 ///
 /// The on-disk representation of an Id depends on the relevant [IdSize].
-#[derive(CopyGetters, Copy, Clone, Debug, Eq, Hash, PartialEq)]
+///
+/// Ids are ordered numerically, which is handy for sorting, but otherwise meaningless: object ids
+/// are typically memory addresses, so the order doesn't reflect allocation order.
+#[derive(CopyGetters, Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct Id {
     // inflate 4-byte ids to 8-byte since if we have a small 32-bit heap, no worries about memory anyway
     #[get_copy = "pub"]
     id: u64,
 }
 
+impl Id {
+    /// Parse a hex id like `7f3a5c8d0` or `0x7f3a5c8d0`, as found in logs or the output of other
+    /// JVM tools.
+    pub fn from_hex_str(s: &str) -> Result<Id, num::ParseIntError> {
+        u64::from_str_radix(strip_hex_prefix(s).unwrap_or(s), 16).map(Id::from)
+    }
+}
+
 impl From<u64> for Id {
     fn from(id: u64) -> Id {
         Id { id }
     }
 }
 
+/// Parses decimal, or hex if prefixed with `0x`.
+impl str::FromStr for Id {
+    type Err = num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match strip_hex_prefix(s) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map(Id::from)
+    }
+}
+
 impl fmt::Display for Id {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.id)
@@ -91,19 +141,43 @@ impl fmt::UpperHex for Id {
 /// human readable class names (which are available in [LoadClass], [StackFrame] uses
 /// `class_serial`, whereas if you were inspecting a class's fields via [crate::heap_dump::Class],
 /// that only has the class's `obj_id` available.
-#[derive(CopyGetters, Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(CopyGetters, Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct Serial {
     /// The plain serial number.
     #[get_copy = "pub"]
     num: u32,
 }
 
+impl Serial {
+    /// Parse a hex serial like `1a2b` or `0x1a2b`.
+    pub fn from_hex_str(s: &str) -> Result<Serial, num::ParseIntError> {
+        u32::from_str_radix(strip_hex_prefix(s).unwrap_or(s), 16).map(Serial::from)
+    }
+}
+
 impl From<u32> for Serial {
     fn from(num: u32) -> Self {
         Serial { num }
     }
 }
 
+/// Parses decimal, or hex if prefixed with `0x`.
+impl str::FromStr for Serial {
+    type Err = num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match strip_hex_prefix(s) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map(Serial::from)
+    }
+}
+
+fn strip_hex_prefix(s: &str) -> Option<&str> {
+    s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))
+}
+
 impl fmt::Display for Serial {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.num)
@@ -137,7 +211,8 @@ pub enum IdSize {
 }
 
 impl IdSize {
-    fn size_in_bytes(&self) -> usize {
+    /// The number of bytes used to store an [Id] of this size.
+    pub fn size_in_bytes(&self) -> usize {
         match self {
             IdSize::U32 => 4,
             IdSize::U64 => 8,
@@ -152,33 +227,567 @@ pub struct Hprof<'a> {
     #[get_copy = "pub"]
     header: Header<'a>,
     records: &'a [u8],
+    handlers: Option<&'a Handlers<'a>>,
 }
 
 impl<'a> Hprof<'a> {
     /// Iterate over the [Record] data in the hprof.
     ///
     /// Iteration is cheap, as each [Record] defers parsing the bulk of its data until later.
+    ///
+    /// Iteration ends after the first error, which is an [HprofParseError] of the same kind that
+    /// [parse_hprof_strict] would report for that hprof. If the hprof is truncated partway through
+    /// a record's body, as when the JVM died while writing the heap dump, that record is still
+    /// produced with whatever part of its body is present, followed by an
+    /// [HprofParseError::TruncatedRecord] error. The sub records of a truncated heap dump segment
+    /// can therefore still be iterated up to the point where the data runs out.
+    ///
+    /// Before 0.2.0, items were `ParseResult<Record>`, i.e. nom errors without any indication of
+    /// where or why parsing failed.
     pub fn records_iter(&self) -> Records<'a> {
         Records {
             remaining: self.records,
+            end_offset: self.header_len() + self.records.len(),
+            id_size: self.header.id_size,
+            handlers: self.handlers,
+            truncation_error: None,
+        }
+    }
+
+    /// Iterate over only the [Record]s whose tag is in `tags`.
+    ///
+    /// Other records are skipped by reading just their length, so their bodies aren't even
+    /// sliced, and tags that aren't a known [RecordTag] are skipped rather than causing a panic.
+    /// Handy for passes that only need a record type or two, like collecting all the [Utf8]s.
+    /// Errors end iteration, as with [Hprof::records_iter].
+    pub fn records_iter_filtered(&self, tags: &[RecordTag]) -> FilteredRecords<'a> {
+        let mut allowed = [false; 256];
+        for tag in tags {
+            allowed[tag.tag_byte() as usize] = true;
+        }
+
+        FilteredRecords {
+            records: self.records_iter(),
+            allowed,
+        }
+    }
+
+    /// Iterate over just the tag, micros since the header timestamp, and body length of each
+    /// record, along with the offset of the record's tag byte from the start of the hprof.
+    ///
+    /// This reads only the 9 bytes before each record's body, then jumps over the body, so it's
+    /// the cheapest way to count records or find where the heap dump segments are when nothing
+    /// inside them is needed. The body of a record at `offset` starts 9 bytes later.
+    ///
+    /// Records with tags that aren't a known [RecordTag] are skipped, including those that would
+    /// be passed to [Handlers] (which aren't called). Rather than produce errors, scanning stops at
+    /// a record that runs past the end of the data; use [Hprof::records_iter] to find out what
+    /// went wrong.
+    pub fn scan_record_headers(&self) -> RecordHeaders<'a> {
+        RecordHeaders {
+            remaining: self.records,
+            offset: self.header_len(),
+        }
+    }
+
+    /// The length of the header, and so the offset of the first record
+    fn header_len(&self) -> usize {
+        // label, NUL, id size, timestamp
+        self.header.label.len() + 1 + 4 + 8
+    }
+
+    /// Parse the single sub record at `offset` bytes into the heap dump segment that is the
+    /// `record_index`th record (counting from zero, as with `records_iter().enumerate()`).
+    ///
+    /// Offsets are obtained from [HeapDumpSegment::sub_records_with_offsets], typically while
+    /// building some sort of index, so that a particular object can later be inspected without
+    /// parsing every sub record that precedes it. Finding the record still requires iterating
+    /// over records, but that's cheap since records are length-prefixed.
+    ///
+    /// Returns an error if there is no such record, the record isn't a heap dump segment, or
+    /// `offset` is past the end of the segment. An offset that isn't at the start of a sub record
+    /// will most likely produce a parse error, but could also produce garbage. As with
+    /// [heap_dump::SubRecord::parse_one], only the standard sub record types are supported.
+    pub fn sub_record_at(
+        &self,
+        record_index: usize,
+        offset: usize,
+    ) -> ParseResult<'a, heap_dump::SubRecord<'a>> {
+        let record = match self.records_iter().nth(record_index) {
+            Some(r) => r?,
+            None => {
+                return Err(nom::Err::Error((
+                    &self.records[self.records.len()..],
+                    nom::error::ErrorKind::Eof,
+                )))
+            }
+        };
+
+        let segment = match record.tag {
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                HeapDumpSegment::parse(record.body, record.id_size, record.handlers)?
+            }
+            _ => return Err(nom::Err::Error((record.body, nom::error::ErrorKind::Tag))),
+        };
+
+        if offset >= segment.records.len() {
+            return Err(nom::Err::Error((
+                &segment.records[segment.records.len()..],
+                nom::error::ErrorKind::Eof,
+            )));
+        }
+
+        heap_dump::SubRecord::parse_one(&segment.records[offset..], segment.id_size)
+            .map(|(_len, sub_record)| sub_record)
+    }
+
+    /// The total length in bytes of the bodies of all [RecordTag::HeapDump] and
+    /// [RecordTag::HeapDumpSegment] records, as per [HeapDumpSegment::body_len].
+    ///
+    /// Since records are length-prefixed, this is cheap to calculate up front, e.g. to report
+    /// progress through the heap dump: only each record's length is read, and the segments aren't
+    /// parsed.
+    pub fn total_heap_dump_bytes(&self) -> Result<u64, HprofParseError> {
+        let mut total = 0_u64;
+
+        for r in self.records_iter_filtered(&[RecordTag::HeapDump, RecordTag::HeapDumpSegment]) {
+            total += r?.body_len() as u64;
+        }
+
+        Ok(total)
+    }
+
+    /// Collect every [StackTrace] in the hprof, keyed by serial.
+    ///
+    /// Look up the `stack_trace_serial` of an object, thread, etc to find where it came from (e.g.
+    /// for an instance, where it was allocated), then resolve the trace's frame ids with
+    /// [StackFrame] records. A serial of 0 means "no trace", so don't expect to find a trace for
+    /// it.
+    pub fn stack_traces_by_serial(
+        &self,
+    ) -> ParseResult<'a, collections::HashMap<Serial, StackTrace<'a>>> {
+        let mut traces = collections::HashMap::new();
+
+        for r in self.records_iter() {
+            let record = r?;
+            if record.tag == RecordTag::StackTrace {
+                let trace = StackTrace::parse(record.body, record.id_size)?;
+                traces.insert(trace.stack_trace_serial(), trace);
+            }
+        }
+
+        Ok(traces)
+    }
+
+    /// Find the [StackTrace]s with at least one [StackFrame] in the class with serial
+    /// `class_serial` (as per [LoadClass::class_serial]), in the order they appear in the hprof.
+    ///
+    /// Useful to see which code paths were running in a particular class when the heap was
+    /// dumped, or where objects were allocated if allocation traces were recorded.
+    pub fn stack_traces_with_class(
+        &self,
+        class_serial: Serial,
+    ) -> ParseResult<'a, Vec<StackTrace<'a>>> {
+        // frames may come before or after the traces that use them
+        let mut matching_frame_ids = collections::HashSet::new();
+        let mut traces = Vec::new();
+
+        for r in self.records_iter() {
+            let record = r?;
+            match record.tag {
+                RecordTag::StackTrace => {
+                    traces.push(StackTrace::parse(record.body, record.id_size)?)
+                }
+                RecordTag::StackFrame => {
+                    let frame = StackFrame::parse(record.body, record.id_size)?;
+                    if frame.class_serial() == class_serial {
+                        matching_frame_ids.insert(frame.id());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut matching_traces = Vec::new();
+        for trace in traces {
+            for frame_id in trace.frame_ids() {
+                if matching_frame_ids.contains(&frame_id?) {
+                    matching_traces.push(trace);
+                    break;
+                }
+            }
+        }
+
+        Ok(matching_traces)
+    }
+
+    /// The name of each thread with a [StartThread] record, keyed by thread serial, so that
+    /// [heap_dump::GcRootThreadObj::thread_serial] and the like can be shown as a name.
+    ///
+    /// Threads whose name [Utf8] record is missing are left out, as are all threads in hprofs
+    /// without [StartThread] records, which includes those written by HotSpot.
+    pub fn thread_names(&self) -> ParseResult<'a, collections::HashMap<Serial, Utf8<'a>>> {
+        // names may come before or after the threads that use them
+        let mut name_ids = Vec::new();
+        let mut utf8 = collections::HashMap::new();
+
+        for r in self.records_iter() {
+            let record = r?;
+            match record.tag {
+                RecordTag::StartThread => {
+                    let thread = StartThread::parse(record.body, record.id_size)?;
+                    name_ids.push((thread.thread_serial(), thread.thread_name_id()));
+                }
+                RecordTag::Utf8 => {
+                    let u = Utf8::parse(record.body, record.id_size)?;
+                    utf8.insert(u.name_id(), u);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(name_ids
+            .into_iter()
+            .filter_map(|(serial, name_id)| utf8.get(&name_id).map(|&u| (serial, u)))
+            .collect())
+    }
+
+    /// Collect the stack of each thread, along with the [StackFrame]s in them, so that the frame
+    /// index of a [heap_dump::GcRootJavaStackFrame] or [heap_dump::GcRootJniLocalRef] can be
+    /// resolved to a frame.
+    pub fn thread_frames(&self) -> ParseResult<'a, ThreadFrames> {
+        let mut frame_ids_by_trace = collections::HashMap::new();
+        let mut traces_by_thread = collections::HashMap::<_, Vec<_>>::new();
+        let mut frames = collections::HashMap::new();
+
+        for r in self.records_iter() {
+            let record = r?;
+            match record.tag {
+                RecordTag::StackTrace => {
+                    let trace = StackTrace::parse(record.body, record.id_size)?;
+                    let frame_ids = trace.frame_ids().collect::<Result<Vec<_>, _>>()?;
+                    frame_ids_by_trace.insert(trace.stack_trace_serial(), frame_ids);
+                    traces_by_thread
+                        .entry(trace.thread_serial())
+                        .or_default()
+                        .push(trace.stack_trace_serial());
+                }
+                RecordTag::StackFrame => {
+                    let frame = StackFrame::parse(record.body, record.id_size)?;
+                    frames.insert(frame.id(), frame);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(ThreadFrames {
+            frame_ids_by_trace,
+            traces_by_thread,
+            frames,
+        })
+    }
+
+    /// Iterate over every [heap_dump::Instance] along with its decoded field values, keyed by
+    /// field name id, in the order they're stored: the instance's class's fields first, then its
+    /// superclass's, and so on.
+    ///
+    /// This makes an initial pass over the heap dump to collect the field layout of every class,
+    /// so it's a convenient alternative to assembling layouts and calling
+    /// [heap_dump::Instance::field_values] by hand, at the cost of holding every class's
+    /// [heap_dump::FieldDescriptor]s in memory.
+    ///
+    /// An instance whose fields don't match its class's layout (including when its class is
+    /// missing and it has any field data) produces a [nom::error::ErrorKind::LengthValue] error,
+    /// and iteration continues with the next instance. Any other parse error ends iteration.
+    pub fn instances_with_fields(&self) -> InstancesWithFields<'a> {
+        let mut records = self.records_iter();
+
+        let (layouts, error) = match self.class_field_layouts() {
+            Ok(layouts) => (layouts, None),
+            Err(e) => {
+                records.stop();
+                (collections::HashMap::new(), Some(e))
+            }
+        };
+
+        InstancesWithFields {
+            records,
+            sub_records: None,
+            layouts,
             id_size: self.header.id_size,
+            error,
         }
     }
+
+    /// The complete instance field layout of each class, keyed by class obj id.
+    fn class_field_layouts(
+        &self,
+    ) -> ParseResult<'a, collections::HashMap<Id, Vec<heap_dump::FieldDescriptor>>> {
+        // class obj id -> (super class obj id, fields declared in that class)
+        let mut declared = collections::HashMap::new();
+
+        for r in self.records_iter() {
+            let record = r?;
+            if let RecordTag::HeapDump | RecordTag::HeapDumpSegment = record.tag {
+                let segment = HeapDumpSegment::parse(record.body, record.id_size, record.handlers)?;
+                for s in segment.sub_records() {
+                    if let heap_dump::SubRecord::Class(class) = s? {
+                        let descriptors = class
+                            .instance_field_descriptors()
+                            .collect::<Result<Vec<_>, _>>()?;
+                        declared.insert(class.obj_id(), (class.super_class_obj_id(), descriptors));
+                    }
+                }
+            }
+        }
+
+        Ok(field_layouts(&declared))
+    }
+}
+
+/// The complete instance field layout of each class in `declared`, which maps class obj id to
+/// the class's super class obj id and the fields declared in the class itself.
+pub(crate) fn field_layouts(
+    declared: &collections::HashMap<Id, (Option<Id>, Vec<heap_dump::FieldDescriptor>)>,
+) -> collections::HashMap<Id, Vec<heap_dump::FieldDescriptor>> {
+    declared
+        .keys()
+        .map(|&class_obj_id| {
+            let mut layout = Vec::new();
+            let mut next = Some(class_obj_id);
+            // bounded in case of a cycle in a corrupt hprof
+            for _ in 0..declared.len() {
+                match next.and_then(|id| declared.get(&id)) {
+                    Some((super_class_obj_id, descriptors)) => {
+                        layout.extend_from_slice(descriptors);
+                        next = *super_class_obj_id;
+                    }
+                    None => break,
+                }
+            }
+
+            (class_obj_id, layout)
+        })
+        .collect()
 }
 
 /// Entry point for parsing.
 ///
 /// This is intended to be used with a memory mapped hprof file, or, for small heap dumps that can
-/// fit comfortably in memory, just an in-memory buffer.
+/// fit comfortably in memory, just an in-memory buffer. See [MappedHprof] for the common case of
+/// mapping a file.
+///
+/// Input that doesn't start with the `JAVA PROFILE` label of an hprof header is rejected before
+/// anything else is parsed. Use [parse_hprof_strict] for errors that explain what's wrong.
 pub fn parse_hprof(input: &[u8]) -> ParseResult<Hprof> {
     let (input, header) = Header::parse(input)?;
 
     Ok(Hprof {
         header,
         records: input,
+        handlers: None,
     })
 }
 
+/// Like [parse_hprof], but records and sub records with tags that aren't otherwise recognized
+/// are passed to the corresponding handler in `handlers` rather than causing a panic.
+///
+/// See [Handlers].
+pub fn parse_hprof_with_handlers<'a>(
+    input: &'a [u8],
+    handlers: &'a Handlers<'a>,
+) -> ParseResult<'a, Hprof<'a>> {
+    parse_hprof(input).map(|hprof| Hprof {
+        handlers: Some(handlers),
+        ..hprof
+    })
+}
+
+/// Like [parse_hprof], but also checks that the records are well-formed enough that iterating over
+/// them won't end with an error, which usually indicates a truncated or corrupt file.
+///
+/// Specifically, every record must have a known tag and a complete body (a body cut off by the
+/// end of the file is reported as [HprofParseError::TruncatedRecord]), there must be no
+/// trailing bytes that don't form a complete record, and if there are any
+/// [RecordTag::HeapDumpSegment]s, a [RecordTag::HeapDumpEnd] record must be present. Legacy
+/// [RecordTag::HeapDump] records don't need a [RecordTag::HeapDumpEnd].
+///
+/// This only reads each record's tag and length, so it's much cheaper than parsing every record,
+/// but it does touch the whole file.
+pub fn parse_hprof_strict(input: &[u8]) -> Result<Hprof<'_>, HprofParseError> {
+    let hprof = parse_hprof(input).map_err(HprofParseError::from_header_error)?;
+
+    let mut remaining = hprof.records;
+    let mut heap_dump_segment_present = false;
+    let mut heap_dump_end_present = false;
+
+    while !remaining.is_empty() {
+        let offset = input.len() - remaining.len();
+
+        let tag =
+            RecordTag::from_tag_byte(remaining[0]).ok_or(HprofParseError::UnknownRecordTag {
+                offset,
+                tag_byte: remaining[0],
+            })?;
+
+        remaining = match Record::skip(remaining) {
+            Ok((input, _)) => input,
+            Err(_) => return Err(HprofParseError::from_incomplete_record(remaining, offset)),
+        };
+
+        match tag {
+            RecordTag::HeapDumpSegment => heap_dump_segment_present = true,
+            RecordTag::HeapDumpEnd => heap_dump_end_present = true,
+            _ => {}
+        }
+    }
+
+    if heap_dump_segment_present && !heap_dump_end_present {
+        return Err(HprofParseError::MissingHeapDumpEnd);
+    }
+
+    Ok(hprof)
+}
+
+/// Problems with the overall structure of an hprof, as detected by [parse_hprof_strict] (or
+/// [Hprof::records_iter], for the problems it can find while iterating).
+///
+/// Offsets are in bytes from the start of the hprof.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HprofParseError {
+    /// The input doesn't start with the `JAVA PROFILE` label that every hprof does, so it's some
+    /// other kind of file
+    NotAnHprof,
+    /// The header couldn't be parsed
+    InvalidHeader,
+    /// The header's id size isn't 4 or 8 bytes. Hprofs are big-endian, so this usually means the
+    /// file was written with the wrong byte order, or isn't an hprof at all.
+    UnsupportedIdSize { id_size: u32 },
+    /// A record's tag byte isn't one of the known [RecordTag]s
+    UnknownRecordTag { offset: usize, tag_byte: u8 },
+    /// The last `len` bytes, starting at `offset`, are too short to even hold a record's tag and
+    /// length
+    TrailingBytes { offset: usize, len: usize },
+    /// The record at `offset` has a body length of `declared_len`, but only `available` bytes
+    /// remain after its length field
+    TruncatedRecord {
+        offset: usize,
+        declared_len: u32,
+        available: usize,
+    },
+    /// Heap dump segments were present, but no [RecordTag::HeapDumpEnd] was found after them
+    MissingHeapDumpEnd,
+    /// The contents of the record at `offset` couldn't be parsed, as reported by
+    /// [OwnedHprof::load]
+    InvalidRecord { offset: usize, tag: RecordTag },
+}
+
+impl HprofParseError {
+    /// Describe an error from [Header::parse]
+    pub(crate) fn from_header_error(
+        e: nom::Err<(&[u8], nom::error::ErrorKind)>,
+    ) -> HprofParseError {
+        match e {
+            nom::Err::Error((_, nom::error::ErrorKind::Tag)) => HprofParseError::NotAnHprof,
+            nom::Err::Error((i, nom::error::ErrorKind::Verify)) => {
+                HprofParseError::UnsupportedIdSize {
+                    id_size: u32::from_be_bytes([i[0], i[1], i[2], i[3]]),
+                }
+            }
+            _ => HprofParseError::InvalidHeader,
+        }
+    }
+
+    /// Describe why the record at the start of `remaining`, which is at `offset`, couldn't be
+    /// read: either its body runs past the end of the data, or there isn't even room for its
+    /// tag and length.
+    pub(crate) fn from_incomplete_record(remaining: &[u8], offset: usize) -> HprofParseError {
+        match Record::parse_prefix(remaining) {
+            Ok((body, (_tag_byte, _micros, declared_len))) => HprofParseError::TruncatedRecord {
+                offset,
+                declared_len,
+                available: body.len(),
+            },
+            Err(_) => HprofParseError::TrailingBytes {
+                offset,
+                len: remaining.len(),
+            },
+        }
+    }
+}
+
+/// For the parts of the API that produce a [ParseResult], which can only say which kind of
+/// problem it was.
+impl From<HprofParseError> for nom::Err<(&[u8], nom::error::ErrorKind)> {
+    fn from(e: HprofParseError) -> Self {
+        let kind = match e {
+            HprofParseError::TrailingBytes { .. } | HprofParseError::TruncatedRecord { .. } => {
+                nom::error::ErrorKind::Eof
+            }
+            HprofParseError::UnknownRecordTag { .. } | HprofParseError::NotAnHprof => {
+                nom::error::ErrorKind::Tag
+            }
+            _ => nom::error::ErrorKind::Verify,
+        };
+
+        nom::Err::Error((&[], kind))
+    }
+}
+
+impl fmt::Display for HprofParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HprofParseError::NotAnHprof => write!(
+                f,
+                "Not an hprof file: it doesn't start with \"{}\"",
+                str::from_utf8(HEADER_LABEL_MAGIC).unwrap()
+            ),
+            HprofParseError::InvalidHeader => write!(f, "Invalid hprof header"),
+            HprofParseError::UnsupportedIdSize { id_size } => {
+                write!(f, "Unsupported id size {} (expected 4 or 8)", id_size)?;
+                if let 4 | 8 = id_size.swap_bytes() {
+                    write!(
+                        f,
+                        "; byte-swapped it would be {}, so the header may be little-endian \
+                        rather than big-endian",
+                        id_size.swap_bytes()
+                    )?;
+                }
+                Ok(())
+            }
+            HprofParseError::UnknownRecordTag { offset, tag_byte } => write!(
+                f,
+                "Unknown record tag {:#04X} at offset {}",
+                tag_byte, offset
+            ),
+            HprofParseError::TrailingBytes { offset, len } => write!(
+                f,
+                "{} trailing bytes at offset {} do not form a complete record (truncated file?)",
+                len, offset
+            ),
+            HprofParseError::TruncatedRecord {
+                offset,
+                declared_len,
+                available,
+            } => write!(
+                f,
+                "Record at offset {} declares a {} byte body, but only {} bytes remain \
+                (truncated file?)",
+                offset, declared_len, available
+            ),
+            HprofParseError::MissingHeapDumpEnd => write!(
+                f,
+                "Heap dump segments present without a HeapDumpEnd record (truncated file?)"
+            ),
+            HprofParseError::InvalidRecord { offset, tag } => {
+                write!(f, "Invalid {:?} record at offset {}", tag, offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HprofParseError {}
+
 /// Basic metadata about the hprof
 #[derive(CopyGetters, Copy, Clone)]
 pub struct Header<'a> {
@@ -190,17 +799,52 @@ pub struct Header<'a> {
     timestamp_millis: u64,
 }
 
+/// The longest header label that will be accepted.
+const MAX_HEADER_LABEL_LEN: usize = 128;
+
+/// How every header label starts, whatever the version after it.
+const HEADER_LABEL_MAGIC: &[u8] = b"JAVA PROFILE";
+
 impl<'a> Header<'a> {
     pub fn label(&self) -> Result<&'a str, std::str::Utf8Error> {
         std::str::from_utf8(self.label)
     }
 
+    /// The hprof format version given at the end of the label, e.g. [ProfileVersion::V1_0_2] for
+    /// `JAVA PROFILE 1.0.2`.
+    ///
+    /// Returns `None` if the label isn't `JAVA PROFILE` followed by one of the known versions, in
+    /// which case the hprof may use records this crate doesn't understand.
+    pub fn profile_version(&self) -> Option<ProfileVersion> {
+        match self.label {
+            b"JAVA PROFILE 1.0.1" => Some(ProfileVersion::V1_0_1),
+            b"JAVA PROFILE 1.0.2" => Some(ProfileVersion::V1_0_2),
+            _ => None,
+        }
+    }
+
     fn parse(input: &[u8]) -> nom::IResult<&[u8], Header> {
-        // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L63
-        let (input, label) = bytes::take_until(&b"\0"[..])(input)?;
-        let (input, _) = bytes::take_while_m_n(1, 1, |b| b == 0)(input)?;
+        // Check for the magic first, so that some other kind of file is reported as such rather
+        // than as a mangled header.
+        if !input.starts_with(HEADER_LABEL_MAGIC) {
+            return Err(nom::Err::Error((input, nom::error::ErrorKind::Tag)));
+        }
 
-        // TODO confirm endianness
+        // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L63
+        // The label is a short version string like "JAVA PROFILE 1.0.2", so if there's no NUL
+        // near the start, this isn't an hprof, and there's no sense scanning the rest of what may
+        // be a very large file for one.
+        let label_len = input[..cmp::min(input.len(), MAX_HEADER_LABEL_LEN + 1)]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(nom::Err::Error((input, nom::error::ErrorKind::TakeUntil)))?;
+        // An empty label is fine: the NUL is then the first byte
+        let (input, label) = bytes::take(label_len)(input)?;
+        let (input, _) = bytes::tag(&b"\0"[..])(input)?;
+
+        // Everything in an hprof is big-endian, and since the id size is the first number, it's
+        // where a little-endian (or otherwise not quite hprof) file will first show up.
+        let id_size_input = input;
         let (input, id_size_num) = number::be_u32(input)?;
         let (input, epoch_hi) = number::be_u32(input)?;
         let (input, epoch_lo) = number::be_u32(input)?;
@@ -210,7 +854,12 @@ impl<'a> Header<'a> {
         let id_size = match id_size_num {
             4 => IdSize::U32,
             8 => IdSize::U64,
-            _ => panic!("unexpected size {}", id_size_num), // TODO
+            _ => {
+                return Err(nom::Err::Error((
+                    id_size_input,
+                    nom::error::ErrorKind::Verify,
+                )))
+            }
         };
 
         Ok((
@@ -234,28 +883,288 @@ impl<'a> fmt::Debug for Header<'a> {
     }
 }
 
+/// A version of the hprof format, as found in the [Header] label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProfileVersion {
+    /// `1.0.1`: the heap dump is a single [RecordTag::HeapDump] record, so it can't be more than
+    /// 4GiB, the most a record's u32 length can describe.
+    V1_0_1,
+    /// `1.0.2`: the heap dump is split across [RecordTag::HeapDumpSegment] records, followed by a
+    /// [RecordTag::HeapDumpEnd]. This is what current JVMs write.
+    V1_0_2,
+}
+
+impl ProfileVersion {
+    /// Whether heap dumps of this version are written as [RecordTag::HeapDumpSegment] records
+    /// rather than one [RecordTag::HeapDump].
+    pub fn uses_heap_dump_segments(&self) -> bool {
+        match self {
+            ProfileVersion::V1_0_1 => false,
+            ProfileVersion::V1_0_2 => true,
+        }
+    }
+}
+
+impl fmt::Display for ProfileVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            ProfileVersion::V1_0_1 => write!(f, "1.0.1"),
+            ProfileVersion::V1_0_2 => write!(f, "1.0.2"),
+        }
+    }
+}
+
 /// Iterator over the [Record] data in an hprof.
 pub struct Records<'a> {
     remaining: &'a [u8],
+    /// Offset of the end of the hprof, to find the offset of `remaining`
+    end_offset: usize,
     id_size: IdSize,
+    handlers: Option<&'a Handlers<'a>>,
+    /// Set after producing a truncated record, to be returned by the next call to `next()`
+    truncation_error: Option<HprofParseError>,
 }
 
 impl<'a> Iterator for Records<'a> {
-    type Item = ParseResult<'a, Record<'a>>;
+    type Item = Result<Record<'a>, HprofParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.truncation_error.take() {
+            return Some(Err(e));
+        }
+
+        if let Err(e) = self.dispatch_to_handlers() {
+            self.stop();
+            return Some(Err(e));
+        }
+
         if self.remaining.is_empty() {
             return None;
         }
 
-        let res = Record::parse(self.remaining, self.id_size);
+        let res = Record::parse(self.remaining, self.id_size, self.handlers);
         match res {
             Ok((input, record)) => {
                 self.remaining = input;
                 Some(Ok(record))
             }
-            Err(e) => Some(Err(e)),
+            Err(_) => {
+                let e = self.incomplete_record_error();
+                let truncated =
+                    Record::parse_truncated(self.remaining, self.id_size, self.handlers);
+                self.stop();
+                match truncated {
+                    Some(record) => {
+                        self.truncation_error = Some(e);
+                        Some(Ok(record))
+                    }
+                    None => Some(Err(e)),
+                }
+            }
+        }
+    }
+
+    /// Skips `n` records by reading only their length prefix, so skipped records' tags aren't
+    /// validated and none of their bodies are sliced or parsed.
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        for _ in 0..n {
+            // records passed to a handler aren't returned by `next()`, so they don't count
+            if let Err(e) = self.dispatch_to_handlers() {
+                self.stop();
+                return Some(Err(e));
+            }
+
+            if self.remaining.is_empty() {
+                return None;
+            }
+
+            match Record::skip(self.remaining) {
+                Ok((input, _)) => self.remaining = input,
+                Err(_) => {
+                    let e = self.incomplete_record_error();
+                    self.stop();
+                    return Some(Err(e));
+                }
+            }
         }
+
+        self.next()
+    }
+}
+
+impl<'a> Records<'a> {
+    /// Drop the remaining input so that iteration ends after an error.
+    fn stop(&mut self) {
+        self.remaining = &self.remaining[self.remaining.len()..];
+    }
+
+    /// Describe why the record at the start of the remaining input couldn't be read.
+    fn incomplete_record_error(&self) -> HprofParseError {
+        HprofParseError::from_incomplete_record(
+            self.remaining,
+            self.end_offset - self.remaining.len(),
+        )
+    }
+
+    /// Pass any records at the start of the remaining input that have a registered handler to
+    /// that handler.
+    fn dispatch_to_handlers(&mut self) -> Result<(), HprofParseError> {
+        let handlers = match self.handlers {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+
+        while let Some(handler) = self
+            .remaining
+            .first()
+            .and_then(|&tag_byte| handlers.record_handler(tag_byte))
+        {
+            let (input, (_tag_byte, _micros, body)) =
+                Record::parse_raw(self.remaining).map_err(|_| self.incomplete_record_error())?;
+            self.remaining = input;
+            handler(body, self.id_size);
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator over the [Record]s in an hprof with particular tags, as per
+/// [Hprof::records_iter_filtered].
+pub struct FilteredRecords<'a> {
+    records: Records<'a>,
+    /// Indexed by tag byte
+    allowed: [bool; 256],
+}
+
+impl<'a> Iterator for FilteredRecords<'a> {
+    type Item = Result<Record<'a>, HprofParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // a pending truncation error is for an allowed record, so let `next()` return it
+        while self.records.truncation_error.is_none() {
+            if let Err(e) = self.records.dispatch_to_handlers() {
+                self.records.stop();
+                return Some(Err(e));
+            }
+
+            match self.records.remaining.first() {
+                Some(&tag_byte) if !self.allowed[tag_byte as usize] => {
+                    match Record::skip(self.records.remaining) {
+                        Ok((input, _)) => self.records.remaining = input,
+                        Err(_) => {
+                            let e = self.records.incomplete_record_error();
+                            self.records.stop();
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        self.records.next()
+    }
+}
+
+/// Iterator over the [heap_dump::Instance]s in an hprof and their field values, as per
+/// [Hprof::instances_with_fields].
+pub struct InstancesWithFields<'a> {
+    records: Records<'a>,
+    /// Sub records of the heap dump segment currently being iterated over
+    sub_records: Option<SubRecords<'a>>,
+    /// class obj id -> all instance fields, own class first
+    layouts: collections::HashMap<Id, Vec<heap_dump::FieldDescriptor>>,
+    id_size: IdSize,
+    /// Set if collecting layouts failed, to be returned by the first call to `next()`
+    error: Option<nom::Err<(&'a [u8], nom::error::ErrorKind)>>,
+}
+
+impl<'a> Iterator for InstancesWithFields<'a> {
+    type Item = ParseResult<'a, (heap_dump::Instance<'a>, Vec<(Id, heap_dump::FieldValue)>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        loop {
+            if let Some(sub_records) = self.sub_records.as_mut() {
+                match sub_records.next() {
+                    Some(Ok(heap_dump::SubRecord::Instance(instance))) => {
+                        let layout = self
+                            .layouts
+                            .get(&instance.class_obj_id())
+                            .map(|l| l.as_slice())
+                            .unwrap_or(&[]);
+                        let values = instance
+                            .field_values(layout, self.id_size)
+                            .map(|r| r.map(|(fd, value)| (fd.name_id(), value)))
+                            .collect::<Result<Vec<_>, _>>();
+
+                        return Some(values.map(|v| (instance, v)));
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        self.sub_records = None;
+                        self.records.stop();
+                        return Some(Err(e));
+                    }
+                    None => self.sub_records = None,
+                }
+            }
+
+            let record = match self.records.next()? {
+                Ok(r) => r,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if let RecordTag::HeapDump | RecordTag::HeapDumpSegment = record.tag {
+                match HeapDumpSegment::parse(record.body, record.id_size, record.handlers) {
+                    Ok(segment) => self.sub_records = Some(segment.sub_records()),
+                    Err(e) => {
+                        self.records.stop();
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the tag, micros, body length, and offset of each record in an hprof, as per
+/// [Hprof::scan_record_headers].
+pub struct RecordHeaders<'a> {
+    remaining: &'a [u8],
+    /// Offset of `remaining` from the start of the hprof
+    offset: usize,
+}
+
+impl<'a> Iterator for RecordHeaders<'a> {
+    type Item = (RecordTag, u32, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.remaining.is_empty() {
+            let (body, (tag_byte, micros, len)) = match Record::parse_prefix(self.remaining) {
+                Ok(prefix) => prefix,
+                Err(_) => break,
+            };
+            let len = len as usize;
+            if body.len() < len {
+                break;
+            }
+
+            let offset = self.offset;
+            let record_len = self.remaining.len() - body.len() + len;
+            self.remaining = &body[len..];
+            self.offset += record_len;
+
+            if let Some(tag) = RecordTag::from_tag_byte(tag_byte) {
+                return Some((tag, micros, len, offset));
+            }
+        }
+
+        self.remaining = &self.remaining[self.remaining.len()..];
+        None
     }
 }
 
@@ -313,6 +1222,7 @@ pub struct Record<'a> {
     micros_since_header_ts: u32,
     id_size: IdSize,
     body: &'a [u8],
+    handlers: Option<&'a Handlers<'a>>,
 }
 
 impl<'a> Record<'a> {
@@ -348,42 +1258,100 @@ impl<'a> Record<'a> {
         }
     }
 
+    /// Returns `Some` if the tag is [RecordTag::StartThread] and `None` otherwise.
+    pub fn as_start_thread(&self) -> Option<ParseResult<'a, StartThread>> {
+        match self.tag {
+            RecordTag::StartThread => Some(StartThread::parse(self.body, self.id_size)),
+            _ => None,
+        }
+    }
+
     /// Returns `Some` if the tag is [RecordTag::HeapDump] or [RecordTag::HeapDumpSegment] and
     /// `None` otherwise.
     pub fn as_heap_dump_segment(&self) -> Option<ParseResult<HeapDumpSegment<'a>>> {
         match self.tag {
-            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
-                Some(HeapDumpSegment::parse(self.body, self.id_size))
-            }
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => Some(HeapDumpSegment::parse(
+                self.body,
+                self.id_size,
+                self.handlers,
+            )),
             _ => None,
         }
     }
 
-    fn parse(input: &[u8], id_size: IdSize) -> nom::IResult<&[u8], Record> {
-        // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L76
-        let (input, tag_byte) = bytes::take(1_usize)(input)?;
-
-        let tag = match tag_byte[0] {
-            0x01 => RecordTag::Utf8,
-            0x02 => RecordTag::LoadClass,
-            0x03 => RecordTag::UnloadClass,
-            0x04 => RecordTag::StackFrame,
-            0x05 => RecordTag::StackTrace,
-            0x06 => RecordTag::AllocSites,
-            0x07 => RecordTag::HeapSummary,
-            0x0A => RecordTag::StartThread,
-            0x0B => RecordTag::EndThread,
-            0x0C => RecordTag::HeapDump,
-            0x0D => RecordTag::CpuSamples,
-            0x0E => RecordTag::ControlSettings,
-            0x1C => RecordTag::HeapDumpSegment,
-            0x2C => RecordTag::HeapDumpEnd,
-            _ => panic!("unexpected tag: {:#X?}", tag_byte[0]),
-        };
+    /// The record's body, i.e. everything after the tag, timestamp, and length, without any
+    /// parsing.
+    ///
+    /// For record types that don't have an `as_*` method, this is the only way to get at their
+    /// contents, e.g. to hexdump them or parse them some other way.
+    pub fn raw_body(&self) -> &'a [u8] {
+        self.body
+    }
 
-        let (input, micros) = number::be_u32(input)?;
+    /// The length in bytes of the record's body, as given in the record's header.
+    pub fn body_len(&self) -> usize {
+        self.body.len()
+    }
+
+    /// The time the record was written, as microseconds since epoch.
+    ///
+    /// `header` should be the header of the hprof this record came from.
+    pub fn absolute_timestamp_micros(&self, header: &Header) -> u64 {
+        header.timestamp_millis() * 1000 + self.micros_since_header_ts as u64
+    }
+
+    /// The time the record was written.
+    ///
+    /// `header` should be the header of the hprof this record came from.
+    #[cfg(feature = "chrono")]
+    pub fn absolute_timestamp(&self, header: &Header) -> chrono::DateTime<chrono::Utc> {
+        use chrono::TimeZone;
+
+        let micros = self.absolute_timestamp_micros(header);
+        chrono::Utc.timestamp(
+            (micros / 1_000_000) as i64,
+            (micros % 1_000_000) as u32 * 1000,
+        )
+    }
+
+    /// Parse the single record at the start of `buffer`, ignoring anything after it.
+    ///
+    /// This is for tools that keep track of where records are (e.g. in an index) and later want to
+    /// look at one without iterating over all the records before it. `buffer` must start at a
+    /// record boundary, i.e. with a record's tag byte. A slice that starts anywhere else will
+    /// most likely produce a parse error, but could also produce garbage. `id_size` should be
+    /// from the header of the hprof the record is in.
+    ///
+    /// Unlike when iterating over records, a tag that isn't a known [RecordTag] produces an error
+    /// rather than a panic.
+    pub fn parse_at(buffer: &'a [u8], id_size: IdSize) -> ParseResult<'a, Record<'a>> {
+        match buffer.first().copied().map(RecordTag::from_tag_byte) {
+            Some(Some(_)) => Record::parse(buffer, id_size, None).map(|(_input, record)| record),
+            _ => Err(nom::Err::Error((buffer, nom::error::ErrorKind::Tag))),
+        }
+    }
+
+    /// Skip over a record without inspecting its tag or body.
+    pub(crate) fn skip(input: &[u8]) -> nom::IResult<&[u8], ()> {
+        let (input, _tag) = number::be_u8(input)?;
+        let (input, _micros) = number::be_u32(input)?;
         let (input, len) = number::be_u32(input)?;
-        let (input, body) = bytes::take(len)(input)?;
+        let (input, _body) = bytes::take(len)(input)?;
+
+        Ok((input, ()))
+    }
+
+    fn parse(
+        input: &'a [u8],
+        id_size: IdSize,
+        handlers: Option<&'a Handlers<'a>>,
+    ) -> nom::IResult<&'a [u8], Record<'a>> {
+        let (input, (tag_byte, micros, body)) = Record::parse_raw(input)?;
+
+        let tag = match RecordTag::from_tag_byte(tag_byte) {
+            Some(t) => t,
+            None => panic!("unexpected tag: {:#X?}", tag_byte),
+        };
 
         Ok((
             input,
@@ -392,9 +1360,54 @@ impl<'a> Record<'a> {
                 micros_since_header_ts: micros,
                 id_size,
                 body,
+                handlers,
             },
         ))
     }
+
+    /// Parse a record whose body is cut short by the end of `input`, using whatever part of the
+    /// body is there.
+    ///
+    /// Returns `None` if the tag isn't a known [RecordTag] or the tag, micros, and length
+    /// themselves aren't all present.
+    fn parse_truncated(
+        input: &'a [u8],
+        id_size: IdSize,
+        handlers: Option<&'a Handlers<'a>>,
+    ) -> Option<Record<'a>> {
+        let (body, (tag_byte, micros, len)) = Record::parse_prefix(input).ok()?;
+
+        if body.len() >= len as usize {
+            // not truncated, so the error was something else
+            return None;
+        }
+
+        RecordTag::from_tag_byte(tag_byte).map(|tag| Record {
+            tag,
+            micros_since_header_ts: micros,
+            id_size,
+            body,
+            handlers,
+        })
+    }
+
+    /// Parse the tag byte, micros, and body of a record without interpreting the tag.
+    fn parse_raw(input: &[u8]) -> nom::IResult<&[u8], (u8, u32, &[u8])> {
+        let (input, (tag_byte, micros, len)) = Record::parse_prefix(input)?;
+        let (input, body) = bytes::take(len)(input)?;
+
+        Ok((input, (tag_byte, micros, body)))
+    }
+
+    /// Parse the tag byte, micros, and body length that precede a record's body.
+    fn parse_prefix(input: &[u8]) -> nom::IResult<&[u8], (u8, u32, u32)> {
+        // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L76
+        let (input, tag_byte) = number::be_u8(input)?;
+        let (input, micros) = number::be_u32(input)?;
+        let (input, len) = number::be_u32(input)?;
+
+        Ok((input, (tag_byte, micros, len)))
+    }
 }
 
 /// Indicates what type of data is contained in a particular [Record].
@@ -422,15 +1435,16 @@ pub enum RecordTag {
     EndThread,
     /// Unused?
     HeapSummary,
-    /// See [HeapDumpSegment]
+    /// The whole heap dump in one record, as written by older JVMs. See [HeapDumpSegment].
     HeapDump,
     /// Unused?
     CpuSamples,
     /// Unused?
     ControlSettings,
-    /// See [HeapDumpSegment]
+    /// Part of a heap dump split across many records, followed by a [RecordTag::HeapDumpEnd]. See
+    /// [HeapDumpSegment].
     HeapDumpSegment,
-    /// Denotes the end of a heap dump
+    /// Denotes the end of a heap dump written as [RecordTag::HeapDumpSegment]s
     HeapDumpEnd,
 }
 
@@ -453,6 +1467,26 @@ impl RecordTag {
             RecordTag::HeapDumpEnd => 0x2C,
         }
     }
+
+    fn from_tag_byte(tag_byte: u8) -> Option<RecordTag> {
+        match tag_byte {
+            0x01 => Some(RecordTag::Utf8),
+            0x02 => Some(RecordTag::LoadClass),
+            0x03 => Some(RecordTag::UnloadClass),
+            0x04 => Some(RecordTag::StackFrame),
+            0x05 => Some(RecordTag::StackTrace),
+            0x06 => Some(RecordTag::AllocSites),
+            0x07 => Some(RecordTag::HeapSummary),
+            0x0A => Some(RecordTag::StartThread),
+            0x0B => Some(RecordTag::EndThread),
+            0x0C => Some(RecordTag::HeapDump),
+            0x0D => Some(RecordTag::CpuSamples),
+            0x0E => Some(RecordTag::ControlSettings),
+            0x1C => Some(RecordTag::HeapDumpSegment),
+            0x2C => Some(RecordTag::HeapDumpEnd),
+            _ => None,
+        }
+    }
 }
 
 impl cmp::Ord for RecordTag {
@@ -485,17 +1519,30 @@ impl<'a> Utf8<'a> {
     pub fn text_as_str(&self) -> Result<&'a str, std::str::Utf8Error> {
         std::str::from_utf8(self.text)
     }
+
+    /// Like [Utf8::text_as_str], but with any invalid UTF-8 replaced with U+FFFD, for when
+    /// best-effort display is good enough.
+    ///
+    /// Borrows the text if it's valid UTF-8, so this is no more expensive than `text_as_str` in the
+    /// common case.
+    pub fn text_lossy(&self) -> borrow::Cow<'a, str> {
+        String::from_utf8_lossy(self.text)
+    }
 }
 
 /// Contents of a [Record] with tag [RecordTag::LoadClass].
-#[derive(CopyGetters, Copy, Clone)]
+///
+/// Equality compares every field, so a class that was loaded more than once (with a different
+/// serial each time) yields distinct values.
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct LoadClass {
     #[get_copy = "pub"]
     class_serial: Serial,
     #[get_copy = "pub"]
     class_obj_id: Id,
+    /// `None` if the JVM didn't record where the class was loaded, which it writes as serial 0
     #[get_copy = "pub"]
-    stack_trace_serial: Serial,
+    stack_trace_serial: Option<Serial>,
     #[get_copy = "pub"]
     class_name_id: Id,
 }
@@ -505,13 +1552,13 @@ impl LoadClass {
         // https://github.com/openjdk/jdk/blob/08822b4e0526fe001c39fe08e241b849eddf481d/src/hotspot/share/services/heapDumper.cpp#L93
         let (input, class_serial) = number::be_u32(input)?;
         let (input, class_obj_id) = Id::parse(input, id_size)?;
-        let (input, stack_trace_serial) = number::be_u32(input)?;
+        let (input, stack_trace_serial) = heap_dump::parse_optional_serial(input)?;
         let (_input, class_name_id) = Id::parse(input, id_size)?;
 
         Ok(LoadClass {
             class_serial: class_serial.into(),
             class_obj_id,
-            stack_trace_serial: stack_trace_serial.into(),
+            stack_trace_serial,
             class_name_id,
         })
     }
@@ -524,7 +1571,10 @@ struct UnloadClass {
 }
 
 /// Contents of a [Record] with tag [RecordTag::StackFrame].
-#[derive(CopyGetters, Clone)]
+///
+/// Equality, hashing, and ordering only consider [StackFrame::id], since that's what stack traces
+/// use to refer to frames. This makes it easy to dedupe frames that are shared between traces.
+#[derive(CopyGetters, Clone, Debug)]
 pub struct StackFrame {
     #[get_copy = "pub"]
     id: Id,
@@ -532,8 +1582,9 @@ pub struct StackFrame {
     method_name_id: Id,
     #[get_copy = "pub"]
     method_signature_id: Id,
+    /// `None` if the frame has no source file, e.g. for generated classes
     #[get_copy = "pub"]
-    source_file_name_id: Id,
+    source_file_name_id: Option<Id>,
     #[get_copy = "pub"]
     class_serial: Serial,
     #[get_copy = "pub"]
@@ -546,8 +1597,7 @@ impl StackFrame {
         let (input, id) = Id::parse(input, id_size)?;
         let (input, method_name_id) = Id::parse(input, id_size)?;
         let (input, method_signature_id) = Id::parse(input, id_size)?;
-        // TODO Option?
-        let (input, source_file_name_id) = Id::parse(input, id_size)?;
+        let (input, source_file_name_id) = heap_dump::parse_optional_id(input, id_size)?;
         let (input, class_serial) = number::be_u32(input)?;
         let (_input, line_num) = LineNum::parse(input)?;
 
@@ -562,6 +1612,32 @@ impl StackFrame {
     }
 }
 
+impl PartialEq for StackFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for StackFrame {}
+
+impl hash::Hash for StackFrame {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl PartialOrd for StackFrame {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StackFrame {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
 /// Contents of a [Record] with tag [RecordTag::StackTrace].
 #[derive(CopyGetters, Clone)]
 pub struct StackTrace<'a> {
@@ -590,7 +1666,7 @@ impl<'a> StackTrace<'a> {
         })
     }
 
-    pub fn frame_ids(&self) -> Ids {
+    pub fn frame_ids(&self) -> Ids<'a> {
         Ids {
             iter: ParsingIterator::new_stateless_id_size(
                 self.id_size,
@@ -599,6 +1675,77 @@ impl<'a> StackTrace<'a> {
             ),
         }
     }
+
+    /// The [StackFrame] for each of [StackTrace::frame_ids], looked up in `frames` (frame id ->
+    /// frame), innermost frame first.
+    ///
+    /// Frames that aren't in `frames`, or whose ids can't be parsed, are `None`.
+    pub fn resolved_frames<'f>(
+        &self,
+        frames: &'f collections::HashMap<Id, StackFrame>,
+    ) -> impl Iterator<Item = Option<&'f StackFrame>> + 'f
+    where
+        'a: 'f,
+    {
+        self.frame_ids()
+            .map(move |r| r.ok().and_then(|id| frames.get(&id)))
+    }
+}
+
+/// The [StackFrame]s in each thread's stack, as collected by [Hprof::thread_frames].
+///
+/// GC roots for local variables and JNI local references identify their frame by thread serial
+/// and an index into that thread's [StackTrace], innermost frame first, which makes it possible
+/// to tell which method is holding on to an object.
+///
+/// HotSpot writes one [StackTrace] per thread, but other tools may write several for the same
+/// thread, so every trace is kept.
+pub struct ThreadFrames {
+    /// stack trace serial -> frame ids
+    frame_ids_by_trace: collections::HashMap<Serial, Vec<Id>>,
+    /// thread serial -> stack trace serials, in the order they appear in the hprof
+    traces_by_thread: collections::HashMap<Serial, Vec<Serial>>,
+    frames: collections::HashMap<Id, StackFrame>,
+}
+
+impl ThreadFrames {
+    /// The frame at `frame_index` in the stack of the thread with serial `thread_serial`.
+    ///
+    /// If the thread has more than one stack trace, the first one in the hprof is used. To pick a
+    /// particular one, use [ThreadFrames::frame_in_trace], e.g. with the thread's
+    /// [heap_dump::GcRootThreadObj::stack_trace_serial].
+    ///
+    /// Returns `None` if `frame_index` is `None`, the thread has no stack trace, the trace has
+    /// fewer frames than `frame_index`, or the frame's [StackFrame] record is missing.
+    pub fn frame(&self, thread_serial: Serial, frame_index: Option<u32>) -> Option<&StackFrame> {
+        self.traces_by_thread
+            .get(&thread_serial)
+            .and_then(|serials| serials.first())
+            .and_then(|&trace_serial| self.frame_in_trace(trace_serial, frame_index))
+    }
+
+    /// The frame at `frame_index` in the stack trace with serial `stack_trace_serial`.
+    ///
+    /// Returns `None` under the same conditions as [ThreadFrames::frame].
+    pub fn frame_in_trace(
+        &self,
+        stack_trace_serial: Serial,
+        frame_index: Option<u32>,
+    ) -> Option<&StackFrame> {
+        self.frame_ids_by_trace
+            .get(&stack_trace_serial)
+            .and_then(|ids| ids.get(frame_index? as usize))
+            .and_then(|id| self.frames.get(id))
+    }
+
+    /// The serials of the stack traces of the thread with serial `thread_serial`, in the order
+    /// they appear in the hprof.
+    pub fn stack_trace_serials(&self, thread_serial: Serial) -> &[Serial] {
+        self.traces_by_thread
+            .get(&thread_serial)
+            .map(|serials| serials.as_slice())
+            .unwrap_or(&[])
+    }
 }
 
 /// Heap allocation sites, obtained after GC
@@ -615,17 +1762,46 @@ struct AllocSites {
     // TODO iterator over following AllocSite instances
 }
 
-// TODO referenced in heapDumper.cpp, but not actually written?
-#[allow(unused)]
-struct StartThread {
+/// Contents of a [Record] with tag [RecordTag::StartThread].
+///
+/// HotSpot's heap dumper doesn't write these, but the old HPROF agent and some other tools do. When
+/// present, they're the only place an hprof records thread names: see [Hprof::thread_names].
+#[derive(CopyGetters, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StartThread {
+    #[get_copy = "pub"]
     thread_serial: Serial,
-    thread_id: Id,
+    #[get_copy = "pub"]
+    thread_obj_id: Id,
+    #[get_copy = "pub"]
     stack_trace_serial: Serial,
+    #[get_copy = "pub"]
     thread_name_id: Id,
+    #[get_copy = "pub"]
     thread_group_name_id: Id,
+    #[get_copy = "pub"]
     thread_group_parent_name_id: Id,
 }
 
+impl StartThread {
+    fn parse(input: &[u8], id_size: IdSize) -> ParseResult<'_, StartThread> {
+        let (input, thread_serial) = number::be_u32(input)?;
+        let (input, thread_obj_id) = Id::parse(input, id_size)?;
+        let (input, stack_trace_serial) = number::be_u32(input)?;
+        let (input, thread_name_id) = Id::parse(input, id_size)?;
+        let (input, thread_group_name_id) = Id::parse(input, id_size)?;
+        let (_input, thread_group_parent_name_id) = Id::parse(input, id_size)?;
+
+        Ok(StartThread {
+            thread_serial: thread_serial.into(),
+            thread_obj_id,
+            stack_trace_serial: stack_trace_serial.into(),
+            thread_name_id,
+            thread_group_name_id,
+            thread_group_parent_name_id,
+        })
+    }
+}
+
 // TODO referenced in heapDumper.cpp, but not actually written?
 #[allow(unused)]
 struct EndThread {
@@ -642,25 +1818,57 @@ struct HeapSummary {
 ///
 /// Contains many [heap_dump::SubRecord]s.
 ///
+/// A [RecordTag::HeapDump] is the older form, with the entire heap dump in one record. Since a
+/// record's length is a `u32`, that limits the heap dump to 4GiB, so newer JVMs split heap dumps
+/// into [RecordTag::HeapDumpSegment]s and finish with a [RecordTag::HeapDumpEnd]. The sub records
+/// are the same either way, and a file may contain both forms (e.g. when multiple dumps have been
+/// appended by different tools), so code that handles heap dumps should always handle both tags.
+///
 /// See the [heap_dump] module.
 pub struct HeapDumpSegment<'a> {
     id_size: IdSize,
     records: &'a [u8],
+    handlers: Option<&'a Handlers<'a>>,
 }
 
 impl<'a> HeapDumpSegment<'a> {
-    fn parse(input: &[u8], id_size: IdSize) -> ParseResult<HeapDumpSegment> {
+    fn parse(
+        input: &'a [u8],
+        id_size: IdSize,
+        handlers: Option<&'a Handlers<'a>>,
+    ) -> ParseResult<'a, HeapDumpSegment<'a>> {
         Ok(HeapDumpSegment {
             id_size,
             records: input,
+            handlers,
         })
     }
 
+    /// The length in bytes of the segment's body, i.e. all of its sub records.
+    ///
+    /// A record's length is a `u32`, so this is at most 4GiB; bigger heap dumps are split across
+    /// many segments.
+    pub fn body_len(&self) -> usize {
+        self.records.len()
+    }
+
     /// Iterate over the [heap_dump::SubRecord]s in this [Record].
+    ///
+    /// A segment with an empty body is valid, and simply has no sub records.
     pub fn sub_records(&self) -> SubRecords<'a> {
         SubRecords {
             id_size: self.id_size,
             remaining: self.records,
+            handlers: self.handlers,
+        }
+    }
+
+    /// Iterate over the [heap_dump::SubRecord]s in this [Record] along with the offset of each
+    /// one within the segment, for use with [Hprof::sub_record_at].
+    pub fn sub_records_with_offsets(&self) -> SubRecordsWithOffsets<'a> {
+        SubRecordsWithOffsets {
+            segment_len: self.records.len(),
+            sub_records: self.sub_records(),
         }
     }
 }
@@ -669,12 +1877,53 @@ impl<'a> HeapDumpSegment<'a> {
 pub struct SubRecords<'a> {
     id_size: IdSize,
     remaining: &'a [u8],
+    handlers: Option<&'a Handlers<'a>>,
+}
+
+impl<'a> SubRecords<'a> {
+    /// Pass any sub records at the start of the remaining input that have a registered handler
+    /// to that handler.
+    fn dispatch_to_handlers(&mut self) -> ParseResult<'a, ()> {
+        let handlers = match self.handlers {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+
+        while let Some(handler) = self
+            .remaining
+            .first()
+            .and_then(|&tag_byte| handlers.sub_record_handler(tag_byte))
+        {
+            let (input, ()) = handler(&self.remaining[1..], self.id_size)?;
+            self.remaining = input;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> SubRecords<'a> {
+    /// Iterate over only the sub records that can refer to other objects: GC roots, classes
+    /// with signers, a protection domain, or static fields of object type, instances, and object
+    /// arrays. Primitive arrays, GC roots without an object, and classes with none of those
+    /// references are skipped.
+    ///
+    /// Handy when building a graph of references, since there's no need to handle the sub records
+    /// that can't contribute any edges.
+    pub fn reference_sources(self) -> ReferenceSources<'a> {
+        ReferenceSources { sub_records: self }
+    }
 }
 
 impl<'a> Iterator for SubRecords<'a> {
     type Item = ParseResult<'a, heap_dump::SubRecord<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.dispatch_to_handlers() {
+            self.remaining = &self.remaining[self.remaining.len()..];
+            return Some(Err(e));
+        }
+
         if self.remaining.is_empty() {
             return None;
         }
@@ -685,8 +1934,59 @@ impl<'a> Iterator for SubRecords<'a> {
                 self.remaining = input;
                 Some(Ok(record))
             }
-            Err(e) => Some(Err(e)),
+            Err(e) => {
+                // iteration ends after the first error
+                self.remaining = &self.remaining[self.remaining.len()..];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Iterator over just the sub records that can refer to other objects.
+///
+/// See [SubRecords::reference_sources].
+pub struct ReferenceSources<'a> {
+    sub_records: SubRecords<'a>,
+}
+
+impl<'a> Iterator for ReferenceSources<'a> {
+    type Item = ParseResult<'a, heap_dump::ReferenceSource<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for res in &mut self.sub_records {
+            match res.and_then(heap_dump::ReferenceSource::from_sub_record) {
+                Ok(Some(source)) => return Some(Ok(source)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        None
+    }
+}
+
+/// Iterator over [heap_dump::SubRecord] data and the offset of each sub record within its
+/// segment.
+pub struct SubRecordsWithOffsets<'a> {
+    segment_len: usize,
+    sub_records: SubRecords<'a>,
+}
+
+impl<'a> Iterator for SubRecordsWithOffsets<'a> {
+    type Item = ParseResult<'a, (usize, heap_dump::SubRecord<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // so that the offset is for the next sub record that will actually be returned
+        if let Err(e) = self.sub_records.dispatch_to_handlers() {
+            return Some(Err(e));
         }
+
+        let offset = self.segment_len - self.sub_records.remaining.len();
+
+        self.sub_records
+            .next()
+            .map(|res| res.map(|sub_record| (offset, sub_record)))
     }
 }
 
@@ -706,7 +2006,7 @@ struct ControlSettings {
 }
 
 /// A line referenced from a stack frame.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum LineNum {
     /// A line in a source file
     Normal(u32),
@@ -872,3 +2172,1451 @@ impl<T: strum::IntoEnumIterator> EnumIterable for T {
         T::iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_dump::{ReferenceSource, SubRecord};
+    use crate::test_util::*;
+    use std::sync;
+
+    #[test]
+    fn profile_version_from_label() {
+        let version = |label: &[u8]| {
+            let bytes = HprofBuilder::new().label(label).build();
+            parse_hprof(&bytes[..]).unwrap().header().profile_version()
+        };
+
+        assert_eq!(Some(ProfileVersion::V1_0_1), version(b"JAVA PROFILE 1.0.1"));
+        assert_eq!(Some(ProfileVersion::V1_0_2), version(b"JAVA PROFILE 1.0.2"));
+        assert!(!ProfileVersion::V1_0_1.uses_heap_dump_segments());
+        assert!(ProfileVersion::V1_0_2.uses_heap_dump_segments());
+        assert_eq!("1.0.2", ProfileVersion::V1_0_2.to_string());
+
+        assert_eq!(None, version(b"JAVA PROFILE 1.0.3"));
+        assert_eq!(None, version(b"JAVA PROFILE 1.0.2 "));
+        assert_eq!(None, version(b"JAVA PROFILE"));
+    }
+
+    #[test]
+    fn header_with_magic_only_label() {
+        let bytes = HprofBuilder::with_id_size(4)
+            .label(b"JAVA PROFILE")
+            .timestamp_millis(0x0102_0304_0506_0708)
+            .utf8(1, "hello")
+            .build();
+
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        assert_eq!("JAVA PROFILE", hprof.header().label().unwrap());
+        assert_eq!(4, hprof.header().id_size().size_in_bytes());
+        assert_eq!(0x0102_0304_0506_0708, hprof.header().timestamp_millis());
+
+        let utf8 = hprof
+            .records_iter()
+            .next()
+            .unwrap()
+            .unwrap()
+            .as_utf_8()
+            .unwrap()
+            .unwrap();
+        assert_eq!("hello", utf8.text_as_str().unwrap());
+    }
+
+    #[test]
+    fn header_rejects_missing_nul() {
+        let padded_label = |len: usize| {
+            let mut label = HEADER_LABEL_MAGIC.to_vec();
+            label.resize(len, b'J');
+            label
+        };
+
+        // no NUL at all
+        let err = parse_hprof_strict(&padded_label(1000)[..]).err().unwrap();
+        assert_eq!(HprofParseError::InvalidHeader, err);
+
+        // NUL too far in to be the end of a label
+        let bytes = HprofBuilder::new()
+            .label(&padded_label(MAX_HEADER_LABEL_LEN + 1)[..])
+            .build();
+        assert!(parse_hprof(&bytes[..]).is_err());
+
+        // longest acceptable label
+        let bytes = HprofBuilder::new()
+            .label(&padded_label(MAX_HEADER_LABEL_LEN)[..])
+            .build();
+        assert_eq!(
+            MAX_HEADER_LABEL_LEN,
+            parse_hprof(&bytes[..])
+                .unwrap()
+                .header()
+                .label()
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn header_rejects_other_files() {
+        let random_bytes = (0..4096).map(|_| rand::random()).collect::<Vec<u8>>();
+
+        for bytes in [
+            &random_bytes[..],
+            &[][..],
+            &b"JAVA"[..],
+            &b"java profile 1.0.2\0"[..],
+            &b"PK\x03\x04 not a heap dump"[..],
+        ]
+        .iter()
+        {
+            assert!(parse_hprof(bytes).is_err());
+            let err = parse_hprof_strict(bytes).err().unwrap();
+            assert_eq!(HprofParseError::NotAnHprof, err);
+            assert!(err.to_string().contains("JAVA PROFILE"));
+        }
+    }
+
+    #[test]
+    fn header_rejects_byte_swapped_id_size() {
+        let mut bytes = HprofBuilder::with_id_size(4).utf8(1, "hello").build();
+        // the id size is right after the label's NUL
+        let id_size_offset = bytes.iter().position(|&b| b == 0).unwrap() + 1;
+        bytes[id_size_offset..id_size_offset + 4].reverse();
+
+        assert!(parse_hprof(&bytes[..]).is_err());
+
+        let err = parse_hprof_strict(&bytes[..]).err().unwrap();
+        assert_eq!(
+            HprofParseError::UnsupportedIdSize {
+                id_size: 0x0400_0000
+            },
+            err
+        );
+        assert!(err.to_string().contains("little-endian"));
+    }
+
+    #[test]
+    fn strict_accepts_well_formed() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000);
+        let bytes = builder
+            .utf8(1, "hello")
+            .heap_dump_segment(&segment)
+            .heap_dump_end()
+            .build();
+
+        assert!(parse_hprof_strict(&bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn strict_accepts_no_heap_dump() {
+        let mut builder = HprofBuilder::new();
+        let bytes = builder.utf8(1, "hello").build();
+
+        assert!(parse_hprof_strict(&bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn strict_accepts_legacy_heap_dump_without_end() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000);
+        let bytes = builder.heap_dump(&segment).build();
+
+        assert!(parse_hprof_strict(&bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn legacy_heap_dump_and_segments_in_one_file() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 100, &[1000])
+            .gc_root_unknown(1000);
+        let bytes = builder
+            .heap_dump(&segment)
+            .heap_dump_segment(&segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof_strict(&bytes[..]).unwrap();
+
+        let records = hprof.records_iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                RecordTag::HeapDump,
+                RecordTag::HeapDumpSegment,
+                RecordTag::HeapDumpEnd
+            ],
+            records.iter().map(|r| r.tag()).collect::<Vec<_>>()
+        );
+
+        let sub_records = records[..2]
+            .iter()
+            .map(|r| {
+                r.as_heap_dump_segment()
+                    .unwrap()
+                    .unwrap()
+                    .sub_records_with_offsets()
+                    .map(|r| {
+                        let (offset, s) = r.unwrap();
+                        (offset, s.tag(), s.as_gc_root_obj_id())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(4, sub_records[0].len());
+        assert_eq!(sub_records[0], sub_records[1]);
+
+        assert_eq!(
+            2 * segment.body().len() as u64,
+            hprof.total_heap_dump_bytes().unwrap()
+        );
+    }
+
+    #[test]
+    fn strict_rejects_trailing_junk() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000);
+        let mut bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let junk_offset = bytes.len();
+        // looks like the start of a Utf8 record, but cut off partway through the length
+        bytes.extend_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        // lenient parsing doesn't notice
+        assert!(parse_hprof(&bytes[..]).is_ok());
+
+        assert_eq!(
+            HprofParseError::TrailingBytes {
+                offset: junk_offset,
+                len: 7
+            },
+            parse_hprof_strict(&bytes[..]).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn strict_rejects_truncated_record() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000).gc_root_unknown(2000);
+        let mut bytes = builder
+            .utf8(1, "hello")
+            .heap_dump_segment(&segment)
+            .heap_dump_end()
+            .build();
+        // cut off partway through the segment, and the end record with it
+        let segment_offset = bytes.len() - (1 + 4 + 4) - (1 + 4 + 4 + 2 * (1 + 8));
+        bytes.truncate(bytes.len() - (1 + 4 + 4) - 3);
+
+        assert_eq!(
+            HprofParseError::TruncatedRecord {
+                offset: segment_offset,
+                declared_len: 18,
+                available: 15
+            },
+            parse_hprof_strict(&bytes[..]).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn strict_rejects_unknown_tag() {
+        let mut builder = HprofBuilder::new();
+        let bytes = builder.utf8(1, "hello").record(0x99, 0, b"what").build();
+
+        assert_eq!(
+            HprofParseError::UnknownRecordTag {
+                // tag, micros, and length, then the body
+                offset: bytes.len() - (1 + 4 + 4 + 4),
+                tag_byte: 0x99
+            },
+            parse_hprof_strict(&bytes[..]).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn strict_rejects_missing_heap_dump_end() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000);
+        let bytes = builder.heap_dump_segment(&segment).build();
+
+        assert_eq!(
+            HprofParseError::MissingHeapDumpEnd,
+            parse_hprof_strict(&bytes[..]).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn records_nth_matches_sequential_iteration() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000);
+        builder
+            .utf8(1, "first")
+            .utf8(2, "second")
+            .load_class(1, 100, 0, 1)
+            .heap_dump_segment(&segment)
+            .utf8(3, "last")
+            .heap_dump_end();
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let all = hprof.records_iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(6, all.len());
+
+        for (k, record) in all.iter().enumerate() {
+            let nth = hprof.records_iter().nth(k).unwrap().unwrap();
+
+            assert_eq!(record.tag(), nth.tag());
+            assert_eq!(record.body, nth.body);
+        }
+
+        assert!(hprof.records_iter().nth(6).is_none());
+        assert!(hprof.records_iter().nth(100).is_none());
+
+        // iteration carries on normally after nth
+        let mut records = hprof.records_iter();
+        records.nth(1).unwrap().unwrap();
+        assert_eq!(RecordTag::LoadClass, records.next().unwrap().unwrap().tag());
+    }
+
+    #[test]
+    fn records_nth_does_not_parse_skipped_records() {
+        let mut builder = HprofBuilder::new();
+        builder
+            // parsing this would panic on the unknown tag
+            .record(0x99, 0, b"unknown")
+            .utf8(1, "after");
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let record = hprof.records_iter().nth(1).unwrap().unwrap();
+        assert_eq!(
+            "after",
+            record.as_utf_8().unwrap().unwrap().text_as_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn id_from_decimal_and_hex() {
+        assert_eq!(Id::from(1234), "1234".parse().unwrap());
+        assert_eq!(
+            Id::from(0x7f00_1234_abcd),
+            "0x7f001234abcd".parse().unwrap()
+        );
+        assert_eq!(
+            Id::from(0x7f00_1234_abcd),
+            "0X7F001234ABCD".parse().unwrap()
+        );
+        assert!("7f".parse::<Id>().is_err());
+        assert!("0x".parse::<Id>().is_err());
+
+        assert_eq!(Id::from(0x7f), Id::from_hex_str("7f").unwrap());
+        assert_eq!(Id::from(0x7f), Id::from_hex_str("0x7f").unwrap());
+        assert!(Id::from_hex_str("0xnope").is_err());
+    }
+
+    #[test]
+    fn serial_from_decimal_and_hex() {
+        assert_eq!(Serial::from(1234), "1234".parse().unwrap());
+        assert_eq!(Serial::from(0x1a2b), "0x1a2b".parse().unwrap());
+        assert!("1a2b".parse::<Serial>().is_err());
+        // too big for u32
+        assert!("0x100000000".parse::<Serial>().is_err());
+
+        assert_eq!(Serial::from(0x1a2b), Serial::from_hex_str("1a2b").unwrap());
+        assert_eq!(
+            Serial::from(0x1a2b),
+            Serial::from_hex_str("0x1a2b").unwrap()
+        );
+    }
+
+    #[test]
+    fn reference_sources_skip_primitive_arrays() {
+        let with_obj_static = TestClass {
+            static_fields: vec![(1, Val::Obj(0))],
+            ..TestClass::new(100, 0)
+        };
+        let with_int_static = TestClass {
+            static_fields: vec![(2, Val::Int(3))],
+            ..TestClass::new(101, 0)
+        };
+        let with_protection_domain = TestClass {
+            protection_domain_obj_id: 4000,
+            ..TestClass::new(102, 0)
+        };
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_jni_global(1000, 1)
+            // no thread obj
+            .gc_root_thread_obj(0, 1, 0)
+            .class(&with_obj_static)
+            .class(&with_int_static)
+            .class(&with_protection_domain)
+            .int_array(3000, &[1, 2, 3])
+            .instance(1000, 101, &[])
+            .byte_array(3001, &[4])
+            .object_array(2000, 100, &[1000]);
+        let bytes = builder.heap_dump_segment(&segment).build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let segment = hprof
+            .records_iter()
+            .next()
+            .unwrap()
+            .unwrap()
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap();
+        let sources = segment
+            .sub_records()
+            .reference_sources()
+            .map(|r| match r.unwrap() {
+                ReferenceSource::GcRoot { tag, obj_id } => format!("{:?} {}", tag, obj_id),
+                ReferenceSource::Class(c) => format!("Class {}", c.obj_id()),
+                ReferenceSource::Instance(i) => format!("Instance {}", i.obj_id()),
+                ReferenceSource::ObjectArray(oa) => format!("ObjectArray {}", oa.obj_id()),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "GcRootJniGlobal 1000",
+                "Class 100",
+                "Class 102",
+                "Instance 1000",
+                "ObjectArray 2000"
+            ],
+            sources
+        );
+    }
+
+    #[test]
+    fn reference_source_referenced_ids() {
+        let class = TestClass {
+            class_loader_obj_id: 50,
+            static_fields: vec![(1, Val::Obj(1000)), (2, Val::Int(3)), (3, Val::Obj(0))],
+            instance_fields: vec![(4, TYPE_OBJ), (5, TYPE_INT), (6, TYPE_OBJ)],
+            ..TestClass::new(100, 99)
+        };
+
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_jni_global(1000, 1)
+            .class(&class)
+            .instance(1000, 100, &[Val::Obj(2000), Val::Int(7), Val::Obj(0)])
+            .object_array(2000, 100, &[1000, 0, 1001])
+            .int_array(3000, &[1, 2, 3]);
+        let bytes = builder.heap_dump_segment(&segment).build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let id_size = hprof.header().id_size();
+
+        let segment = hprof
+            .records_iter()
+            .next()
+            .unwrap()
+            .unwrap()
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap();
+        let sources = segment
+            .sub_records()
+            .reference_sources()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+
+        let field_descriptors = match &sources[1] {
+            ReferenceSource::Class(c) => c
+                .instance_field_descriptors()
+                .map(|r| r.unwrap())
+                .collect::<Vec<_>>(),
+            _ => panic!("Expected class"),
+        };
+
+        let referenced_ids = sources
+            .iter()
+            .map(|s| {
+                s.referenced_ids(id_size, &field_descriptors)
+                    .map(|r| r.unwrap().id())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // the superclass and class loader aren't references from the class, and the int[] isn't
+        // a source at all
+        assert_eq!(
+            vec![vec![1000], vec![1000], vec![2000], vec![1000, 1001]],
+            referenced_ids
+        );
+    }
+
+    #[test]
+    fn handlers_for_custom_tags() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_unknown(1000)
+            // custom sub record: tag, then a u32
+            .raw(&[0xAB, 0, 0, 0, 42])
+            .gc_root_unknown(2000);
+        let bytes = builder
+            .utf8(1, "before")
+            .record(0x99, 0, b"custom")
+            .heap_dump_segment(&segment)
+            .utf8(2, "after")
+            .heap_dump_end()
+            .build();
+
+        let custom_records = sync::Mutex::new(Vec::new());
+        let custom_sub_records = sync::Mutex::new(Vec::new());
+        let mut handlers = Handlers::new();
+        handlers
+            .record(0x99, |body, _id_size| {
+                custom_records.lock().unwrap().push(body.to_vec())
+            })
+            .sub_record(0xAB, |input, _id_size| {
+                let (input, num) = number::be_u32(input)?;
+                custom_sub_records.lock().unwrap().push(num);
+                Ok((input, ()))
+            });
+
+        let hprof = parse_hprof_with_handlers(&bytes[..], &handlers).unwrap();
+
+        let tags = hprof
+            .records_iter()
+            .map(|r| r.unwrap().tag())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                RecordTag::Utf8,
+                RecordTag::HeapDumpSegment,
+                RecordTag::Utf8,
+                RecordTag::HeapDumpEnd
+            ],
+            tags
+        );
+
+        let segment = hprof
+            .records_iter()
+            .nth(1)
+            .unwrap()
+            .unwrap()
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap();
+        let offsets = segment
+            .sub_records_with_offsets()
+            .map(|r| {
+                let (offset, sub_record) = r.unwrap();
+                match sub_record {
+                    SubRecord::GcRootUnknown(gc_root) => (offset, gc_root.obj_id()),
+                    _ => panic!("Unexpected sub record {:?}", sub_record),
+                }
+            })
+            .collect::<Vec<_>>();
+        // tag + id, then the custom sub record
+        assert_eq!(vec![(0, Id::from(1000)), (9 + 5, Id::from(2000))], offsets);
+
+        // handlers are called each time iteration passes the record, once for each of the
+        // `records_iter()` calls above
+        assert_eq!(
+            vec![b"custom".to_vec(), b"custom".to_vec()],
+            *custom_records.lock().unwrap()
+        );
+        assert_eq!(vec![42], *custom_sub_records.lock().unwrap());
+    }
+
+    #[test]
+    fn record_parse_at_offset() {
+        let bytes = HprofBuilder::with_id_size(4)
+            .utf8(1, "first")
+            .utf8(2, "second")
+            .load_class(1, 100, 0, 2)
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let header_len = bytes.len() - hprof.records.len();
+        // tag, micros, length, then 4 byte id and text
+        let second_offset = header_len + 9 + 4 + "first".len();
+
+        let record = Record::parse_at(&bytes[second_offset..], IdSize::U32).unwrap();
+        assert_eq!(RecordTag::Utf8, record.tag());
+        let utf8 = record.as_utf_8().unwrap().unwrap();
+        assert_eq!(Id::from(2), utf8.name_id());
+        assert_eq!("second", utf8.text_as_str().unwrap());
+
+        // not a record boundary: the first byte of the header label
+        assert!(Record::parse_at(&bytes[..], IdSize::U32).is_err());
+        // length prefix runs past the end of the buffer
+        assert!(Record::parse_at(&bytes[second_offset..second_offset + 10], IdSize::U32).is_err());
+        assert!(Record::parse_at(&[], IdSize::U32).is_err());
+    }
+
+    #[test]
+    fn record_raw_body() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "hello")
+            // heap summary, which has no as_* method
+            .record(0x07, 0, &[1, 2, 3, 4]);
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let records = hprof.records_iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(
+            &[&[0, 0, 0, 0, 0, 0, 0, 1][..], b"hello"].concat()[..],
+            records[0].raw_body()
+        );
+        assert_eq!(RecordTag::HeapSummary, records[1].tag());
+        assert_eq!(&[1, 2, 3, 4], records[1].raw_body());
+    }
+
+    #[test]
+    fn record_body_len() {
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "hello");
+        let mut segment = builder.segment();
+        segment.int_array(1000, &[1, 2, 3]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        assert_eq!(
+            // 8 byte id, then the text; the segment's length is its sub records; end is empty
+            vec![8 + 5, segment.body().len(), 0],
+            hprof
+                .records_iter()
+                .map(|r| r.unwrap().body_len())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn truncated_segment_yields_records_then_one_error() {
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "hello").utf8(2, "world");
+        let mut segment = builder.segment();
+        segment
+            .instance(1000, 100, &[])
+            .instance(1001, 100, &[])
+            .int_array(2000, &[1, 2, 3]);
+        let mut bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        // drop the end record and the last 4 bytes of the int array
+        bytes.truncate(bytes.len() - 9 - 4);
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut records = hprof.records_iter();
+        assert_eq!(RecordTag::Utf8, records.next().unwrap().unwrap().tag());
+        assert_eq!(RecordTag::Utf8, records.next().unwrap().unwrap().tag());
+        let truncated = records.next().unwrap().unwrap();
+        assert_eq!(RecordTag::HeapDumpSegment, truncated.tag());
+        assert_eq!(segment.body().len() - 4, truncated.body_len());
+        // the same error as parse_hprof_strict reports
+        let expected = parse_hprof_strict(&bytes[..]).err().unwrap();
+        assert!(matches!(expected, HprofParseError::TruncatedRecord { .. }));
+        assert_eq!(
+            Some(Err(expected.clone())),
+            records.next().map(|r| r.map(|r| r.tag()))
+        );
+        assert!(records.next().is_none());
+
+        let mut filtered = hprof.records_iter_filtered(&[RecordTag::HeapDumpSegment]);
+        assert_eq!(
+            RecordTag::HeapDumpSegment,
+            filtered.next().unwrap().unwrap().tag()
+        );
+        assert_eq!(
+            Some(Err(expected)),
+            filtered.next().map(|r| r.map(|r| r.tag()))
+        );
+
+        let mut sub_records = truncated
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap()
+            .sub_records();
+        for obj_id in &[1000, 1001] {
+            match sub_records.next().unwrap().unwrap() {
+                heap_dump::SubRecord::Instance(i) => assert_eq!(Id::from(*obj_id), i.obj_id()),
+                _ => panic!("not an instance"),
+            }
+        }
+        assert!(sub_records.next().unwrap().is_err());
+        assert!(sub_records.next().is_none());
+    }
+
+    #[test]
+    fn records_iter_filtered_yields_only_allowed_tags() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000);
+        let bytes = builder
+            .utf8(1, "hello")
+            .load_class(1, 100, 0, 1)
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .record(0x99, 0, b"unknown tag")
+            .utf8(2, "world")
+            .heap_dump_segment(&segment)
+            .load_class(2, 101, 0, 2)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        assert_eq!(
+            vec![
+                RecordTag::Utf8,
+                RecordTag::LoadClass,
+                RecordTag::Utf8,
+                RecordTag::LoadClass
+            ],
+            hprof
+                .records_iter_filtered(&[RecordTag::Utf8, RecordTag::LoadClass])
+                .map(|r| r.unwrap().tag())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            1,
+            hprof
+                .records_iter_filtered(&[RecordTag::HeapDumpSegment])
+                .count()
+        );
+        assert_eq!(0, hprof.records_iter_filtered(&[]).count());
+    }
+
+    #[test]
+    fn records_iter_filtered_ends_on_truncation() {
+        let mut bytes = HprofBuilder::new()
+            .utf8(1, "hello")
+            .load_class(1, 100, 0, 1)
+            .build();
+        // cut into the load class body
+        bytes.truncate(bytes.len() - 2);
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let results = hprof
+            .records_iter_filtered(&[RecordTag::Utf8])
+            .collect::<Vec<_>>();
+        assert_eq!(2, results.len());
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn scan_record_headers_matches_full_iteration() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment.gc_root_unknown(1000).int_array(2000, &[1, 2, 3]);
+        let bytes = builder
+            .utf8(1, "hello")
+            .record(0x01, 1234, &[0, 0, 0, 0, 0, 0, 0, 2])
+            .load_class(1, 100, 0, 1)
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .heap_dump_segment(&segment)
+            .heap_dump_segment(&segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let expected = hprof
+            .records_iter()
+            .map(|r| {
+                let r = r.unwrap();
+                (r.tag(), r.micros_since_header_ts(), r.body.len())
+            })
+            .collect::<Vec<_>>();
+        let scanned = hprof.scan_record_headers().collect::<Vec<_>>();
+        assert_eq!(
+            expected,
+            scanned
+                .iter()
+                .map(|&(tag, micros, len, _offset)| (tag, micros, len))
+                .collect::<Vec<_>>()
+        );
+
+        for (r, &(tag, _micros, len, offset)) in hprof.records_iter().zip(scanned.iter()) {
+            assert_eq!(tag.tag_byte(), bytes[offset]);
+            assert_eq!(r.unwrap().body, &bytes[offset + 9..offset + 9 + len]);
+        }
+        let &(_, _, len, offset) = scanned.last().unwrap();
+        assert_eq!(bytes.len(), offset + 9 + len);
+    }
+
+    #[test]
+    fn scan_record_headers_skips_unknown_tags_and_stops_at_truncation() {
+        let mut bytes = HprofBuilder::new()
+            .utf8(1, "hello")
+            .record(0x99, 0, b"unknown tag")
+            .utf8(2, "world")
+            .load_class(1, 100, 0, 1)
+            .build();
+        // cut into the load class body
+        bytes.truncate(bytes.len() - 2);
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let tags = hprof
+            .scan_record_headers()
+            .map(|(tag, _micros, _len, _offset)| tag)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![RecordTag::Utf8, RecordTag::Utf8], tags);
+
+        let mut scanner = hprof.scan_record_headers();
+        assert_eq!(2, scanner.by_ref().count());
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn truncated_header_yields_one_error() {
+        let mut bytes = HprofBuilder::new().utf8(1, "hello").build();
+        bytes.push(RecordTag::Utf8.tag_byte());
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let results = hprof.records_iter().collect::<Vec<_>>();
+        assert_eq!(2, results.len());
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn total_heap_dump_bytes_sums_segments() {
+        let mut builder = HprofBuilder::new();
+        let mut first_segment = builder.segment();
+        first_segment.int_array(1000, &[1, 2, 3]);
+        let mut second_segment = builder.segment();
+        second_segment
+            .instance(2000, 100, &[])
+            .gc_root_unknown(2000);
+        let bytes = builder
+            .utf8(1, "not a segment")
+            .heap_dump_segment(&first_segment)
+            .heap_dump_segment(&second_segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut segment_lens = Vec::new();
+        for r in hprof.records_iter() {
+            if let Some(segment) = r.unwrap().as_heap_dump_segment() {
+                segment_lens.push(segment.unwrap().body_len());
+            }
+        }
+        assert_eq!(
+            vec![first_segment.body().len(), second_segment.body().len()],
+            segment_lens
+        );
+        assert_eq!(
+            (first_segment.body().len() + second_segment.body().len()) as u64,
+            hprof.total_heap_dump_bytes().unwrap()
+        );
+
+        // drop the end record and the last byte of the second segment
+        let truncated = parse_hprof(&bytes[..bytes.len() - 9 - 1]).unwrap();
+        assert!(matches!(
+            truncated.total_heap_dump_bytes(),
+            Err(HprofParseError::TruncatedRecord { .. })
+        ));
+    }
+
+    #[test]
+    fn empty_and_single_sub_record_segments() {
+        let mut builder = HprofBuilder::new();
+        let empty_segment = builder.segment();
+        let mut single_segment = builder.segment();
+        single_segment.gc_root_unknown(1000);
+        let bytes = builder
+            .heap_dump(&empty_segment)
+            .heap_dump_segment(&empty_segment)
+            .heap_dump_segment(&single_segment)
+            .heap_dump_segment(&empty_segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut segments = Vec::new();
+        for r in hprof.records_iter() {
+            if let Some(segment) = r.unwrap().as_heap_dump_segment() {
+                segments.push(segment.unwrap());
+            }
+        }
+        assert_eq!(4, segments.len());
+
+        for &i in &[0, 1, 3] {
+            assert_eq!(0, segments[i].body_len());
+            let mut sub_records = segments[i].sub_records();
+            assert!(sub_records.next().is_none());
+            // and stays that way
+            assert!(sub_records.next().is_none());
+            assert!(segments[i].sub_records_with_offsets().next().is_none());
+        }
+
+        let obj_ids = segments[2]
+            .sub_records()
+            .map(|r| match r.unwrap() {
+                SubRecord::GcRootUnknown(gc_root) => gc_root.obj_id(),
+                s => panic!("Unexpected sub record {:?}", s),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Id::from(1000)], obj_ids);
+
+        let mut with_offsets = segments[2].sub_records_with_offsets();
+        assert_eq!(0, with_offsets.next().unwrap().unwrap().0);
+        assert!(with_offsets.next().is_none());
+
+        assert_eq!(
+            single_segment.body().len() as u64,
+            hprof.total_heap_dump_bytes().unwrap()
+        );
+    }
+
+    #[test]
+    fn stack_traces_by_serial() {
+        let mut builder = HprofBuilder::new();
+        let bytes = builder
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .stack_frame(11, 1, 2, 3, 1, 6)
+            .stack_trace(1, 100, &[10, 11])
+            .stack_trace(2, 100, &[11])
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let traces = hprof.stack_traces_by_serial().unwrap();
+
+        assert_eq!(2, traces.len());
+        let trace = traces.get(&Serial::from(1)).unwrap();
+        assert_eq!(Serial::from(100), trace.thread_serial());
+        assert_eq!(
+            vec![Id::from(10), Id::from(11)],
+            trace.frame_ids().map(|r| r.unwrap()).collect::<Vec<_>>()
+        );
+        assert!(!traces.contains_key(&Serial::from(0)));
+    }
+
+    #[test]
+    fn stack_traces_with_class() {
+        let mut builder = HprofBuilder::new();
+        let bytes = builder
+            .stack_trace(1, 100, &[10, 11])
+            .stack_trace(2, 101, &[11])
+            .stack_trace(3, 102, &[12, 10])
+            .stack_frame(10, 1, 2, 3, 7, 5)
+            .stack_frame(11, 1, 2, 3, 1, 6)
+            .stack_frame(12, 1, 2, 3, 1, 6)
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        assert_eq!(
+            vec![Serial::from(1), Serial::from(3)],
+            hprof
+                .stack_traces_with_class(Serial::from(7))
+                .unwrap()
+                .iter()
+                .map(|t| t.stack_trace_serial())
+                .collect::<Vec<_>>()
+        );
+        assert!(hprof
+            .stack_traces_with_class(Serial::from(8))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn stack_trace_resolved_frames() {
+        let mut builder = HprofBuilder::new();
+        let bytes = builder
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .stack_frame(11, 4, 2, 3, 1, 6)
+            .stack_trace(1, 100, &[10, 11])
+            // no frame record for 12
+            .stack_trace(2, 100, &[12, 10])
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let frames = hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .filter(|r| r.tag() == RecordTag::StackFrame)
+            .map(|r| r.as_stack_frame().unwrap().unwrap())
+            .map(|f| (f.id(), f))
+            .collect::<collections::HashMap<_, _>>();
+        let traces = hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .filter(|r| r.tag() == RecordTag::StackTrace)
+            .map(|r| r.as_stack_trace().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        let resolved = traces[0].resolved_frames(&frames).collect::<Vec<_>>();
+        assert_eq!(2, resolved.len());
+        assert_eq!(Id::from(10), resolved[0].unwrap().id());
+        assert_eq!(LineNum::Normal(5), resolved[0].unwrap().line_num());
+        assert_eq!(Id::from(11), resolved[1].unwrap().id());
+        assert_eq!(Id::from(4), resolved[1].unwrap().method_name_id());
+
+        assert_eq!(
+            vec![None, Some(Id::from(10))],
+            traces[1]
+                .resolved_frames(&frames)
+                .map(|f| f.map(|f| f.id()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn thread_names_join_thread_roots() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_thread_obj(1000, 1, 0)
+            .gc_root_thread_obj(1001, 2, 0);
+        let bytes = builder
+            .start_thread(1, 1000, 0, 10)
+            // name comes after the thread
+            .start_thread(2, 1001, 0, 11)
+            .utf8(10, "main")
+            .utf8(11, "Finalizer")
+            // no name record
+            .start_thread(3, 1002, 0, 12)
+            .heap_dump_segment(&segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let names = hprof.thread_names().unwrap();
+        assert_eq!(2, names.len());
+
+        let mut root_names = Vec::new();
+        for r in hprof.records_iter().map(|r| r.unwrap()) {
+            if let Some(segment) = r.as_heap_dump_segment() {
+                for s in segment.unwrap().sub_records() {
+                    if let SubRecord::GcRootThreadObj(t) = s.unwrap() {
+                        let name = names.get(&t.thread_serial()).unwrap();
+                        root_names.push(name.text_as_str().unwrap());
+                    }
+                }
+            }
+        }
+        assert_eq!(vec!["main", "Finalizer"], root_names);
+
+        let start_thread = hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .find_map(|r| r.as_start_thread())
+            .unwrap()
+            .unwrap();
+        assert_eq!(Serial::from(1), start_thread.thread_serial());
+        assert_eq!(Id::from(1000), start_thread.thread_obj_id());
+        assert_eq!(Id::from(10), start_thread.thread_name_id());
+    }
+
+    #[test]
+    fn instances_with_fields_include_superclass_fields() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            // instance comes before its classes
+            .instance(1000, 101, &[Val::Int(7), Val::Obj(1001), Val::Long(-3)])
+            .class(&TestClass {
+                instance_fields: vec![(10, TYPE_INT), (11, TYPE_OBJ)],
+                ..TestClass::new(101, 100)
+            })
+            .class(&TestClass {
+                instance_fields: vec![(12, TYPE_LONG)],
+                ..TestClass::new(100, 0)
+            })
+            .instance(1001, 100, &[Val::Long(5)])
+            // unknown class, so no layout to decode with
+            .instance(1002, 999, &[Val::Int(1)])
+            .instance(1003, 999, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let instances = hprof.instances_with_fields().collect::<Vec<_>>();
+        assert_eq!(4, instances.len());
+
+        let (instance, fields) = instances[0].as_ref().unwrap();
+        assert_eq!(Id::from(1000), instance.obj_id());
+        assert_eq!(
+            vec![Id::from(10), Id::from(11), Id::from(12)],
+            fields
+                .iter()
+                .map(|(name_id, _)| *name_id)
+                .collect::<Vec<_>>()
+        );
+        assert!(matches!(fields[0].1, heap_dump::FieldValue::Int(7)));
+        assert!(matches!(
+            fields[1].1,
+            heap_dump::FieldValue::ObjectId(Some(id)) if id == Id::from(1001)
+        ));
+        assert!(matches!(fields[2].1, heap_dump::FieldValue::Long(-3)));
+
+        let (instance, fields) = instances[1].as_ref().unwrap();
+        assert_eq!(Id::from(1001), instance.obj_id());
+        assert_eq!(1, fields.len());
+        assert!(matches!(fields[0].1, heap_dump::FieldValue::Long(5)));
+
+        assert!(matches!(
+            instances[2],
+            Err(nom::Err::Error((_, nom::error::ErrorKind::LengthValue)))
+        ));
+
+        let (instance, fields) = instances[3].as_ref().unwrap();
+        assert_eq!(Id::from(1003), instance.obj_id());
+        assert!(fields.is_empty());
+    }
+
+    #[test]
+    fn gc_root_stack_frames() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .gc_root_java_stack_frame(1000, 100, 1)
+            .gc_root_jni_local_ref(1001, 100, 0)
+            // no frame
+            .gc_root_java_stack_frame(1002, 100, u32::MAX)
+            // past the end of the stack
+            .gc_root_java_stack_frame(1003, 100, 2)
+            // unknown thread
+            .gc_root_java_stack_frame(1004, 200, 0);
+        let bytes = builder
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .stack_frame(11, 4, 2, 3, 1, 6)
+            // innermost frame first
+            .stack_trace(1, 100, &[10, 11])
+            .heap_dump_segment(&segment)
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let thread_frames = hprof.thread_frames().unwrap();
+
+        let segment = hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .find(|r| r.tag() == RecordTag::HeapDumpSegment)
+            .unwrap()
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap();
+        let frame_ids = segment
+            .sub_records()
+            .map(|r| match r.unwrap() {
+                SubRecord::GcRootJavaStackFrame(root) => root.stack_frame(&thread_frames),
+                SubRecord::GcRootJniLocalRef(root) => root.stack_frame(&thread_frames),
+                _ => panic!("Unexpected sub record"),
+            })
+            .map(|f| f.map(|f| f.id()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![Some(Id::from(11)), Some(Id::from(10)), None, None, None],
+            frame_ids
+        );
+        assert_eq!(
+            Id::from(4),
+            thread_frames
+                .frame(Serial::from(100), Some(1))
+                .unwrap()
+                .method_name_id()
+        );
+    }
+
+    #[test]
+    fn thread_frames_keeps_every_trace_of_a_thread() {
+        let mut builder = HprofBuilder::new();
+        let bytes = builder
+            .stack_frame(10, 1, 2, 3, 1, 5)
+            .stack_frame(11, 4, 2, 3, 1, 6)
+            .stack_trace(1, 100, &[10])
+            .stack_trace(2, 100, &[11, 10])
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let thread_frames = hprof.thread_frames().unwrap();
+
+        assert_eq!(
+            &[Serial::from(1), Serial::from(2)],
+            thread_frames.stack_trace_serials(Serial::from(100))
+        );
+        assert!(thread_frames
+            .stack_trace_serials(Serial::from(200))
+            .is_empty());
+        // the first trace, rather than whichever came last
+        assert_eq!(
+            Some(Id::from(10)),
+            thread_frames
+                .frame(Serial::from(100), Some(0))
+                .map(|f| f.id())
+        );
+        assert_eq!(None, thread_frames.frame(Serial::from(100), Some(1)));
+        assert_eq!(
+            Some(Id::from(11)),
+            thread_frames
+                .frame_in_trace(Serial::from(2), Some(0))
+                .map(|f| f.id())
+        );
+        assert_eq!(
+            Some(Id::from(10)),
+            thread_frames
+                .frame_in_trace(Serial::from(2), Some(1))
+                .map(|f| f.id())
+        );
+    }
+
+    #[test]
+    fn utf8_text_lossy() {
+        let mut builder = HprofBuilder::new();
+        // a stray continuation byte
+        let bytes = builder
+            .utf8(1, "fine")
+            .utf8_bytes(2, b"not \x80 fine")
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let utf8 = hprof
+            .records_iter()
+            .map(|r| r.unwrap().as_utf_8().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!("fine", utf8[0].text_lossy());
+        assert!(matches!(utf8[0].text_lossy(), borrow::Cow::Borrowed(_)));
+
+        assert!(utf8[1].text_as_str().is_err());
+        assert_eq!("not \u{FFFD} fine", utf8[1].text_lossy());
+    }
+
+    #[test]
+    fn record_absolute_timestamp() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .timestamp_millis(1_600_000_000_123)
+            // UTF8 record with a 2.5s offset
+            .record(0x01, 2_500_000, &[0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let record = hprof.records_iter().next().unwrap().unwrap();
+
+        assert_eq!(
+            1_600_000_002_623_000,
+            record.absolute_timestamp_micros(&hprof.header())
+        );
+
+        #[cfg(feature = "chrono")]
+        assert_eq!(
+            "2020-09-13T12:26:42.623Z",
+            record
+                .absolute_timestamp(&hprof.header())
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+        );
+    }
+
+    #[test]
+    fn stack_frame_without_source_file() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .stack_frame(1, 10, 11, 12, 1, 5)
+            .stack_frame(2, 10, 11, 0, 1, 5);
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let frames = hprof
+            .records_iter()
+            .map(|r| r.unwrap().as_stack_frame().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(Some(Id::from(12)), frames[0].source_file_name_id());
+        assert_eq!(None, frames[1].source_file_name_id());
+    }
+
+    #[test]
+    fn stack_frames_compare_by_id() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .stack_frame(1, 10, 11, 12, 1, 5)
+            // same frame id, different contents
+            .stack_frame(1, 20, 21, 22, 2, -1)
+            .stack_frame(2, 10, 11, 12, 1, 5);
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let frames = hprof
+            .records_iter()
+            .map(|r| r.unwrap().as_stack_frame().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(frames[0], frames[1]);
+        assert_ne!(frames[0], frames[2]);
+        assert!(frames[0] < frames[2]);
+
+        let deduped = frames
+            .iter()
+            .cloned()
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(2, deduped.len());
+
+        let mut sorted = [frames[2].clone(), frames[0].clone()];
+        sorted.sort();
+        assert_eq!(
+            vec![Id::from(1), Id::from(2)],
+            sorted.iter().map(|f| f.id()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn load_classes_compare_all_fields() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .load_class(1, 100, 0, 10)
+            .load_class(1, 100, 0, 10)
+            // reloaded with a new serial
+            .load_class(2, 100, 0, 10);
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let load_classes = hprof
+            .records_iter()
+            .map(|r| r.unwrap().as_load_class().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(load_classes[0], load_classes[1]);
+        assert_ne!(load_classes[0], load_classes[2]);
+        assert_eq!(
+            2,
+            load_classes
+                .iter()
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+        );
+    }
+
+    #[test]
+    fn sub_record_at_offsets_from_iteration() {
+        let mut builder = HprofBuilder::new();
+
+        let mut first_segment = builder.segment();
+        first_segment
+            .gc_root_system_class(100)
+            .class(&TestClass {
+                instance_fields: vec![(1, TYPE_INT)],
+                ..TestClass::new(100, 0)
+            })
+            .instance(1000, 100, &[Val::Int(7)]);
+
+        let mut second_segment = builder.segment();
+        second_segment
+            .object_array(2000, 100, &[1000, 0])
+            .int_array(3000, &[1, 2, 3])
+            .instance(1001, 100, &[Val::Int(8)]);
+
+        builder
+            .utf8(1, "field")
+            .heap_dump_segment(&first_segment)
+            .heap_dump_segment(&second_segment)
+            .heap_dump_end();
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut locations = Vec::new();
+        for (record_index, record) in hprof.records_iter().map(|r| r.unwrap()).enumerate() {
+            if let Some(segment) = record.as_heap_dump_segment() {
+                for p in segment.unwrap().sub_records_with_offsets() {
+                    let (offset, sub_record) = p.unwrap();
+                    locations.push((record_index, offset, sub_record.tag()));
+                }
+            }
+        }
+
+        assert_eq!(6, locations.len());
+        assert_eq!((1, 0), (locations[0].0, locations[0].1));
+        assert_eq!((2, 0), (locations[3].0, locations[3].1));
+
+        for &(record_index, offset, tag) in locations.iter() {
+            assert_eq!(
+                tag,
+                hprof.sub_record_at(record_index, offset).unwrap().tag()
+            );
+        }
+
+        match hprof.sub_record_at(locations[5].0, locations[5].1).unwrap() {
+            SubRecord::Instance(instance) => {
+                assert_eq!(1001, instance.obj_id().id());
+                assert_eq!(&[0, 0, 0, 8], instance.fields());
+            }
+            _ => panic!("Should be an instance"),
+        }
+
+        // not a heap dump segment
+        assert!(hprof.sub_record_at(0, 0).is_err());
+        // past the last record
+        assert!(hprof.sub_record_at(10, 0).is_err());
+        // past the end of the segment
+        assert!(hprof.sub_record_at(1, 1_000).is_err());
+        // inside the GC root's id, which doesn't start with a sub record tag
+        assert!(hprof.sub_record_at(1, 1).is_err());
+    }
+
+    #[test]
+    fn stack_trace_serial_zero_is_none() {
+        let mut builder = HprofBuilder::new();
+        builder.load_class(1, 100, 0, 1).load_class(2, 101, 7, 2);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass {
+                stack_trace_serial: 7,
+                ..TestClass::new(101, 0)
+            })
+            .instance(1000, 100, &[])
+            .instance_with_stack_trace(1001, 7, 100, &[])
+            .object_array(2000, 101, &[])
+            .int_array(3000, &[]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let load_class_serials = hprof
+            .records_iter_filtered(&[RecordTag::LoadClass])
+            .map(|r| r.unwrap().as_load_class().unwrap().unwrap())
+            .map(|lc| lc.stack_trace_serial())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![None, Some(Serial::from(7))], load_class_serials);
+
+        let segment_record = hprof
+            .records_iter_filtered(&[RecordTag::HeapDumpSegment])
+            .next()
+            .unwrap()
+            .unwrap();
+        let sub_record_serials = segment_record
+            .as_heap_dump_segment()
+            .unwrap()
+            .unwrap()
+            .sub_records()
+            .map(|s| match s.unwrap() {
+                SubRecord::Class(c) => c.stack_trace_serial(),
+                SubRecord::Instance(i) => i.stack_trace_serial(),
+                SubRecord::ObjectArray(oa) => oa.stack_trace_serial(),
+                SubRecord::PrimitiveArray(pa) => pa.stack_trace_serial(),
+                other => panic!("Unexpected sub record {:?}", other.tag()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                None,
+                Some(Serial::from(7)),
+                None,
+                Some(Serial::from(7)),
+                None,
+                None
+            ],
+            sub_record_serials
+        );
+    }
+}
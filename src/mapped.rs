@@ -0,0 +1,110 @@
+use crate::*;
+use std::{fs, io, path};
+
+/// An hprof file that has been memory mapped, which is the usual way to parse large heap dumps.
+///
+/// Since [Hprof] borrows the bytes it parses, this owns the memory map so that the map and the
+/// [Hprof] don't have to be kept alive separately.
+///
+/// Available with the `mmap` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jvm_hprof::MappedHprof;
+///
+/// let mapped = MappedHprof::open("path/to/heap.hprof").unwrap();
+/// let hprof = mapped.hprof();
+///
+/// println!("{} records", hprof.records_iter().count());
+/// ```
+pub struct MappedHprof {
+    mmap: memmap::Mmap,
+}
+
+impl MappedHprof {
+    /// Open and memory map the file at `path`, making sure the header can be parsed.
+    ///
+    /// As with any memory mapped file, the file must not be modified while it is mapped.
+    pub fn open<P: AsRef<path::Path>>(path: P) -> io::Result<MappedHprof> {
+        let file = fs::File::open(path)?;
+        // Safety: the usual mmap caveat (see above) is documented for callers
+        let mmap = unsafe { memmap::MmapOptions::new().map(&file) }?;
+
+        // fail early rather than on every call to hprof()
+        Header::parse(&mmap[..]).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                HprofParseError::from_header_error(e),
+            )
+        })?;
+
+        Ok(MappedHprof { mmap })
+    }
+
+    /// The [Hprof] for the mapped file.
+    pub fn hprof(&self) -> Hprof<'_> {
+        parse_hprof(&self.mmap[..]).expect("Header was already parsed when opened")
+    }
+
+    /// The [Hprof] for the mapped file, using `handlers` for unknown tags as in
+    /// [parse_hprof_with_handlers].
+    pub fn hprof_with_handlers<'a>(&'a self, handlers: &'a Handlers<'a>) -> Hprof<'a> {
+        parse_hprof_with_handlers(&self.mmap[..], handlers)
+            .expect("Header was already parsed when opened")
+    }
+}
+
+impl AsRef<[u8]> for MappedHprof {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::io::Write;
+
+    #[test]
+    fn open_temp_file() -> Result<(), io::Error> {
+        let bytes = HprofBuilder::new()
+            .timestamp_millis(1234)
+            .utf8(1, "hello")
+            .utf8(2, "world")
+            .build();
+
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&bytes)?;
+        file.flush()?;
+
+        let mapped = MappedHprof::open(file.path())?;
+        let hprof = mapped.hprof();
+
+        assert_eq!(1234, hprof.header().timestamp_millis());
+        assert_eq!(
+            vec!["hello", "world"],
+            hprof
+                .records_iter()
+                .map(|r| r.unwrap().as_utf_8().unwrap().unwrap())
+                .map(|u| u.text_as_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_not_an_hprof() -> Result<(), io::Error> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(b"no nul")?;
+        file.flush()?;
+
+        let err = MappedHprof::open(file.path()).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().starts_with("Not an hprof file"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+use crate::heap_dump::*;
+use crate::*;
+use getset::Getters;
+
+/// An hprof that has been parsed all at once into plain owned data, so it can be queried without
+/// keeping the input buffer around or threading its lifetime through.
+///
+/// This is meant for test fixtures and small heap dumps. Every record is parsed and every
+/// instance's field values are decoded up front, and each object gets its own allocations and
+/// hash map entry, so the model takes up several times as much memory as the hprof itself. For
+/// anything bigger than a few tens of MiB, use [Hprof] with [HprofTables] or an on-disk index
+/// instead.
+///
+/// Only the parts of the heap dump that describe objects are kept: [Utf8] text, classes,
+/// instances, and arrays. GC roots, stack traces, and other records are skipped.
+#[derive(CopyGetters, Getters)]
+pub struct OwnedHprof {
+    #[get_copy = "pub"]
+    id_size: IdSize,
+    #[get_copy = "pub"]
+    timestamp_millis: u64,
+    /// [Utf8] text by name id, with invalid UTF-8 replaced by U+FFFD
+    #[get = "pub"]
+    utf8: collections::HashMap<Id, String>,
+    /// Classes by obj id
+    #[get = "pub"]
+    classes: collections::HashMap<Id, OwnedClass>,
+    /// Instances by obj id
+    #[get = "pub"]
+    instances: collections::HashMap<Id, OwnedInstance>,
+    /// Object arrays by obj id
+    #[get = "pub"]
+    object_arrays: collections::HashMap<Id, OwnedObjectArray>,
+    /// Primitive arrays by obj id
+    #[get = "pub"]
+    primitive_arrays: collections::HashMap<Id, OwnedPrimitiveArray>,
+}
+
+impl OwnedHprof {
+    /// Parse everything in `input`.
+    ///
+    /// The hprof is first checked with [parse_hprof_strict], so any of its errors may be
+    /// returned, as well as [HprofParseError::InvalidRecord] for a record whose contents can't be
+    /// parsed, including an instance whose field data doesn't match its class's fields. Instances
+    /// of classes that aren't in the heap dump have no fields.
+    pub fn load(input: &[u8]) -> Result<OwnedHprof, HprofParseError> {
+        let hprof = parse_hprof_strict(input)?;
+        let id_size = hprof.header().id_size();
+
+        let mut utf8 = collections::HashMap::new();
+        // class obj id -> class name id
+        let mut class_name_ids = collections::HashMap::new();
+        let mut classes = collections::HashMap::new();
+        let mut object_arrays = collections::HashMap::new();
+        let mut primitive_arrays = collections::HashMap::new();
+        // offsets of heap dump records, to go back to for their instances
+        let mut heap_dumps = Vec::new();
+
+        for ((tag, offset), record) in records_with_offsets(&hprof) {
+            let invalid = |_| HprofParseError::InvalidRecord { offset, tag };
+            let record = record?;
+
+            match tag {
+                RecordTag::Utf8 => {
+                    let u = record.as_utf_8().unwrap().map_err(invalid)?;
+                    utf8.insert(u.name_id(), String::from_utf8_lossy(u.text()).into_owned());
+                }
+                RecordTag::LoadClass => {
+                    let lc = record.as_load_class().unwrap().map_err(invalid)?;
+                    class_name_ids.insert(lc.class_obj_id(), lc.class_name_id());
+                }
+                RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                    heap_dumps.push((tag, offset));
+
+                    let segment = record.as_heap_dump_segment().unwrap().map_err(invalid)?;
+                    for s in segment.sub_records() {
+                        match s.map_err(invalid)? {
+                            SubRecord::Class(c) => {
+                                let class = OwnedClass::from_class(&c).map_err(invalid)?;
+                                classes.insert(class.obj_id, class);
+                            }
+                            SubRecord::ObjectArray(oa) => {
+                                let elements = oa
+                                    .elements(id_size)
+                                    .collect::<Result<Vec<_>, _>>()
+                                    // borrows `oa`, so it's a shorter lived error than `invalid` takes
+                                    .map_err(|_| HprofParseError::InvalidRecord { offset, tag })?;
+                                object_arrays.insert(
+                                    oa.obj_id(),
+                                    OwnedObjectArray {
+                                        obj_id: oa.obj_id(),
+                                        array_class_obj_id: oa.array_class_obj_id(),
+                                        elements,
+                                    },
+                                );
+                            }
+                            SubRecord::PrimitiveArray(pa) => {
+                                primitive_arrays.insert(
+                                    pa.obj_id(),
+                                    OwnedPrimitiveArray {
+                                        obj_id: pa.obj_id(),
+                                        values: PrimitiveArrayValues::from_array(&pa),
+                                    },
+                                );
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for class in classes.values_mut() {
+            class.name = class_name_ids
+                .get(&class.obj_id)
+                .and_then(|name_id| utf8.get(name_id))
+                .cloned();
+        }
+
+        // classes can come after their instances, so fields are decoded in a second pass
+        let layouts = field_layouts(
+            &classes
+                .values()
+                .map(|c| {
+                    (
+                        c.obj_id,
+                        (c.super_class_obj_id, c.instance_field_descriptors.clone()),
+                    )
+                })
+                .collect(),
+        );
+        let mut instances = collections::HashMap::new();
+
+        for (tag, offset) in heap_dumps {
+            let invalid = |_| HprofParseError::InvalidRecord { offset, tag };
+            let (_, record) = Record::parse(&input[offset..], id_size, None).map_err(invalid)?;
+            let segment = record.as_heap_dump_segment().unwrap().map_err(invalid)?;
+
+            for s in segment.sub_records() {
+                if let SubRecord::Instance(instance) = s.map_err(invalid)? {
+                    let fields = match layouts.get(&instance.class_obj_id()) {
+                        Some(layout) => instance
+                            .field_values(layout, id_size)
+                            .map(|r| r.map(|(fd, value)| (fd.name_id(), value)))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(invalid)?,
+                        None => Vec::new(),
+                    };
+
+                    instances.insert(
+                        instance.obj_id(),
+                        OwnedInstance {
+                            obj_id: instance.obj_id(),
+                            class_obj_id: instance.class_obj_id(),
+                            fields,
+                        },
+                    );
+                }
+            }
+        }
+
+        Ok(OwnedHprof {
+            id_size,
+            timestamp_millis: hprof.header().timestamp_millis(),
+            utf8,
+            classes,
+            instances,
+            object_arrays,
+            primitive_arrays,
+        })
+    }
+
+    /// The name of the class with obj id `class_obj_id`, as found via its [LoadClass].
+    pub fn class_name(&self, class_obj_id: Id) -> Option<&str> {
+        self.classes
+            .get(&class_obj_id)
+            .and_then(|c| c.name.as_deref())
+    }
+
+    /// The value of the instance field named `field_name` in the instance with obj id `obj_id`.
+    ///
+    /// If a subclass field hides a superclass field with the same name, the subclass's field is
+    /// used, as in Java.
+    pub fn field_value(&self, obj_id: Id, field_name: &str) -> Option<FieldValue> {
+        self.instances.get(&obj_id).and_then(|instance| {
+            instance
+                .fields
+                .iter()
+                .find(|(name_id, _)| self.utf8.get(name_id).map(|s| s.as_str()) == Some(field_name))
+                .map(|&(_, value)| value)
+        })
+    }
+}
+
+/// The tag and offset of each record, along with the parsed record.
+fn records_with_offsets<'a>(
+    hprof: &Hprof<'a>,
+) -> impl Iterator<Item = ((RecordTag, usize), Result<Record<'a>, HprofParseError>)> {
+    // after parse_hprof_strict, every record has a known tag and a complete body, so the headers
+    // line up one for one with the records
+    hprof
+        .scan_record_headers()
+        .map(|(tag, _micros, _len, offset)| (tag, offset))
+        .zip(hprof.records_iter())
+}
+
+/// A [Class] with its name, as part of an [OwnedHprof].
+#[derive(CopyGetters, Getters, Clone, Debug)]
+pub struct OwnedClass {
+    #[get_copy = "pub"]
+    obj_id: Id,
+    /// The name from the class's [LoadClass], if there is one
+    #[get = "pub"]
+    name: Option<String>,
+    #[get_copy = "pub"]
+    super_class_obj_id: Option<Id>,
+    #[get_copy = "pub"]
+    class_loader_obj_id: Option<Id>,
+    #[get_copy = "pub"]
+    instance_size_bytes: u32,
+    #[get = "pub"]
+    static_fields: Vec<StaticFieldEntry>,
+    /// Just the instance fields declared by this class, not including superclasses
+    #[get = "pub"]
+    instance_field_descriptors: Vec<FieldDescriptor>,
+}
+
+impl OwnedClass {
+    fn from_class<'a>(class: &Class<'a>) -> ParseResult<'a, OwnedClass> {
+        Ok(OwnedClass {
+            obj_id: class.obj_id(),
+            name: None,
+            super_class_obj_id: class.super_class_obj_id(),
+            class_loader_obj_id: class.class_loader_obj_id(),
+            instance_size_bytes: class.instance_size_bytes(),
+            static_fields: class.static_fields().collect::<Result<_, _>>()?,
+            instance_field_descriptors: class
+                .instance_field_descriptors()
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// An [Instance] with its decoded field values, as part of an [OwnedHprof].
+#[derive(CopyGetters, Getters, Clone, Debug)]
+pub struct OwnedInstance {
+    #[get_copy = "pub"]
+    obj_id: Id,
+    #[get_copy = "pub"]
+    class_obj_id: Id,
+    /// Field name id and value for each field, the instance's own class's fields first and the
+    /// root class's fields last
+    #[get = "pub"]
+    fields: Vec<(Id, FieldValue)>,
+}
+
+/// An [ObjectArray] with its elements, as part of an [OwnedHprof].
+#[derive(CopyGetters, Getters, Clone, Debug)]
+pub struct OwnedObjectArray {
+    #[get_copy = "pub"]
+    obj_id: Id,
+    #[get_copy = "pub"]
+    array_class_obj_id: Id,
+    /// `None` for null elements
+    #[get = "pub"]
+    elements: Vec<Option<Id>>,
+}
+
+/// A [PrimitiveArray] with its contents, as part of an [OwnedHprof].
+#[derive(CopyGetters, Getters, Clone, Debug)]
+pub struct OwnedPrimitiveArray {
+    #[get_copy = "pub"]
+    obj_id: Id,
+    #[get = "pub"]
+    values: PrimitiveArrayValues,
+}
+
+impl OwnedPrimitiveArray {
+    pub fn primitive_type(&self) -> PrimitiveArrayType {
+        match self.values {
+            PrimitiveArrayValues::Boolean(_) => PrimitiveArrayType::Boolean,
+            PrimitiveArrayValues::Char(_) => PrimitiveArrayType::Char,
+            PrimitiveArrayValues::Float(_) => PrimitiveArrayType::Float,
+            PrimitiveArrayValues::Double(_) => PrimitiveArrayType::Double,
+            PrimitiveArrayValues::Byte(_) => PrimitiveArrayType::Byte,
+            PrimitiveArrayValues::Short(_) => PrimitiveArrayType::Short,
+            PrimitiveArrayValues::Int(_) => PrimitiveArrayType::Int,
+            PrimitiveArrayValues::Long(_) => PrimitiveArrayType::Long,
+        }
+    }
+}
+
+/// The contents of an [OwnedPrimitiveArray].
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrimitiveArrayValues {
+    Boolean(Vec<bool>),
+    Char(Vec<u16>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    Byte(Vec<i8>),
+    Short(Vec<i16>),
+    Int(Vec<i32>),
+    Long(Vec<i64>),
+}
+
+impl PrimitiveArrayValues {
+    fn from_array(array: &PrimitiveArray) -> PrimitiveArrayValues {
+        // each `to_vec_*` is `Some` only for the matching type
+        match array.primitive_type() {
+            PrimitiveArrayType::Boolean => {
+                PrimitiveArrayValues::Boolean(array.to_vec_booleans().unwrap())
+            }
+            PrimitiveArrayType::Char => PrimitiveArrayValues::Char(array.to_vec_chars().unwrap()),
+            PrimitiveArrayType::Float => {
+                PrimitiveArrayValues::Float(array.to_vec_floats().unwrap())
+            }
+            PrimitiveArrayType::Double => {
+                PrimitiveArrayValues::Double(array.to_vec_doubles().unwrap())
+            }
+            PrimitiveArrayType::Byte => PrimitiveArrayValues::Byte(array.to_vec_bytes().unwrap()),
+            PrimitiveArrayType::Short => {
+                PrimitiveArrayValues::Short(array.to_vec_shorts().unwrap())
+            }
+            PrimitiveArrayType::Int => PrimitiveArrayValues::Int(array.to_vec_ints().unwrap()),
+            PrimitiveArrayType::Long => PrimitiveArrayValues::Long(array.to_vec_longs().unwrap()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn load_and_query_fields() {
+        // class Base { int id; }
+        let base_class = TestClass {
+            instance_fields: vec![(10, TYPE_INT)],
+            ..TestClass::new(100, 0)
+        };
+        // class Point extends Base { int x; Object label; }
+        let point_class = TestClass {
+            instance_fields: vec![(11, TYPE_INT), (12, TYPE_OBJ)],
+            ..TestClass::new(101, 100)
+        };
+
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Base")
+            .utf8(2, "Point")
+            .utf8(10, "id")
+            .utf8(11, "x")
+            .utf8(12, "label")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2);
+
+        let mut segment = builder.segment();
+        segment
+            // instance before its class
+            .instance(1000, 101, &[Val::Int(7), Val::Obj(3000), Val::Int(42)])
+            .class(&base_class)
+            .class(&point_class)
+            .instance(1001, 999, &[Val::Int(1)])
+            .object_array(2000, 101, &[1000, 0])
+            .int_array(3000, &[1, 2]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+
+        let owned = OwnedHprof::load(&bytes[..]).unwrap();
+        // the input isn't needed any more
+        drop(bytes);
+
+        assert_eq!(Some("Point"), owned.class_name(Id::from(101)));
+        assert_eq!(
+            Some(Id::from(100)),
+            owned.classes()[&Id::from(101)].super_class_obj_id()
+        );
+
+        assert!(matches!(
+            owned.field_value(Id::from(1000), "x"),
+            Some(FieldValue::Int(7))
+        ));
+        assert!(matches!(
+            owned.field_value(Id::from(1000), "id"),
+            Some(FieldValue::Int(42))
+        ));
+        assert!(matches!(
+            owned.field_value(Id::from(1000), "label"),
+            Some(FieldValue::ObjectId(Some(id))) if id == Id::from(3000)
+        ));
+        assert!(owned.field_value(Id::from(1000), "nope").is_none());
+
+        // class 999 is missing
+        let orphan = &owned.instances()[&Id::from(1001)];
+        assert_eq!(Id::from(999), orphan.class_obj_id());
+        assert!(orphan.fields().is_empty());
+
+        assert_eq!(
+            &vec![Some(Id::from(1000)), None],
+            owned.object_arrays()[&Id::from(2000)].elements()
+        );
+        let ints = &owned.primitive_arrays()[&Id::from(3000)];
+        assert_eq!(PrimitiveArrayType::Int, ints.primitive_type());
+        assert_eq!(&PrimitiveArrayValues::Int(vec![1, 2]), ints.values());
+    }
+
+    #[test]
+    fn mismatched_fields_are_invalid() {
+        let mut builder = HprofBuilder::new();
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass {
+                instance_fields: vec![(10, TYPE_INT)],
+                ..TestClass::new(100, 0)
+            })
+            // a long where the class says there's an int
+            .instance(1000, 100, &[Val::Long(7)]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+
+        assert!(matches!(
+            OwnedHprof::load(&bytes[..]),
+            Err(HprofParseError::InvalidRecord {
+                tag: RecordTag::HeapDumpSegment,
+                ..
+            })
+        ));
+    }
+}
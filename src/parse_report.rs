@@ -0,0 +1,249 @@
+use crate::heap_dump::*;
+use crate::*;
+use std::collections;
+
+impl<'a> Hprof<'a> {
+    /// Fully parse every record and sub record, tallying successes and failures for each type.
+    ///
+    /// Unlike iterating with [Hprof::records_iter], this carries on past errors, so it's useful
+    /// for assessing how much of a damaged hprof is usable, or for asserting in CI that a corpus
+    /// of heap dumps parses cleanly.
+    ///
+    /// Sub records aren't length-prefixed, so a sub record that fails to parse leaves no way to
+    /// find the next one: the rest of that heap dump segment is skipped and counted in
+    /// [ParseReport::unparsed_bytes]. A heap dump segment counts as a failure if any of its sub
+    /// records do. Records with an unrecognized tag can be skipped since records are
+    /// length-prefixed.
+    pub fn parse_report(&self) -> ParseReport {
+        let mut report = ParseReport::default();
+        let mut remaining = self.records;
+
+        while !remaining.is_empty() {
+            let tag = match RecordTag::from_tag_byte(remaining[0]) {
+                Some(t) => t,
+                None => {
+                    report.unknown_tags += 1;
+                    match Record::skip(remaining) {
+                        Ok((input, _)) => {
+                            remaining = input;
+                            continue;
+                        }
+                        Err(_) => {
+                            report.unparsed_bytes += remaining.len() as u64;
+                            break;
+                        }
+                    }
+                }
+            };
+
+            match Record::parse(remaining, self.header.id_size, self.handlers) {
+                Ok((input, record)) => {
+                    remaining = input;
+                    report.check_record(&record);
+                }
+                Err(_) => {
+                    report.records.entry(tag).or_default().failures += 1;
+                    report.unparsed_bytes += remaining.len() as u64;
+                    break;
+                }
+            }
+        }
+
+        report
+    }
+}
+
+/// The outcome of fully parsing an hprof with [Hprof::parse_report].
+#[derive(CopyGetters, Clone, Debug, Default)]
+pub struct ParseReport {
+    records: collections::HashMap<RecordTag, ParseCounts>,
+    sub_records: collections::HashMap<SubRecordTag, ParseCounts>,
+    /// The number of records or sub records whose tag byte wasn't recognized
+    #[get_copy = "pub"]
+    unknown_tags: u64,
+    /// The number of bytes that couldn't be parsed at all because an earlier failure made it
+    /// impossible to find where the next record or sub record starts
+    #[get_copy = "pub"]
+    unparsed_bytes: u64,
+}
+
+impl ParseReport {
+    /// Counts for records with the provided tag (all zero if there were none).
+    pub fn record_counts(&self, tag: RecordTag) -> ParseCounts {
+        self.records.get(&tag).copied().unwrap_or_default()
+    }
+
+    /// Counts for sub records with the provided tag (all zero if there were none).
+    pub fn sub_record_counts(&self, tag: SubRecordTag) -> ParseCounts {
+        self.sub_records.get(&tag).copied().unwrap_or_default()
+    }
+
+    /// Failures across all record and sub record types, plus unknown tags.
+    pub fn total_failures(&self) -> u64 {
+        self.records
+            .values()
+            .chain(self.sub_records.values())
+            .map(|c| c.failures)
+            .sum::<u64>()
+            + self.unknown_tags
+    }
+
+    /// True if everything in the hprof parsed successfully.
+    pub fn is_clean(&self) -> bool {
+        self.total_failures() == 0 && self.unparsed_bytes == 0
+    }
+
+    fn check_record(&mut self, record: &Record) {
+        let ok = match record.tag() {
+            RecordTag::Utf8 => record.as_utf_8().unwrap().is_ok(),
+            RecordTag::LoadClass => record.as_load_class().unwrap().is_ok(),
+            RecordTag::StackFrame => record.as_stack_frame().unwrap().is_ok(),
+            RecordTag::StackTrace => record
+                .as_stack_trace()
+                .unwrap()
+                .map(|st| st.frame_ids().all(|r| r.is_ok()))
+                .unwrap_or(false),
+            RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                match record.as_heap_dump_segment().unwrap() {
+                    Ok(segment) => self.check_sub_records(segment.records, segment.id_size),
+                    Err(_) => false,
+                }
+            }
+            // other record types aren't parsed any further than their tag and length
+            _ => true,
+        };
+
+        self.records.entry(record.tag()).or_default().tally(ok);
+    }
+
+    /// Returns true if every sub record parsed successfully.
+    fn check_sub_records(&mut self, mut remaining: &[u8], id_size: IdSize) -> bool {
+        let mut all_ok = true;
+
+        while !remaining.is_empty() {
+            let tag = match SubRecordTag::from_tag_byte(remaining[0]) {
+                Some(t) => t,
+                None => {
+                    self.unknown_tags += 1;
+                    self.unparsed_bytes += remaining.len() as u64;
+                    return false;
+                }
+            };
+
+            match SubRecord::parse(remaining, id_size) {
+                Ok((input, sub_record)) => {
+                    remaining = input;
+
+                    let ok = match sub_record {
+                        SubRecord::Class(c) => {
+                            c.static_fields().all(|r| r.is_ok())
+                                && c.instance_field_descriptors().all(|r| r.is_ok())
+                        }
+                        SubRecord::ObjectArray(oa) => oa.elements(id_size).all(|r| r.is_ok()),
+                        // everything else is fully parsed already
+                        _ => true,
+                    };
+
+                    self.sub_records.entry(tag).or_default().tally(ok);
+                    all_ok &= ok;
+                }
+                Err(_) => {
+                    self.sub_records.entry(tag).or_default().failures += 1;
+                    self.unparsed_bytes += remaining.len() as u64;
+                    return false;
+                }
+            }
+        }
+
+        all_ok
+    }
+}
+
+/// Success and failure counts for one type of record or sub record.
+#[derive(CopyGetters, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseCounts {
+    #[get_copy = "pub"]
+    successes: u64,
+    #[get_copy = "pub"]
+    failures: u64,
+}
+
+impl ParseCounts {
+    fn tally(&mut self, ok: bool) {
+        if ok {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn report_counts_bad_sub_record_and_continues() {
+        let mut builder = HprofBuilder::new();
+
+        let mut bad_segment = builder.segment();
+        bad_segment
+            .class(&TestClass::new(100, 0))
+            .instance(1000, 100, &[])
+            // an instance truncated partway through its obj id
+            .raw(&[0x21, 0x00, 0x00]);
+
+        let mut good_segment = builder.segment();
+        good_segment.gc_root_unknown(1000);
+
+        builder
+            .utf8(1, "hello")
+            .heap_dump_segment(&bad_segment)
+            .record(0x99, 0, b"unknown")
+            .heap_dump_segment(&good_segment)
+            .heap_dump_end();
+
+        let bytes = builder.build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let report = hprof.parse_report();
+
+        assert_eq!(
+            ParseCounts {
+                successes: 1,
+                failures: 1
+            },
+            report.sub_record_counts(SubRecordTag::Instance)
+        );
+        assert_eq!(
+            ParseCounts {
+                successes: 1,
+                failures: 0
+            },
+            report.sub_record_counts(SubRecordTag::Class)
+        );
+        // parsing carried on after the failure
+        assert_eq!(
+            ParseCounts {
+                successes: 1,
+                failures: 0
+            },
+            report.sub_record_counts(SubRecordTag::GcRootUnknown)
+        );
+        // the bad segment's sub record failure counts against it too
+        assert_eq!(
+            ParseCounts {
+                successes: 1,
+                failures: 1
+            },
+            report.record_counts(RecordTag::HeapDumpSegment)
+        );
+        assert_eq!(1, report.record_counts(RecordTag::Utf8).successes());
+        assert_eq!(1, report.record_counts(RecordTag::HeapDumpEnd).successes());
+        assert_eq!(1, report.unknown_tags());
+        assert_eq!(3, report.unparsed_bytes());
+        assert_eq!(3, report.total_failures());
+        assert!(!report.is_clean());
+    }
+}
@@ -55,7 +55,12 @@ impl<'a, T, P: Parser<T>> Iterator for ParsingIterator<'a, T, P> {
                 self.num_remaining -= 1;
                 Some(Ok(val))
             }
-            Err(e) => Some(Err(e)),
+            Err(e) => {
+                // iteration ends after the first error
+                self.remaining = &self.remaining[self.remaining.len()..];
+                self.num_remaining = 0;
+                Some(Err(e))
+            }
         }
     }
 }
@@ -0,0 +1,391 @@
+use crate::*;
+use std::io::Read;
+use std::{convert::TryFrom, io};
+
+/// Length of the tag, micros, and body length that precede every record's body
+const RECORD_PREFIX_LEN: u64 = 1 + 4 + 4;
+
+/// A [Record] that owns a copy of its body, so it can outlive the bytes it was parsed from, e.g.
+/// to send it to another thread, or to keep it after [HprofReader] has reused its buffer.
+///
+/// Use [OwnedRecord::as_record] to get a [Record] to parse the body with.
+#[derive(CopyGetters, Clone, Debug)]
+pub struct OwnedRecord {
+    #[get_copy = "pub"]
+    tag: RecordTag,
+    /// Microseconds since the timestamp in the header
+    #[get_copy = "pub"]
+    micros_since_header_ts: u32,
+    id_size: IdSize,
+    body: Vec<u8>,
+}
+
+impl OwnedRecord {
+    /// Copy the body of `record`.
+    pub fn from_record(record: &Record) -> OwnedRecord {
+        OwnedRecord {
+            tag: record.tag,
+            micros_since_header_ts: record.micros_since_header_ts,
+            id_size: record.id_size,
+            body: record.body.to_vec(),
+        }
+    }
+
+    /// The record's body, i.e. everything after the tag, timestamp, and length.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// A [Record] borrowing this record's body.
+    ///
+    /// [Handlers] aren't carried over, so heap dump segments can only contain the standard sub
+    /// record types.
+    pub fn as_record(&self) -> Record<'_> {
+        Record {
+            tag: self.tag,
+            micros_since_header_ts: self.micros_since_header_ts,
+            id_size: self.id_size,
+            body: &self.body,
+            handlers: None,
+        }
+    }
+}
+
+/// Reads an hprof one record at a time from a seekable reader, rather than needing the whole file
+/// in memory or mapped into the address space.
+///
+/// Each record's body is read into a buffer that's reused for the next record, so memory use is
+/// bounded by the largest record rather than the size of the file. That's still a lot for a heap
+/// dump written as one big [RecordTag::HeapDump] record, but modern JVMs split heap dumps into
+/// [RecordTag::HeapDumpSegment]s, typically of 1GiB or less. Records that aren't wanted can be
+/// skipped with [HprofReader::next_matching_record] without reading their bodies at all.
+///
+/// Records can be borrowed from the buffer with [HprofReader::next_record], or copied out as
+/// [OwnedRecord]s by iterating.
+///
+/// Problems with the structure of the hprof (e.g. truncated records) are reported as
+/// [io::ErrorKind::InvalidData] errors wrapping an [HprofParseError].
+///
+/// # Examples
+///
+/// ```no_run
+/// use jvm_hprof::{HprofReader, RecordTag};
+/// use std::{fs, io};
+///
+/// let file = io::BufReader::new(fs::File::open("path/to/heap.hprof").unwrap());
+/// let mut reader = HprofReader::new(file).unwrap();
+///
+/// let mut utf8_count = 0;
+/// while let Some(record) = reader
+///     .next_matching_record(|tag| tag == RecordTag::Utf8)
+///     .unwrap()
+/// {
+///     record.as_utf_8().unwrap().unwrap();
+///     utf8_count += 1;
+/// }
+/// println!("{} utf8 records", utf8_count);
+/// ```
+pub struct HprofReader<R> {
+    reader: R,
+    /// The raw header, so that [HprofReader::header] can borrow its label
+    header: Vec<u8>,
+    id_size: IdSize,
+    /// Position in `reader` where the hprof starts
+    start: u64,
+    /// Length of the hprof from `start` to the end of `reader`
+    len: u64,
+    /// Offset of the next record from the start of the hprof
+    offset: u64,
+    buffer: Vec<u8>,
+}
+
+impl<R: io::Read + io::Seek> HprofReader<R> {
+    /// Parse the header of the hprof starting at the current position of `reader`.
+    ///
+    /// Since each record's body is read with its own read call, `reader` should be buffered if
+    /// it's something like a file.
+    pub fn new(mut reader: R) -> io::Result<HprofReader<R>> {
+        let start = reader.stream_position()?;
+        let len = reader.seek(io::SeekFrom::End(0))? - start;
+        reader.seek(io::SeekFrom::Start(start))?;
+
+        // the label is NUL terminated, followed by the id size and timestamp
+        let max_header_len = MAX_HEADER_LABEL_LEN as u64 + 1 + 4 + 8;
+        let mut header = Vec::new();
+        (&mut reader)
+            .take(max_header_len)
+            .read_to_end(&mut header)?;
+        let header_len = match Header::parse(&header) {
+            Ok((remaining, _)) => header.len() - remaining.len(),
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    HprofParseError::from_header_error(e),
+                ))
+            }
+        };
+        header.truncate(header_len);
+        let id_size = Header::parse(&header)
+            .expect("Header was just parsed")
+            .1
+            .id_size();
+        reader.seek(io::SeekFrom::Start(start + header_len as u64))?;
+
+        Ok(HprofReader {
+            reader,
+            header,
+            id_size,
+            start,
+            len,
+            offset: header_len as u64,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// The header of the hprof.
+    pub fn header(&self) -> Header<'_> {
+        Header::parse(&self.header)
+            .expect("Header was already parsed when created")
+            .1
+    }
+
+    /// The offset, from the start of the hprof, of the record that will be read next.
+    ///
+    /// Offsets are the same as those from [Hprof::scan_record_headers].
+    pub fn next_record_offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Continue reading at the record at `offset` bytes from the start of the hprof, e.g. as
+    /// previously found with [HprofReader::next_record_offset].
+    ///
+    /// `offset` must be at a record boundary. Anywhere else will most likely produce an error
+    /// when the next record is read, but could also produce garbage. Offsets past the end of the
+    /// hprof are an [io::ErrorKind::InvalidInput] error.
+    pub fn seek_to_record(&mut self, offset: u64) -> io::Result<()> {
+        if offset > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Record offset {} is past the end of the {} byte hprof",
+                    offset, self.len
+                ),
+            ));
+        }
+
+        self.reader.seek(io::SeekFrom::Start(self.start + offset))?;
+        self.offset = offset;
+
+        Ok(())
+    }
+
+    /// Read the next record, or `None` at the end of the hprof.
+    ///
+    /// The record borrows the reader's buffer, so it must be dropped before the next one is read.
+    /// Use [OwnedRecord::from_record] to keep it around.
+    pub fn next_record(&mut self) -> io::Result<Option<Record<'_>>> {
+        self.next_matching_record(|_| true)
+    }
+
+    /// Read the next record whose tag `wanted` returns true for, or `None` at the end of the
+    /// hprof.
+    ///
+    /// Records that aren't wanted are seeked past without reading their bodies.
+    pub fn next_matching_record<F: FnMut(RecordTag) -> bool>(
+        &mut self,
+        mut wanted: F,
+    ) -> io::Result<Option<Record<'_>>> {
+        loop {
+            let (tag, micros, body_len) = match self.read_prefix() {
+                Ok(Some(prefix)) => prefix,
+                Ok(None) => return Ok(None),
+                Err(e) => {
+                    // don't try to make sense of whatever comes after
+                    self.offset = self.len;
+                    return Err(e);
+                }
+            };
+            self.offset += RECORD_PREFIX_LEN + body_len as u64;
+
+            if !wanted(tag) {
+                self.reader.seek(io::SeekFrom::Current(body_len as i64))?;
+                continue;
+            }
+
+            // keeps its capacity, so the buffer only grows when a record is bigger than any so far,
+            // and then only to fit that record
+            self.buffer.clear();
+            self.buffer.reserve_exact(body_len as usize);
+            self.buffer.resize(body_len as usize, 0);
+            self.reader.read_exact(&mut self.buffer)?;
+
+            return Ok(Some(Record {
+                tag,
+                micros_since_header_ts: micros,
+                id_size: self.id_size,
+                body: &self.buffer,
+                handlers: None,
+            }));
+        }
+    }
+
+    /// Read the tag, micros, and body length of the record at `offset`, checking that the whole
+    /// body is present.
+    fn read_prefix(&mut self) -> io::Result<Option<(RecordTag, u32, u32)>> {
+        let remaining = self.len - self.offset;
+        if remaining == 0 {
+            return Ok(None);
+        }
+
+        let offset = offset_usize(self.offset);
+        if remaining < RECORD_PREFIX_LEN {
+            return Err(invalid_data(HprofParseError::TrailingBytes {
+                offset,
+                len: remaining as usize,
+            }));
+        }
+
+        let mut prefix = [0_u8; RECORD_PREFIX_LEN as usize];
+        self.reader.read_exact(&mut prefix)?;
+        let tag = RecordTag::from_tag_byte(prefix[0]).ok_or_else(|| {
+            invalid_data(HprofParseError::UnknownRecordTag {
+                offset,
+                tag_byte: prefix[0],
+            })
+        })?;
+        let micros = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]);
+        let body_len = u32::from_be_bytes([prefix[5], prefix[6], prefix[7], prefix[8]]);
+
+        let available = remaining - RECORD_PREFIX_LEN;
+        if available < body_len as u64 {
+            return Err(invalid_data(HprofParseError::TruncatedRecord {
+                offset,
+                declared_len: body_len,
+                available: offset_usize(available),
+            }));
+        }
+
+        Ok(Some((tag, micros, body_len)))
+    }
+}
+
+impl<R: io::Read + io::Seek> Iterator for HprofReader<R> {
+    type Item = io::Result<OwnedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+            .map(|opt| opt.map(|r| OwnedRecord::from_record(&r)))
+            .transpose()
+    }
+}
+
+fn invalid_data(e: HprofParseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// [HprofParseError] offsets are `usize`, which may be too small for a file that was too big
+/// to map on a 32-bit host in the first place.
+fn offset_usize(offset: u64) -> usize {
+    usize::try_from(offset).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn reads_all_records_into_reused_buffer() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "a")
+            .utf8(2, &"b".repeat(100))
+            .load_class(1, 100, 0, 1);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .int_array(1000, &[1, 2, 3]);
+        builder.heap_dump_segment(&segment).heap_dump_end();
+        builder.utf8(3, "c");
+        let bytes = builder.build();
+
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+        let expected = hprof
+            .records_iter()
+            .map(|r| r.unwrap())
+            .map(|r| (r.tag(), r.raw_body().to_vec()))
+            .collect::<Vec<_>>();
+        let largest_body = expected.iter().map(|(_, body)| body.len()).max().unwrap();
+
+        let mut reader = HprofReader::new(io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(
+            hprof.header().label().unwrap(),
+            reader.header().label().unwrap()
+        );
+        assert_eq!(
+            hprof.header().id_size().size_in_bytes(),
+            reader.header().id_size().size_in_bytes()
+        );
+
+        let actual = reader
+            .by_ref()
+            .map(|r| r.unwrap())
+            .map(|r| (r.tag(), r.body().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, actual);
+        // reserve_exact may still allocate more
+        assert!(reader.buffer.capacity() >= largest_body);
+
+        // skipped records aren't read at all
+        let offsets = hprof
+            .scan_record_headers()
+            .map(|(_, _, _, offset)| offset as u64)
+            .collect::<Vec<_>>();
+        reader.seek_to_record(offsets[0]).unwrap();
+        let mut body_lens = Vec::new();
+        while let Some(record) = reader
+            .next_matching_record(|tag| tag == RecordTag::Utf8)
+            .unwrap()
+        {
+            body_lens.push(record.body_len());
+        }
+        assert_eq!(vec![8 + 1, 8 + 100, 8 + 1], body_lens);
+
+        // the end is where the last record stops, but nothing can be past it
+        reader.seek_to_record(bytes.len() as u64).unwrap();
+        assert!(reader.next_record().unwrap().is_none());
+        assert_eq!(
+            io::ErrorKind::InvalidInput,
+            reader
+                .seek_to_record(bytes.len() as u64 + 1)
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[test]
+    fn truncated_record_is_invalid_data() {
+        let mut builder = HprofBuilder::new();
+        builder.utf8(1, "complete").utf8(2, "truncated");
+        let mut bytes = builder.build();
+        bytes.truncate(bytes.len() - 3);
+
+        let mut reader = HprofReader::new(io::Cursor::new(&bytes)).unwrap();
+        reader.next_record().unwrap().unwrap();
+        let offset = reader.next_record_offset() as usize;
+
+        let e = reader.next_record().err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+        assert_eq!(
+            Some(&HprofParseError::TruncatedRecord {
+                offset,
+                declared_len: 8 + 9,
+                available: 8 + 9 - 3,
+            }),
+            e.get_ref()
+                .and_then(|e| e.downcast_ref::<HprofParseError>())
+        );
+        // nothing more after an error
+        assert!(reader.next_record().unwrap().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::*;
+use std::{sync, thread};
+
+/// Parse the hprof in `data` on a new thread, sending each record over a channel as an
+/// [OwnedRecord].
+///
+/// This suits pipelines where each worker keeps its own state: clone the receiver for each
+/// worker, and each record will be received by exactly one of them. At most `capacity` records
+/// wait in the channel at once, so parsing slows to the pace of the workers rather than filling up
+/// memory. Compared to iterating with rayon's `par_bridge`, this costs a copy of each record body.
+///
+/// The returned thread handle resolves to the first parse error, if any, which also ends the
+/// stream. Parsing stops early if all receivers are dropped.
+///
+/// Available with the `channel` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jvm_hprof::spawn_record_stream;
+/// use std::{fs, sync, thread};
+///
+/// let bytes = sync::Arc::new(fs::read("path/to/heap.hprof").unwrap());
+/// let (receiver, parser) = spawn_record_stream(bytes, 1024);
+///
+/// let workers = (0..4)
+///     .map(|_| {
+///         let receiver = receiver.clone();
+///         thread::spawn(move || receiver.iter().filter(|r| r.body().len() > 1024).count())
+///     })
+///     .collect::<Vec<_>>();
+///
+/// let big_records: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+/// parser.join().unwrap().unwrap();
+/// println!("{} records over 1KiB", big_records);
+/// ```
+pub fn spawn_record_stream<D>(
+    data: sync::Arc<D>,
+    capacity: usize,
+) -> (
+    crossbeam_channel::Receiver<OwnedRecord>,
+    thread::JoinHandle<Result<(), HprofParseError>>,
+)
+where
+    D: AsRef<[u8]> + Send + Sync + 'static,
+{
+    let (sender, receiver) = crossbeam_channel::bounded(capacity);
+
+    let handle = thread::spawn(move || {
+        let hprof =
+            parse_hprof(data.as_ref().as_ref()).map_err(HprofParseError::from_header_error)?;
+
+        for r in hprof.records_iter() {
+            let record = r?;
+            if sender.send(OwnedRecord::from_record(&record)).is_err() {
+                // nobody's listening
+                break;
+            }
+        }
+
+        Ok(())
+    });
+
+    (receiver, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn each_record_received_exactly_once() {
+        let mut builder = HprofBuilder::new();
+        for i in 0..100 {
+            builder.utf8(i, &format!("string {}", i));
+        }
+        let bytes = builder.build();
+        let (receiver, parser) = spawn_record_stream(sync::Arc::new(bytes), 4);
+
+        let workers = (0..4)
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || {
+                    receiver
+                        .iter()
+                        .map(|r| {
+                            let utf8 = r.as_record().as_utf_8().unwrap().unwrap();
+                            utf8.name_id().id()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(receiver);
+
+        let mut ids = workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect::<Vec<_>>();
+        parser.join().unwrap().unwrap();
+
+        ids.sort_unstable();
+        assert_eq!((0..100).collect::<Vec<_>>(), ids);
+    }
+
+    #[test]
+    fn parse_error_ends_stream() {
+        let (receiver, parser) = spawn_record_stream(sync::Arc::new(vec![1, 2, 3]), 4);
+
+        assert_eq!(0, receiver.iter().count());
+        assert_eq!(Err(HprofParseError::NotAnHprof), parser.join().unwrap());
+    }
+}
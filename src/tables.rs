@@ -0,0 +1,208 @@
+use crate::heap_dump::*;
+use crate::*;
+use getset::Getters;
+use std::{borrow, collections};
+
+/// The name to show for a class whose [LoadClass] or [Class] record isn't in the heap dump, as can
+/// happen with truncated or otherwise damaged dumps: `(unknown class <id>)`.
+pub fn unknown_class_name(class_obj_id: Id) -> String {
+    format!("(unknown class {})", class_obj_id)
+}
+
+/// The lookup tables most tools need before they can make sense of objects, built with a single
+/// pass over the hprof.
+///
+/// Everything is held in memory, including an entry for every object in
+/// [HprofTables::obj_class_ids], so for very large heap dumps, consider an on-disk index instead.
+#[derive(Getters, Default)]
+pub struct HprofTables<'a> {
+    /// [Utf8] records by name id
+    #[get = "pub"]
+    utf8: collections::HashMap<Id, Utf8<'a>>,
+    /// [LoadClass] records by class obj id
+    #[get = "pub"]
+    load_classes_by_obj_id: collections::HashMap<Id, LoadClass>,
+    /// [LoadClass] records by class serial
+    #[get = "pub"]
+    load_classes_by_serial: collections::HashMap<Serial, LoadClass>,
+    /// [StackFrame] records by frame id
+    #[get = "pub"]
+    stack_frames: collections::HashMap<Id, StackFrame>,
+    /// [StackTrace] records by stack trace serial
+    #[get = "pub"]
+    stack_traces: collections::HashMap<Serial, StackTrace<'a>>,
+    /// [Class] sub records by obj id
+    #[get = "pub"]
+    classes: collections::HashMap<Id, Class<'a>>,
+    /// The class obj id of each instance, and the array class obj id of each object array, by obj
+    /// id
+    #[get = "pub"]
+    obj_class_ids: collections::HashMap<Id, Id>,
+    /// The element type of each primitive array, by obj id
+    #[get = "pub"]
+    prim_array_types: collections::HashMap<Id, PrimitiveArrayType>,
+}
+
+impl<'a> HprofTables<'a> {
+    /// Populate all the tables from one pass over `hprof`'s records.
+    pub fn build(hprof: &Hprof<'a>) -> ParseResult<'a, HprofTables<'a>> {
+        let mut tables = HprofTables::default();
+        hprof.accept(&mut tables)?;
+        Ok(tables)
+    }
+
+    /// The name of the class with obj id `class_obj_id`, as found via its [LoadClass].
+    ///
+    /// Returns `None` if there's no such class or its name isn't valid UTF-8.
+    pub fn class_name(&self, class_obj_id: Id) -> Option<&'a str> {
+        self.load_classes_by_obj_id
+            .get(&class_obj_id)
+            .and_then(|lc| self.utf8.get(&lc.class_name_id()))
+            .and_then(|u| u.text_as_str().ok())
+    }
+
+    /// Like [HprofTables::class_name], but falls back to [unknown_class_name] so that output can
+    /// carry on when a class is missing.
+    pub fn class_name_or_unknown(&self, class_obj_id: Id) -> borrow::Cow<'a, str> {
+        match self.class_name(class_obj_id) {
+            Some(name) => borrow::Cow::Borrowed(name),
+            None => borrow::Cow::Owned(unknown_class_name(class_obj_id)),
+        }
+    }
+}
+
+impl<'a> HprofVisitor<'a> for HprofTables<'a> {
+    fn visit_utf8(&mut self, utf8: Utf8<'a>) {
+        self.utf8.insert(utf8.name_id(), utf8);
+    }
+
+    fn visit_load_class(&mut self, load_class: LoadClass) {
+        self.load_classes_by_obj_id
+            .insert(load_class.class_obj_id(), load_class);
+        self.load_classes_by_serial
+            .insert(load_class.class_serial(), load_class);
+    }
+
+    fn visit_stack_frame(&mut self, stack_frame: StackFrame) {
+        self.stack_frames.insert(stack_frame.id(), stack_frame);
+    }
+
+    fn visit_stack_trace(&mut self, stack_trace: StackTrace<'a>) {
+        self.stack_traces
+            .insert(stack_trace.stack_trace_serial(), stack_trace);
+    }
+
+    fn visit_class(&mut self, class: Class<'a>) {
+        self.classes.insert(class.obj_id(), class);
+    }
+
+    fn visit_instance(&mut self, instance: Instance<'a>) {
+        self.obj_class_ids
+            .insert(instance.obj_id(), instance.class_obj_id());
+    }
+
+    fn visit_object_array(&mut self, object_array: ObjectArray<'a>) {
+        self.obj_class_ids
+            .insert(object_array.obj_id(), object_array.array_class_obj_id());
+    }
+
+    fn visit_primitive_array(&mut self, primitive_array: PrimitiveArray<'a>) {
+        self.prim_array_types
+            .insert(primitive_array.obj_id(), primitive_array.primitive_type());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn build_populates_all_tables() {
+        let mut builder = HprofBuilder::new();
+        builder
+            .utf8(1, "Thing")
+            .utf8(2, "[LThing;")
+            .load_class(1, 100, 0, 1)
+            .load_class(2, 101, 0, 2)
+            .stack_frame(10, 3, 4, 5, 1, 42)
+            .stack_trace(7, 1, &[10]);
+        let mut segment = builder.segment();
+        segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .instance(1000, 100, &[])
+            .object_array(2000, 101, &[1000])
+            .int_array(3000, &[1, 2]);
+        let bytes = builder.heap_dump_segment(&segment).heap_dump_end().build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let tables = HprofTables::build(&hprof).unwrap();
+
+        assert_eq!(
+            "Thing",
+            tables
+                .utf8()
+                .get(&Id::from(1))
+                .unwrap()
+                .text_as_str()
+                .unwrap()
+        );
+        assert_eq!(2, tables.utf8().len());
+        assert_eq!(
+            Id::from(2),
+            tables
+                .load_classes_by_obj_id()
+                .get(&Id::from(101))
+                .unwrap()
+                .class_name_id()
+        );
+        assert_eq!(
+            Id::from(100),
+            tables
+                .load_classes_by_serial()
+                .get(&Serial::from(1))
+                .unwrap()
+                .class_obj_id()
+        );
+        assert_eq!(
+            Id::from(3),
+            tables
+                .stack_frames()
+                .get(&Id::from(10))
+                .unwrap()
+                .method_name_id()
+        );
+        assert_eq!(
+            Serial::from(1),
+            tables
+                .stack_traces()
+                .get(&Serial::from(7))
+                .unwrap()
+                .thread_serial()
+        );
+        let mut class_ids = tables.classes().keys().copied().collect::<Vec<_>>();
+        class_ids.sort();
+        assert_eq!(vec![Id::from(100), Id::from(101)], class_ids);
+        assert_eq!(
+            Some(&Id::from(100)),
+            tables.obj_class_ids().get(&Id::from(1000))
+        );
+        assert_eq!(
+            Some(&Id::from(101)),
+            tables.obj_class_ids().get(&Id::from(2000))
+        );
+        assert_eq!(2, tables.obj_class_ids().len());
+        assert_eq!(
+            Some(&PrimitiveArrayType::Int),
+            tables.prim_array_types().get(&Id::from(3000))
+        );
+        assert_eq!(Some("[LThing;"), tables.class_name(Id::from(101)));
+        assert_eq!(None, tables.class_name(Id::from(999)));
+        assert_eq!("[LThing;", tables.class_name_or_unknown(Id::from(101)));
+        assert_eq!(
+            "(unknown class 999)",
+            tables.class_name_or_unknown(Id::from(999))
+        );
+    }
+}
@@ -0,0 +1,488 @@
+//! Writes synthetic hprof data for tests.
+//!
+//! This only depends on `std` so that the examples' tests can include it too.
+#![allow(dead_code)]
+
+/// Builds the bytes of an hprof file, one record at a time.
+pub struct HprofBuilder {
+    label: Vec<u8>,
+    id_size: u32,
+    timestamp_millis: u64,
+    records: Vec<u8>,
+}
+
+impl HprofBuilder {
+    /// A builder using 8-byte ids.
+    pub fn new() -> HprofBuilder {
+        HprofBuilder::with_id_size(8)
+    }
+
+    pub fn with_id_size(id_size: u32) -> HprofBuilder {
+        HprofBuilder {
+            label: b"JAVA PROFILE 1.0.2".to_vec(),
+            id_size,
+            timestamp_millis: 0,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn label(&mut self, label: &[u8]) -> &mut Self {
+        self.label = label.to_vec();
+        self
+    }
+
+    pub fn timestamp_millis(&mut self, timestamp_millis: u64) -> &mut Self {
+        self.timestamp_millis = timestamp_millis;
+        self
+    }
+
+    /// A record with an arbitrary tag and body.
+    pub fn record(&mut self, tag: u8, micros: u32, body: &[u8]) -> &mut Self {
+        self.records.push(tag);
+        self.records.extend_from_slice(&micros.to_be_bytes());
+        self.records
+            .extend_from_slice(&(body.len() as u32).to_be_bytes());
+        self.records.extend_from_slice(body);
+        self
+    }
+
+    pub fn utf8(&mut self, name_id: u64, text: &str) -> &mut Self {
+        self.utf8_bytes(name_id, text.as_bytes())
+    }
+
+    pub fn utf8_bytes(&mut self, name_id: u64, text: &[u8]) -> &mut Self {
+        let mut body = Vec::new();
+        write_id(&mut body, self.id_size, name_id);
+        body.extend_from_slice(text);
+        self.record(0x01, 0, &body)
+    }
+
+    pub fn load_class(
+        &mut self,
+        class_serial: u32,
+        class_obj_id: u64,
+        stack_trace_serial: u32,
+        class_name_id: u64,
+    ) -> &mut Self {
+        let mut body = Vec::new();
+        body.extend_from_slice(&class_serial.to_be_bytes());
+        write_id(&mut body, self.id_size, class_obj_id);
+        body.extend_from_slice(&stack_trace_serial.to_be_bytes());
+        write_id(&mut body, self.id_size, class_name_id);
+        self.record(0x02, 0, &body)
+    }
+
+    /// Names and load class records for `java.lang.ref.Reference`
+    /// (`class Reference { Object referent; Object queue; }`) and its subclass
+    /// `java.lang.ref.WeakReference`, with class obj ids [REFERENCE_CLASS_OBJ_ID] and
+    /// [WEAK_REFERENCE_CLASS_OBJ_ID]. Uses name ids 1, 2, 20, and 21, and class serials 1 and 2.
+    ///
+    /// Their class sub records are written by [SegmentBuilder::reference_classes].
+    pub fn reference_classes(&mut self) -> &mut Self {
+        self.utf8(1, "java/lang/ref/Reference")
+            .utf8(2, "java/lang/ref/WeakReference")
+            .utf8(20, "referent")
+            .utf8(21, "queue")
+            .load_class(1, REFERENCE_CLASS_OBJ_ID, 0, 1)
+            .load_class(2, WEAK_REFERENCE_CLASS_OBJ_ID, 0, 2)
+    }
+
+    pub fn stack_frame(
+        &mut self,
+        id: u64,
+        method_name_id: u64,
+        method_signature_id: u64,
+        source_file_name_id: u64,
+        class_serial: u32,
+        line_num: i32,
+    ) -> &mut Self {
+        let mut body = Vec::new();
+        write_id(&mut body, self.id_size, id);
+        write_id(&mut body, self.id_size, method_name_id);
+        write_id(&mut body, self.id_size, method_signature_id);
+        write_id(&mut body, self.id_size, source_file_name_id);
+        body.extend_from_slice(&class_serial.to_be_bytes());
+        body.extend_from_slice(&line_num.to_be_bytes());
+        self.record(0x04, 0, &body)
+    }
+
+    pub fn stack_trace(
+        &mut self,
+        stack_trace_serial: u32,
+        thread_serial: u32,
+        frame_ids: &[u64],
+    ) -> &mut Self {
+        let mut body = Vec::new();
+        body.extend_from_slice(&stack_trace_serial.to_be_bytes());
+        body.extend_from_slice(&thread_serial.to_be_bytes());
+        body.extend_from_slice(&(frame_ids.len() as u32).to_be_bytes());
+        for &id in frame_ids {
+            write_id(&mut body, self.id_size, id);
+        }
+        self.record(0x05, 0, &body)
+    }
+
+    pub fn start_thread(
+        &mut self,
+        thread_serial: u32,
+        thread_obj_id: u64,
+        stack_trace_serial: u32,
+        thread_name_id: u64,
+    ) -> &mut Self {
+        let mut body = Vec::new();
+        body.extend_from_slice(&thread_serial.to_be_bytes());
+        write_id(&mut body, self.id_size, thread_obj_id);
+        body.extend_from_slice(&stack_trace_serial.to_be_bytes());
+        write_id(&mut body, self.id_size, thread_name_id);
+        // no thread group or parent group name
+        write_id(&mut body, self.id_size, 0);
+        write_id(&mut body, self.id_size, 0);
+        self.record(0x0A, 0, &body)
+    }
+
+    /// A segment builder using this builder's id size, to be added with `heap_dump_segment`.
+    pub fn segment(&self) -> SegmentBuilder {
+        SegmentBuilder {
+            id_size: self.id_size,
+            body: Vec::new(),
+        }
+    }
+
+    /// A legacy single-record heap dump, which isn't followed by a heap dump end record.
+    pub fn heap_dump(&mut self, segment: &SegmentBuilder) -> &mut Self {
+        self.record(0x0C, 0, &segment.body)
+    }
+
+    pub fn heap_dump_segment(&mut self, segment: &SegmentBuilder) -> &mut Self {
+        self.record(0x1C, 0, &segment.body)
+    }
+
+    pub fn heap_dump_end(&mut self) -> &mut Self {
+        self.record(0x2C, 0, &[])
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut hprof = Vec::new();
+        hprof.extend_from_slice(&self.label);
+        hprof.push(0);
+        hprof.extend_from_slice(&self.id_size.to_be_bytes());
+        hprof.extend_from_slice(&((self.timestamp_millis >> 32) as u32).to_be_bytes());
+        hprof.extend_from_slice(&(self.timestamp_millis as u32).to_be_bytes());
+        hprof.extend_from_slice(&self.records);
+        hprof
+    }
+}
+
+/// Builds the body of a heap dump segment, one sub record at a time.
+pub struct SegmentBuilder {
+    id_size: u32,
+    body: Vec<u8>,
+}
+
+impl SegmentBuilder {
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Arbitrary bytes, e.g. for sub records that can't otherwise be built
+    pub fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        self.body.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn gc_root_unknown(&mut self, obj_id: u64) -> &mut Self {
+        self.body.push(0xFF);
+        self.id(obj_id)
+    }
+
+    pub fn gc_root_thread_obj(
+        &mut self,
+        thread_obj_id: u64,
+        thread_serial: u32,
+        stack_trace_serial: u32,
+    ) -> &mut Self {
+        self.body.push(0x08);
+        self.id(thread_obj_id)
+            .u32(thread_serial)
+            .u32(stack_trace_serial)
+    }
+
+    pub fn gc_root_jni_global(&mut self, obj_id: u64, jni_global_ref_id: u64) -> &mut Self {
+        self.body.push(0x01);
+        self.id(obj_id).id(jni_global_ref_id)
+    }
+
+    pub fn gc_root_jni_local_ref(
+        &mut self,
+        obj_id: u64,
+        thread_serial: u32,
+        frame_index: u32,
+    ) -> &mut Self {
+        self.body.push(0x02);
+        self.id(obj_id).u32(thread_serial).u32(frame_index)
+    }
+
+    pub fn gc_root_java_stack_frame(
+        &mut self,
+        obj_id: u64,
+        thread_serial: u32,
+        frame_index: u32,
+    ) -> &mut Self {
+        self.body.push(0x03);
+        self.id(obj_id).u32(thread_serial).u32(frame_index)
+    }
+
+    pub fn gc_root_native_stack(&mut self, obj_id: u64, thread_serial: u32) -> &mut Self {
+        self.body.push(0x04);
+        self.id(obj_id).u32(thread_serial)
+    }
+
+    pub fn gc_root_system_class(&mut self, obj_id: u64) -> &mut Self {
+        self.body.push(0x05);
+        self.id(obj_id)
+    }
+
+    pub fn gc_root_thread_block(&mut self, obj_id: u64, thread_serial: u32) -> &mut Self {
+        self.body.push(0x06);
+        self.id(obj_id).u32(thread_serial)
+    }
+
+    pub fn gc_root_busy_monitor(&mut self, obj_id: u64) -> &mut Self {
+        self.body.push(0x07);
+        self.id(obj_id)
+    }
+
+    pub fn class(&mut self, class: &TestClass) -> &mut Self {
+        self.body.push(0x20);
+        self.id(class.obj_id)
+            .u32(class.stack_trace_serial)
+            .id(class.super_class_obj_id)
+            .id(class.class_loader_obj_id)
+            .id(class.signers_obj_id)
+            .id(class.protection_domain_obj_id)
+            // reserved
+            .id(0)
+            .id(0)
+            .u32(class.instance_size_bytes)
+            .u16(class.constant_pool.len() as u16);
+
+        for (index, value) in class.constant_pool.iter() {
+            self.u16(*index);
+            self.body.push(value.type_code());
+            self.value(value);
+        }
+
+        self.u16(class.static_fields.len() as u16);
+
+        for (name_id, value) in class.static_fields.iter() {
+            self.id(*name_id);
+            self.body.push(value.type_code());
+            self.value(value);
+        }
+
+        self.u16(class.instance_fields.len() as u16);
+        for (name_id, type_code) in class.instance_fields.iter() {
+            self.id(*name_id);
+            self.body.push(*type_code);
+        }
+
+        self
+    }
+
+    /// Class sub records to go with [HprofBuilder::reference_classes], with `WeakReference`
+    /// instances taking `weak_reference_size_bytes`.
+    pub fn reference_classes(&mut self, weak_reference_size_bytes: u32) -> &mut Self {
+        self.class(&TestClass {
+            instance_fields: vec![(20, TYPE_OBJ), (21, TYPE_OBJ)],
+            ..TestClass::new(REFERENCE_CLASS_OBJ_ID, 0)
+        })
+        .class(&TestClass {
+            instance_size_bytes: weak_reference_size_bytes,
+            ..TestClass::new(WEAK_REFERENCE_CLASS_OBJ_ID, REFERENCE_CLASS_OBJ_ID)
+        })
+    }
+
+    pub fn instance(&mut self, obj_id: u64, class_obj_id: u64, fields: &[Val]) -> &mut Self {
+        self.instance_with_stack_trace(obj_id, 0, class_obj_id, fields)
+    }
+
+    pub fn instance_with_stack_trace(
+        &mut self,
+        obj_id: u64,
+        stack_trace_serial: u32,
+        class_obj_id: u64,
+        fields: &[Val],
+    ) -> &mut Self {
+        let mut field_bytes = SegmentBuilder {
+            id_size: self.id_size,
+            body: Vec::new(),
+        };
+        for f in fields {
+            field_bytes.value(f);
+        }
+
+        self.body.push(0x21);
+        self.id(obj_id)
+            .u32(stack_trace_serial)
+            .id(class_obj_id)
+            .u32(field_bytes.body.len() as u32)
+            .raw(&field_bytes.body)
+    }
+
+    pub fn object_array(
+        &mut self,
+        obj_id: u64,
+        array_class_obj_id: u64,
+        elements: &[u64],
+    ) -> &mut Self {
+        self.body.push(0x22);
+        self.id(obj_id)
+            .u32(0)
+            .u32(elements.len() as u32)
+            .id(array_class_obj_id);
+        for &e in elements {
+            self.id(e);
+        }
+        self
+    }
+
+    /// `type_code` as per `PrimitiveArrayType::type_code()`, and `contents` already encoded.
+    pub fn primitive_array(
+        &mut self,
+        obj_id: u64,
+        type_code: u8,
+        num_elements: u32,
+        contents: &[u8],
+    ) -> &mut Self {
+        self.body.push(0x23);
+        self.id(obj_id).u32(0).u32(num_elements);
+        self.body.push(type_code);
+        self.raw(contents)
+    }
+
+    pub fn char_array(&mut self, obj_id: u64, text: &str) -> &mut Self {
+        let chars: Vec<u16> = text.encode_utf16().collect();
+        let contents: Vec<u8> = chars.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.primitive_array(obj_id, 0x05, chars.len() as u32, &contents)
+    }
+
+    pub fn int_array(&mut self, obj_id: u64, ints: &[i32]) -> &mut Self {
+        let contents: Vec<u8> = ints.iter().flat_map(|i| i.to_be_bytes()).collect();
+        self.primitive_array(obj_id, 0x0A, ints.len() as u32, &contents)
+    }
+
+    pub fn byte_array(&mut self, obj_id: u64, bytes: &[u8]) -> &mut Self {
+        self.primitive_array(obj_id, 0x08, bytes.len() as u32, bytes)
+    }
+
+    fn id(&mut self, id: u64) -> &mut Self {
+        write_id(&mut self.body, self.id_size, id);
+        self
+    }
+
+    fn u32(&mut self, num: u32) -> &mut Self {
+        self.body.extend_from_slice(&num.to_be_bytes());
+        self
+    }
+
+    fn u16(&mut self, num: u16) -> &mut Self {
+        self.body.extend_from_slice(&num.to_be_bytes());
+        self
+    }
+
+    fn value(&mut self, value: &Val) -> &mut Self {
+        match *value {
+            Val::Obj(id) => write_id(&mut self.body, self.id_size, id),
+            Val::Bool(b) => self.body.push(b as u8),
+            Val::Char(c) => self.body.extend_from_slice(&c.to_be_bytes()),
+            Val::Float(f) => self.body.extend_from_slice(&f.to_be_bytes()),
+            Val::Double(d) => self.body.extend_from_slice(&d.to_be_bytes()),
+            Val::Byte(b) => self.body.extend_from_slice(&b.to_be_bytes()),
+            Val::Short(s) => self.body.extend_from_slice(&s.to_be_bytes()),
+            Val::Int(i) => self.body.extend_from_slice(&i.to_be_bytes()),
+            Val::Long(l) => self.body.extend_from_slice(&l.to_be_bytes()),
+        }
+        self
+    }
+}
+
+/// A field value to encode. Object ids of 0 are null.
+#[derive(Clone, Copy, Debug)]
+pub enum Val {
+    Obj(u64),
+    Bool(bool),
+    Char(u16),
+    Float(f32),
+    Double(f64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+}
+
+impl Val {
+    pub fn type_code(&self) -> u8 {
+        match self {
+            Val::Obj(_) => TYPE_OBJ,
+            Val::Bool(_) => TYPE_BOOL,
+            Val::Char(_) => TYPE_CHAR,
+            Val::Float(_) => TYPE_FLOAT,
+            Val::Double(_) => TYPE_DOUBLE,
+            Val::Byte(_) => TYPE_BYTE,
+            Val::Short(_) => TYPE_SHORT,
+            Val::Int(_) => TYPE_INT,
+            Val::Long(_) => TYPE_LONG,
+        }
+    }
+}
+
+pub const TYPE_OBJ: u8 = 0x02;
+pub const TYPE_BOOL: u8 = 0x04;
+pub const TYPE_CHAR: u8 = 0x05;
+pub const TYPE_FLOAT: u8 = 0x06;
+pub const TYPE_DOUBLE: u8 = 0x07;
+pub const TYPE_BYTE: u8 = 0x08;
+pub const TYPE_SHORT: u8 = 0x09;
+pub const TYPE_INT: u8 = 0x0A;
+pub const TYPE_LONG: u8 = 0x0B;
+
+/// Class obj id of `java.lang.ref.Reference`, as per [HprofBuilder::reference_classes]
+pub const REFERENCE_CLASS_OBJ_ID: u64 = 100;
+/// Class obj id of `java.lang.ref.WeakReference`, as per [HprofBuilder::reference_classes]
+pub const WEAK_REFERENCE_CLASS_OBJ_ID: u64 = 101;
+
+/// A class sub record. Ids of 0 are absent.
+#[derive(Clone, Debug, Default)]
+pub struct TestClass {
+    pub obj_id: u64,
+    pub stack_trace_serial: u32,
+    pub super_class_obj_id: u64,
+    pub class_loader_obj_id: u64,
+    pub signers_obj_id: u64,
+    pub protection_domain_obj_id: u64,
+    pub instance_size_bytes: u32,
+    /// constant pool index, value
+    pub constant_pool: Vec<(u16, Val)>,
+    /// name id, value
+    pub static_fields: Vec<(u64, Val)>,
+    /// name id, type code
+    pub instance_fields: Vec<(u64, u8)>,
+}
+
+impl TestClass {
+    pub fn new(obj_id: u64, super_class_obj_id: u64) -> TestClass {
+        TestClass {
+            obj_id,
+            super_class_obj_id,
+            ..TestClass::default()
+        }
+    }
+}
+
+fn write_id(buf: &mut Vec<u8>, id_size: u32, id: u64) {
+    match id_size {
+        4 => buf.extend_from_slice(&(id as u32).to_be_bytes()),
+        8 => buf.extend_from_slice(&id.to_be_bytes()),
+        _ => panic!("Unsupported id size {}", id_size),
+    }
+}
@@ -0,0 +1,171 @@
+use crate::heap_dump::*;
+use crate::*;
+
+/// Callbacks for each type of record and sub record, for use with [Hprof::accept].
+///
+/// Every method has a no-op default, so implement just the ones for the data you're interested
+/// in. Methods are called in the order the data appears in the hprof, so, for instance, the
+/// [Utf8] and [LoadClass] records for a class will usually (but not necessarily) be visited before
+/// its [Class] sub record.
+///
+/// # Examples
+///
+/// ```
+/// use jvm_hprof::{heap_dump::Instance, HprofVisitor};
+///
+/// struct InstanceCounter {
+///     count: u64,
+/// }
+///
+/// impl<'a> HprofVisitor<'a> for InstanceCounter {
+///     fn visit_instance(&mut self, _instance: Instance<'a>) {
+///         self.count += 1;
+///     }
+/// }
+/// ```
+pub trait HprofVisitor<'a> {
+    /// Called for every record, before the method for its particular type (if any).
+    fn visit_record(&mut self, _record: Record<'a>) {}
+
+    fn visit_utf8(&mut self, _utf8: Utf8<'a>) {}
+
+    fn visit_load_class(&mut self, _load_class: LoadClass) {}
+
+    fn visit_stack_frame(&mut self, _stack_frame: StackFrame) {}
+
+    fn visit_stack_trace(&mut self, _stack_trace: StackTrace<'a>) {}
+
+    fn visit_start_thread(&mut self, _start_thread: StartThread) {}
+
+    /// Called for each of the `GcRoot*` variants of [SubRecord].
+    fn visit_gc_root(&mut self, _gc_root: SubRecord<'a>) {}
+
+    fn visit_class(&mut self, _class: Class<'a>) {}
+
+    fn visit_instance(&mut self, _instance: Instance<'a>) {}
+
+    fn visit_object_array(&mut self, _object_array: ObjectArray<'a>) {}
+
+    fn visit_primitive_array(&mut self, _primitive_array: PrimitiveArray<'a>) {}
+}
+
+impl<'a> Hprof<'a> {
+    /// Parse every record and sub record, calling the corresponding method on `visitor` for each.
+    ///
+    /// Stops at the first parse error.
+    pub fn accept<V: HprofVisitor<'a>>(&self, visitor: &mut V) -> ParseResult<'a, ()> {
+        for r in self.records_iter() {
+            let record = r?;
+            visitor.visit_record(record);
+
+            match record.tag {
+                RecordTag::Utf8 => visitor.visit_utf8(Utf8::parse(record.body, record.id_size)?),
+                RecordTag::LoadClass => {
+                    visitor.visit_load_class(LoadClass::parse(record.body, record.id_size)?)
+                }
+                RecordTag::StackFrame => {
+                    visitor.visit_stack_frame(StackFrame::parse(record.body, record.id_size)?)
+                }
+                RecordTag::StackTrace => {
+                    visitor.visit_stack_trace(StackTrace::parse(record.body, record.id_size)?)
+                }
+                RecordTag::StartThread => {
+                    visitor.visit_start_thread(StartThread::parse(record.body, record.id_size)?)
+                }
+                RecordTag::HeapDump | RecordTag::HeapDumpSegment => {
+                    let segment =
+                        HeapDumpSegment::parse(record.body, record.id_size, record.handlers)?;
+                    for s in segment.sub_records() {
+                        match s? {
+                            SubRecord::Class(c) => visitor.visit_class(c),
+                            SubRecord::Instance(instance) => visitor.visit_instance(instance),
+                            SubRecord::ObjectArray(oa) => visitor.visit_object_array(oa),
+                            SubRecord::PrimitiveArray(pa) => visitor.visit_primitive_array(pa),
+                            gc_root => visitor.visit_gc_root(gc_root),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[derive(Default)]
+    struct Counter {
+        records: u32,
+        utf8_texts: Vec<String>,
+        gc_roots: u32,
+        instance_class_ids: Vec<Id>,
+        arrays: u32,
+    }
+
+    impl<'a> HprofVisitor<'a> for Counter {
+        fn visit_record(&mut self, _record: Record<'a>) {
+            self.records += 1;
+        }
+
+        fn visit_utf8(&mut self, utf8: Utf8<'a>) {
+            self.utf8_texts.push(utf8.text_as_str().unwrap().to_owned());
+        }
+
+        fn visit_gc_root(&mut self, _gc_root: SubRecord<'a>) {
+            self.gc_roots += 1;
+        }
+
+        fn visit_instance(&mut self, instance: Instance<'a>) {
+            self.instance_class_ids.push(instance.class_obj_id());
+        }
+
+        fn visit_object_array(&mut self, _object_array: ObjectArray<'a>) {
+            self.arrays += 1;
+        }
+
+        fn visit_primitive_array(&mut self, _primitive_array: PrimitiveArray<'a>) {
+            self.arrays += 1;
+        }
+    }
+
+    #[test]
+    fn visitor_counts_instances() {
+        let mut builder = HprofBuilder::new();
+        let mut first_segment = builder.segment();
+        first_segment
+            .class(&TestClass::new(100, 0))
+            .class(&TestClass::new(101, 0))
+            .instance(1000, 100, &[])
+            .gc_root_unknown(1000)
+            .int_array(2000, &[1]);
+        let mut second_segment = builder.segment();
+        second_segment
+            .instance(1001, 101, &[])
+            .instance(1002, 100, &[])
+            .object_array(2001, 100, &[1000]);
+        let bytes = builder
+            .utf8(1, "hello")
+            .heap_dump_segment(&first_segment)
+            .heap_dump_segment(&second_segment)
+            .heap_dump_end()
+            .build();
+        let hprof = parse_hprof(&bytes[..]).unwrap();
+
+        let mut counter = Counter::default();
+        hprof.accept(&mut counter).unwrap();
+
+        assert_eq!(4, counter.records);
+        assert_eq!(vec!["hello".to_owned()], counter.utf8_texts);
+        assert_eq!(1, counter.gc_roots);
+        assert_eq!(
+            vec![Id::from(100), Id::from(101), Id::from(100)],
+            counter.instance_class_ids
+        );
+        assert_eq!(2, counter.arrays);
+    }
+}